@@ -0,0 +1,33 @@
+use std::hint::black_box;
+use std::time::Duration;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+
+use ups_power_status_from_beeps::status::{AmbiguityPolicy, CONTINUOUS_BEEP_DURATION, MatchConfig, ZERO_DURATION, get_status_from_beep_durations};
+
+// A matched pattern, the synthetic pair of a timeout during a beep, and a pair matching nothing, which has to go through the whole table
+const PAIRS: [(&str, Duration, Duration); 3] = [
+  ("low_on_battery", Duration::from_millis(250), Duration::from_secs(1)),
+  ("timeout_in_beep", CONTINUOUS_BEEP_DURATION, ZERO_DURATION),
+  ("unknown", Duration::from_millis(700), Duration::from_millis(700)),
+];
+
+fn bench_get_status_from_beep_durations(criterion: &mut Criterion) {
+  let mut group = criterion.benchmark_group("get_status_from_beep_durations");
+  let config = MatchConfig::default();
+  for (name, beep, inter_beep) in PAIRS {
+    group.bench_function(name, |bencher| {
+      bencher.iter(|| get_status_from_beep_durations(black_box(beep), black_box(inter_beep), &config))
+    });
+  }
+
+  // With a 50% margin this pair matches two statuses, so the policy has to rank them
+  let ambiguous_config = MatchConfig { error_margin: 0.5, on_ambiguous: AmbiguityPolicy::HighestSeverity, ..MatchConfig::default() };
+  group.bench_function("ambiguous_highest_severity", |bencher| {
+    bencher.iter(|| get_status_from_beep_durations(black_box(Duration::from_millis(250)), black_box(Duration::from_millis(1400)), &ambiguous_config))
+  });
+  group.finish();
+}
+
+criterion_group!(benches, bench_get_status_from_beep_durations);
+criterion_main!(benches);
@@ -0,0 +1,46 @@
+// The push_capped the detector caps its beep history with, dropping the oldest entry once it is full, against shifting a Vec down by one the way it used to,
+// at the size of the detector's history and at the larger ones longer looks back at the beeps would need
+use std::collections::VecDeque;
+use std::hint::black_box;
+use std::time::Duration;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+
+use ups_power_status_from_beeps::history::push_capped;
+
+const CAPACITIES: [usize; 3] = [10, 100, 1000];
+const PUSHES: u64 = 1000;
+
+fn push_capped_vec(history: &mut Vec<Duration>, duration: Duration, capacity: usize) {
+  history.push(duration);
+  if history.len() > capacity {
+    history.remove(0);
+  }
+}
+
+fn bench_push_capped(criterion: &mut Criterion) {
+  let mut group = criterion.benchmark_group("push_capped");
+  for capacity in CAPACITIES {
+    // Full already, so every push drops the oldest
+    group.bench_with_input(BenchmarkId::new("vec_remove_first", capacity), &capacity, |bencher, &capacity| {
+      let mut history = vec![Duration::ZERO; capacity];
+      bencher.iter(|| {
+        for millis in 0..PUSHES {
+          push_capped_vec(&mut history, black_box(Duration::from_millis(millis)), capacity);
+        }
+      });
+    });
+    group.bench_with_input(BenchmarkId::new("vec_deque_pop_front", capacity), &capacity, |bencher, &capacity| {
+      let mut history: VecDeque<Duration> = vec![Duration::ZERO; capacity].into();
+      bencher.iter(|| {
+        for millis in 0..PUSHES {
+          push_capped(&mut history, black_box(Duration::from_millis(millis)), capacity);
+        }
+      });
+    });
+  }
+  group.finish();
+}
+
+criterion_group!(benches, bench_push_capped);
+criterion_main!(benches);
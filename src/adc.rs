@@ -0,0 +1,112 @@
+use rppal::spi::{Bus, Mode, SlaveSelect, Spi};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::detector::Edge;
+use crate::source::{EdgeSource, ReadFailures, SourceEvent};
+
+// Reads the tap through an MCP3008 on SPI0 with CE0 as chip select: VDD and VREF to 3.3V, AGND and DGND to ground,
+// CLK to GPIO 11, DOUT to GPIO 9 (MISO), DIN to GPIO 10 (MOSI) and CS to GPIO 8 (CE0), the tap going into one of CH0 to CH7,
+// the piezo's own oscillation is much faster than the sampling so the tap has to be rectified and smoothed into an envelope first,
+// a diode followed by a capacitor and a resistor to ground is enough
+pub const ADC_CHANNELS: u8 = 8;
+pub const ADC_MAX_VALUE: u16 = 1023;
+pub const DEFAULT_ADC_THRESHOLD: u16 = 512;
+pub const DEFAULT_ADC_HYSTERESIS: u16 = 16;
+
+// Well within the 1.35MHz the MCP3008 manages at 3.3V
+const SPI_CLOCK_SPEED: u32 = 1_000_000;
+// Fine enough for the 50ms bounce window of the detector, without keeping a core busy
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(2);
+
+pub struct AdcSource {
+  spi: Spi,
+  channel: u8,
+  threshold: u16,
+  hysteresis: u16,
+
+  is_on: bool,
+  read_failures: ReadFailures,
+}
+
+impl AdcSource {
+  pub fn new(channel: u8, threshold: u16, hysteresis: u16) -> Result<AdcSource, String> {
+    let spi = Spi::new(Bus::Spi0, SlaveSelect::Ss0, SPI_CLOCK_SPEED, Mode::Mode0)
+      .map_err(|error| format!("could not set up SPI for the ADC: {}", error))?;
+    let mut adc_source = AdcSource { spi, channel, threshold, hysteresis, is_on: false, read_failures: ReadFailures::default() };
+    adc_source.is_on = adc_source.read_value()? >= threshold;
+    Ok(adc_source)
+  }
+
+  // Single ended conversion, the start bit, then the mode and channel bits, the 10 bit result coming back over the last two bytes
+  fn read_value(&self) -> Result<u16, String> {
+    let mut read_buffer = [0; 3];
+    self.spi.transfer(&mut read_buffer, &[0x01, (0x08 | self.channel) << 4, 0x00])
+      .map_err(|error| format!("could not read ADC channel {}: {}", self.channel, error))?;
+    Ok(get_sample_value(&read_buffer))
+  }
+}
+
+fn get_sample_value(read_buffer: &[u8; 3]) -> u16 {
+  (u16::from(read_buffer[1] & 0x03) << 8) | u16::from(read_buffer[2])
+}
+
+// The line turns on once the value climbs past the threshold by the hysteresis and off once it drops below it by as much,
+// so a value hovering around the threshold doesn't turn into a burst of edges
+fn get_threshold_edge(is_on: bool, value: u16, threshold: u16, hysteresis: u16) -> Option<Edge> {
+  if !is_on && value >= threshold.saturating_add(hysteresis) {
+    Some(Edge::BeepStart)
+  } else if is_on && value < threshold.saturating_sub(hysteresis) {
+    Some(Edge::BeepEnd)
+  } else {
+    None
+  }
+}
+
+impl EdgeSource for AdcSource {
+  fn next_event(&mut self, timeout: Duration) -> Option<SourceEvent> {
+    let deadline = Instant::now() + timeout;
+    loop {
+      // A failed read counts as a sample that changed nothing
+      let value = match self.read_value() {
+        Ok(value) => {
+          self.read_failures.on_success();
+          Some(value)
+        },
+        Err(error) => {
+          self.read_failures.on_failure(error);
+          None
+        },
+      };
+      let now = Instant::now();
+      if let Some(edge) = value.and_then(|value| get_threshold_edge(self.is_on, value, self.threshold, self.hysteresis)) {
+        self.is_on = edge == Edge::BeepStart;
+        return Some(SourceEvent::Edge(edge, now));
+      }
+      if now >= deadline {
+        return Some(SourceEvent::Timeout(now));
+      }
+      thread::sleep(SAMPLE_INTERVAL.min(deadline - now));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reads_ten_bit_samples() {
+    assert_eq!(get_sample_value(&[0xFF, 0x03, 0xFF]), ADC_MAX_VALUE);
+    assert_eq!(get_sample_value(&[0xFF, 0xFE, 0x01]), 0x201);
+  }
+
+  #[test]
+  fn edges_need_to_clear_the_hysteresis() {
+    assert_eq!(get_threshold_edge(false, 520, 512, 16), None);
+    assert_eq!(get_threshold_edge(false, 528, 512, 16), Some(Edge::BeepStart));
+    assert_eq!(get_threshold_edge(true, 500, 512, 16), None);
+    assert_eq!(get_threshold_edge(true, 495, 512, 16), Some(Edge::BeepEnd));
+    assert_eq!(get_threshold_edge(true, 0, 8, 16), None);
+  }
+}
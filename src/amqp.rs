@@ -0,0 +1,479 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::delivery::{Broker, start_publisher};
+use crate::json::get_transition_json;
+use crate::state::{SharedState, Transition};
+
+pub const DEFAULT_AMQP_PORT: u16 = 5672;
+pub const DEFAULT_AMQP_VIRTUAL_HOST: &str = "/";
+// Declared by every RabbitMQ, the routing key is matched against the bindings of the queues like a topic
+pub const DEFAULT_AMQP_EXCHANGE: &str = "amq.topic";
+pub const DEFAULT_AMQP_ROUTING_KEY: &str = "ups.status";
+// What RabbitMQ lets in from localhost out of the box
+const DEFAULT_CREDENTIALS: (&str, &str) = ("guest", "guest");
+
+// For connecting and for every frame written or read, a broker slower than that is treated as unreachable
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+// How often a heartbeat is sent when the broker doesn't ask for heartbeats at all, a dead connection only shows up when writing
+const IDLE_HEARTBEAT_DURATION: Duration = Duration::from_secs(30);
+// Largest frame read or written whatever the broker allows, plenty for the JSON of a transition and the methods around it,
+// and the smallest every broker has to take
+const MAX_FRAME_SIZE: u32 = 131_072;
+const MIN_FRAME_SIZE: u32 = 4096;
+
+// AMQP 0-9-1, what RabbitMQ speaks
+const PROTOCOL_HEADER: &[u8; 8] = b"AMQP\x00\x00\x09\x01";
+const FRAME_END: u8 = 0xce;
+const METHOD_FRAME: u8 = 1;
+const HEADER_FRAME: u8 = 2;
+const BODY_FRAME: u8 = 3;
+const HEARTBEAT_FRAME: u8 = 8;
+// The one channel publishing happens on, channel 0 being the connection's own
+const CHANNEL: u16 = 1;
+
+const CONNECTION_CLASS: u16 = 10;
+const CHANNEL_CLASS: u16 = 20;
+const BASIC_CLASS: u16 = 60;
+const CONFIRM_CLASS: u16 = 85;
+const CONNECTION_START: u16 = 10;
+const CONNECTION_START_OK: u16 = 11;
+const CONNECTION_TUNE: u16 = 30;
+const CONNECTION_TUNE_OK: u16 = 31;
+const CONNECTION_OPEN: u16 = 40;
+const CONNECTION_OPEN_OK: u16 = 41;
+const CONNECTION_CLOSE: u16 = 50;
+const CONNECTION_CLOSE_OK: u16 = 51;
+const CHANNEL_OPEN: u16 = 10;
+const CHANNEL_OPEN_OK: u16 = 11;
+const CHANNEL_CLOSE: u16 = 40;
+const BASIC_PUBLISH: u16 = 40;
+const BASIC_ACK: u16 = 80;
+const BASIC_NACK: u16 = 120;
+const CONFIRM_SELECT: u16 = 10;
+const CONFIRM_SELECT_OK: u16 = 11;
+const CONTENT_TYPE_FLAG: u16 = 0x8000;
+const REPLY_SUCCESS: u16 = 200;
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct AmqpConfig {
+  pub host: String,
+  pub port: u16,
+  pub virtual_host: String,
+  pub exchange: String,
+  pub routing_key: String,
+  // The username along with the password, if there is one, the guest account when not given
+  pub credentials: Option<(String, Option<String>)>,
+}
+
+// A connection with the channel open and in confirm mode, so every publish is answered and a broker refusing it doesn't go unnoticed
+pub struct AmqpConnection {
+  stream: TcpStream,
+  frame_max: u32,
+  // Half the interval the broker asked for, so it never goes without one for the whole interval
+  heartbeat_interval: Duration,
+}
+
+// Publishes every transition as the same JSON object as the json format to the exchange with the routing key,
+// see start_publisher for the delivery
+pub fn start_amqp_publisher(config: AmqpConfig, state: SharedState) -> JoinHandle<()> {
+  start_publisher(config, state)
+}
+
+impl Broker for AmqpConfig {
+  type Connection = AmqpConnection;
+
+  fn describe(&self) -> String {
+    format!("the AMQP broker at {}:{}", self.host, self.port)
+  }
+
+  fn connect(&self) -> io::Result<AmqpConnection> {
+    connect(self)
+  }
+
+  fn publish_transitions(&self, connection: &mut AmqpConnection, current: Option<Transition>, transitions: &Receiver<Transition>) -> io::Result<()> {
+    if let Some(current) = current {
+      publish(connection, self, get_transition_json(&current).as_bytes())?;
+    }
+    loop {
+      match transitions.recv_timeout(connection.heartbeat_interval) {
+        Ok(transition) => publish(connection, self, get_transition_json(&transition).as_bytes())?,
+        // The broker's own heartbeats are skipped over whenever an answer is read next
+        Err(RecvTimeoutError::Timeout) => connection.stream.write_all(&get_frame(HEARTBEAT_FRAME, 0, &[]))?,
+        Err(RecvTimeoutError::Disconnected) => {
+          let mut arguments = REPLY_SUCCESS.to_be_bytes().to_vec();
+          push_short_string(&mut arguments, b"");
+          arguments.extend_from_slice(&[0, 0, 0, 0]);
+          connection.stream.write_all(&get_method_frame(0, CONNECTION_CLASS, CONNECTION_CLOSE, &arguments))?;
+          return read_expected_method(&mut connection.stream, CONNECTION_CLASS, CONNECTION_CLOSE_OK).map(|_| ());
+        },
+      }
+    }
+  }
+}
+
+fn connect(config: &AmqpConfig) -> io::Result<AmqpConnection> {
+  let address = (config.host.as_str(), config.port).to_socket_addrs()?.next()
+    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host has no address"))?;
+  let mut stream = TcpStream::connect_timeout(&address, IO_TIMEOUT)?;
+  stream.set_read_timeout(Some(IO_TIMEOUT))?;
+  stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+  stream.write_all(PROTOCOL_HEADER)?;
+  read_expected_method(&mut stream, CONNECTION_CLASS, CONNECTION_START)?;
+  let (username, password) = match &config.credentials {
+    Some((username, password)) => (username.as_str(), password.as_deref().unwrap_or("")),
+    None => DEFAULT_CREDENTIALS,
+  };
+  stream.write_all(&get_method_frame(0, CONNECTION_CLASS, CONNECTION_START_OK, &get_start_ok_arguments(username, password)))?;
+
+  let tune = read_expected_method(&mut stream, CONNECTION_CLASS, CONNECTION_TUNE)?;
+  let (frame_max, heartbeat_secs) = parse_tune_arguments(&tune)?;
+  let mut arguments = 1u16.to_be_bytes().to_vec();
+  arguments.extend_from_slice(&frame_max.to_be_bytes());
+  arguments.extend_from_slice(&heartbeat_secs.to_be_bytes());
+  stream.write_all(&get_method_frame(0, CONNECTION_CLASS, CONNECTION_TUNE_OK, &arguments))?;
+
+  let mut arguments = vec![];
+  push_short_string(&mut arguments, config.virtual_host.as_bytes());
+  arguments.extend_from_slice(&[0, 0]);
+  stream.write_all(&get_method_frame(0, CONNECTION_CLASS, CONNECTION_OPEN, &arguments))?;
+  read_expected_method(&mut stream, CONNECTION_CLASS, CONNECTION_OPEN_OK)?;
+  stream.write_all(&get_method_frame(CHANNEL, CHANNEL_CLASS, CHANNEL_OPEN, &[0]))?;
+  read_expected_method(&mut stream, CHANNEL_CLASS, CHANNEL_OPEN_OK)?;
+  stream.write_all(&get_method_frame(CHANNEL, CONFIRM_CLASS, CONFIRM_SELECT, &[0]))?;
+  read_expected_method(&mut stream, CONFIRM_CLASS, CONFIRM_SELECT_OK)?;
+
+  let heartbeat_interval = if heartbeat_secs == 0 { IDLE_HEARTBEAT_DURATION } else { Duration::from_secs(heartbeat_secs.into()) / 2 };
+  Ok(AmqpConnection { stream, frame_max, heartbeat_interval })
+}
+
+// PLAIN, which every broker takes, with the name of the program for the broker's list of connections
+fn get_start_ok_arguments(username: &str, password: &str) -> Vec<u8> {
+  let mut client_properties = vec![];
+  push_short_string(&mut client_properties, b"product");
+  client_properties.push(b'S');
+  push_long_string(&mut client_properties, env!("CARGO_PKG_NAME").as_bytes());
+
+  let mut arguments = vec![];
+  push_long_string(&mut arguments, &client_properties);
+  push_short_string(&mut arguments, b"PLAIN");
+  push_long_string(&mut arguments, format!("\0{}\0{}", username, password).as_bytes());
+  push_short_string(&mut arguments, b"en_US");
+  arguments
+}
+
+// The frame size and heartbeat interval to go with, the broker's, with 0 for no limit on the frame size taken as the limit here
+fn parse_tune_arguments(arguments: &[u8]) -> io::Result<(u32, u16)> {
+  let &[_, _, frame_max_0, frame_max_1, frame_max_2, frame_max_3, heartbeat_0, heartbeat_1] = arguments else {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated connection tuning"));
+  };
+  let frame_max = match u32::from_be_bytes([frame_max_0, frame_max_1, frame_max_2, frame_max_3]) {
+    0 => MAX_FRAME_SIZE,
+    frame_max => frame_max.clamp(MIN_FRAME_SIZE, MAX_FRAME_SIZE),
+  };
+  Ok((frame_max, u16::from_be_bytes([heartbeat_0, heartbeat_1])))
+}
+
+// The method, its content header and as many body frames as the frame size takes, written at once and then waited on
+// until the broker confirms it
+fn publish(connection: &mut AmqpConnection, config: &AmqpConfig, body: &[u8]) -> io::Result<()> {
+  let mut arguments = vec![0, 0];
+  push_short_string(&mut arguments, config.exchange.as_bytes());
+  push_short_string(&mut arguments, config.routing_key.as_bytes());
+  // Neither mandatory nor immediate, a status nobody has bound a queue for is just dropped
+  arguments.push(0);
+  let mut frames = get_method_frame(CHANNEL, BASIC_CLASS, BASIC_PUBLISH, &arguments);
+
+  let mut header = BASIC_CLASS.to_be_bytes().to_vec();
+  header.extend_from_slice(&[0, 0]);
+  header.extend_from_slice(&(body.len() as u64).to_be_bytes());
+  header.extend_from_slice(&CONTENT_TYPE_FLAG.to_be_bytes());
+  push_short_string(&mut header, b"application/json");
+  frames.extend(get_frame(HEADER_FRAME, CHANNEL, &header));
+  // The frame size counts the 8 bytes around the payload too
+  for chunk in body.chunks(connection.frame_max as usize - 8) {
+    frames.extend(get_frame(BODY_FRAME, CHANNEL, chunk));
+  }
+  connection.stream.write_all(&frames)?;
+
+  match read_method(&mut connection.stream)? {
+    (BASIC_CLASS, BASIC_ACK, _) => Ok(()),
+    (BASIC_CLASS, BASIC_NACK, _) => Err(io::Error::other("the broker didn't take the status")),
+    (class_id, method_id, _) => Err(get_unexpected_method_error(class_id, method_id)),
+  }
+}
+
+fn read_expected_method(stream: &mut TcpStream, class_id: u16, method_id: u16) -> io::Result<Vec<u8>> {
+  match read_method(stream)? {
+    (read_class_id, read_method_id, arguments) if (read_class_id, read_method_id) == (class_id, method_id) => Ok(arguments),
+    (read_class_id, read_method_id, _) => Err(get_unexpected_method_error(read_class_id, read_method_id)),
+  }
+}
+
+// The class, method and arguments of the next method frame, heartbeats skipped, the broker closing the connection or the channel
+// being an error with the reason it gave
+fn read_method(stream: &mut TcpStream) -> io::Result<(u16, u16, Vec<u8>)> {
+  loop {
+    let (frame_type, payload) = read_frame(stream)?;
+    match frame_type {
+      HEARTBEAT_FRAME => continue,
+      METHOD_FRAME if payload.len() >= 4 => {},
+      _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected frame of type {}", frame_type))),
+    }
+    let class_id = u16::from_be_bytes([payload[0], payload[1]]);
+    let method_id = u16::from_be_bytes([payload[2], payload[3]]);
+    if matches!((class_id, method_id), (CONNECTION_CLASS, CONNECTION_CLOSE) | (CHANNEL_CLASS, CHANNEL_CLOSE)) {
+      return Err(io::Error::new(io::ErrorKind::ConnectionRefused, get_close_description(&payload[4..])));
+    }
+    return Ok((class_id, method_id, payload[4..].to_vec()));
+  }
+}
+
+fn read_frame(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+  let mut header = [0; 7];
+  stream.read_exact(&mut header)?;
+  // A broker that doesn't speak the version answers with the protocol header of the one it does and hangs up
+  if header.starts_with(b"AMQP") {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "the broker doesn't speak AMQP 0-9-1"));
+  }
+  let size = u32::from_be_bytes([header[3], header[4], header[5], header[6]]);
+  if size > MAX_FRAME_SIZE {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {} bytes is too large", size)));
+  }
+  let mut payload = vec![0; size as usize + 1];
+  stream.read_exact(&mut payload)?;
+  if payload.pop() != Some(FRAME_END) {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "frame without its end"));
+  }
+  Ok((header[0], payload))
+}
+
+fn get_unexpected_method_error(class_id: u16, method_id: u16) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, format!("unexpected method {}.{}", class_id, method_id))
+}
+
+// The reply code and text of a close, as in "403 ACCESS_REFUSED - Login was refused"
+fn get_close_description(arguments: &[u8]) -> String {
+  match arguments {
+    [code_high, code_low, length, rest @ ..] if rest.len() >= *length as usize => {
+      format!("the broker closed the connection with {} {}", u16::from_be_bytes([*code_high, *code_low]), String::from_utf8_lossy(&rest[..*length as usize]))
+    },
+    _ => "the broker closed the connection".to_string(),
+  }
+}
+
+fn get_method_frame(channel: u16, class_id: u16, method_id: u16, arguments: &[u8]) -> Vec<u8> {
+  let mut payload = class_id.to_be_bytes().to_vec();
+  payload.extend_from_slice(&method_id.to_be_bytes());
+  payload.extend_from_slice(arguments);
+  get_frame(METHOD_FRAME, channel, &payload)
+}
+
+fn get_frame(frame_type: u8, channel: u16, payload: &[u8]) -> Vec<u8> {
+  let mut frame = vec![frame_type];
+  frame.extend_from_slice(&channel.to_be_bytes());
+  frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+  frame.extend_from_slice(payload);
+  frame.push(FRAME_END);
+  frame
+}
+
+fn push_short_string(arguments: &mut Vec<u8>, string: &[u8]) {
+  arguments.push(string.len() as u8);
+  arguments.extend_from_slice(string);
+}
+
+fn push_long_string(arguments: &mut Vec<u8>, string: &[u8]) {
+  arguments.extend_from_slice(&(string.len() as u32).to_be_bytes());
+  arguments.extend_from_slice(string);
+}
+
+// Exchanges, routing keys and virtual hosts all go in short strings, which hold at most 255 bytes
+pub fn is_valid_short_string(value: &str) -> bool {
+  value.len() <= u8::MAX as usize
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::net::TcpListener;
+  use std::sync::{Arc, Mutex};
+  use std::thread;
+  use std::time::{Instant, SystemTime};
+
+  use crate::guidance::GuidanceTable;
+  use crate::report::Origin;
+  use crate::state::StatusState;
+  use crate::status::Status;
+
+  fn get_config(port: u16) -> AmqpConfig {
+    AmqpConfig {
+      host: "127.0.0.1".to_string(),
+      port,
+      virtual_host: DEFAULT_AMQP_VIRTUAL_HOST.to_string(),
+      exchange: DEFAULT_AMQP_EXCHANGE.to_string(),
+      routing_key: DEFAULT_AMQP_ROUTING_KEY.to_string(),
+      credentials: None,
+    }
+  }
+
+  fn get_transition(status: Status) -> Transition {
+    Transition {
+      from: None,
+      to: status,
+      guidance: GuidanceTable::new(vec![]).get(status),
+      cleared: false,
+      confidence: 1.0,
+      beep_duration: Duration::from_millis(250),
+      inter_beep_duration: Duration::from_secs(60),
+      origin: Origin::Observed,
+      at: SystemTime::now(),
+      at_instant: Instant::now(),
+      sequence: 0,
+      in_maintenance_window: false,
+    }
+  }
+
+  // The broker's side of the handshake up to the channel in confirm mode, returning the Start-Ok and Tune-Ok it was sent
+  fn accept_handshake(stream: &mut TcpStream, heartbeat_secs: u16) -> (Vec<u8>, Vec<u8>) {
+    let mut protocol_header = [0; 8];
+    stream.read_exact(&mut protocol_header).unwrap();
+    assert_eq!(&protocol_header, PROTOCOL_HEADER);
+    stream.write_all(&get_method_frame(0, CONNECTION_CLASS, CONNECTION_START, &[0, 9, 0, 0, 0, 0, 0, 0, 0, 5, b'P', b'L', b'A', b'I', b'N', 0, 0, 0, 5, b'e', b'n', b'_', b'U', b'S'])).unwrap();
+    let start_ok = read_expected_method(stream, CONNECTION_CLASS, CONNECTION_START_OK).unwrap();
+    let mut tune = vec![0, 0, 0, 0, 0x10, 0];
+    tune.extend_from_slice(&heartbeat_secs.to_be_bytes());
+    stream.write_all(&get_method_frame(0, CONNECTION_CLASS, CONNECTION_TUNE, &tune)).unwrap();
+    let tune_ok = read_expected_method(stream, CONNECTION_CLASS, CONNECTION_TUNE_OK).unwrap();
+    assert_eq!(read_expected_method(stream, CONNECTION_CLASS, CONNECTION_OPEN).unwrap(), vec![1, b'/', 0, 0]);
+    stream.write_all(&get_method_frame(0, CONNECTION_CLASS, CONNECTION_OPEN_OK, &[0])).unwrap();
+    read_expected_method(stream, CHANNEL_CLASS, CHANNEL_OPEN).unwrap();
+    stream.write_all(&get_method_frame(CHANNEL, CHANNEL_CLASS, CHANNEL_OPEN_OK, &[0, 0, 0, 0])).unwrap();
+    read_expected_method(stream, CONFIRM_CLASS, CONFIRM_SELECT).unwrap();
+    stream.write_all(&get_method_frame(CHANNEL, CONFIRM_CLASS, CONFIRM_SELECT_OK, &[])).unwrap();
+    (start_ok, tune_ok)
+  }
+
+  // The arguments of the publish and the body put together from the frames after it, acknowledged
+  fn accept_publish(stream: &mut TcpStream) -> (Vec<u8>, String) {
+    let arguments = read_expected_method(stream, BASIC_CLASS, BASIC_PUBLISH).unwrap();
+    let (frame_type, header) = read_frame(stream).unwrap();
+    assert_eq!(frame_type, HEADER_FRAME);
+    let body_size = u64::from_be_bytes(header[4..12].try_into().unwrap()) as usize;
+    assert_eq!(&header[12..], b"\x80\x00\x10application/json");
+    let mut body = vec![];
+    while body.len() < body_size {
+      let (frame_type, payload) = read_frame(stream).unwrap();
+      assert_eq!(frame_type, BODY_FRAME);
+      body.extend(payload);
+    }
+    stream.write_all(&get_method_frame(CHANNEL, BASIC_CLASS, BASIC_ACK, &[0; 9])).unwrap();
+    (arguments, String::from_utf8(body).unwrap())
+  }
+
+  #[test]
+  fn encodes_frames() {
+    assert_eq!(get_frame(HEARTBEAT_FRAME, 0, &[]), vec![HEARTBEAT_FRAME, 0, 0, 0, 0, 0, 0, FRAME_END]);
+    assert_eq!(get_method_frame(CHANNEL, CHANNEL_CLASS, CHANNEL_OPEN, &[0]), vec![METHOD_FRAME, 0, 1, 0, 0, 0, 5, 0, 20, 0, 10, 0, FRAME_END]);
+    let arguments = get_start_ok_arguments("ha", "pw");
+    assert!(arguments.ends_with(b"\x05PLAIN\x00\x00\x00\x06\x00ha\x00pw\x05en_US"));
+  }
+
+  #[test]
+  fn takes_the_tuning_of_the_broker() {
+    assert_eq!(parse_tune_arguments(&[0, 0, 0, 0, 0x10, 0, 0, 60]).unwrap(), (4096, 60));
+    assert_eq!(parse_tune_arguments(&[0, 0, 0, 0, 0, 8, 0, 60]).unwrap(), (MIN_FRAME_SIZE, 60));
+    assert_eq!(parse_tune_arguments(&[0, 0, 0, 0, 0, 0, 0, 0]).unwrap(), (MAX_FRAME_SIZE, 0));
+    assert_eq!(parse_tune_arguments(&[0, 0, 0xff, 0xff, 0xff, 0xff, 0, 0]).unwrap(), (MAX_FRAME_SIZE, 0));
+    assert!(parse_tune_arguments(&[0, 0, 0]).is_err());
+    assert!(parse_tune_arguments(&[0; 9]).is_err());
+  }
+
+  #[test]
+  fn publishes_the_current_status_and_every_transition() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let state = Arc::new(Mutex::new(StatusState::new(10)));
+    state.lock().unwrap().record(get_transition(Status::OnMains));
+    let publisher = start_amqp_publisher(get_config(listener.local_addr().unwrap().port()), state.clone());
+
+    let (mut stream, _) = listener.accept().unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let (start_ok, tune_ok) = accept_handshake(&mut stream, 60);
+    assert!(start_ok.ends_with(b"\x05PLAIN\x00\x00\x00\x0c\x00guest\x00guest\x05en_US"));
+    assert_eq!(tune_ok, vec![0, 1, 0, 0, 0x10, 0, 0, 60]);
+
+    let (arguments, body) = accept_publish(&mut stream);
+    assert_eq!(arguments, b"\0\0\x09amq.topic\x0aups.status\0");
+    assert!(body.contains("\"status\":\"OnMains\""));
+
+    state.lock().unwrap().record(get_transition(Status::OnBattery));
+    let (_, body) = accept_publish(&mut stream);
+    assert!(body.contains("\"status\":\"OnBattery\""));
+
+    // Stopping publishes what was recorded before it, then closes the connection
+    state.lock().unwrap().record(get_transition(Status::OnMains));
+    state.lock().unwrap().close_subscriptions();
+    assert!(accept_publish(&mut stream).1.contains("\"status\":\"OnMains\""));
+    let (frame_type, payload) = read_frame(&mut stream).unwrap();
+    assert_eq!((frame_type, &payload[..6]), (METHOD_FRAME, &[0, 10, 0, 50, 0, 200][..]));
+    stream.write_all(&get_method_frame(0, CONNECTION_CLASS, CONNECTION_CLOSE_OK, &[])).unwrap();
+    publisher.join().unwrap();
+  }
+
+  #[test]
+  fn splits_bodies_across_frames() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (mut broker_stream, _) = listener.accept().unwrap();
+    let mut connection = AmqpConnection { stream, frame_max: MIN_FRAME_SIZE, heartbeat_interval: IDLE_HEARTBEAT_DURATION };
+    let body = "x".repeat(5000);
+    let broker = thread::spawn(move || {
+      let arguments = read_expected_method(&mut broker_stream, BASIC_CLASS, BASIC_PUBLISH).unwrap();
+      read_frame(&mut broker_stream).unwrap();
+      let sizes = [read_frame(&mut broker_stream).unwrap(), read_frame(&mut broker_stream).unwrap()].map(|(_, payload)| payload.len());
+      broker_stream.write_all(&get_method_frame(CHANNEL, BASIC_CLASS, BASIC_NACK, &[0; 9])).unwrap();
+      (arguments, sizes)
+    });
+    let config = AmqpConfig { exchange: String::new(), routing_key: "ups".to_string(), ..get_config(0) };
+    assert_eq!(publish(&mut connection, &config, body.as_bytes()).unwrap_err().to_string(), "the broker didn't take the status");
+    assert_eq!(broker.join().unwrap(), (b"\0\0\0\x03ups\0".to_vec(), [4088, 912]));
+  }
+
+  #[test]
+  fn refused_connections_are_described() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let broker = thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      let mut protocol_header = [0; 8];
+      stream.read_exact(&mut protocol_header).unwrap();
+      stream.write_all(&get_method_frame(0, CONNECTION_CLASS, CONNECTION_START, &[0, 9, 0, 0, 0, 0])).unwrap();
+      read_frame(&mut stream).unwrap();
+      let mut close = 403u16.to_be_bytes().to_vec();
+      push_short_string(&mut close, b"ACCESS_REFUSED - Login was refused");
+      close.extend_from_slice(&[0, 0, 0, 0]);
+      stream.write_all(&get_method_frame(0, CONNECTION_CLASS, CONNECTION_CLOSE, &close)).unwrap();
+    });
+    let config = AmqpConfig { credentials: Some(("ha".to_string(), Some("wrong".to_string()))), ..get_config(port) };
+    assert_eq!(connect(&config).err().unwrap().to_string(), "the broker closed the connection with 403 ACCESS_REFUSED - Login was refused");
+    broker.join().unwrap();
+  }
+
+  #[test]
+  fn brokers_of_another_version_are_described() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let broker = thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      let mut protocol_header = [0; 8];
+      stream.read_exact(&mut protocol_header).unwrap();
+      stream.write_all(b"AMQP\x01\x01\x00\x0a").unwrap();
+    });
+    assert_eq!(connect(&get_config(port)).err().unwrap().to_string(), "the broker doesn't speak AMQP 0-9-1");
+    broker.join().unwrap();
+  }
+}
@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+use crate::classifier::Classifier;
+use crate::status::{CONTINUOUS_BEEP_DURATION, Classification, Status, ZERO_DURATION, get_closeness};
+
+// The pairs measured so far that are close enough to each other to be the same pattern
+struct Cluster {
+  count: u32,
+  beep_total: Duration,
+  inter_beep_total: Duration,
+  beep_range: (Duration, Duration),
+  inter_beep_range: (Duration, Duration),
+}
+
+impl Cluster {
+  fn new(beep: Duration, inter_beep: Duration) -> Cluster {
+    Cluster { count: 1, beep_total: beep, inter_beep_total: inter_beep, beep_range: (beep, beep), inter_beep_range: (inter_beep, inter_beep) }
+  }
+
+  fn get_means(&self) -> (Duration, Duration) {
+    (self.beep_total / self.count, self.inter_beep_total / self.count)
+  }
+
+  fn add(&mut self, beep: Duration, inter_beep: Duration) {
+    self.count += 1;
+    self.beep_total += beep;
+    self.inter_beep_total += inter_beep;
+    self.beep_range = (self.beep_range.0.min(beep), self.beep_range.1.max(beep));
+    self.inter_beep_range = (self.inter_beep_range.0.min(inter_beep), self.inter_beep_range.1.max(inter_beep));
+  }
+}
+
+// Prints every measured pair instead of classifying it, along with how many pairs like it have come and how far apart they ranged,
+// for reading the pattern of a UPS off of it, a pair belongs with the first cluster whose means it is as close to as a match is to its target,
+// nothing is ever classified so nothing gets reported while calibrating
+pub struct Calibrator {
+  error_margin: f64,
+  min_error_duration: Duration,
+  clusters: Vec<Cluster>,
+}
+
+impl Calibrator {
+  pub fn new(error_margin: f64, min_error_duration: Duration) -> Calibrator {
+    Calibrator { error_margin, min_error_duration, clusters: vec![] }
+  }
+
+  fn measure(&mut self, beep: Duration, inter_beep: Duration) -> String {
+    let is_close = |duration: Duration, target: Duration| get_closeness(duration, target, self.error_margin, self.min_error_duration).is_some();
+    let index = match self.clusters.iter().position(|cluster| {
+      let (beep_mean, inter_beep_mean) = cluster.get_means();
+      is_close(beep, beep_mean) && is_close(inter_beep, inter_beep_mean)
+    }) {
+      Some(index) => {
+        self.clusters[index].add(beep, inter_beep);
+        index
+      },
+      None => {
+        self.clusters.push(Cluster::new(beep, inter_beep));
+        self.clusters.len() - 1
+      },
+    };
+    let cluster = &self.clusters[index];
+    format!(
+      "Beep {}ms, inter beep {}ms, cluster {} of {}: {} seen, beep {}-{}ms, inter beep {}-{}ms",
+      beep.as_millis(),
+      inter_beep.as_millis(),
+      index + 1,
+      self.clusters.len(),
+      cluster.count,
+      cluster.beep_range.0.as_millis(),
+      cluster.beep_range.1.as_millis(),
+      cluster.inter_beep_range.0.as_millis(),
+      cluster.inter_beep_range.1.as_millis(),
+    )
+  }
+}
+
+impl Classifier for Calibrator {
+  // A timeout during a beep isn't a measurement, only its length so far is
+  fn classify(&mut self, beep: Duration, inter_beep: Duration) -> Classification {
+    if (beep, inter_beep) == (CONTINUOUS_BEEP_DURATION, ZERO_DURATION) {
+      println!("Beep still going after {}ms", beep.as_millis());
+    } else {
+      println!("{}", self.measure(beep, inter_beep));
+    }
+    Classification { status: Status::Unknown, confidence: 0.0, beep_duration: beep, inter_beep_duration: inter_beep }
+  }
+
+  fn classify_silence(&mut self, silence: Duration) -> Classification {
+    Classification { status: Status::Unknown, confidence: 0.0, beep_duration: ZERO_DURATION, inter_beep_duration: silence }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn clusters_pairs_close_to_each_other() {
+    let mut calibrator = Calibrator::new(0.1, Duration::from_millis(50));
+    let mut measure = |beep_ms: u64, inter_beep_ms: u64| calibrator.measure(Duration::from_millis(beep_ms), Duration::from_millis(inter_beep_ms));
+    assert_eq!(measure(250, 30_000), "Beep 250ms, inter beep 30000ms, cluster 1 of 1: 1 seen, beep 250-250ms, inter beep 30000-30000ms");
+    assert_eq!(measure(280, 29_500), "Beep 280ms, inter beep 29500ms, cluster 1 of 1: 2 seen, beep 250-280ms, inter beep 29500-30000ms");
+    // The same beep with a gap far off the first one's is another pattern
+    assert_eq!(measure(260, 2_000), "Beep 260ms, inter beep 2000ms, cluster 2 of 2: 1 seen, beep 260-260ms, inter beep 2000-2000ms");
+    assert_eq!(measure(240, 31_000), "Beep 240ms, inter beep 31000ms, cluster 1 of 2: 3 seen, beep 240-280ms, inter beep 29500-31000ms");
+  }
+
+  #[test]
+  fn nothing_is_classified() {
+    let mut calibrator = Calibrator::new(0.1, Duration::from_millis(50));
+    assert_eq!(calibrator.classify(Duration::from_millis(250), Duration::from_secs(30)).status, Status::Unknown);
+    assert_eq!(calibrator.classify(CONTINUOUS_BEEP_DURATION, ZERO_DURATION).status, Status::Unknown);
+    assert_eq!(calibrator.classify_silence(Duration::from_secs(3)).status, Status::Unknown);
+    assert_eq!(calibrator.clusters.len(), 1);
+  }
+}
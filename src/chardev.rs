@@ -0,0 +1,152 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::detector::Edge;
+use crate::logging::info;
+use crate::source::{EdgeSource, ReadFailures, SourceEvent};
+
+// GPIO_GET_LINEEVENT_IOCTL of the v1 character device ABI, _IOWR(0xB4, 0x04, struct gpioevent_request)
+const GET_LINE_EVENT_REQUEST: u64 = 0xC030_B404;
+const HANDLE_REQUEST_INPUT: u32 = 1 << 0;
+const EVENT_REQUEST_BOTH_EDGES: u32 = 0x3;
+const EVENT_RISING_EDGE: u32 = 0x1;
+
+// Shown as the consumer of the line by gpioinfo, so it is clear which process holds it
+const CONSUMER_LABEL: &str = env!("CARGO_PKG_NAME");
+
+// EBUSY, what requesting a line another process already requested fails with
+const BUSY_ERROR_CODE: i32 = 16;
+
+// struct gpioevent_request
+#[repr(C)]
+struct LineEventRequest {
+  line_offset: u32,
+  handle_flags: u32,
+  event_flags: u32,
+  consumer_label: [u8; 32],
+  fd: libc::c_int,
+}
+
+// struct gpioevent_data, a u64 timestamp followed by the u32 event id and padding
+const EVENT_DATA_SIZE: usize = 16;
+const EVENT_ID_OFFSET: usize = 8;
+
+// Reads edges through a given /dev/gpiochipN rather than the one rppal finds on its own,
+// which is what a container gets when only that device is passed through to it
+pub struct ChardevSource {
+  events: File,
+  read_failures: ReadFailures,
+}
+
+impl ChardevSource {
+  pub fn new(chip_path: &str, line_offset: u8) -> Result<ChardevSource, String> {
+    let chip = OpenOptions::new().read(true).write(true).open(chip_path)
+      .map_err(|error| format!("could not open GPIO chip {}: {}", chip_path, error))?;
+
+    let mut request = LineEventRequest {
+      line_offset: line_offset.into(),
+      handle_flags: HANDLE_REQUEST_INPUT,
+      event_flags: EVENT_REQUEST_BOTH_EDGES,
+      consumer_label: get_consumer_label(),
+      fd: -1,
+    };
+    // Safe as the request is laid out as the kernel expects and outlives the call
+    let result = unsafe { libc::ioctl(chip.as_raw_fd(), GET_LINE_EVENT_REQUEST as libc::Ioctl, &mut request) };
+    if result < 0 {
+      return Err(get_line_error_description(chip_path, line_offset, io::Error::last_os_error()));
+    }
+
+    info!("Watching GPIO line {} of {} for edges", line_offset, chip_path);
+    // Safe as the kernel just handed the descriptor over and nothing else owns it
+    Ok(ChardevSource { events: unsafe { File::from_raw_fd(request.fd) }, read_failures: ReadFailures::default() })
+  }
+}
+
+fn get_consumer_label() -> [u8; 32] {
+  let mut consumer_label = [0; 32];
+  let length = CONSUMER_LABEL.len().min(consumer_label.len() - 1);
+  consumer_label[..length].copy_from_slice(&CONSUMER_LABEL.as_bytes()[..length]);
+  consumer_label
+}
+
+fn get_line_error_description(chip_path: &str, line_offset: u8, error: io::Error) -> String {
+  if error.raw_os_error() == Some(BUSY_ERROR_CODE) {
+    return format!("GPIO line {} of {} is busy, it is requested by another process", line_offset, chip_path);
+  }
+  format!("could not request GPIO line {} of {}: {}", line_offset, chip_path, error)
+}
+
+fn get_event_edge(event_data: &[u8; EVENT_DATA_SIZE]) -> Edge {
+  let id = u32::from_ne_bytes(event_data[EVENT_ID_OFFSET..EVENT_ID_OFFSET + 4].try_into().unwrap());
+  if id == EVENT_RISING_EDGE { Edge::BeepStart } else { Edge::BeepEnd }
+}
+
+impl EdgeSource for ChardevSource {
+  fn next_event(&mut self, timeout: Duration) -> Option<SourceEvent> {
+    let mut poll_fd = libc::pollfd { fd: self.events.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+    // Rounded up so a wait never ends just short of the timeout
+    let timeout_millis = timeout.as_micros().div_ceil(1000).min(libc::c_int::MAX as u128) as libc::c_int;
+    // Safe as the single pollfd outlives the call
+    let result = unsafe { libc::poll(&mut poll_fd, 1, timeout_millis) };
+    let now = Instant::now();
+    if result < 0 {
+      let error = io::Error::last_os_error();
+      // A signal such as the one toggling pausing cut the wait short, which is as good as a timeout
+      if error.kind() == io::ErrorKind::Interrupted {
+        return Some(SourceEvent::Timeout(now));
+      }
+      // Otherwise it counts as a wait that saw no edge, after waiting as long as it would have
+      self.read_failures.on_failure(format!("Could not wait for GPIO events: {}", error));
+      thread::sleep(timeout);
+      return Some(SourceEvent::Timeout(Instant::now()));
+    }
+    if result == 0 {
+      return Some(SourceEvent::Timeout(now));
+    }
+
+    let mut event_data = [0; EVENT_DATA_SIZE];
+    if let Err(error) = self.events.read_exact(&mut event_data) {
+      self.read_failures.on_failure(format!("Could not read a GPIO event: {}", error));
+      return Some(SourceEvent::Timeout(now));
+    }
+    self.read_failures.on_success();
+    Some(SourceEvent::Edge(get_event_edge(&event_data), now))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::mem;
+
+  #[test]
+  fn request_matches_kernel_layout() {
+    assert_eq!(mem::size_of::<LineEventRequest>(), 48);
+    assert_eq!(GET_LINE_EVENT_REQUEST >> 16 & 0x3FFF, mem::size_of::<LineEventRequest>() as u64);
+  }
+
+  #[test]
+  fn reads_edges_from_event_ids() {
+    let mut event_data = [0; EVENT_DATA_SIZE];
+    event_data[EVENT_ID_OFFSET..EVENT_ID_OFFSET + 4].copy_from_slice(&1u32.to_ne_bytes());
+    assert_eq!(get_event_edge(&event_data), Edge::BeepStart);
+    event_data[EVENT_ID_OFFSET..EVENT_ID_OFFSET + 4].copy_from_slice(&2u32.to_ne_bytes());
+    assert_eq!(get_event_edge(&event_data), Edge::BeepEnd);
+  }
+
+  #[test]
+  fn describes_busy_and_missing_lines() {
+    assert_eq!(get_line_error_description("/dev/gpiochip0", 17, io::Error::from_raw_os_error(BUSY_ERROR_CODE)), "GPIO line 17 of /dev/gpiochip0 is busy, it is requested by another process");
+    assert!(ChardevSource::new("/nonexistent/gpiochip0", 17).err().unwrap().starts_with("could not open GPIO chip /nonexistent/gpiochip0"));
+  }
+
+  #[test]
+  fn consumer_label_is_nul_terminated() {
+    let consumer_label = get_consumer_label();
+    assert!(consumer_label.starts_with(CONSUMER_LABEL.as_bytes()));
+    assert_eq!(consumer_label[31], 0);
+  }
+}
@@ -0,0 +1,243 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{Receiver, channel};
+use std::thread;
+use std::time::Duration;
+
+use crate::logging::warn;
+use crate::state::SharedState;
+use crate::status::{CONTINUOUS_BEEP_DURATION, Classification, MatchConfig, Status, TIMEOUT_DURATION, ZERO_DURATION, get_longest_gap, get_status_from_beep_durations, get_status_from_name, get_status_from_silence};
+
+// How long the external classifier gets to answer before the built-in table is used for that pair instead
+const EXTERNAL_CLASSIFIER_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub trait Classifier {
+  fn classify(&mut self, beep: Duration, inter_beep: Duration) -> Classification;
+  // Sustained silence after a beep pattern, which has no pair of durations to classify
+  fn classify_silence(&mut self, silence: Duration) -> Classification;
+  // The longest gap of any pattern it knows, for telling the silence after a lone beep from the wait for the next beep of a pattern,
+  // None when it knows nothing of gaps
+  fn get_longest_gap(&self) -> Option<Duration> {
+    None
+  }
+}
+
+// Matches pairs against the built-in table of status beep durations
+#[derive(Default)]
+pub struct BuiltinClassifier {
+  config: MatchConfig,
+}
+
+impl BuiltinClassifier {
+  pub fn new(config: MatchConfig) -> BuiltinClassifier {
+    BuiltinClassifier { config }
+  }
+}
+
+impl Classifier for BuiltinClassifier {
+  fn classify(&mut self, beep: Duration, inter_beep: Duration) -> Classification {
+    get_status_from_beep_durations(beep, inter_beep, &self.config)
+  }
+
+  fn classify_silence(&mut self, silence: Duration) -> Classification {
+    get_status_from_silence(silence, &self.config)
+  }
+
+  fn get_longest_gap(&self) -> Option<Duration> {
+    get_longest_gap(&self.config)
+  }
+}
+
+// Publishes every measured pair before handing it on, for calibrating remotely over /raw or UDP,
+// neither the synthetic pair of a timeout during a beep nor silence are measurements and both are left out
+pub struct MeasurementPublisher {
+  classifier: Box<dyn Classifier>,
+  state: SharedState,
+}
+
+impl MeasurementPublisher {
+  pub fn new(classifier: Box<dyn Classifier>, state: SharedState) -> MeasurementPublisher {
+    MeasurementPublisher { classifier, state }
+  }
+}
+
+impl Classifier for MeasurementPublisher {
+  fn classify(&mut self, beep: Duration, inter_beep: Duration) -> Classification {
+    if (beep, inter_beep) != (CONTINUOUS_BEEP_DURATION, ZERO_DURATION) {
+      self.state.lock().unwrap().publish_measurement(beep, inter_beep);
+    }
+    self.classifier.classify(beep, inter_beep)
+  }
+
+  fn classify_silence(&mut self, silence: Duration) -> Classification {
+    self.classifier.classify_silence(silence)
+  }
+
+  fn get_longest_gap(&self) -> Option<Duration> {
+    self.classifier.get_longest_gap()
+  }
+}
+
+// Hands every pair to an external program over a line protocol, the program is written "<beep_ms> <inter_beep_ms>" on its stdin
+// and has to answer with a status name followed optionally by a confidence between 0 and 1, as in "OnBattery 0.8",
+// timeouts are sent as well, "0 3000" for silence and "3000 0" for a beep that hasn't ended
+pub struct ExternalClassifier {
+  command: String,
+  child: Child,
+  stdin: ChildStdin,
+  lines: Receiver<String>,
+  fallback: BuiltinClassifier,
+}
+
+impl ExternalClassifier {
+  pub fn spawn(command: &str, fallback: BuiltinClassifier) -> Result<ExternalClassifier, String> {
+    let mut child = Command::new("sh")
+      .arg("-c")
+      .arg(command)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .spawn()
+      .map_err(|error| format!("could not start classifier {}: {}", command, error))?;
+
+    let stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+    // Answers are read on their own thread so a hung classifier can be timed out instead of stalling detection
+    let (sender, lines) = channel();
+    thread::spawn(move || {
+      for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+        if sender.send(line).is_err() {
+          break;
+        }
+      }
+    });
+
+    Ok(ExternalClassifier {
+      command: command.to_string(),
+      child,
+      stdin,
+      lines,
+      fallback,
+    })
+  }
+
+  fn ask(&mut self, beep: Duration, inter_beep: Duration) -> Result<Classification, String> {
+    writeln!(self.stdin, "{} {}", beep.as_millis(), inter_beep.as_millis())
+      .and_then(|_| self.stdin.flush())
+      .map_err(|error| format!("could not write to classifier {}: {}", self.command, error))?;
+    let line = self.lines.recv_timeout(EXTERNAL_CLASSIFIER_TIMEOUT)
+      .map_err(|_| format!("classifier {} did not answer", self.command))?;
+    parse_answer(&line, beep, inter_beep)
+  }
+}
+
+impl Classifier for ExternalClassifier {
+  fn classify(&mut self, beep: Duration, inter_beep: Duration) -> Classification {
+    self.ask(beep, inter_beep).unwrap_or_else(|error| {
+      warn!("{}, using the built-in table instead", error);
+      self.fallback.classify(beep, inter_beep)
+    })
+  }
+
+  // Asked as the pair it has always been sent as, the answer then carries the actual silence
+  fn classify_silence(&mut self, silence: Duration) -> Classification {
+    match self.ask(ZERO_DURATION, TIMEOUT_DURATION) {
+      Ok(classification) => Classification { inter_beep_duration: silence, ..classification },
+      Err(error) => {
+        warn!("{}, using the built-in table instead", error);
+        self.fallback.classify_silence(silence)
+      },
+    }
+  }
+
+  // The program is never asked, the patterns it matches are taken to be the ones of the table it falls back on
+  fn get_longest_gap(&self) -> Option<Duration> {
+    self.fallback.get_longest_gap()
+  }
+}
+
+impl Drop for ExternalClassifier {
+  fn drop(&mut self) {
+    let _ = self.child.kill();
+    let _ = self.child.wait();
+  }
+}
+
+fn parse_answer(line: &str, beep: Duration, inter_beep: Duration) -> Result<Classification, String> {
+  let invalid_answer = || format!("invalid classifier answer {}", line);
+
+  let mut fields = line.split_whitespace();
+  let status = fields.next().and_then(get_status_from_name).ok_or_else(invalid_answer)?;
+  let confidence = match fields.next() {
+    Some(confidence) => confidence.parse().ok().filter(|confidence| (0.0..=1.0).contains(confidence)).ok_or_else(invalid_answer)?,
+    None if status == Status::Unknown => 0.0,
+    None => 1.0,
+  };
+  if fields.next().is_some() {
+    return Err(invalid_answer());
+  }
+
+  Ok(Classification {
+    status,
+    confidence,
+    beep_duration: beep,
+    inter_beep_duration: inter_beep,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const BEEP: Duration = Duration::from_millis(250);
+  const INTER_BEEP: Duration = Duration::from_secs(1);
+
+  #[test]
+  fn parses_answers() {
+    assert_eq!(parse_answer("OnBattery", BEEP, INTER_BEEP).unwrap().confidence, 1.0);
+    assert_eq!(parse_answer("ReplaceBattery 0.25", BEEP, INTER_BEEP).unwrap().status, Status::ReplaceBattery);
+    assert_eq!(parse_answer("ReplaceBattery 0.25", BEEP, INTER_BEEP).unwrap().confidence, 0.25);
+    assert_eq!(parse_answer("Unknown", BEEP, INTER_BEEP).unwrap().confidence, 0.0);
+    assert!(parse_answer("Battery", BEEP, INTER_BEEP).is_err());
+    assert!(parse_answer("OnBattery 2", BEEP, INTER_BEEP).is_err());
+    assert!(parse_answer("OnBattery 1 more", BEEP, INTER_BEEP).is_err());
+  }
+
+  #[test]
+  fn classifies_through_external_program() {
+    let mut classifier = ExternalClassifier::spawn("while read beep inter_beep; do if [ $inter_beep -gt 5000 ]; then echo OnBattery 0.5; else echo ReplaceBattery; fi; done", BuiltinClassifier::default()).unwrap();
+    let classification = classifier.classify(BEEP, Duration::from_secs(60));
+    assert_eq!((classification.status, classification.confidence), (Status::OnBattery, 0.5));
+    assert_eq!(classifier.classify(BEEP, INTER_BEEP).status, Status::ReplaceBattery);
+  }
+
+  #[test]
+  fn silence_is_sent_to_external_program_as_its_pair() {
+    let mut classifier = ExternalClassifier::spawn("while read beep inter_beep; do if [ \"$beep $inter_beep\" = \"0 3000\" ]; then echo NoLoadOnBattery; else echo Unknown; fi; done", BuiltinClassifier::default()).unwrap();
+    let classification = classifier.classify_silence(Duration::from_secs(9));
+    assert_eq!((classification.status, classification.inter_beep_duration), (Status::NoLoadOnBattery, Duration::from_secs(9)));
+
+    let mut exited_classifier = ExternalClassifier::spawn("exit 0", BuiltinClassifier::default()).unwrap();
+    assert_eq!(exited_classifier.classify_silence(TIMEOUT_DURATION).status, Status::OnMains);
+  }
+
+  #[test]
+  fn falls_back_to_builtin_table_on_bad_answers() {
+    let mut classifier = ExternalClassifier::spawn("while read line; do echo nonsense; done", BuiltinClassifier::default()).unwrap();
+    assert_eq!(classifier.classify(BEEP, INTER_BEEP).status, Status::LowOnBattery);
+
+    let mut exited_classifier = ExternalClassifier::spawn("exit 0", BuiltinClassifier::default()).unwrap();
+    assert_eq!(exited_classifier.classify(BEEP, INTER_BEEP).status, Status::LowOnBattery);
+  }
+
+  #[cfg(feature = "http")]
+  #[test]
+  fn publishes_only_measured_pairs() {
+    let state = SharedState::default();
+    let receiver = state.lock().unwrap().subscribe_to_measurements();
+    let mut classifier = MeasurementPublisher::new(Box::new(BuiltinClassifier::default()), state.clone());
+    assert_eq!(classifier.classify_silence(TIMEOUT_DURATION).status, Status::OnMains);
+    assert_eq!(classifier.classify(CONTINUOUS_BEEP_DURATION, ZERO_DURATION).status, Status::OverTemperatureOnBatteryOrInternalError);
+    assert_eq!(classifier.classify(BEEP, INTER_BEEP).status, Status::LowOnBattery);
+    assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![(BEEP, INTER_BEEP)]);
+  }
+}
@@ -0,0 +1,1567 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+#[cfg(feature = "adc")]
+use crate::adc::{ADC_CHANNELS, ADC_MAX_VALUE, DEFAULT_ADC_HYSTERESIS, DEFAULT_ADC_THRESHOLD};
+use crate::detector::{DEFAULT_ON_MAINS_GRACE_DURATION, FirstEdgePolicy, GapBasis};
+use crate::exit::{ExitCondition, parse_exit_condition};
+use crate::expander::EXPANDER_CHANNELS;
+use crate::frame::{DEFAULT_FRAME_DELIMITER_DURATION, parse_frame_code};
+use crate::led::parse_led_pin;
+use crate::glyph::parse_glyph_override;
+use crate::gpio::{DEFAULT_PIN, MAX_HEADER_PIN, TriggerMode};
+use crate::guidance::{Guidance, parse_guidance_override};
+use crate::hook::{WebhookUrl, parse_delayed_hook, parse_webhook_url};
+use crate::noise::{NoiseConfig, get_time_seed};
+use crate::output::OutputTarget;
+use crate::group::{BeepGroup, parse_beep_group};
+use crate::maintenance::{MaintenanceWindow, parse_maintenance_window};
+#[cfg(feature = "amqp")]
+use crate::amqp::{AmqpConfig, DEFAULT_AMQP_EXCHANGE, DEFAULT_AMQP_PORT, DEFAULT_AMQP_ROUTING_KEY, DEFAULT_AMQP_VIRTUAL_HOST, is_valid_short_string};
+#[cfg(feature = "mqtt")]
+use crate::mqtt::{DEFAULT_MQTT_PORT, DEFAULT_MQTT_TOPIC, MqttConfig, is_valid_topic};
+use crate::pattern::{PatternOverride, parse_pattern_override, parse_target_tolerance};
+use crate::profile::DEFAULT_PROFILE_NAME;
+use crate::remap::parse_status_map;
+use crate::report::{Format, RestartPolicy, parse_sink};
+use crate::rules::{Rule, parse_rule};
+use crate::pwm::{DutyCycleBand, parse_duty_cycle_band};
+use crate::state::DEFAULT_MAX_TRANSITIONS;
+use crate::summary::parse_summary_period;
+use crate::table::{Table, load_table_file};
+use crate::status::{AmbiguityPolicy, ERROR_MARGIN, MIN_ERROR_DURATION, MatchMetric, Status, TIMEOUT_DURATION, Tolerance, get_status_from_name};
+use crate::wear::DEFAULT_ESCALATION_SCORE;
+
+// How the edges on the status line carry the status
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Encoding {
+  // Durations of the beeps and of the gaps between them
+  Beep,
+  // Duty cycle of a PWM signal
+  Pwm,
+  // Count of short beeps after a long delimiter beep
+  Frame,
+}
+
+// Long enough to see two beeps of OnBattery, the pattern with the longest gap
+pub const DEFAULT_ONCE_TIMEOUT_DURATION: Duration = Duration::from_secs(130);
+
+#[derive(Debug)]
+pub struct Options {
+  pub show_features: bool,
+  // Prints the names of the built-in profiles and of the models in the database and exits
+  pub list_models: bool,
+  // How many levels past the default the diagnostics on stderr go, given with -v or -vv, RUST_LOG picks the level without them
+  pub verbosity: u8,
+  // Prints the range of beep and inter beep durations every status matches and exits
+  pub show_windows: bool,
+  // Writes the table in effect, with the --pattern overrides applied, as a profile file to load with --profile and exits
+  pub dump_profile_path: Option<String>,
+  // File the effective config is written to in the --config format before exiting, the command line given along with it included
+  pub dump_config_path: Option<String>,
+  // Exits with the exit code of the first status reported, or with NO_STATUS_EXIT_CODE if none is within the timeout
+  pub once: bool,
+  pub once_timeout_duration: Duration,
+  // Statuses that end the process, with their exit code, once detected continuously and confidently enough for the given grace duration
+  pub exit_conditions: Vec<ExitCondition>,
+  // Reports this status once as a test event and exits, without reading any edges
+  pub emit_status: Option<Status>,
+  pub show_confidence: bool,
+  pub show_guidance: bool,
+  pub show_origin: bool,
+  // Starts every text line with its UTC time to the millisecond and the sequence number of its transition
+  pub show_timestamps: bool,
+  // Prints the symbol of every beep and gap pair ahead of its status
+  pub show_symbols: bool,
+  // Prints every measured pair of durations and the cluster of pairs like it instead of classifying them
+  pub calibrate: bool,
+  pub guidance_overrides: Vec<(Status, Guidance)>,
+  pub glyph_overrides: Vec<(Status, String)>,
+  // Statuses reported as another one, applied to the final classification so every sink gets the mapped one
+  pub status_maps: Vec<(Status, Status)>,
+  // Where the status lines go and in which format, a single one from --output and --format unless --sink is given
+  pub sinks: Vec<(OutputTarget, Format)>,
+  // How often a summary of the outages is written, to the summary sinks or else to the same sinks as the status lines
+  pub summary_period: Option<Duration>,
+  pub summary_sinks: Vec<(OutputTarget, Format)>,
+  // Indents the objects of every json sink over several lines, for reading them rather than parsing them
+  pub pretty_json: bool,
+  // File the time spent in every status is persisted to
+  pub stats_path: Option<String>,
+  // Prints the time per status saved in the stats file and exits
+  pub dump_stats: bool,
+  // How many recent transitions are kept for /history and the SIGUSR2 dump
+  pub history_size: usize,
+  pub replace_battery_escalation_score: f64,
+  pub min_beep_duration: Duration,
+  pub on_mains_grace_duration: Duration,
+  // How long silence after a battery pattern is held as a muted alarm before OnMains gets inferred
+  pub mute_hold_duration: Duration,
+  // Ceiling measured beep and inter beep durations are clamped to, as a stalled line can measure anything
+  pub max_measured_duration: Option<Duration>,
+  // Longest the line may go without an edge after the warmup before it gets warned about, for UPSes that chirp even on mains
+  pub activity_interval: Option<Duration>,
+  // Annotates the output when the host suspends and resumes, and drops the timing in progress across it
+  pub is_watching_suspend: bool,
+  // Whether the gaps of the table are matched against the gaps between beeps or the periods between their starts
+  pub gap_basis: GapBasis,
+  // Whether the gap after a beep already going on at startup is measured or everything waits for a whole beep
+  pub first_edge_policy: FirstEdgePolicy,
+  // How long Unknown has to persist before it gets reported
+  pub unknown_debounce_duration: Duration,
+  // Window within which a second battery pattern has to follow the first before leaving OnMains gets reported
+  pub outage_corroboration_window: Option<Duration>,
+  // How many times in a row a matched pattern has to come up before a new status is reported
+  pub confirmations: usize,
+  // How long after starting only critical statuses get reported
+  pub warmup_duration: Duration,
+  // How long the output may go quiet before the current status is written again as a heartbeat
+  pub heartbeat_interval: Option<Duration>,
+  // How long on battery since the mains went before that is warned about, once per outage
+  pub on_battery_warn_duration: Option<Duration>,
+  // Stretches of the week during which nothing is reported, given with --maintenance-window
+  pub maintenance_windows: Vec<MaintenanceWindow>,
+  // Whether the status saved in the stats file carries over a restart until a whole cycle has been seen
+  pub restart_policy: RestartPolicy,
+  // Edges arriving sooner than this after the last one are dropped before they reach the detector
+  pub min_edge_interval: Option<Duration>,
+  // Name of a built-in profile or path of a profile file describing how the UPS signals its status
+  pub profile: String,
+  // Name of a UPS model in the bundled database, used instead of the profile
+  pub model: Option<String>,
+  // TOML file whose patterns take the place of the whole table of the profile or model, its silence kept and its polarity unless the file gives one,
+  // read here as the pin, tolerances and sinks it gives are only kept when their flags aren't
+  pub config: Option<Table>,
+  // Whether the line is low while beeping whatever the profile, model or config says, the pin being pulled up in between
+  pub active_low: bool,
+  // Tolerance around every target duration, as a fraction of the target
+  pub error_margin: f64,
+  // Least a duration is allowed to be off by however small the margin makes it, zero for the margin alone
+  pub min_error_duration: Duration,
+  // Table entries given with --pattern, each taking the place of its status in the profile's table
+  pub pattern_overrides: Vec<PatternOverride>,
+  // Runs of quick beeps given with --beep-group, matched as a whole ahead of the table
+  pub beep_groups: Vec<BeepGroup>,
+  // Length of the lone chirp of voltage regulation, with its own tolerance when given one
+  pub avr_chirp: Option<(Duration, Option<Tolerance>)>,
+  pub on_ambiguous: AmbiguityPolicy,
+  pub match_metric: MatchMetric,
+  // Beeps at least this long are only matched against long beep patterns and shorter ones only against short beep patterns
+  pub long_beep_threshold: Option<Duration>,
+  pub encoding: Encoding,
+  // Only used with the PWM encoding, which needs at least one
+  pub duty_cycle_bands: Vec<DutyCycleBand>,
+  // Only used with the frame encoding, which needs at least one code
+  pub frame_delimiter_duration: Duration,
+  pub frame_codes: Vec<(u32, Status)>,
+  pub replay_path: Option<String>,
+  // Classifies a capture taken while the UPS was known to be in the status, prints how much of it matched and exits
+  pub validation: Option<(Status, String)>,
+  // Capture labeled with the statuses the UPS was in, classified with every tolerance to recommend the ones getting it most right
+  pub threshold_sweep_path: Option<String>,
+  pub replay_speed: f64,
+  // Bounces and jitter injected into the replayed capture, to check the detector copes with a noisy line
+  pub replay_noise: Option<NoiseConfig>,
+  // I2C address and channel of the MCP23017 expander to read edges from instead of the pin directly
+  pub expander: Option<(u16, u8)>,
+  // Pin the status line is wired to, the offset of its line with --gpiochip and that of the expander's interrupt output with an expander
+  pub pin: u8,
+  // How long to wait for an edge before checking how long the line has been silent
+  pub timeout_duration: Duration,
+  // Pin of a relay that is high while mains power is present, trusted over the beeps whenever the two disagree
+  pub mains_pin: Option<u8>,
+  // Pins of the UPS's own status LED along with the status each color stands for, only ever checked against the beeps and logged
+  pub led_pins: Vec<(u8, Status)>,
+  // Evaluated in order on every classification, the first one met decides the status over the mains reconciliation
+  pub rules: Vec<Rule>,
+  // Takes a lock on the status pin at startup and exits if another instance already holds it
+  pub exclusive_gpio: bool,
+  // How often the pin level is read to catch edges whose interrupt got lost
+  pub sample_interval: Option<Duration>,
+  // Edges of the pin that raise an interrupt, with only one of them the other is read from the level every sample interval
+  pub trigger_mode: TriggerMode,
+  // GPIO character device to read the pin's line from instead of the chip rppal finds, for containers given only that device
+  pub gpiochip_path: Option<String>,
+  // MCP3008 channel the tap is wired to, along with the threshold and hysteresis its samples are turned into edges with
+  #[cfg(feature = "adc")]
+  pub adc: Option<(u8, u16, u16)>,
+  // Program to classify beep and inter beep duration pairs with instead of the built-in table
+  pub classifier_command: Option<String>,
+  // Profile whose table classifies every pair alongside the active one, only to log where the two disagree
+  pub shadow_profile: Option<String>,
+  // Shell commands run on every reported transition, {status} and {description} replaced by the new status and its description,
+  // each with how long the status has to hold before it is acted on, zero for right away
+  pub on_change_commands: Vec<(String, Duration)>,
+  // URLs the JSON object of every reported transition is POSTed to, with their delays likewise
+  pub webhook_urls: Vec<(WebhookUrl, Duration)>,
+  // Address every measured beep and inter beep pair is sent to over UDP, for plotting them live
+  pub udp_raw_address: Option<String>,
+  // Unix domain socket answering every connection with the current status, for local tools to query on demand
+  pub status_socket_path: Option<String>,
+  // Database every transition and measured pair is kept in, for querying the history with SQL
+  #[cfg(feature = "sqlite")]
+  pub sqlite_path: Option<String>,
+  // Broker every status change is published to
+  #[cfg(feature = "mqtt")]
+  pub mqtt: Option<MqttConfig>,
+  // Broker and exchange every reported transition is published to as JSON
+  #[cfg(feature = "amqp")]
+  pub amqp: Option<AmqpConfig>,
+  #[cfg(feature = "http")]
+  pub http_address: Option<String>,
+  // Serves every measured beep and inter beep pair on /raw to clients presenting this token, /raw isn't served at all without one
+  #[cfg(feature = "http")]
+  pub raw_token: Option<String>,
+  // Serves the current status, the transitions and the last measured durations on /metrics for Prometheus to scrape
+  #[cfg(feature = "http")]
+  pub metrics_address: Option<String>,
+}
+
+const USAGE: &str = "Usage: ups-power-status-from-beeps [-v|-vv] [--features] [--list-models] [--show-windows] [--dump-profile <file>] [--dump-config <file>] [--once [--once-timeout-secs <secs>]] [--emit <status>] [--exit-on <status>:<grace secs>[:<min confidence>]]... [--confidence] [--show-guidance] [--show-origin] [--timestamps] [--symbols] [--calibrate] [--guidance <status>=<severity>:<action>]... [--format text|char|json] [--json-pretty] [--glyph <status>=<glyph>]... [--map <status>=<status>]... [--output stdout|stderr|syslog|syslog-rfc5424|<file>] [--sink <target>:<format>]... [--summary-every daily|weekly|<secs> [--summary-sink <target>:<format>]...] [--history-size <count>] [--stats-file <file> [--dump-stats]] [--replace-battery-escalation <score>] [--min-beep-ms <ms>] [--on-mains-grace-secs <secs>] [--mute-hold-secs <secs>] [--max-duration-secs <secs>] [--match-on gap|period] [--first-edge measure|skip] [--unknown-debounce-secs <secs>] [--corroborate-outage-secs <secs>] [--confirmations <count>] [--warmup-secs <secs>] [--heartbeat-secs <secs>] [--on-battery-warn-secs <secs>] [--maintenance-window <days>@<HH:MM>-<HH:MM>[Z|+HH:MM|-HH:MM]]... [--on-restart report|wait-for-cycle] [--expect-activity-secs <secs>] [--watch-suspend] [--min-edge-interval-ms <ms>] [--profile <name>|<file>] [--model <name>] [--config <file.toml>] [--active-low] [--error-margin <fraction>] [--error-floor-ms <ms>] [--pattern <status>:beep=<duration>[±<tolerance>],gap=<duration>[±<tolerance>]]... [--beep-group <status>:[count=<count>,]beep=<duration>[±<tolerance>],gap=<duration>[±<tolerance>]]... [--avr-chirp <duration>[±<tolerance>]] [--on-ambiguous first|unknown|closest|highest-severity] [--match-metric axiswise|euclidean|normalized] [--long-beep-threshold-ms <ms>] [--encoding beep|pwm|frame] [--duty-cycle-band <status>=<min>-<max>]... [--frame-delimiter-ms <ms>] [--frame-code <code>=<status>]... [--replay <file> [--speed <factor>] [--inject-bounce <fraction>] [--inject-jitter-ms <ms>] [--noise-seed <seed>]] [--validate <status> <capture>] [--threshold-sweep <labeled capture>] [--pin <pin>] [--timeout-ms <ms>] [--expander-address <address> --expander-channel <channel>] [--mains-pin <pin>] [--led-pin <pin>=<status>]... [--rule <condition>[,<condition>]...=<status>]... [--exclusive-gpio] [--sample-interval-ms <ms>] [--trigger both|rising|falling] [--gpiochip <path>] [--adc-channel <channel> [--adc-threshold <value>] [--adc-hysteresis <value>]] [--classifier-command <command>] [--shadow-profile <name>|<file>] [--on-change <command>]... [--on-change-after <delay secs>:<command>]... [--webhook-url <url>]... [--webhook-url-after <delay secs>:<url>]... [--udp-raw <address>:<port>] [--status-socket <path>] [--sqlite <file>] [--mqtt-host <host> [--mqtt-port <port>] [--mqtt-topic <topic>] [--mqtt-username <username> [--mqtt-password <password>]] [--mqtt-retain]] [--amqp-host <host> [--amqp-port <port>] [--amqp-vhost <vhost>] [--amqp-exchange <exchange>] [--amqp-routing-key <key>] [--amqp-username <username> [--amqp-password <password>]]] [--http-addr <address> [--raw-token <token>]] [--metrics-addr <address>]";
+
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+  let mut options = Options {
+    show_features: false,
+    list_models: false,
+    verbosity: 0,
+    show_windows: false,
+    dump_profile_path: None,
+    dump_config_path: None,
+    once: false,
+    once_timeout_duration: DEFAULT_ONCE_TIMEOUT_DURATION,
+    exit_conditions: vec![],
+    emit_status: None,
+    show_confidence: false,
+    show_guidance: false,
+    show_origin: false,
+    show_timestamps: false,
+    show_symbols: false,
+    calibrate: false,
+    guidance_overrides: vec![],
+    glyph_overrides: vec![],
+    status_maps: vec![],
+    sinks: vec![],
+    summary_period: None,
+    summary_sinks: vec![],
+    pretty_json: false,
+    history_size: DEFAULT_MAX_TRANSITIONS,
+    stats_path: None,
+    dump_stats: false,
+    replace_battery_escalation_score: DEFAULT_ESCALATION_SCORE,
+    min_beep_duration: Duration::ZERO,
+    on_mains_grace_duration: DEFAULT_ON_MAINS_GRACE_DURATION,
+    mute_hold_duration: Duration::ZERO,
+    max_measured_duration: None,
+    activity_interval: None,
+    is_watching_suspend: false,
+    gap_basis: GapBasis::Gap,
+    first_edge_policy: FirstEdgePolicy::Measure,
+    unknown_debounce_duration: Duration::ZERO,
+    outage_corroboration_window: None,
+    confirmations: 1,
+    warmup_duration: Duration::ZERO,
+    heartbeat_interval: None,
+    on_battery_warn_duration: None,
+    maintenance_windows: vec![],
+    restart_policy: RestartPolicy::Report,
+    min_edge_interval: None,
+    profile: DEFAULT_PROFILE_NAME.to_string(),
+    model: None,
+    config: None,
+    active_low: false,
+    error_margin: ERROR_MARGIN,
+    min_error_duration: MIN_ERROR_DURATION,
+    pattern_overrides: vec![],
+    beep_groups: vec![],
+    avr_chirp: None,
+    on_ambiguous: AmbiguityPolicy::Closest,
+    match_metric: MatchMetric::Axiswise,
+    long_beep_threshold: None,
+    encoding: Encoding::Beep,
+    duty_cycle_bands: vec![],
+    frame_delimiter_duration: DEFAULT_FRAME_DELIMITER_DURATION,
+    frame_codes: vec![],
+    replay_path: None,
+    validation: None,
+    threshold_sweep_path: None,
+    replay_speed: 1.0,
+    replay_noise: None,
+    expander: None,
+    pin: DEFAULT_PIN,
+    timeout_duration: TIMEOUT_DURATION,
+    mains_pin: None,
+    led_pins: vec![],
+    rules: vec![],
+    exclusive_gpio: false,
+    sample_interval: None,
+    trigger_mode: TriggerMode::Both,
+    gpiochip_path: None,
+    #[cfg(feature = "adc")]
+    adc: None,
+    classifier_command: None,
+    shadow_profile: None,
+    on_change_commands: vec![],
+    webhook_urls: vec![],
+    udp_raw_address: None,
+    status_socket_path: None,
+    #[cfg(feature = "sqlite")]
+    sqlite_path: None,
+    #[cfg(feature = "mqtt")]
+    mqtt: None,
+    #[cfg(feature = "amqp")]
+    amqp: None,
+    #[cfg(feature = "http")]
+    http_address: None,
+    #[cfg(feature = "http")]
+    raw_token: None,
+    #[cfg(feature = "http")]
+    metrics_address: None,
+  };
+  let mut replay_speed = None;
+  let (mut bounce_probability, mut jitter, mut noise_seed) = (None, None, None);
+  let mut once_timeout_duration = None;
+  let mut frame_delimiter_duration = None;
+  let mut expander_address = None;
+  let mut expander_channel = None;
+  #[cfg(feature = "adc")]
+  let (mut adc_channel, mut adc_threshold, mut adc_hysteresis) = (None, None, None);
+  #[cfg(feature = "mqtt")]
+  let (mut mqtt_host, mut mqtt_port, mut mqtt_topic, mut mqtt_username, mut mqtt_password, mut mqtt_retain) = (None, None, None, None, None, false);
+  #[cfg(feature = "amqp")]
+  let (mut amqp_host, mut amqp_port, mut amqp_vhost, mut amqp_exchange, mut amqp_routing_key, mut amqp_username, mut amqp_password) = (None, None, None, None, None, None, None);
+  let mut format = None;
+  let mut output = None;
+  let mut config_path = None;
+  let mut pin = None;
+  let mut error_margin = None;
+  let mut min_error_duration = None;
+
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--features" => options.show_features = true,
+      "--list-models" => options.list_models = true,
+      "-v" => options.verbosity += 1,
+      "-vv" => options.verbosity += 2,
+      "--show-windows" => options.show_windows = true,
+      "--dump-profile" => options.dump_profile_path = Some(parse_value(&arg, args.next())?),
+      "--dump-config" => options.dump_config_path = Some(parse_value(&arg, args.next())?),
+      "--once" => options.once = true,
+      "--exit-on" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.exit_conditions.push(parse_exit_condition(&value).map_err(|error| format!("{}\n{}", error, USAGE))?);
+      },
+      "--emit" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.emit_status = Some(get_status_from_name(&value).ok_or_else(|| format!("unknown status {} for {}\n{}", value, arg, USAGE))?);
+      },
+      "--once-timeout-secs" => once_timeout_duration = Some(Duration::from_secs(parse_value(&arg, args.next())?)),
+      "--confidence" => options.show_confidence = true,
+      "--show-guidance" => options.show_guidance = true,
+      "--show-origin" => options.show_origin = true,
+      "--timestamps" => options.show_timestamps = true,
+      "--symbols" => options.show_symbols = true,
+      "--calibrate" => options.calibrate = true,
+      "--format" => {
+        let value: String = parse_value(&arg, args.next())?;
+        format = Some(Format::from_name(&value).ok_or_else(|| format!("invalid value {} for {}\n{}", value, arg, USAGE))?);
+      },
+      "--json-pretty" => options.pretty_json = true,
+      "--glyph" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.glyph_overrides.push(parse_glyph_override(&value).map_err(|error| format!("{}\n{}", error, USAGE))?);
+      },
+      "--map" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.status_maps.push(parse_status_map(&value).map_err(|error| format!("{}\n{}", error, USAGE))?);
+      },
+      "--guidance" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.guidance_overrides.push(parse_guidance_override(&value).map_err(|error| format!("{}\n{}", error, USAGE))?);
+      },
+      "--output" => output = Some(OutputTarget::from_name(&parse_value::<String>(&arg, args.next())?)),
+      "--sink" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.sinks.push(parse_sink(&value).map_err(|error| format!("{}\n{}", error, USAGE))?);
+      },
+      "--stats-file" => options.stats_path = Some(parse_value(&arg, args.next())?),
+      "--dump-stats" => options.dump_stats = true,
+      "--summary-every" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.summary_period = Some(parse_summary_period(&value).map_err(|error| format!("{}\n{}", error, USAGE))?);
+      },
+      "--summary-sink" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.summary_sinks.push(parse_sink(&value).map_err(|error| format!("{}\n{}", error, USAGE))?);
+      },
+      "--history-size" => {
+        options.history_size = parse_value(&arg, args.next())?;
+        if options.history_size == 0 {
+          return Err(format!("invalid value 0 for {}\n{}", arg, USAGE));
+        }
+      },
+      "--replace-battery-escalation" => {
+        options.replace_battery_escalation_score = parse_value(&arg, args.next())?;
+        if !(options.replace_battery_escalation_score > 0.0 && options.replace_battery_escalation_score.is_finite()) {
+          return Err(format!("invalid value {} for {}\n{}", options.replace_battery_escalation_score, arg, USAGE));
+        }
+      },
+      "--min-beep-ms" => options.min_beep_duration = Duration::from_millis(parse_value(&arg, args.next())?),
+      "--on-mains-grace-secs" => options.on_mains_grace_duration = Duration::from_secs(parse_value(&arg, args.next())?),
+      "--unknown-debounce-secs" => options.unknown_debounce_duration = Duration::from_secs(parse_value(&arg, args.next())?),
+      "--corroborate-outage-secs" => {
+        let outage_corroboration_window = Duration::from_secs(parse_value(&arg, args.next())?);
+        if outage_corroboration_window.is_zero() {
+          return Err(format!("invalid value 0 for {}\n{}", arg, USAGE));
+        }
+        options.outage_corroboration_window = Some(outage_corroboration_window);
+      },
+      "--confirmations" => {
+        let confirmations = parse_value(&arg, args.next())?;
+        if confirmations == 0 {
+          return Err(format!("invalid value 0 for {}\n{}", arg, USAGE));
+        }
+        options.confirmations = confirmations;
+      },
+      "--mute-hold-secs" => options.mute_hold_duration = Duration::from_secs(parse_value(&arg, args.next())?),
+      "--match-on" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.gap_basis = GapBasis::from_name(&value).ok_or_else(|| format!("invalid value {} for {}\n{}", value, arg, USAGE))?;
+      },
+      "--watch-suspend" => options.is_watching_suspend = true,
+      "--first-edge" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.first_edge_policy = FirstEdgePolicy::from_name(&value).ok_or_else(|| format!("invalid value {} for {}\n{}", value, arg, USAGE))?;
+      },
+      "--expect-activity-secs" => {
+        let activity_interval = Duration::from_secs(parse_value(&arg, args.next())?);
+        if activity_interval.is_zero() {
+          return Err(format!("invalid value 0 for {}\n{}", arg, USAGE));
+        }
+        options.activity_interval = Some(activity_interval);
+      },
+      "--max-duration-secs" => {
+        let max_measured_duration = Duration::from_secs(parse_value(&arg, args.next())?);
+        if max_measured_duration.is_zero() {
+          return Err(format!("invalid value 0 for {}\n{}", arg, USAGE));
+        }
+        options.max_measured_duration = Some(max_measured_duration);
+      },
+      "--warmup-secs" => options.warmup_duration = Duration::from_secs(parse_value(&arg, args.next())?),
+      "--on-restart" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.restart_policy = RestartPolicy::from_name(&value).ok_or_else(|| format!("invalid value {} for {}\n{}", value, arg, USAGE))?;
+      },
+      "--heartbeat-secs" => {
+        let heartbeat_interval = Duration::from_secs(parse_value(&arg, args.next())?);
+        if heartbeat_interval.is_zero() {
+          return Err(format!("invalid value 0 for {}\n{}", arg, USAGE));
+        }
+        options.heartbeat_interval = Some(heartbeat_interval);
+      },
+      "--on-battery-warn-secs" => {
+        let on_battery_warn_duration = Duration::from_secs(parse_value(&arg, args.next())?);
+        if on_battery_warn_duration.is_zero() {
+          return Err(format!("invalid value 0 for {}\n{}", arg, USAGE));
+        }
+        options.on_battery_warn_duration = Some(on_battery_warn_duration);
+      },
+      "--maintenance-window" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.maintenance_windows.push(parse_maintenance_window(&value).map_err(|error| format!("{}\n{}", error, USAGE))?);
+      },
+      "--min-edge-interval-ms" => options.min_edge_interval = Some(Duration::from_millis(parse_value(&arg, args.next())?)),
+      "--profile" => options.profile = parse_value(&arg, args.next())?,
+      "--model" => options.model = Some(parse_value(&arg, args.next())?),
+      "--config" => config_path = Some(parse_value::<String>(&arg, args.next())?),
+      "--active-low" => options.active_low = true,
+      "--error-margin" => {
+        let value: f64 = parse_value(&arg, args.next())?;
+        if !(value >= 0.0 && value.is_finite()) {
+          return Err(format!("invalid value {} for {}\n{}", value, arg, USAGE));
+        }
+        error_margin = Some(value);
+      },
+      "--pattern" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.pattern_overrides.push(parse_pattern_override(&value).map_err(|error| format!("{}\n{}", error, USAGE))?);
+      },
+      "--avr-chirp" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.avr_chirp = Some(parse_target_tolerance(&value).ok_or_else(|| format!("invalid value {} for {}\n{}", value, arg, USAGE))?);
+      },
+      "--beep-group" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.beep_groups.push(parse_beep_group(&value).map_err(|error| format!("{}\n{}", error, USAGE))?);
+      },
+      "--error-floor-ms" => min_error_duration = Some(Duration::from_millis(parse_value(&arg, args.next())?)),
+      "--on-ambiguous" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.on_ambiguous = AmbiguityPolicy::from_name(&value).ok_or_else(|| format!("invalid value {} for {}\n{}", value, arg, USAGE))?;
+      },
+      "--match-metric" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.match_metric = MatchMetric::from_name(&value).ok_or_else(|| format!("invalid value {} for {}\n{}", value, arg, USAGE))?;
+      },
+      "--long-beep-threshold-ms" => {
+        let long_beep_threshold = Duration::from_millis(parse_value(&arg, args.next())?);
+        if long_beep_threshold.is_zero() {
+          return Err(format!("invalid value 0 for {}\n{}", arg, USAGE));
+        }
+        options.long_beep_threshold = Some(long_beep_threshold);
+      },
+      "--encoding" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.encoding = match value.as_str() {
+          "beep" => Encoding::Beep,
+          "pwm" => Encoding::Pwm,
+          "frame" => Encoding::Frame,
+          _ => return Err(format!("invalid value {} for {}\n{}", value, arg, USAGE)),
+        };
+      },
+      "--duty-cycle-band" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.duty_cycle_bands.push(parse_duty_cycle_band(&value).map_err(|error| format!("{}\n{}", error, USAGE))?);
+      },
+      "--frame-delimiter-ms" => frame_delimiter_duration = Some(Duration::from_millis(parse_value(&arg, args.next())?)),
+      "--frame-code" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.frame_codes.push(parse_frame_code(&value).map_err(|error| format!("{}\n{}", error, USAGE))?);
+      },
+      "--replay" => options.replay_path = Some(parse_value(&arg, args.next())?),
+      "--validate" => {
+        let value: String = parse_value(&arg, args.next())?;
+        let status = get_status_from_name(&value).ok_or_else(|| format!("unknown status {} for {}\n{}", value, arg, USAGE))?;
+        options.validation = Some((status, parse_value(&arg, args.next())?));
+      },
+      "--threshold-sweep" => options.threshold_sweep_path = Some(parse_value(&arg, args.next())?),
+      "--speed" => replay_speed = Some(parse_value(&arg, args.next())?),
+      "--inject-bounce" => bounce_probability = Some(parse_value(&arg, args.next())?),
+      "--inject-jitter-ms" => jitter = Some(Duration::from_millis(parse_value(&arg, args.next())?)),
+      "--noise-seed" => noise_seed = Some(parse_value(&arg, args.next())?),
+      "--trigger" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.trigger_mode = TriggerMode::from_name(&value).ok_or_else(|| format!("invalid value {} for {}\n{}", value, arg, USAGE))?;
+      },
+      "--gpiochip" => options.gpiochip_path = Some(parse_value(&arg, args.next())?),
+      #[cfg(feature = "adc")]
+      "--adc-channel" => adc_channel = Some(parse_value(&arg, args.next())?),
+      #[cfg(feature = "adc")]
+      "--adc-threshold" => adc_threshold = Some(parse_value(&arg, args.next())?),
+      #[cfg(feature = "adc")]
+      "--adc-hysteresis" => adc_hysteresis = Some(parse_value(&arg, args.next())?),
+      "--expander-address" => expander_address = Some(parse_address(&arg, args.next())?),
+      "--expander-channel" => expander_channel = Some(parse_value(&arg, args.next())?),
+      "--pin" => pin = Some(parse_value(&arg, args.next())?),
+      "--timeout-ms" => {
+        let timeout_duration = Duration::from_millis(parse_value(&arg, args.next())?);
+        if timeout_duration.is_zero() {
+          return Err(format!("invalid value 0 for {}\n{}", arg, USAGE));
+        }
+        options.timeout_duration = timeout_duration;
+      },
+      "--mains-pin" => options.mains_pin = Some(parse_value(&arg, args.next())?),
+      "--led-pin" => {
+        let value: String = parse_value(&arg, args.next())?;
+        let led_pin = parse_led_pin(&value).map_err(|error| format!("{}\n{}", error, USAGE))?;
+        if options.led_pins.iter().any(|(pin, _)| *pin == led_pin.0) {
+          return Err(format!("LED pin {} given more than once\n{}", led_pin.0, USAGE));
+        }
+        options.led_pins.push(led_pin);
+      },
+      "--rule" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.rules.push(parse_rule(&value).map_err(|error| format!("{}\n{}", error, USAGE))?);
+      },
+      "--exclusive-gpio" => options.exclusive_gpio = true,
+      "--sample-interval-ms" => {
+        let sample_interval = Duration::from_millis(parse_value(&arg, args.next())?);
+        if sample_interval.is_zero() {
+          return Err(format!("invalid value 0 for {}\n{}", arg, USAGE));
+        }
+        options.sample_interval = Some(sample_interval);
+      },
+      "--classifier-command" => options.classifier_command = Some(parse_value(&arg, args.next())?),
+      "--shadow-profile" => options.shadow_profile = Some(parse_value(&arg, args.next())?),
+      "--on-change" => options.on_change_commands.push((parse_value(&arg, args.next())?, Duration::ZERO)),
+      "--on-change-after" => {
+        let value: String = parse_value(&arg, args.next())?;
+        let (delay, command) = parse_delayed_hook(&value).map_err(|error| format!("{}\n{}", error, USAGE))?;
+        options.on_change_commands.push((command.to_string(), delay));
+      },
+      "--webhook-url" => {
+        let value: String = parse_value(&arg, args.next())?;
+        options.webhook_urls.push((parse_webhook_url(&value).map_err(|error| format!("{}\n{}", error, USAGE))?, Duration::ZERO));
+      },
+      "--webhook-url-after" => {
+        let value: String = parse_value(&arg, args.next())?;
+        let (delay, url) = parse_delayed_hook(&value).map_err(|error| format!("{}\n{}", error, USAGE))?;
+        options.webhook_urls.push((parse_webhook_url(url).map_err(|error| format!("{}\n{}", error, USAGE))?, delay));
+      },
+      "--udp-raw" => options.udp_raw_address = Some(parse_value(&arg, args.next())?),
+      "--status-socket" => options.status_socket_path = Some(parse_value(&arg, args.next())?),
+      #[cfg(feature = "sqlite")]
+      "--sqlite" => options.sqlite_path = Some(parse_value(&arg, args.next())?),
+      #[cfg(feature = "mqtt")]
+      "--mqtt-host" => mqtt_host = Some(parse_value(&arg, args.next())?),
+      #[cfg(feature = "mqtt")]
+      "--mqtt-port" => mqtt_port = Some(parse_value(&arg, args.next())?),
+      #[cfg(feature = "mqtt")]
+      "--mqtt-topic" => {
+        let topic: String = parse_value(&arg, args.next())?;
+        if !is_valid_topic(&topic) {
+          return Err(format!("invalid value {} for {}, a topic to publish to can't be empty or have wildcards\n{}", topic, arg, USAGE));
+        }
+        mqtt_topic = Some(topic);
+      },
+      #[cfg(feature = "mqtt")]
+      "--mqtt-username" => mqtt_username = Some(parse_value(&arg, args.next())?),
+      #[cfg(feature = "mqtt")]
+      "--mqtt-password" => mqtt_password = Some(parse_value(&arg, args.next())?),
+      #[cfg(feature = "mqtt")]
+      "--mqtt-retain" => mqtt_retain = true,
+      #[cfg(feature = "amqp")]
+      "--amqp-host" => amqp_host = Some(parse_value(&arg, args.next())?),
+      #[cfg(feature = "amqp")]
+      "--amqp-port" => amqp_port = Some(parse_value(&arg, args.next())?),
+      #[cfg(feature = "amqp")]
+      "--amqp-vhost" => amqp_vhost = Some(parse_short_string(&arg, args.next())?),
+      #[cfg(feature = "amqp")]
+      "--amqp-exchange" => amqp_exchange = Some(parse_short_string(&arg, args.next())?),
+      #[cfg(feature = "amqp")]
+      "--amqp-routing-key" => amqp_routing_key = Some(parse_short_string(&arg, args.next())?),
+      #[cfg(feature = "amqp")]
+      "--amqp-username" => amqp_username = Some(parse_value(&arg, args.next())?),
+      #[cfg(feature = "amqp")]
+      "--amqp-password" => amqp_password = Some(parse_value(&arg, args.next())?),
+      #[cfg(feature = "http")]
+      "--http-addr" => options.http_address = Some(parse_value(&arg, args.next())?),
+      #[cfg(feature = "http")]
+      "--raw-token" => options.raw_token = Some(parse_value(&arg, args.next())?),
+      #[cfg(feature = "http")]
+      "--metrics-addr" => options.metrics_address = Some(parse_value(&arg, args.next())?),
+      _ => return Err(format!("unknown flag {}\n{}", arg, USAGE)),
+    }
+  }
+
+  if !options.sinks.is_empty() && (output.is_some() || format.is_some()) {
+    return Err(format!("--output and --format cannot be used with --sink\n{}", USAGE));
+  }
+  // Flags win over the file, taking the sinks of the file only when the command line says nothing about where the lines go
+  options.config = config_path.map(|config_path| load_table_file(&config_path).map_err(|error| error.to_string())).transpose()?;
+  if let Some(table) = &options.config {
+    pin = pin.or(table.pin);
+    error_margin = error_margin.or(table.error_margin);
+    min_error_duration = min_error_duration.or(table.min_error_duration);
+    if options.sinks.is_empty() && output.is_none() && format.is_none() && let Some(sinks) = &table.sinks {
+      options.sinks = sinks.clone();
+    }
+  }
+  options.pin = pin.unwrap_or(DEFAULT_PIN);
+  options.error_margin = error_margin.unwrap_or(ERROR_MARGIN);
+  options.min_error_duration = min_error_duration.unwrap_or(MIN_ERROR_DURATION);
+  if options.sinks.is_empty() {
+    options.sinks.push((output.unwrap_or(OutputTarget::Stdout), format.unwrap_or(Format::Text)));
+  }
+
+  if options.summary_period.is_none() && !options.summary_sinks.is_empty() {
+    return Err(format!("--summary-sink requires --summary-every\n{}", USAGE));
+  }
+
+  if options.pretty_json && !options.sinks.iter().chain(&options.summary_sinks).any(|sink| sink.1 == Format::Json) {
+    return Err(format!("--json-pretty requires a sink in the json format\n{}", USAGE));
+  }
+
+  if options.model.is_some() && options.profile != DEFAULT_PROFILE_NAME {
+    return Err(format!("--model and --profile cannot be used together\n{}", USAGE));
+  }
+
+  if options.dump_stats && options.stats_path.is_none() {
+    return Err(format!("--dump-stats requires --stats-file\n{}", USAGE));
+  }
+
+  if let Some(once_timeout_duration) = once_timeout_duration {
+    if !options.once {
+      return Err(format!("--once-timeout-secs requires --once\n{}", USAGE));
+    }
+    options.once_timeout_duration = once_timeout_duration;
+  }
+
+  if let Some(replay_speed) = replay_speed {
+    if options.replay_path.is_none() {
+      return Err(format!("--speed requires --replay\n{}", USAGE));
+    }
+    if !(replay_speed >= 0.0 && f64::is_finite(replay_speed)) {
+      return Err(format!("invalid value {} for --speed\n{}", replay_speed, USAGE));
+    }
+    options.replay_speed = replay_speed;
+  }
+
+  if bounce_probability.is_some() || jitter.is_some() || noise_seed.is_some() {
+    if options.replay_path.is_none() {
+      return Err(format!("--inject-bounce, --inject-jitter-ms and --noise-seed require --replay\n{}", USAGE));
+    }
+    let bounce_probability: f64 = bounce_probability.unwrap_or(0.0);
+    if !(0.0..=1.0).contains(&bounce_probability) {
+      return Err(format!("invalid value {} for --inject-bounce, expected a fraction\n{}", bounce_probability, USAGE));
+    }
+    options.replay_noise = Some(NoiseConfig {
+      bounce_probability,
+      jitter: jitter.unwrap_or(Duration::ZERO),
+      seed: noise_seed.unwrap_or_else(get_time_seed),
+    });
+  }
+
+  if options.udp_raw_address.is_some() && options.encoding != Encoding::Beep {
+    return Err(format!("--udp-raw requires --encoding beep, only beeps are measured in pairs\n{}", USAGE));
+  }
+  if options.shadow_profile.is_some() && options.encoding != Encoding::Beep {
+    return Err(format!("--shadow-profile requires --encoding beep, only beeps are classified with a table\n{}", USAGE));
+  }
+  if options.calibrate && (options.encoding != Encoding::Beep || options.classifier_command.is_some() || options.shadow_profile.is_some()) {
+    return Err(format!("--calibrate cannot be used with another encoding, --classifier-command or --shadow-profile, nothing is classified while calibrating\n{}", USAGE));
+  }
+  if options.validation.is_some() && (options.replay_path.is_some() || options.encoding != Encoding::Beep) {
+    return Err(format!("--validate cannot be used with --replay or another encoding\n{}", USAGE));
+  }
+  if options.threshold_sweep_path.is_some() && (options.replay_path.is_some() || options.validation.is_some() || options.encoding != Encoding::Beep) {
+    return Err(format!("--threshold-sweep cannot be used with --replay, --validate or another encoding\n{}", USAGE));
+  }
+
+  // Lines of another chip go past the header, so only the Pi's own pins are checked up front
+  if options.gpiochip_path.is_none() && options.pin > MAX_HEADER_PIN {
+    return Err(format!("invalid pin {} for --pin, expected 0 to {}\n{}", options.pin, MAX_HEADER_PIN, USAGE));
+  }
+  if options.mains_pin.is_some() && options.replay_path.is_some() {
+    return Err(format!("--mains-pin cannot be used with --replay\n{}", USAGE));
+  }
+  if !options.led_pins.is_empty() && options.replay_path.is_some() {
+    return Err(format!("--led-pin cannot be used with --replay\n{}", USAGE));
+  }
+  #[cfg(feature = "http")]
+  if options.raw_token.is_some() && options.http_address.is_none() {
+    return Err(format!("--raw-token requires --http-addr\n{}", USAGE));
+  }
+
+  if options.mains_pin.is_none() && options.rules.iter().any(Rule::uses_mains) {
+    return Err(format!("--rule with a mains condition requires --mains-pin\n{}", USAGE));
+  }
+  if options.exclusive_gpio && options.replay_path.is_some() {
+    return Err(format!("--exclusive-gpio cannot be used with --replay\n{}", USAGE));
+  }
+
+  match (options.encoding, options.duty_cycle_bands.is_empty()) {
+    (Encoding::Pwm, true) => return Err(format!("--encoding pwm requires at least one --duty-cycle-band\n{}", USAGE)),
+    (Encoding::Beep | Encoding::Frame, false) => return Err(format!("--duty-cycle-band requires --encoding pwm\n{}", USAGE)),
+    _ => {},
+  }
+
+  if options.encoding == Encoding::Frame {
+    if options.frame_codes.is_empty() {
+      return Err(format!("--encoding frame requires at least one --frame-code\n{}", USAGE));
+    }
+    options.frame_delimiter_duration = frame_delimiter_duration.unwrap_or(DEFAULT_FRAME_DELIMITER_DURATION);
+  } else if !options.frame_codes.is_empty() || frame_delimiter_duration.is_some() {
+    return Err(format!("--frame-code and --frame-delimiter-ms require --encoding frame\n{}", USAGE));
+  }
+
+  match (expander_address, expander_channel) {
+    (Some(address), Some(channel)) if channel < EXPANDER_CHANNELS => options.expander = Some((address, channel)),
+    (Some(_), Some(channel)) => return Err(format!("invalid value {} for --expander-channel, expected 0 to {}\n{}", channel, EXPANDER_CHANNELS - 1, USAGE)),
+    (None, None) => {},
+    _ => return Err(format!("--expander-address and --expander-channel must be given together\n{}", USAGE)),
+  }
+
+  if options.sample_interval.is_some() && (options.replay_path.is_some() || options.expander.is_some() || options.gpiochip_path.is_some()) {
+    return Err(format!("--sample-interval-ms only applies to reading the pin directly\n{}", USAGE));
+  }
+  if options.trigger_mode != TriggerMode::Both && (options.replay_path.is_some() || options.expander.is_some() || options.gpiochip_path.is_some()) {
+    return Err(format!("--trigger only applies to reading the pin directly\n{}", USAGE));
+  }
+  if options.gpiochip_path.is_some() && (options.replay_path.is_some() || options.expander.is_some()) {
+    return Err(format!("--gpiochip cannot be used with --replay or the expander\n{}", USAGE));
+  }
+
+  #[cfg(feature = "adc")]
+  match adc_channel {
+    Some(channel) if channel >= ADC_CHANNELS => return Err(format!("invalid value {} for --adc-channel, expected 0 to {}\n{}", channel, ADC_CHANNELS - 1, USAGE)),
+    Some(channel) => {
+      let threshold = adc_threshold.unwrap_or(DEFAULT_ADC_THRESHOLD);
+      if threshold > ADC_MAX_VALUE {
+        return Err(format!("invalid value {} for --adc-threshold, expected 0 to {}\n{}", threshold, ADC_MAX_VALUE, USAGE));
+      }
+      if options.replay_path.is_some() || options.expander.is_some() || options.gpiochip_path.is_some() || options.sample_interval.is_some() || options.trigger_mode != TriggerMode::Both {
+        return Err(format!("--adc-channel cannot be used with --replay, the expander, --gpiochip, --sample-interval-ms or --trigger\n{}", USAGE));
+      }
+      options.adc = Some((channel, threshold, adc_hysteresis.unwrap_or(DEFAULT_ADC_HYSTERESIS)));
+    },
+    None if adc_threshold.is_some() || adc_hysteresis.is_some() => return Err(format!("--adc-threshold and --adc-hysteresis require --adc-channel\n{}", USAGE)),
+    None => {},
+  }
+
+  #[cfg(feature = "mqtt")]
+  match mqtt_host {
+    Some(host) => {
+      if mqtt_password.is_some() && mqtt_username.is_none() {
+        return Err(format!("--mqtt-password requires --mqtt-username\n{}", USAGE));
+      }
+      options.mqtt = Some(MqttConfig {
+        host,
+        port: mqtt_port.unwrap_or(DEFAULT_MQTT_PORT),
+        topic: mqtt_topic.unwrap_or_else(|| DEFAULT_MQTT_TOPIC.to_string()),
+        credentials: mqtt_username.map(|username| (username, mqtt_password)),
+        retain: mqtt_retain,
+      });
+    },
+    None if mqtt_port.is_some() || mqtt_topic.is_some() || mqtt_username.is_some() || mqtt_password.is_some() || mqtt_retain => {
+      return Err(format!("--mqtt-port, --mqtt-topic, --mqtt-username, --mqtt-password and --mqtt-retain require --mqtt-host\n{}", USAGE));
+    },
+    None => {},
+  }
+
+  #[cfg(feature = "amqp")]
+  match amqp_host {
+    Some(host) => {
+      if amqp_password.is_some() && amqp_username.is_none() {
+        return Err(format!("--amqp-password requires --amqp-username\n{}", USAGE));
+      }
+      options.amqp = Some(AmqpConfig {
+        host,
+        port: amqp_port.unwrap_or(DEFAULT_AMQP_PORT),
+        virtual_host: amqp_vhost.unwrap_or_else(|| DEFAULT_AMQP_VIRTUAL_HOST.to_string()),
+        exchange: amqp_exchange.unwrap_or_else(|| DEFAULT_AMQP_EXCHANGE.to_string()),
+        routing_key: amqp_routing_key.unwrap_or_else(|| DEFAULT_AMQP_ROUTING_KEY.to_string()),
+        credentials: amqp_username.map(|username| (username, amqp_password)),
+      });
+    },
+    None if amqp_port.is_some() || amqp_vhost.is_some() || amqp_exchange.is_some() || amqp_routing_key.is_some() || amqp_username.is_some() || amqp_password.is_some() => {
+      return Err(format!("--amqp-port, --amqp-vhost, --amqp-exchange, --amqp-routing-key, --amqp-username and --amqp-password require --amqp-host\n{}", USAGE));
+    },
+    None => {},
+  }
+
+  Ok(options)
+}
+
+// Accepts the address either in decimal or in hexadecimal with a 0x prefix, as I2C addresses are usually written
+fn parse_address(flag: &str, value: Option<String>) -> Result<u16, String> {
+  let value = value.ok_or_else(|| format!("missing value for {}\n{}", flag, USAGE))?;
+  let address = match value.strip_prefix("0x") {
+    Some(hex) => u16::from_str_radix(hex, 16),
+    None => value.parse(),
+  };
+  address.map_err(|_| format!("invalid value {} for {}\n{}", value, flag, USAGE))
+}
+
+// The names AMQP has, which go in strings of at most 255 bytes
+#[cfg(feature = "amqp")]
+fn parse_short_string(flag: &str, value: Option<String>) -> Result<String, String> {
+  let value: String = parse_value(flag, value)?;
+  if !is_valid_short_string(&value) {
+    return Err(format!("invalid value {} for {}, it can be at most 255 bytes long\n{}", value, flag, USAGE));
+  }
+  Ok(value)
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+  let value = value.ok_or_else(|| format!("missing value for {}\n{}", flag, USAGE))?;
+  value.parse().map_err(|_| format!("invalid value {} for {}\n{}", value, flag, USAGE))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::{env, fs, process};
+
+  use crate::output::SyslogFormat;
+
+  fn parse(args: &[&str]) -> Result<Options, String> {
+    parse_args(args.iter().map(|arg| arg.to_string()))
+  }
+
+  #[test]
+  fn defaults_without_flags() {
+    let options = parse(&[]).unwrap();
+    assert!(!options.show_features);
+    assert!(!options.show_confidence);
+    assert!(!options.show_guidance);
+    assert!(!options.show_origin);
+    assert!(!options.show_timestamps);
+    assert_eq!(options.min_beep_duration, Duration::ZERO);
+  }
+
+  #[test]
+  fn parses_features_flag() {
+    assert!(parse(&["--features"]).unwrap().show_features);
+    assert!(parse(&["--list-models"]).unwrap().list_models);
+  }
+
+  #[test]
+  fn counts_verbosity() {
+    assert_eq!(parse(&[]).unwrap().verbosity, 0);
+    assert_eq!(parse(&["-v"]).unwrap().verbosity, 1);
+    assert_eq!(parse(&["-vv"]).unwrap().verbosity, 2);
+    assert_eq!(parse(&["-v", "-v"]).unwrap().verbosity, 2);
+    assert!(parse(&["--show-windows"]).unwrap().show_windows);
+  }
+
+  #[test]
+  fn parses_dump_profile() {
+    assert_eq!(parse(&[]).unwrap().dump_profile_path, None);
+    assert_eq!(parse(&["--dump-profile", "ups.txt"]).unwrap().dump_profile_path.as_deref(), Some("ups.txt"));
+    assert!(parse(&["--dump-profile"]).is_err());
+  }
+
+  #[test]
+  fn parses_once() {
+    let options = parse(&["--once"]).unwrap();
+    assert!(options.once);
+    assert_eq!(options.once_timeout_duration, DEFAULT_ONCE_TIMEOUT_DURATION);
+    assert_eq!(parse(&["--once", "--once-timeout-secs", "10"]).unwrap().once_timeout_duration, Duration::from_secs(10));
+    assert!(parse(&["--once-timeout-secs", "10"]).unwrap_err().starts_with("--once-timeout-secs requires --once"));
+  }
+
+  #[test]
+  fn parses_exit_on() {
+    let options = parse(&["--exit-on", "LowOnBattery:30", "--exit-on", "PowerOff:0"]).unwrap();
+    assert_eq!(options.exit_conditions, vec![
+      ExitCondition { status: Status::LowOnBattery, grace_duration: Duration::from_secs(30), min_confidence: 0.0 },
+      ExitCondition { status: Status::PowerOff, grace_duration: Duration::ZERO, min_confidence: 0.0 },
+    ]);
+    assert!(parse(&["--exit-on", "LowOnBattery"]).unwrap_err().starts_with("invalid exit condition LowOnBattery"));
+  }
+
+  #[test]
+  fn parses_emit() {
+    assert_eq!(parse(&[]).unwrap().emit_status, None);
+    assert_eq!(parse(&["--emit", "LowOnBattery"]).unwrap().emit_status, Some(Status::LowOnBattery));
+    assert!(parse(&["--emit", "Low"]).unwrap_err().starts_with("unknown status Low for --emit"));
+  }
+
+  #[test]
+  fn parses_confidence_flag() {
+    assert!(parse(&["--confidence"]).unwrap().show_confidence);
+  }
+
+  #[test]
+  fn parses_show_origin_flag() {
+    assert!(parse(&["--show-origin"]).unwrap().show_origin);
+  }
+
+  #[test]
+  fn parses_timestamps_flag() {
+    assert!(parse(&["--timestamps"]).unwrap().show_timestamps);
+  }
+
+  #[test]
+  fn parses_symbols_flag() {
+    assert!(parse(&["--symbols"]).unwrap().show_symbols);
+  }
+
+  #[test]
+  fn parses_calibrate() {
+    assert!(!parse(&[]).unwrap().calibrate);
+    assert!(parse(&["--calibrate"]).unwrap().calibrate);
+    assert!(parse(&["--calibrate", "--encoding", "pwm"]).unwrap_err().starts_with("--calibrate cannot be used"));
+    assert!(parse(&["--calibrate", "--classifier-command", "classify"]).unwrap_err().starts_with("--calibrate cannot be used"));
+  }
+
+  #[test]
+  fn parses_guidance() {
+    let options = parse(&["--show-guidance", "--guidance", "OnBattery=critical:prepare-shutdown", "--guidance", "ReplaceBattery=info:none"]).unwrap();
+    assert!(options.show_guidance);
+    assert_eq!(options.guidance_overrides.len(), 2);
+    assert_eq!(options.guidance_overrides[0].0, Status::OnBattery);
+    assert!(parse(&["--guidance", "OnBattery"]).unwrap_err().starts_with("invalid guidance OnBattery"));
+  }
+
+  #[test]
+  fn parses_format_and_glyphs() {
+    assert_eq!(parse(&[]).unwrap().sinks, vec![(OutputTarget::Stdout, Format::Text)]);
+    let options = parse(&["--format", "char", "--glyph", "OnMains=⚡"]).unwrap();
+    assert_eq!(options.sinks, vec![(OutputTarget::Stdout, Format::Char)]);
+    assert_eq!(options.glyph_overrides, vec![(Status::OnMains, "⚡".to_string())]);
+    assert!(parse(&["--format", "xml"]).unwrap_err().starts_with("invalid value xml for --format"));
+    assert!(parse(&["--glyph", "OnMains"]).unwrap_err().starts_with("invalid glyph OnMains"));
+  }
+
+  #[test]
+  fn parses_output() {
+    assert_eq!(parse(&["--output", "syslog"]).unwrap().sinks, vec![(OutputTarget::Syslog(SyslogFormat::Plain), Format::Text)]);
+    assert_eq!(parse(&["--output", "/var/log/ups.log"]).unwrap().sinks, vec![(OutputTarget::File("/var/log/ups.log".to_string()), Format::Text)]);
+    assert!(parse(&["--output"]).unwrap_err().starts_with("missing value for --output"));
+  }
+
+  #[test]
+  fn parses_sinks() {
+    let options = parse(&["--sink", "stdout:human", "--sink", "/var/log/ups.jsonl:json"]).unwrap();
+    assert_eq!(options.sinks, vec![(OutputTarget::Stdout, Format::Text), (OutputTarget::File("/var/log/ups.jsonl".to_string()), Format::Json)]);
+    assert!(parse(&["--sink", "stdout"]).unwrap_err().starts_with("invalid sink stdout"));
+    assert!(parse(&["--sink", "stdout:human", "--output", "syslog"]).unwrap_err().starts_with("--output and --format cannot be used with --sink"));
+  }
+
+  #[test]
+  fn parses_json_pretty() {
+    assert!(!parse(&[]).unwrap().pretty_json);
+    assert!(parse(&["--format", "json", "--json-pretty"]).unwrap().pretty_json);
+    assert!(parse(&["--sink", "stdout:text", "--summary-every", "daily", "--summary-sink", "stdout:json", "--json-pretty"]).unwrap().pretty_json);
+    assert!(parse(&["--json-pretty"]).unwrap_err().starts_with("--json-pretty requires a sink in the json format"));
+  }
+
+  #[test]
+  fn parses_summaries() {
+    let options = parse(&["--summary-every", "weekly", "--summary-sink", "/var/log/ups-summary.jsonl:json"]).unwrap();
+    assert_eq!(options.summary_period, Some(Duration::from_secs(7 * 24 * 60 * 60)));
+    assert_eq!(options.summary_sinks, vec![(OutputTarget::File("/var/log/ups-summary.jsonl".to_string()), Format::Json)]);
+    assert_eq!(parse(&["--summary-every", "3600"]).unwrap().summary_period, Some(Duration::from_secs(3600)));
+    assert!(parse(&["--summary-every", "hourly"]).unwrap_err().starts_with("invalid summary period hourly"));
+    assert!(parse(&["--summary-sink", "stdout:text"]).unwrap_err().starts_with("--summary-sink requires --summary-every"));
+  }
+
+  #[test]
+  fn parses_stats_file() {
+    let options = parse(&["--stats-file", "/var/lib/ups/stats", "--dump-stats"]).unwrap();
+    assert_eq!(options.stats_path.as_deref(), Some("/var/lib/ups/stats"));
+    assert!(options.dump_stats);
+    assert!(parse(&["--dump-stats"]).unwrap_err().starts_with("--dump-stats requires --stats-file"));
+  }
+
+  #[test]
+  fn parses_history_size() {
+    assert_eq!(parse(&[]).unwrap().history_size, DEFAULT_MAX_TRANSITIONS);
+    assert_eq!(parse(&["--history-size", "200"]).unwrap().history_size, 200);
+    assert!(parse(&["--history-size", "0"]).unwrap_err().starts_with("invalid value 0 for --history-size"));
+  }
+
+  #[test]
+  fn parses_replace_battery_escalation() {
+    assert_eq!(parse(&[]).unwrap().replace_battery_escalation_score, DEFAULT_ESCALATION_SCORE);
+    assert_eq!(parse(&["--replace-battery-escalation", "100"]).unwrap().replace_battery_escalation_score, 100.0);
+    assert!(parse(&["--replace-battery-escalation", "0"]).unwrap_err().starts_with("invalid value 0 for --replace-battery-escalation"));
+  }
+
+  #[test]
+  fn parses_min_beep_ms() {
+    assert_eq!(parse(&["--min-beep-ms", "120"]).unwrap().min_beep_duration, Duration::from_millis(120));
+  }
+
+  #[test]
+  fn parses_long_beep_threshold_ms() {
+    assert_eq!(parse(&[]).unwrap().long_beep_threshold, None);
+    assert_eq!(parse(&["--long-beep-threshold-ms", "800"]).unwrap().long_beep_threshold, Some(Duration::from_millis(800)));
+    assert!(parse(&["--long-beep-threshold-ms", "0"]).unwrap_err().starts_with("invalid value 0 for --long-beep-threshold-ms"));
+  }
+
+  #[test]
+  fn parses_min_edge_interval_ms() {
+    assert_eq!(parse(&[]).unwrap().min_edge_interval, None);
+    assert_eq!(parse(&["--min-edge-interval-ms", "5"]).unwrap().min_edge_interval, Some(Duration::from_millis(5)));
+  }
+
+  #[test]
+  fn parses_profile() {
+    assert_eq!(parse(&[]).unwrap().profile, DEFAULT_PROFILE_NAME);
+    assert_eq!(parse(&["--profile", "beeps-on-mains"]).unwrap().profile, "beeps-on-mains");
+  }
+
+  #[test]
+  fn parses_config() {
+    assert_eq!(parse(&[]).unwrap().config, None);
+    assert!(parse(&["--config"]).is_err());
+    assert!(parse(&["--config", "/nonexistent/acme.toml"]).unwrap_err().starts_with("could not read config /nonexistent/acme.toml"));
+
+    let path = env::temp_dir().join(format!("ups-power-status-cli-{}.toml", process::id())).display().to_string();
+    fs::write(&path, "pin = 17\nerror_margin = 0.2\nerror_floor_ms = 40\nsinks = [\"stderr:json\"]\n[[pattern]]\nstatus = \"OnBattery\"\nbeep_ms = 250\ninter_beep_ms = 60000\n").unwrap();
+    let options = parse(&["--config", &path]).unwrap();
+    assert_eq!(options.config.unwrap().beep_durations, vec![(Status::OnBattery, [Duration::from_millis(250), Duration::from_secs(60)])]);
+    assert_eq!((options.pin, options.error_margin, options.min_error_duration), (17, 0.2, Duration::from_millis(40)));
+    assert_eq!(options.sinks, vec![(OutputTarget::Stderr, Format::Json)]);
+
+    // The flags win over the file, any of --output, --format and --sink over its sinks
+    let options = parse(&["--config", &path, "--pin", "22", "--error-margin", "0.1", "--error-floor-ms", "0", "--format", "char"]).unwrap();
+    assert_eq!((options.pin, options.error_margin, options.min_error_duration), (22, 0.1, Duration::ZERO));
+    assert_eq!(options.sinks, vec![(OutputTarget::Stdout, Format::Char)]);
+    assert_eq!(parse(&["--config", &path, "--sink", "stdout:text"]).unwrap().sinks, vec![(OutputTarget::Stdout, Format::Text)]);
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn parses_dump_config() {
+    assert_eq!(parse(&[]).unwrap().dump_config_path, None);
+    assert_eq!(parse(&["--dump-config", "ups.toml"]).unwrap().dump_config_path.as_deref(), Some("ups.toml"));
+    assert!(parse(&["--dump-config"]).is_err());
+  }
+
+  #[test]
+  fn parses_active_low() {
+    assert!(!parse(&[]).unwrap().active_low);
+    assert!(parse(&["--active-low"]).unwrap().active_low);
+  }
+
+  #[test]
+  fn parses_model() {
+    assert_eq!(parse(&["--model", "Generic line-interactive"]).unwrap().model.as_deref(), Some("Generic line-interactive"));
+    assert!(parse(&["--model", "Generic line-interactive", "--profile", "active-low"]).unwrap_err().starts_with("--model and --profile cannot be used together"));
+  }
+
+  #[test]
+  fn parses_matching_tolerance_and_ambiguity_policy() {
+    let options = parse(&[]).unwrap();
+    assert_eq!(options.error_margin, ERROR_MARGIN);
+    assert_eq!(options.on_ambiguous, AmbiguityPolicy::Closest);
+
+    let options = parse(&["--error-margin", "0.5", "--on-ambiguous", "highest-severity"]).unwrap();
+    assert_eq!(options.error_margin, 0.5);
+    assert_eq!(options.on_ambiguous, AmbiguityPolicy::HighestSeverity);
+    assert!(parse(&["--error-margin", "-0.1"]).unwrap_err().starts_with("invalid value -0.1 for --error-margin"));
+    assert!(parse(&["--on-ambiguous", "last"]).unwrap_err().starts_with("invalid value last for --on-ambiguous"));
+  }
+
+  #[test]
+  fn parses_error_floor_ms() {
+    assert_eq!(parse(&[]).unwrap().min_error_duration, MIN_ERROR_DURATION);
+    assert_eq!(parse(&["--error-floor-ms", "0"]).unwrap().min_error_duration, Duration::ZERO);
+    assert_eq!(parse(&["--error-floor-ms", "50"]).unwrap().min_error_duration, Duration::from_millis(50));
+  }
+
+  #[test]
+  fn parses_patterns() {
+    assert!(parse(&[]).unwrap().pattern_overrides.is_empty());
+    let options = parse(&["--pattern", "LowOnBattery:beep=250ms±10%,gap=1s±300ms", "--pattern", "OnBattery:beep=250ms,gap=30s"]).unwrap();
+    assert_eq!(options.pattern_overrides.iter().map(|pattern_override| pattern_override.status).collect::<Vec<_>>(), vec![Status::LowOnBattery, Status::OnBattery]);
+    assert!(parse(&["--pattern", "LowOnBattery"]).unwrap_err().starts_with("invalid pattern LowOnBattery"));
+  }
+
+  #[test]
+  fn parses_avr_chirp() {
+    assert_eq!(parse(&[]).unwrap().avr_chirp, None);
+    assert_eq!(parse(&["--avr-chirp", "80ms"]).unwrap().avr_chirp, Some((Duration::from_millis(80), None)));
+    let tolerance = Tolerance { error_margin: 0.0, min_error_duration: Duration::from_millis(20) };
+    assert_eq!(parse(&["--avr-chirp", "80ms±20ms"]).unwrap().avr_chirp, Some((Duration::from_millis(80), Some(tolerance))));
+    assert!(parse(&["--avr-chirp", "80"]).unwrap_err().starts_with("invalid value 80 for --avr-chirp"));
+  }
+
+  #[test]
+  fn parses_beep_groups() {
+    assert!(parse(&[]).unwrap().beep_groups.is_empty());
+    let options = parse(&["--beep-group", "TestInProgress:beep=100ms,gap=500ms", "--beep-group", "ReplaceBattery:count=3,beep=100ms,gap=1s"]).unwrap();
+    assert_eq!(options.beep_groups.iter().map(|group| (group.status, group.count)).collect::<Vec<_>>(), vec![(Status::TestInProgress, 2), (Status::ReplaceBattery, 3)]);
+    assert!(parse(&["--beep-group", "TestInProgress:beep=100ms,gap=200ms"]).unwrap_err().starts_with("gap of beep group"));
+  }
+
+  #[test]
+  fn parses_match_metric() {
+    assert_eq!(parse(&[]).unwrap().match_metric, MatchMetric::Axiswise);
+    assert_eq!(parse(&["--match-metric", "euclidean"]).unwrap().match_metric, MatchMetric::Euclidean);
+    assert!(parse(&["--match-metric", "manhattan"]).unwrap_err().starts_with("invalid value manhattan for --match-metric"));
+  }
+
+  #[test]
+  fn parses_pwm_encoding() {
+    assert_eq!(parse(&[]).unwrap().encoding, Encoding::Beep);
+    let options = parse(&["--encoding", "pwm", "--duty-cycle-band", "OnMains=0-10", "--duty-cycle-band", "OnBattery=40-60"]).unwrap();
+    assert_eq!(options.encoding, Encoding::Pwm);
+    assert_eq!(options.duty_cycle_bands.len(), 2);
+    assert_eq!(options.duty_cycle_bands[1].status, Status::OnBattery);
+  }
+
+  #[test]
+  fn rejects_pwm_encoding_without_bands_or_bands_without_it() {
+    assert!(parse(&["--encoding", "pwm"]).unwrap_err().starts_with("--encoding pwm requires at least one --duty-cycle-band"));
+    assert!(parse(&["--duty-cycle-band", "OnMains=0-10"]).unwrap_err().starts_with("--duty-cycle-band requires --encoding pwm"));
+    assert!(parse(&["--encoding", "morse"]).unwrap_err().starts_with("invalid value morse for --encoding"));
+  }
+
+  #[test]
+  fn parses_frame_encoding() {
+    let options = parse(&["--encoding", "frame", "--frame-code", "3=LowOnBattery"]).unwrap();
+    assert_eq!(options.encoding, Encoding::Frame);
+    assert_eq!(options.frame_codes, vec![(3, Status::LowOnBattery)]);
+    assert_eq!(options.frame_delimiter_duration, DEFAULT_FRAME_DELIMITER_DURATION);
+    assert_eq!(parse(&["--encoding", "frame", "--frame-code", "1=OnBattery", "--frame-delimiter-ms", "1500"]).unwrap().frame_delimiter_duration, Duration::from_millis(1500));
+  }
+
+  #[test]
+  fn rejects_frame_encoding_without_codes_or_codes_without_it() {
+    assert!(parse(&["--encoding", "frame"]).unwrap_err().starts_with("--encoding frame requires at least one --frame-code"));
+    assert!(parse(&["--frame-code", "1=OnBattery"]).unwrap_err().starts_with("--frame-code and --frame-delimiter-ms require --encoding frame"));
+    assert!(parse(&["--frame-delimiter-ms", "1500"]).unwrap_err().starts_with("--frame-code and --frame-delimiter-ms require --encoding frame"));
+  }
+
+  #[test]
+  fn parses_replay_with_speed() {
+    let options = parse(&["--replay", "capture.txt", "--speed", "0"]).unwrap();
+    assert_eq!(options.replay_path.as_deref(), Some("capture.txt"));
+    assert_eq!(options.replay_speed, 0.0);
+    assert_eq!(parse(&["--replay", "capture.txt"]).unwrap().replay_speed, 1.0);
+  }
+
+  #[test]
+  fn parses_validate() {
+    let options = parse(&["--validate", "OnBattery", "capture.txt"]).unwrap();
+    assert_eq!(options.validation, Some((Status::OnBattery, "capture.txt".to_string())));
+    assert!(parse(&["--validate", "Battery", "capture.txt"]).unwrap_err().starts_with("unknown status Battery for --validate"));
+    assert!(parse(&["--validate", "OnBattery"]).unwrap_err().starts_with("missing value for --validate"));
+    assert!(parse(&["--validate", "OnBattery", "capture.txt", "--replay", "other.txt"]).unwrap_err().starts_with("--validate cannot be used"));
+  }
+
+  #[test]
+  fn parses_threshold_sweep() {
+    assert_eq!(parse(&["--threshold-sweep", "labeled.txt"]).unwrap().threshold_sweep_path.as_deref(), Some("labeled.txt"));
+    assert!(parse(&["--threshold-sweep", "labeled.txt", "--replay", "other.txt"]).unwrap_err().starts_with("--threshold-sweep cannot be used"));
+    assert!(parse(&["--threshold-sweep", "labeled.txt", "--validate", "OnBattery", "capture.txt"]).unwrap_err().starts_with("--threshold-sweep cannot be used"));
+  }
+
+  #[test]
+  fn parses_replay_noise() {
+    assert_eq!(parse(&["--replay", "capture.txt"]).unwrap().replay_noise, None);
+    let options = parse(&["--replay", "capture.txt", "--inject-bounce", "0.2", "--inject-jitter-ms", "15", "--noise-seed", "42"]).unwrap();
+    assert_eq!(options.replay_noise, Some(NoiseConfig { bounce_probability: 0.2, jitter: Duration::from_millis(15), seed: 42 }));
+    let options = parse(&["--replay", "capture.txt", "--inject-jitter-ms", "15"]).unwrap();
+    assert_eq!(options.replay_noise.unwrap().bounce_probability, 0.0);
+    assert!(parse(&["--noise-seed", "42"]).unwrap_err().starts_with("--inject-bounce, --inject-jitter-ms and --noise-seed require --replay"));
+    assert!(parse(&["--replay", "capture.txt", "--inject-bounce", "1.5"]).unwrap_err().starts_with("invalid value 1.5 for --inject-bounce"));
+  }
+
+  #[test]
+  fn rejects_speed_without_replay_or_negative() {
+    assert!(parse(&["--speed", "2"]).unwrap_err().starts_with("--speed requires --replay"));
+    assert!(parse(&["--replay", "capture.txt", "--speed", "-1"]).unwrap_err().starts_with("invalid value -1 for --speed"));
+  }
+
+  #[test]
+  fn parses_expander() {
+    assert_eq!(parse(&["--expander-address", "0x20", "--expander-channel", "9"]).unwrap().expander, Some((0x20, 9)));
+    assert_eq!(parse(&["--expander-address", "33", "--expander-channel", "0"]).unwrap().expander, Some((33, 0)));
+    assert_eq!(parse(&[]).unwrap().expander, None);
+  }
+
+  #[test]
+  fn rejects_incomplete_or_invalid_expander() {
+    assert!(parse(&["--expander-address", "0x20"]).unwrap_err().starts_with("--expander-address and --expander-channel must be given together"));
+    assert!(parse(&["--expander-address", "0x20", "--expander-channel", "16"]).unwrap_err().starts_with("invalid value 16 for --expander-channel"));
+    assert!(parse(&["--expander-address", "0xZZ", "--expander-channel", "1"]).unwrap_err().starts_with("invalid value 0xZZ for --expander-address"));
+  }
+
+  #[test]
+  fn parses_led_pins() {
+    assert!(parse(&[]).unwrap().led_pins.is_empty());
+    assert_eq!(parse(&["--led-pin", "5=OnMains", "--led-pin", "6=OnBattery"]).unwrap().led_pins, vec![(5, Status::OnMains), (6, Status::OnBattery)]);
+    assert!(parse(&["--led-pin", "5=OnMains", "--led-pin", "5=OnBattery"]).unwrap_err().starts_with("LED pin 5 given more than once"));
+    assert!(parse(&["--led-pin", "5=ReplaceBattery"]).unwrap_err().contains("is neither on mains nor on battery"));
+    assert!(parse(&["--led-pin", "5=OnMains", "--replay", "capture.txt"]).unwrap_err().starts_with("--led-pin cannot be used with --replay"));
+  }
+
+  #[test]
+  fn parses_pin_and_timeout() {
+    let options = parse(&[]).unwrap();
+    assert_eq!((options.pin, options.timeout_duration), (DEFAULT_PIN, TIMEOUT_DURATION));
+    let options = parse(&["--pin", "25", "--timeout-ms", "1500"]).unwrap();
+    assert_eq!((options.pin, options.timeout_duration), (25, Duration::from_millis(1500)));
+    assert!(parse(&["--pin", "28"]).unwrap_err().starts_with("invalid pin 28 for --pin, expected 0 to 27"));
+    assert!(parse(&["--pin", "-1"]).unwrap_err().starts_with("invalid value -1 for --pin"));
+    assert_eq!(parse(&["--pin", "40", "--gpiochip", "/dev/gpiochip4"]).unwrap().pin, 40);
+    assert!(parse(&["--timeout-ms", "0"]).unwrap_err().starts_with("invalid value 0 for --timeout-ms"));
+  }
+
+  #[test]
+  fn parses_mains_pin() {
+    assert_eq!(parse(&[]).unwrap().mains_pin, None);
+    assert_eq!(parse(&["--mains-pin", "27"]).unwrap().mains_pin, Some(27));
+    assert!(parse(&["--mains-pin", "27", "--replay", "capture.txt"]).unwrap_err().starts_with("--mains-pin cannot be used with --replay"));
+  }
+
+  #[test]
+  fn parses_rules() {
+    let options = parse(&["--mains-pin", "27", "--rule", "mains:present,status:OnBattery=SensorConflict", "--rule", "status:Unknown=OnMains"]).unwrap();
+    assert_eq!(options.rules.len(), 2);
+    assert_eq!(options.rules[1].status, Status::OnMains);
+    assert!(parse(&["--rule", "status:Unknown=OnMains"]).is_ok());
+    assert!(parse(&["--rule", "mains:present=SensorConflict"]).unwrap_err().starts_with("--rule with a mains condition requires --mains-pin"));
+    assert!(parse(&["--rule", "mains:present"]).unwrap_err().starts_with("invalid rule mains:present"));
+  }
+
+  #[test]
+  fn parses_exclusive_gpio() {
+    assert!(!parse(&[]).unwrap().exclusive_gpio);
+    assert!(parse(&["--exclusive-gpio"]).unwrap().exclusive_gpio);
+    assert!(parse(&["--exclusive-gpio", "--replay", "capture.txt"]).unwrap_err().starts_with("--exclusive-gpio cannot be used with --replay"));
+  }
+
+  #[test]
+  fn parses_unknown_debounce_secs() {
+    assert_eq!(parse(&[]).unwrap().unknown_debounce_duration, Duration::ZERO);
+    assert_eq!(parse(&["--unknown-debounce-secs", "15"]).unwrap().unknown_debounce_duration, Duration::from_secs(15));
+  }
+
+  #[test]
+  fn parses_confirmations() {
+    assert_eq!(parse(&[]).unwrap().confirmations, 1);
+    assert_eq!(parse(&["--confirmations", "3"]).unwrap().confirmations, 3);
+    assert!(parse(&["--confirmations", "0"]).unwrap_err().starts_with("invalid value 0 for --confirmations"));
+  }
+
+  #[test]
+  fn parses_corroborate_outage_secs() {
+    assert_eq!(parse(&[]).unwrap().outage_corroboration_window, None);
+    assert_eq!(parse(&["--corroborate-outage-secs", "150"]).unwrap().outage_corroboration_window, Some(Duration::from_secs(150)));
+    assert!(parse(&["--corroborate-outage-secs", "0"]).unwrap_err().starts_with("invalid value 0 for --corroborate-outage-secs"));
+  }
+
+  #[test]
+  fn parses_warmup_secs() {
+    assert_eq!(parse(&[]).unwrap().warmup_duration, Duration::ZERO);
+    assert_eq!(parse(&["--warmup-secs", "120"]).unwrap().warmup_duration, Duration::from_secs(120));
+  }
+
+  #[test]
+  fn parses_heartbeat_secs() {
+    assert_eq!(parse(&[]).unwrap().heartbeat_interval, None);
+    assert_eq!(parse(&["--heartbeat-secs", "300"]).unwrap().heartbeat_interval, Some(Duration::from_secs(300)));
+    assert!(parse(&["--heartbeat-secs", "0"]).unwrap_err().starts_with("invalid value 0 for --heartbeat-secs"));
+  }
+
+  #[test]
+  fn parses_on_battery_warn_secs() {
+    assert_eq!(parse(&[]).unwrap().on_battery_warn_duration, None);
+    assert_eq!(parse(&["--on-battery-warn-secs", "600"]).unwrap().on_battery_warn_duration, Some(Duration::from_secs(600)));
+    assert!(parse(&["--on-battery-warn-secs", "0"]).unwrap_err().starts_with("invalid value 0 for --on-battery-warn-secs"));
+  }
+
+  #[test]
+  fn parses_maintenance_windows() {
+    assert!(parse(&[]).unwrap().maintenance_windows.is_empty());
+    let options = parse(&["--maintenance-window", "sun@03:00-04:00", "--maintenance-window", "daily@12:00-12:30Z"]).unwrap();
+    assert_eq!(options.maintenance_windows.len(), 2);
+    assert!(parse(&["--maintenance-window", "sunday@03:00-04:00"]).unwrap_err().starts_with("invalid days sunday"));
+  }
+
+  #[test]
+  fn parses_on_restart() {
+    assert_eq!(parse(&[]).unwrap().restart_policy, RestartPolicy::Report);
+    assert_eq!(parse(&["--on-restart", "wait-for-cycle"]).unwrap().restart_policy, RestartPolicy::WaitForCycle);
+    assert!(parse(&["--on-restart", "wait"]).unwrap_err().starts_with("invalid value wait for --on-restart"));
+  }
+
+  #[test]
+  fn parses_sample_interval_ms() {
+    assert_eq!(parse(&[]).unwrap().sample_interval, None);
+    assert_eq!(parse(&["--sample-interval-ms", "500"]).unwrap().sample_interval, Some(Duration::from_millis(500)));
+    assert!(parse(&["--sample-interval-ms", "0"]).unwrap_err().starts_with("invalid value 0 for --sample-interval-ms"));
+    assert!(parse(&["--sample-interval-ms", "500", "--replay", "capture.txt"]).unwrap_err().starts_with("--sample-interval-ms only applies"));
+    assert!(parse(&["--sample-interval-ms", "500", "--expander-address", "0x20", "--expander-channel", "3"]).unwrap_err().starts_with("--sample-interval-ms only applies"));
+  }
+
+  #[test]
+  fn parses_trigger() {
+    assert_eq!(parse(&[]).unwrap().trigger_mode, TriggerMode::Both);
+    assert_eq!(parse(&["--trigger", "falling", "--sample-interval-ms", "2"]).unwrap().trigger_mode, TriggerMode::Falling);
+    assert!(parse(&["--trigger", "up"]).unwrap_err().starts_with("invalid value up for --trigger"));
+    assert!(parse(&["--trigger", "rising", "--replay", "capture.txt"]).unwrap_err().starts_with("--trigger only applies"));
+    assert_eq!(parse(&["--trigger", "both", "--replay", "capture.txt"]).unwrap().trigger_mode, TriggerMode::Both);
+  }
+
+  #[test]
+  fn parses_gpiochip() {
+    assert_eq!(parse(&[]).unwrap().gpiochip_path, None);
+    assert_eq!(parse(&["--gpiochip", "/dev/gpiochip4"]).unwrap().gpiochip_path.as_deref(), Some("/dev/gpiochip4"));
+    assert!(parse(&["--gpiochip", "/dev/gpiochip4", "--replay", "capture.txt"]).unwrap_err().starts_with("--gpiochip cannot be used"));
+    assert!(parse(&["--gpiochip", "/dev/gpiochip4", "--sample-interval-ms", "500"]).unwrap_err().starts_with("--sample-interval-ms only applies"));
+  }
+
+  #[cfg(feature = "adc")]
+  #[test]
+  fn parses_adc() {
+    assert_eq!(parse(&[]).unwrap().adc, None);
+    assert_eq!(parse(&["--adc-channel", "3"]).unwrap().adc, Some((3, DEFAULT_ADC_THRESHOLD, DEFAULT_ADC_HYSTERESIS)));
+    assert_eq!(parse(&["--adc-channel", "0", "--adc-threshold", "300", "--adc-hysteresis", "8"]).unwrap().adc, Some((0, 300, 8)));
+    assert!(parse(&["--adc-channel", "8"]).unwrap_err().starts_with("invalid value 8 for --adc-channel, expected 0 to 7"));
+    assert!(parse(&["--adc-channel", "0", "--adc-threshold", "1024"]).unwrap_err().starts_with("invalid value 1024 for --adc-threshold"));
+    assert!(parse(&["--adc-threshold", "300"]).unwrap_err().starts_with("--adc-threshold and --adc-hysteresis require --adc-channel"));
+    assert!(parse(&["--adc-channel", "0", "--gpiochip", "/dev/gpiochip4"]).unwrap_err().starts_with("--adc-channel cannot be used"));
+  }
+
+  #[test]
+  fn parses_on_mains_grace_secs() {
+    assert_eq!(parse(&[]).unwrap().on_mains_grace_duration, DEFAULT_ON_MAINS_GRACE_DURATION);
+    assert_eq!(parse(&["--on-mains-grace-secs", "10"]).unwrap().on_mains_grace_duration, Duration::from_secs(10));
+  }
+
+  #[test]
+  fn parses_max_duration_secs() {
+    assert_eq!(parse(&[]).unwrap().max_measured_duration, None);
+    assert_eq!(parse(&["--max-duration-secs", "300"]).unwrap().max_measured_duration, Some(Duration::from_secs(300)));
+    assert!(parse(&["--max-duration-secs", "0"]).unwrap_err().starts_with("invalid value 0 for --max-duration-secs"));
+  }
+
+  #[test]
+  fn parses_match_on() {
+    assert_eq!(parse(&[]).unwrap().gap_basis, GapBasis::Gap);
+    assert_eq!(parse(&["--match-on", "period"]).unwrap().gap_basis, GapBasis::Period);
+    assert!(parse(&["--match-on", "cycle"]).unwrap_err().starts_with("invalid value cycle for --match-on"));
+  }
+
+  #[test]
+  fn parses_watch_suspend() {
+    assert!(!parse(&[]).unwrap().is_watching_suspend);
+    assert!(parse(&["--watch-suspend"]).unwrap().is_watching_suspend);
+  }
+
+  #[test]
+  fn parses_first_edge() {
+    assert_eq!(parse(&[]).unwrap().first_edge_policy, FirstEdgePolicy::Measure);
+    assert_eq!(parse(&["--first-edge", "skip"]).unwrap().first_edge_policy, FirstEdgePolicy::Skip);
+    assert!(parse(&["--first-edge", "drop"]).unwrap_err().starts_with("invalid value drop for --first-edge"));
+  }
+
+  #[test]
+  fn parses_expect_activity_secs() {
+    assert_eq!(parse(&[]).unwrap().activity_interval, None);
+    assert_eq!(parse(&["--expect-activity-secs", "3600"]).unwrap().activity_interval, Some(Duration::from_secs(3600)));
+    assert!(parse(&["--expect-activity-secs", "0"]).unwrap_err().starts_with("invalid value 0 for --expect-activity-secs"));
+  }
+
+  #[test]
+  fn parses_maps() {
+    assert_eq!(parse(&["--map", "NoLoadOnBattery=OnBattery", "--map", "ReplaceBattery=OnMains"]).unwrap().status_maps, vec![
+      (Status::NoLoadOnBattery, Status::OnBattery),
+      (Status::ReplaceBattery, Status::OnMains),
+    ]);
+    assert!(parse(&["--map", "NoLoadOnBattery"]).unwrap_err().starts_with("invalid map NoLoadOnBattery"));
+  }
+
+  #[test]
+  fn parses_mute_hold_secs() {
+    assert_eq!(parse(&[]).unwrap().mute_hold_duration, Duration::ZERO);
+    assert_eq!(parse(&["--mute-hold-secs", "300"]).unwrap().mute_hold_duration, Duration::from_secs(300));
+  }
+
+  #[test]
+  fn parses_on_change_and_webhook_url() {
+    let options = parse(&["--on-change", "notify {status}", "--on-change", "logger {description}", "--webhook-url", "http://nas.local:8123/ups"]).unwrap();
+    assert_eq!(options.on_change_commands, vec![("notify {status}".to_string(), Duration::ZERO), ("logger {description}".to_string(), Duration::ZERO)]);
+    assert_eq!(options.webhook_urls, vec![(WebhookUrl { host: "nas.local".to_string(), port: 8123, path: "/ups".to_string() }, Duration::ZERO)]);
+
+    let options = parse(&["--on-change-after", "120:shutdown -h now", "--webhook-url-after", "30:http://nas.local/ups"]).unwrap();
+    assert_eq!(options.on_change_commands, vec![("shutdown -h now".to_string(), Duration::from_secs(120))]);
+    assert_eq!(options.webhook_urls, vec![(WebhookUrl { host: "nas.local".to_string(), port: 80, path: "/ups".to_string() }, Duration::from_secs(30))]);
+    assert!(parse(&["--on-change-after", "shutdown -h now"]).unwrap_err().starts_with("invalid delayed hook shutdown -h now"));
+    assert!(parse(&["--webhook-url-after", "30:https://example.com/"]).unwrap_err().contains("only http://"));
+    assert!(parse(&["--webhook-url", "https://example.com/"]).unwrap_err().contains("only http://"));
+    assert!(parse(&["--on-change"]).is_err());
+  }
+
+  #[test]
+  fn parses_status_socket() {
+    assert_eq!(parse(&[]).unwrap().status_socket_path, None);
+    assert_eq!(parse(&["--status-socket", "/run/ups.sock"]).unwrap().status_socket_path.as_deref(), Some("/run/ups.sock"));
+  }
+
+  #[test]
+  fn parses_udp_raw() {
+    assert_eq!(parse(&["--udp-raw", "192.168.1.10:9000"]).unwrap().udp_raw_address.as_deref(), Some("192.168.1.10:9000"));
+    assert!(parse(&["--udp-raw", "192.168.1.10:9000", "--encoding", "pwm", "--duty-cycle-band", "OnMains=0-10"]).unwrap_err().starts_with("--udp-raw requires --encoding beep"));
+  }
+
+  #[test]
+  fn parses_classifier_command() {
+    assert_eq!(parse(&["--classifier-command", "./decode.py --model x"]).unwrap().classifier_command.as_deref(), Some("./decode.py --model x"));
+  }
+
+  #[test]
+  fn parses_shadow_profile() {
+    assert_eq!(parse(&["--shadow-profile", "candidate.txt"]).unwrap().shadow_profile.as_deref(), Some("candidate.txt"));
+    assert!(parse(&["--shadow-profile", "candidate.txt", "--encoding", "pwm", "--duty-cycle-band", "OnMains=0-10"]).unwrap_err().starts_with("--shadow-profile requires --encoding beep"));
+  }
+
+  #[cfg(feature = "sqlite")]
+  #[test]
+  fn parses_sqlite() {
+    assert_eq!(parse(&[]).unwrap().sqlite_path, None);
+    assert_eq!(parse(&["--sqlite", "history.sqlite"]).unwrap().sqlite_path.as_deref(), Some("history.sqlite"));
+  }
+
+  #[cfg(feature = "mqtt")]
+  #[test]
+  fn parses_mqtt() {
+    assert_eq!(parse(&[]).unwrap().mqtt, None);
+    assert_eq!(parse(&["--mqtt-host", "broker.local"]).unwrap().mqtt, Some(MqttConfig {
+      host: "broker.local".to_string(),
+      port: DEFAULT_MQTT_PORT,
+      topic: DEFAULT_MQTT_TOPIC.to_string(),
+      credentials: None,
+      retain: false,
+    }));
+    let options = parse(&["--mqtt-host", "broker.local", "--mqtt-port", "8883", "--mqtt-topic", "home/ups", "--mqtt-username", "ha", "--mqtt-password", "s3cret", "--mqtt-retain"]).unwrap();
+    assert_eq!(options.mqtt, Some(MqttConfig {
+      host: "broker.local".to_string(),
+      port: 8883,
+      topic: "home/ups".to_string(),
+      credentials: Some(("ha".to_string(), Some("s3cret".to_string()))),
+      retain: true,
+    }));
+    assert!(parse(&["--mqtt-host", "broker.local", "--mqtt-topic", "home/#"]).unwrap_err().starts_with("invalid value home/# for --mqtt-topic"));
+    assert!(parse(&["--mqtt-host", "broker.local", "--mqtt-password", "s3cret"]).unwrap_err().starts_with("--mqtt-password requires --mqtt-username"));
+    assert!(parse(&["--mqtt-retain"]).unwrap_err().contains("require --mqtt-host"));
+  }
+
+  #[cfg(feature = "amqp")]
+  #[test]
+  fn parses_amqp() {
+    assert_eq!(parse(&[]).unwrap().amqp, None);
+    assert_eq!(parse(&["--amqp-host", "rabbit.local"]).unwrap().amqp, Some(AmqpConfig {
+      host: "rabbit.local".to_string(),
+      port: DEFAULT_AMQP_PORT,
+      virtual_host: DEFAULT_AMQP_VIRTUAL_HOST.to_string(),
+      exchange: DEFAULT_AMQP_EXCHANGE.to_string(),
+      routing_key: DEFAULT_AMQP_ROUTING_KEY.to_string(),
+      credentials: None,
+    }));
+    let options = parse(&[
+      "--amqp-host", "rabbit.local", "--amqp-port", "5673", "--amqp-vhost", "home", "--amqp-exchange", "events", "--amqp-routing-key", "ups.rack",
+      "--amqp-username", "ups", "--amqp-password", "s3cret",
+    ]).unwrap();
+    assert_eq!(options.amqp, Some(AmqpConfig {
+      host: "rabbit.local".to_string(),
+      port: 5673,
+      virtual_host: "home".to_string(),
+      exchange: "events".to_string(),
+      routing_key: "ups.rack".to_string(),
+      credentials: Some(("ups".to_string(), Some("s3cret".to_string()))),
+    }));
+    let long_key = "k".repeat(256);
+    assert!(parse(&["--amqp-host", "rabbit.local", "--amqp-routing-key", &long_key]).unwrap_err().contains("at most 255 bytes"));
+    assert!(parse(&["--amqp-host", "rabbit.local", "--amqp-password", "s3cret"]).unwrap_err().starts_with("--amqp-password requires --amqp-username"));
+    assert!(parse(&["--amqp-exchange", "events"]).unwrap_err().contains("require --amqp-host"));
+  }
+
+  #[cfg(feature = "http")]
+  #[test]
+  fn parses_http_addr() {
+    assert_eq!(parse(&["--http-addr", "0.0.0.0:8080"]).unwrap().http_address.as_deref(), Some("0.0.0.0:8080"));
+  }
+
+  #[cfg(feature = "http")]
+  #[test]
+  fn parses_raw_token() {
+    assert_eq!(parse(&["--http-addr", "0.0.0.0:8080", "--raw-token", "s3cret"]).unwrap().raw_token.as_deref(), Some("s3cret"));
+    assert!(parse(&["--raw-token", "s3cret"]).unwrap_err().starts_with("--raw-token requires --http-addr"));
+  }
+
+  #[cfg(feature = "http")]
+  #[test]
+  fn parses_metrics_addr() {
+    assert_eq!(parse(&[]).unwrap().metrics_address, None);
+    assert_eq!(parse(&["--metrics-addr", "0.0.0.0:9184"]).unwrap().metrics_address.as_deref(), Some("0.0.0.0:9184"));
+  }
+
+  #[test]
+  fn rejects_missing_or_invalid_value() {
+    assert!(parse(&["--min-beep-ms"]).unwrap_err().starts_with("missing value for --min-beep-ms"));
+    assert!(parse(&["--min-beep-ms", "abc"]).unwrap_err().starts_with("invalid value abc for --min-beep-ms"));
+  }
+
+  #[test]
+  fn rejects_unknown_flag() {
+    assert!(parse(&["--mqtt-url"]).unwrap_err().starts_with("unknown flag --mqtt-url"));
+  }
+}
@@ -0,0 +1,173 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// Source of the current instant for anything that measures time on its own, rather than being handed the instant of an event,
+// every duration is measured between instants, the wall clock time is only ever for showing when something happened
+// as it can be stepped back and forth by NTP or by hand
+pub trait Clock {
+  fn now(&self) -> Instant;
+  fn wall_time(&self) -> SystemTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> Instant {
+    Instant::now()
+  }
+
+  fn wall_time(&self) -> SystemTime {
+    SystemTime::now()
+  }
+}
+
+// Moves to the instant of every event it is handed instead, for a replay whose instants keep the recorded spacing whatever its speed,
+// so that whatever gets timed on its own lasts as long as in the capture, the wall clock time moves along by as much,
+// clones share the same instant so the detection loop can keep one while the reporter owns another
+#[derive(Clone)]
+pub struct EventClock {
+  started_at: Instant,
+  started_wall_time: SystemTime,
+  now: Rc<Cell<Instant>>,
+}
+
+impl EventClock {
+  pub fn new() -> EventClock {
+    let started_at = Instant::now();
+    EventClock { started_at, started_wall_time: SystemTime::now(), now: Rc::new(Cell::new(started_at)) }
+  }
+
+  // Never back, the instants of a replay only ever go forward but the noise injected into one can bring them out of order
+  pub fn set(&self, at: Instant) {
+    self.now.set(self.now.get().max(at));
+  }
+}
+
+impl Clock for EventClock {
+  fn now(&self) -> Instant {
+    self.now.get()
+  }
+
+  fn wall_time(&self) -> SystemTime {
+    self.started_wall_time + self.now.get().duration_since(self.started_at)
+  }
+}
+
+// As in 2026-03-14T15:09:26.535Z, UTC so lines from hosts in different time zones sort together,
+// times before 1970 only come from a badly set clock and show as its start
+pub fn get_utc_timestamp(time: SystemTime) -> String {
+  let millis = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+  let (days, millis_of_day) = (millis / 86_400_000, millis % 86_400_000);
+  // Days since 1970 to a date of the proleptic Gregorian calendar, counted in 400 year eras starting on March 1st
+  // so the leap day falls last in every year
+  let days = days + 719_468;
+  let era = days / 146_097;
+  let day_of_era = days % 146_097;
+  let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let month_from_march = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+  let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+  let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+  format!(
+    "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+    year,
+    month,
+    day,
+    millis_of_day / 3_600_000,
+    millis_of_day / 60_000 % 60,
+    millis_of_day / 1000 % 60,
+    millis_of_day % 1000,
+  )
+}
+
+// Only moves when advanced, clones share the same instant so a test can keep one while the code under test owns another,
+// the wall clock time moves along with it unless set on its own
+#[cfg(test)]
+#[derive(Clone)]
+pub struct MockClock {
+  now: std::rc::Rc<std::cell::Cell<Instant>>,
+  wall_time: std::rc::Rc<std::cell::Cell<SystemTime>>,
+}
+
+#[cfg(test)]
+impl MockClock {
+  pub fn new() -> MockClock {
+    MockClock {
+      now: std::rc::Rc::new(std::cell::Cell::new(Instant::now())),
+      wall_time: std::rc::Rc::new(std::cell::Cell::new(SystemTime::now())),
+    }
+  }
+
+  pub fn advance(&self, duration: std::time::Duration) {
+    self.now.set(self.now.get() + duration);
+    self.wall_time.set(self.wall_time.get() + duration);
+  }
+
+  // As a step of the system clock would, without the instant moving at all
+  pub fn set_wall_time(&self, wall_time: SystemTime) {
+    self.wall_time.set(wall_time);
+  }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+  fn now(&self) -> Instant {
+    self.now.get()
+  }
+
+  fn wall_time(&self) -> SystemTime {
+    self.wall_time.get()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  #[test]
+  fn mock_clock_moves_only_when_advanced() {
+    let clock = MockClock::new();
+    let shared_clock = clock.clone();
+    let start = clock.now();
+    assert_eq!(shared_clock.now(), start);
+
+    clock.advance(Duration::from_secs(3));
+    assert_eq!(shared_clock.now(), start + Duration::from_secs(3));
+  }
+
+  #[test]
+  fn event_clock_follows_the_events() {
+    let clock = EventClock::new();
+    let shared_clock = clock.clone();
+    let (start, start_wall_time) = (clock.now(), clock.wall_time());
+    shared_clock.set(start + Duration::from_secs(60));
+    assert_eq!(clock.now(), start + Duration::from_secs(60));
+    assert_eq!(clock.wall_time(), start_wall_time + Duration::from_secs(60));
+
+    shared_clock.set(start + Duration::from_secs(59));
+    assert_eq!(clock.now(), start + Duration::from_secs(60));
+  }
+
+  #[test]
+  fn formats_utc_timestamps() {
+    assert_eq!(get_utc_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    assert_eq!(get_utc_timestamp(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)), "2023-11-14T22:13:20.123Z");
+    assert_eq!(get_utc_timestamp(UNIX_EPOCH + Duration::from_secs(951_782_400)), "2000-02-29T00:00:00.000Z");
+    assert_eq!(get_utc_timestamp(UNIX_EPOCH + Duration::from_secs(951_868_799)), "2000-02-29T23:59:59.000Z");
+    assert_eq!(get_utc_timestamp(UNIX_EPOCH - Duration::from_secs(1)), "1970-01-01T00:00:00.000Z");
+  }
+
+  #[test]
+  fn wall_time_steps_leave_the_instant_alone() {
+    let clock = MockClock::new();
+    let (start, start_wall_time) = (clock.now(), clock.wall_time());
+    clock.advance(Duration::from_secs(3));
+    assert_eq!(clock.wall_time(), start_wall_time + Duration::from_secs(3));
+
+    clock.set_wall_time(start_wall_time - Duration::from_secs(3600));
+    assert_eq!(clock.now(), start + Duration::from_secs(3));
+  }
+}
@@ -0,0 +1,59 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+// What can go wrong loading a configuration file, profiles, the model database and --config tables so far,
+// every variant names the file it is about so main can print it as it is and exit
+#[derive(Debug)]
+pub enum ConfigError {
+  // The file isn't there or can't be read
+  MissingFile { source: String, error: io::Error },
+  // A line that doesn't parse, counted from 1 within the file
+  Parse { source: String, line: usize, text: String },
+  // Every line parses but the file as a whole makes no sense
+  Invalid { source: String, reason: String },
+}
+
+impl ConfigError {
+  // The same error, for when the contents were taken out of a larger file starting after the given number of lines
+  pub fn within(self, source: &str, line_offset: usize) -> ConfigError {
+    match self {
+      ConfigError::Parse { line, text, .. } => ConfigError::Parse { source: source.to_string(), line: line + line_offset, text },
+      ConfigError::Invalid { reason, .. } => ConfigError::Invalid { source: source.to_string(), reason },
+      error => error,
+    }
+  }
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ConfigError::MissingFile { source, error } => write!(formatter, "could not read {}: {}", source, error),
+      ConfigError::Parse { source, line, text } => write!(formatter, "invalid line {} in {}: {}", line, source, text),
+      ConfigError::Invalid { source, reason } => write!(formatter, "{} {}", source, reason),
+    }
+  }
+}
+
+impl Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn describes_where_it_went_wrong() {
+    let missing_file = ConfigError::MissingFile { source: "profile ups.txt".to_string(), error: io::Error::from(io::ErrorKind::NotFound) };
+    assert_eq!(missing_file.to_string(), "could not read profile ups.txt: entity not found");
+    let parse = ConfigError::Parse { source: "profile ups.txt".to_string(), line: 3, text: "polarity sideways".to_string() };
+    assert_eq!(parse.to_string(), "invalid line 3 in profile ups.txt: polarity sideways");
+    let invalid = ConfigError::Invalid { source: "profile ups.txt".to_string(), reason: "has no statuses".to_string() };
+    assert_eq!(invalid.to_string(), "profile ups.txt has no statuses");
+  }
+
+  #[test]
+  fn moves_lines_into_the_enclosing_file() {
+    let parse = ConfigError::Parse { source: "profile Acme".to_string(), line: 2, text: "silence Mains".to_string() };
+    assert_eq!(parse.within("model Acme at models.txt", 10).to_string(), "invalid line 12 in model Acme at models.txt: silence Mains");
+  }
+}
@@ -0,0 +1,88 @@
+use std::io;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::logging::warn;
+use crate::state::{SharedState, Transition};
+
+// Retrying starts at the first and doubles up to the last for as long as the broker stays unreachable
+const MIN_RETRY_DURATION: Duration = Duration::from_secs(1);
+const MAX_RETRY_DURATION: Duration = Duration::from_secs(60);
+
+// A message broker transitions are published to, what it takes to speak its protocol and nothing of getting the transitions there
+pub trait Broker: Send + 'static {
+  type Connection;
+
+  // As in "the MQTT broker at <host>:<port>", for the warnings
+  fn describe(&self) -> String;
+
+  fn connect(&self) -> io::Result<Self::Connection>;
+
+  // Publishes the current status, if there is one, and then every transition, returning once the transitions are closed
+  // after publishing what was sent before and disconnecting, or else with what broke the connection
+  fn publish_transitions(&self, connection: &mut Self::Connection, current: Option<Transition>, transitions: &Receiver<Transition>) -> io::Result<()>;
+}
+
+// Publishes every transition to the broker at most once as nothing is gained from a status delivered late, the current status
+// is published again on every connection so none is missed while the broker was unreachable, which never stops detection,
+// only warns and retries with a growing pause in between, the returned thread publishes what was still waiting, disconnects
+// and ends once the subscriptions are closed
+pub fn start_publisher<B: Broker>(broker: B, state: SharedState) -> JoinHandle<()> {
+  let transitions = state.lock().unwrap().subscribe();
+  thread::spawn(move || {
+    let mut retry_duration = MIN_RETRY_DURATION;
+    loop {
+      // Whatever piled up while unreachable is old news, the current status says where it ended up
+      while transitions.try_recv().is_ok() {}
+      let current = state.lock().unwrap().current;
+      let error = match broker.connect() {
+        Ok(mut connection) => {
+          retry_duration = MIN_RETRY_DURATION;
+          match broker.publish_transitions(&mut connection, current, &transitions) {
+            Ok(()) => return,
+            Err(error) => error,
+          }
+        },
+        Err(error) => error,
+      };
+      warn!("Could not publish to {}: {}, retrying in {}s", broker.describe(), error, retry_duration.as_secs());
+      if !wait_to_retry(&transitions, retry_duration) {
+        return;
+      }
+      retry_duration = (retry_duration * 2).min(MAX_RETRY_DURATION);
+    }
+  })
+}
+
+// Waits on the transitions instead of sleeping so that stopping is never held up by the pause, false once they are closed,
+// the ones that come in meanwhile are old news by the time of connecting again
+fn wait_to_retry(transitions: &Receiver<Transition>, retry_duration: Duration) -> bool {
+  let deadline = Instant::now() + retry_duration;
+  loop {
+    match transitions.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+      Ok(_) => {},
+      Err(RecvTimeoutError::Timeout) => return true,
+      Err(RecvTimeoutError::Disconnected) => return false,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::{Arc, Mutex};
+
+  use crate::state::StatusState;
+
+  #[test]
+  fn stops_waiting_to_retry_once_closed() {
+    let state = Arc::new(Mutex::new(StatusState::new(10)));
+    let transitions = state.lock().unwrap().subscribe();
+    assert!(wait_to_retry(&transitions, Duration::from_millis(10)));
+    state.lock().unwrap().close_subscriptions();
+    let started_at = Instant::now();
+    assert!(!wait_to_retry(&transitions, MAX_RETRY_DURATION));
+    assert!(started_at.elapsed() < MAX_RETRY_DURATION);
+  }
+}
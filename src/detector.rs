@@ -0,0 +1,820 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use crate::classifier::Classifier;
+use crate::group::{BeepGroup, GroupMatch, get_group_match};
+use crate::history::push_capped;
+use crate::logging::{debug, info, warn};
+use crate::status::{CONTINUOUS_BEEP_DURATION, Classification, ERROR_MARGIN, MIN_ERROR_DURATION, Status, Tolerance, ZERO_DURATION, get_closeness, get_error_range};
+
+pub const MAX_ENTRIES: usize = 10;
+
+pub const BEEP_BOUNCE_MAX_DURATION: Duration = Duration::from_millis(50);
+pub const INTER_BEEP_BOUNCE_MAX_DURATION: Duration = Duration::from_millis(300);
+
+// Twice the longest gap any status pattern expects (the 60s of OnBattery), silence this long can only mean the UPS is on mains,
+// so the history gathered before it says nothing about the next pattern and is cleared,
+// unless the last pattern was a battery one, in which case the silence means the power backup has died instead
+const HISTORY_RESET_DURATION: Duration = Duration::from_secs(120);
+
+pub const DEFAULT_ON_MAINS_GRACE_DURATION: Duration = Duration::from_secs(5);
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Edge {
+  BeepStart,
+  BeepEnd,
+}
+
+impl Edge {
+  // For lines that are low while beeping
+  pub fn inverted(self) -> Edge {
+    match self {
+      Edge::BeepStart => Edge::BeepEnd,
+      Edge::BeepEnd => Edge::BeepStart,
+    }
+  }
+}
+
+// Turns edges and timeouts into classifications, the beep detector below being the default way of reading the line
+pub trait Decoder {
+  fn on_edge(&mut self, edge: Edge, now: Instant) -> Option<Classification>;
+  fn on_timeout(&mut self, now: Instant) -> Option<Classification>;
+  // Forgets everything measured so far, for when the time since the last edge can no longer be trusted
+  fn reset(&mut self);
+}
+
+// What the gaps of the table are matched against, the periods between beep starts stay steadier than the gaps
+// on UPSes whose beeps vary in length, as a longer beep then shortens the gap after it by as much
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum GapBasis {
+  // From the end of a beep to the start of the next one
+  Gap,
+  // From the start of a beep to the start of the next one
+  Period,
+}
+
+const GAP_BASIS_NAMES: [(GapBasis, &str); 2] = [
+  (GapBasis::Gap, "gap"),
+  (GapBasis::Period, "period"),
+];
+
+impl GapBasis {
+  pub fn from_name(name: &str) -> Option<GapBasis> {
+    GAP_BASIS_NAMES.iter()
+      .find(|gap_basis_name| gap_basis_name.1 == name)
+      .map(|gap_basis_name| gap_basis_name.0)
+  }
+}
+
+// What to make of a beep end coming before any beep start, as it does when starting in the middle of a beep,
+// the start of that beep was never seen so the beep itself is never measured either way
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum FirstEdgePolicy {
+  // The gap after the partial beep is measured from its end, which is a real one, so the first full beep already completes a pair
+  Measure,
+  // Nothing is measured until a whole beep has been seen, for lines that settle with a spurious edge at startup
+  Skip,
+}
+
+const FIRST_EDGE_POLICY_NAMES: [(FirstEdgePolicy, &str); 2] = [
+  (FirstEdgePolicy::Measure, "measure"),
+  (FirstEdgePolicy::Skip, "skip"),
+];
+
+impl FirstEdgePolicy {
+  pub fn from_name(name: &str) -> Option<FirstEdgePolicy> {
+    FIRST_EDGE_POLICY_NAMES.iter()
+      .find(|first_edge_policy_name| first_edge_policy_name.1 == name)
+      .map(|first_edge_policy_name| first_edge_policy_name.0)
+  }
+}
+
+pub struct DetectorConfig {
+  // Pulses longer than the bounce duration but shorter than this are discarded entirely as noise, as if they never happened
+  pub min_beep_duration: Duration,
+  // How long the silence after the last beep has to last at the least before OnMains gets inferred,
+  // so the few beeps some UPSes still make while stabilizing after the mains returns don't flap the status back and forth,
+  // after a pattern the silence also has to outlast the gap of that pattern with its tolerance as it is only the wait for its next beep until then
+  pub on_mains_grace_duration: Duration,
+  // How long the silence right after a battery pattern keeps that status instead of inferring OnMains,
+  // as the beeping stopping by pressing mute looks no different from the mains coming back, zero to infer it right away
+  pub mute_hold_duration: Duration,
+  // Longest beep or inter beep duration that is believable, longer ones get cut down to it before being stored or classified
+  // so a stalled line doesn't skew everything downstream with one huge value, None to keep them as measured
+  pub max_measured_duration: Option<Duration>,
+  // With periods the inter beep duration classified and reported is the period, the synthetic pairs of timeouts stay as they are
+  pub gap_basis: GapBasis,
+  pub first_edge_policy: FirstEdgePolicy,
+  // Checked after every beep ahead of the table, the first beeps of a group are held back rather than reported as Unknown
+  pub beep_groups: Vec<BeepGroup>,
+  // Length of the lone chirp a line-interactive UPS makes while correcting the mains voltage, with its own tolerance when it has one,
+  // such a chirp making no pattern with the beep before it is reported as VoltageRegulating once the silence after it outlasts the grace period
+  pub avr_chirp: Option<(Duration, Option<Tolerance>)>,
+  // The margin and floor of the table, for the durations of groups and of the chirp without their own
+  pub table_tolerance: Tolerance,
+}
+
+impl Default for DetectorConfig {
+  fn default() -> DetectorConfig {
+    DetectorConfig {
+      min_beep_duration: Duration::ZERO,
+      on_mains_grace_duration: DEFAULT_ON_MAINS_GRACE_DURATION,
+      mute_hold_duration: Duration::ZERO,
+      max_measured_duration: None,
+      gap_basis: GapBasis::Gap,
+      first_edge_policy: FirstEdgePolicy::Measure,
+      beep_groups: vec![],
+      avr_chirp: None,
+      table_tolerance: Tolerance { error_margin: ERROR_MARGIN, min_error_duration: MIN_ERROR_DURATION },
+    }
+  }
+}
+
+// Keeps the beep history and the in-progress timing state, the history is cleared either explicitly through reset()
+// or automatically once the line has been silent for HISTORY_RESET_DURATION
+pub struct Detector {
+  config: DetectorConfig,
+  classifier: Box<dyn Classifier>,
+
+  // Oldest first, capped at MAX_ENTRIES by dropping the oldest
+  beep_durations: VecDeque<Duration>,
+  inter_beep_durations: VecDeque<Duration>,
+
+  current_beep_start_time: Option<Instant>,
+  last_beep_end_time: Option<Instant>,
+  // The beep end the last inter beep duration was measured from, so that the gap can be resumed if the beep after it turns out to be noise
+  inter_beep_start_time: Option<Instant>,
+
+  // The status of the last detected beep pattern, as opposed to the ones inferred on timeouts
+  last_pattern_status: Option<Status>,
+  // The gap measured before the beep that completed the last pattern, the silence after it is expected to last about as long
+  // before the pattern beeps again, None after an Unknown one as nothing is expected of its gaps
+  last_pattern_gap: Option<Duration>,
+  // Whether the current silence is being held as a possibly muted alarm, only so that gets logged once
+  is_holding_for_mute: bool,
+  // Whether any edge has been seen since starting, a reset doesn't count as starting as the level is known by then
+  has_seen_edge: bool,
+}
+
+impl Detector {
+  // Classifies with the built-in table and its default matching
+  #[cfg(test)]
+  pub fn new(config: DetectorConfig) -> Detector {
+    Detector::with_classifier(config, Box::new(crate::classifier::BuiltinClassifier::default()))
+  }
+
+  pub fn with_classifier(config: DetectorConfig, classifier: Box<dyn Classifier>) -> Detector {
+    Detector {
+      config,
+      classifier,
+      beep_durations: VecDeque::with_capacity(MAX_ENTRIES + 1),
+      inter_beep_durations: VecDeque::with_capacity(MAX_ENTRIES + 1),
+      current_beep_start_time: None,
+      last_beep_end_time: None,
+      inter_beep_start_time: None,
+      last_pattern_status: None,
+      last_pattern_gap: None,
+      is_holding_for_mute: false,
+      has_seen_edge: false,
+    }
+  }
+
+  // Clears all accumulated durations and the in-progress timing state, so the next classification is based only on what comes after
+  pub fn reset(&mut self) {
+    self.beep_durations.clear();
+    self.inter_beep_durations.clear();
+    self.current_beep_start_time = None;
+    self.last_beep_end_time = None;
+    self.inter_beep_start_time = None;
+    self.last_pattern_status = None;
+    self.last_pattern_gap = None;
+    self.is_holding_for_mute = false;
+  }
+
+  // Returns the possible power state whenever a beep completes a beep and inter beep duration pair
+  pub fn on_edge(&mut self, edge: Edge, now: Instant) -> Option<Classification> {
+    let mut classification = None;
+
+    // A first beep start needs nothing special, it starts a beep like any other and there is no gap before it to measure
+    if !mem::replace(&mut self.has_seen_edge, true) && edge == Edge::BeepEnd && self.config.first_edge_policy == FirstEdgePolicy::Skip {
+      debug!("Skipping the end of a beep already going on at startup");
+      return None;
+    }
+
+    if edge == Edge::BeepEnd {
+      // Don't update last_beep_end_time if it was already set previously so that on detecting another subsequent beep end without detecting a beep start first,
+      // the original beep end still gets considered as the beep end
+      if self.last_beep_end_time.is_none() {
+        self.last_beep_end_time = Some(now);
+      }
+
+      // Detect beep end only if we had previously detected a beep start,
+      // because we need to calculate the duration of the beep as the time difference between now (beep end) and current_beep_start_time
+      if let Some(beep_start_time) = self.current_beep_start_time {
+        let beep_duration = now.duration_since(beep_start_time);
+        // If the beep end happened too quickly since the beep start then just ignore the last beep start
+        if beep_duration > BEEP_BOUNCE_MAX_DURATION && beep_duration < self.config.min_beep_duration {
+          // Too long to be bounce but too short to be a real beep, so drop the whole pulse and resume the gap from the beep end before it
+          debug!("Ignoring {}ms pulse as noise", beep_duration.as_millis());
+          if let Some(inter_beep_start_time) = self.inter_beep_start_time.take() {
+            self.inter_beep_durations.pop_back();
+            self.last_beep_end_time = Some(inter_beep_start_time);
+          } else {
+            self.last_beep_end_time = None;
+          }
+        } else if beep_duration > BEEP_BOUNCE_MAX_DURATION {
+          self.inter_beep_start_time = None;
+          let beep_duration = self.get_clamped_duration("beep", beep_duration);
+          push_capped(&mut self.beep_durations, beep_duration, MAX_ENTRIES);
+
+          // After every detected beep, check for patterns and report the possible power state
+          if let (Some(beep_duration), Some(inter_beep_duration)) = (self.beep_durations.back(), self.inter_beep_durations.back()) {
+            let inter_beep_duration = match self.config.gap_basis {
+              GapBasis::Gap => *inter_beep_duration,
+              // Falls back to the gap when the beep before it is no longer in the history
+              GapBasis::Period => self.beep_durations.iter().rev().nth(1).map_or(*inter_beep_duration, |previous_beep_duration| *previous_beep_duration + *inter_beep_duration),
+            };
+            let pattern_classification = self.classifier.classify(*beep_duration, inter_beep_duration);
+            let pattern_classification = match get_group_match(&self.config.beep_groups, self.config.table_tolerance, &self.beep_durations, &self.inter_beep_durations) {
+              Some(GroupMatch::Complete(group_classification)) => Some(group_classification),
+              Some(GroupMatch::Started) if pattern_classification.status == Status::Unknown => None,
+              _ => Some(pattern_classification),
+            };
+            if let Some(pattern_classification) = pattern_classification {
+              self.last_pattern_status = Some(pattern_classification.status);
+              self.last_pattern_gap = self.inter_beep_durations.back().copied().filter(|_| pattern_classification.status != Status::Unknown);
+              classification = Some(pattern_classification);
+            }
+          }
+
+        } else if !self.inter_beep_durations.is_empty() {
+          self.inter_beep_durations.pop_back();
+        }
+
+        // Reset the current_beep_start_time variable to prevent detecting another subsequent beep end without detecting a beep start first,
+        self.current_beep_start_time = None;
+      }
+    } else {
+      self.is_holding_for_mute = false;
+
+      // Don't update current_beep_start_time if it was already set previously so that on detecting another subsequent beep start without detecting a beep end first,
+      // the original beep start still gets considered as the beep start
+      if self.current_beep_start_time.is_none() {
+        self.current_beep_start_time = Some(now);
+      }
+
+      // Detect beep start only if we had previously detected a beep end,
+      // because we need to calculate the duration between this and the last beep as the time difference between now (beep start) and  last_beep_end_time
+      if let Some(beep_end_time) = self.last_beep_end_time {
+        let inter_beep_duration = now.duration_since(beep_end_time);
+        // If the beep start happened too quickly since the beep end then just ignore the last beep end
+        if inter_beep_duration > INTER_BEEP_BOUNCE_MAX_DURATION {
+          let inter_beep_duration = self.get_clamped_duration("inter beep", inter_beep_duration);
+          push_capped(&mut self.inter_beep_durations, inter_beep_duration, MAX_ENTRIES);
+          self.inter_beep_start_time = Some(beep_end_time);
+        } else {
+          self.inter_beep_start_time = None;
+          if !self.beep_durations.is_empty() {
+            self.beep_durations.pop_back();
+          }
+        }
+
+        // Reset the last_beep_end_time variable to prevent detecting another subsequent beep start without detecting a beep end first,
+        self.last_beep_end_time = None;
+      }
+    }
+
+    classification
+  }
+
+  // The last beep counts as a chirp when it is the length of one and no pattern was matched with it, a beep heard before it long enough ago
+  // matches nothing, it lasts until the history gets cleared, the silence after it meaning the mains again from then on
+  fn get_chirp_classification(&self, silence_duration: Duration) -> Option<Classification> {
+    let (chirp_duration, chirp_tolerance) = self.config.avr_chirp?;
+    if self.last_pattern_status.is_some_and(|status| status != Status::Unknown) {
+      return None;
+    }
+    let chirp_tolerance = chirp_tolerance.unwrap_or(self.config.table_tolerance);
+    let beep_duration = *self.beep_durations.back()?;
+    let confidence = get_closeness(beep_duration, chirp_duration, chirp_tolerance.error_margin, chirp_tolerance.min_error_duration)?;
+    Some(Classification { status: Status::VoltageRegulating, confidence, beep_duration, inter_beep_duration: silence_duration })
+  }
+
+  // How long the silence has to last before it means anything, the grace period or else the gap of the last pattern with the tolerance of the table,
+  // whichever is longer, a silence any shorter is still just the wait for the next beep of that pattern, the same goes for a lone beep
+  // that could be the first of any pattern, so it waits out the longest gap of them all unless it is a chirp, which is never part of one
+  fn get_silence_grace_duration(&self) -> Duration {
+    let expected_gap = match (self.last_pattern_status, self.last_pattern_gap) {
+      (None, _) if self.get_chirp_classification(ZERO_DURATION).is_none() => self.classifier.get_longest_gap(),
+      (_, Some(last_pattern_gap)) => {
+        let error_range = get_error_range(last_pattern_gap, self.config.table_tolerance.error_margin, self.config.table_tolerance.min_error_duration);
+        Some(last_pattern_gap + Duration::from_nanos(error_range as u64))
+      },
+      _ => None,
+    };
+    expected_gap.map_or(self.config.on_mains_grace_duration, |expected_gap| expected_gap.max(self.config.on_mains_grace_duration))
+  }
+
+  // Flagged as it gets clamped, so the stall behind it still shows up in the diagnostics
+  fn get_clamped_duration(&self, kind: &str, duration: Duration) -> Duration {
+    match self.config.max_measured_duration {
+      Some(max_measured_duration) if duration > max_measured_duration => {
+        warn!("Clamping {}ms {} to {}ms, the line may have stalled", duration.as_millis(), kind, max_measured_duration.as_millis());
+        max_measured_duration
+      },
+      _ => duration,
+    }
+  }
+
+  // Returns the possible power state when a timeout happens waiting for an edge
+  pub fn on_timeout(&mut self, now: Instant) -> Option<Classification> {
+    if let (Some(beep_start_time), None) = (self.current_beep_start_time, self.last_beep_end_time) {
+      // Timeout happened during a beep, once it has gone on long enough to be continuous it means as much on its own as after any history,
+      // which is also what lets profiles of UPSes beeping continuously detect that right after starting
+      if now.duration_since(beep_start_time) < CONTINUOUS_BEEP_DURATION {
+        return None;
+      }
+      return Some(self.classifier.classify(CONTINUOUS_BEEP_DURATION, ZERO_DURATION));
+    }
+    // Silence only means something once at least one beep has been heard
+    if self.beep_durations.is_empty() {
+      return None;
+    }
+
+    if let (None, Some(beep_end_time)) = (self.current_beep_start_time, self.last_beep_end_time) {
+      // Timeout did not happen during a beep, once the silence has lasted long enough the history gets cleared,
+      // the status reported now is then the last report until a fresh beep pattern gets detected
+      let silence_duration = now.duration_since(beep_end_time);
+      if silence_duration < self.get_silence_grace_duration() {
+        return None;
+      }
+      // Reporting nothing keeps the battery status, a real mains pattern ends the hold as soon as it gets detected
+      if silence_duration < self.config.mute_hold_duration && let Some(last_pattern_status) = self.last_pattern_status.filter(|status| status.is_on_battery()) {
+        if !self.is_holding_for_mute {
+          info!("Beeping stopped during {:?}, holding it for up to {}s in case the alarm was muted", last_pattern_status, self.config.mute_hold_duration.as_secs());
+          self.is_holding_for_mute = true;
+        }
+        return None;
+      }
+      if silence_duration >= HISTORY_RESET_DURATION {
+        let was_on_battery = self.last_pattern_status.is_some_and(Status::is_on_battery);
+        self.reset();
+        if was_on_battery {
+          return Some(Classification { status: Status::PowerOff, confidence: 1.0, beep_duration: ZERO_DURATION, inter_beep_duration: silence_duration });
+        }
+      } else if let Some(chirp_classification) = self.get_chirp_classification(silence_duration) {
+        return Some(chirp_classification);
+      }
+      Some(self.classifier.classify_silence(silence_duration))
+    } else {
+      // Neither a beep going on nor one that ended, which is where a pulse dropped as noise with no gap to resume leaves things,
+      // there is no reference to measure any silence from until the next edge
+      None
+    }
+  }
+}
+
+impl Decoder for Detector {
+  fn on_edge(&mut self, edge: Edge, now: Instant) -> Option<Classification> {
+    Detector::on_edge(self, edge, now)
+  }
+
+  fn on_timeout(&mut self, now: Instant) -> Option<Classification> {
+    Detector::on_timeout(self, now)
+  }
+
+  fn reset(&mut self) {
+    Detector::reset(self)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::clock::{Clock, MockClock};
+  use crate::classifier::BuiltinClassifier;
+  use crate::status::{MatchConfig, TIMEOUT_DURATION};
+
+  fn feed_beep(detector: &mut Detector, start: Instant, beep: Duration) -> Option<Status> {
+    detector.on_edge(Edge::BeepStart, start);
+    detector.on_edge(Edge::BeepEnd, start + beep).map(|classification| classification.status)
+  }
+
+  fn timeout_status(detector: &mut Detector, at: Instant) -> Option<Status> {
+    detector.on_timeout(at).map(|classification| classification.status)
+  }
+
+  // Feeds two beeps separated by inter_beep and returns the status reported for the second beep, along with when it ended
+  fn feed_pattern(detector: &mut Detector, start: Instant, beep: Duration, inter_beep: Duration) -> (Option<Status>, Instant) {
+    feed_beep(detector, start, beep);
+    let second_beep_start = start + beep + inter_beep;
+    (feed_beep(detector, second_beep_start, beep), second_beep_start + beep)
+  }
+
+  #[test]
+  fn detects_pattern_from_edges() {
+    let mut detector = Detector::new(DetectorConfig::default());
+    let (status, _) = feed_pattern(&mut detector, Instant::now(), Duration::from_millis(250), Duration::from_secs(1));
+    assert_eq!(status, Some(Status::LowOnBattery));
+  }
+
+  #[test]
+  fn starting_mid_beep_measures_from_its_end() {
+    let beep = Duration::from_millis(250);
+    let start = Instant::now();
+    let mut detector = Detector::new(DetectorConfig::default());
+    assert!(detector.on_edge(Edge::BeepEnd, start).is_none());
+    assert!(detector.beep_durations.is_empty());
+    // Silence after a beep that was never measured says nothing yet
+    assert_eq!(timeout_status(&mut detector, start + TIMEOUT_DURATION), None);
+    assert_eq!(feed_beep(&mut detector, start + Duration::from_secs(1), beep), Some(Status::LowOnBattery));
+    assert_eq!(detector.inter_beep_durations, vec![Duration::from_secs(1)]);
+
+    let mut skipping_detector = Detector::new(DetectorConfig { first_edge_policy: FirstEdgePolicy::Skip, ..DetectorConfig::default() });
+    assert!(skipping_detector.on_edge(Edge::BeepEnd, start).is_none());
+    assert_eq!(feed_beep(&mut skipping_detector, start + Duration::from_secs(1), beep), None);
+    assert!(skipping_detector.inter_beep_durations.is_empty());
+    assert_eq!(feed_beep(&mut skipping_detector, start + Duration::from_millis(2250), beep), Some(Status::LowOnBattery));
+
+    // Only the very first edge is skipped, later stray beep ends are what they always were
+    assert!(skipping_detector.on_edge(Edge::BeepEnd, start + Duration::from_secs(3)).is_none());
+    assert_eq!(feed_beep(&mut skipping_detector, start + Duration::from_millis(3500), beep), Some(Status::LowOnBattery));
+  }
+
+  #[test]
+  fn starting_mid_silence_waits_for_a_second_beep() {
+    let beep = Duration::from_millis(250);
+    let start = Instant::now();
+    for first_edge_policy in [FirstEdgePolicy::Measure, FirstEdgePolicy::Skip] {
+      let mut detector = Detector::new(DetectorConfig { first_edge_policy, ..DetectorConfig::default() });
+      assert_eq!(timeout_status(&mut detector, start + TIMEOUT_DURATION), None);
+      assert_eq!(feed_beep(&mut detector, start + Duration::from_secs(4), beep), None);
+      assert_eq!((detector.beep_durations.clone(), detector.inter_beep_durations.clone()), (VecDeque::from([beep]), VecDeque::new()));
+      assert_eq!(feed_beep(&mut detector, start + Duration::from_millis(5250), beep), Some(Status::LowOnBattery));
+    }
+  }
+
+  #[test]
+  fn names_first_edge_policies() {
+    assert_eq!(FirstEdgePolicy::from_name("skip"), Some(FirstEdgePolicy::Skip));
+    assert_eq!(FirstEdgePolicy::from_name("drop"), None);
+  }
+
+  #[test]
+  fn period_basis_matches_beep_starts_rather_than_gaps() {
+    let beep = Duration::from_millis(250);
+    let mut gap_detector = Detector::new(DetectorConfig::default());
+    let mut period_detector = Detector::new(DetectorConfig { gap_basis: GapBasis::Period, ..DetectorConfig::default() });
+
+    // A beep every second, the gap after each is then shorter than the 1s of LowOnBattery by the beep
+    let start = Instant::now();
+    assert_eq!(feed_pattern(&mut gap_detector, start, beep, Duration::from_millis(750)).0, Some(Status::Unknown));
+    assert_eq!(feed_pattern(&mut period_detector, start, beep, Duration::from_millis(750)).0, Some(Status::LowOnBattery));
+
+    // A 1s gap after every beep, which the period is then longer than
+    let start = start + Duration::from_secs(200);
+    gap_detector.reset();
+    period_detector.reset();
+    assert_eq!(feed_pattern(&mut gap_detector, start, beep, Duration::from_secs(1)).0, Some(Status::LowOnBattery));
+    assert_eq!(feed_pattern(&mut period_detector, start, beep, Duration::from_secs(1)).0, Some(Status::Unknown));
+  }
+
+  #[test]
+  fn clamps_durations_past_the_ceiling() {
+    let max_measured_duration = Duration::from_secs(60);
+    let mut detector = Detector::new(DetectorConfig { max_measured_duration: Some(max_measured_duration), ..DetectorConfig::default() });
+    let start = Instant::now();
+    feed_beep(&mut detector, start, Duration::from_millis(250));
+    let second_beep_start = start + Duration::from_secs(600);
+    detector.on_edge(Edge::BeepStart, second_beep_start);
+    let classification = detector.on_edge(Edge::BeepEnd, second_beep_start + Duration::from_millis(250)).unwrap();
+    assert_eq!(classification.inter_beep_duration, max_measured_duration);
+    assert_eq!(detector.inter_beep_durations, vec![max_measured_duration]);
+    assert_eq!(detector.beep_durations, vec![Duration::from_millis(250); 2]);
+  }
+
+  #[test]
+  fn reset_clears_history_and_timing_state() {
+    let mut detector = Detector::new(DetectorConfig::default());
+    let (_, end) = feed_pattern(&mut detector, Instant::now(), Duration::from_millis(250), Duration::from_secs(1));
+    detector.on_edge(Edge::BeepStart, end + Duration::from_secs(1));
+
+    detector.reset();
+
+    assert!(detector.beep_durations.is_empty());
+    assert!(detector.inter_beep_durations.is_empty());
+    assert_eq!(detector.current_beep_start_time, None);
+    assert_eq!(detector.last_beep_end_time, None);
+    assert_eq!(timeout_status(&mut detector, end + Duration::from_secs(5)), None);
+  }
+
+  #[test]
+  fn reset_yields_same_behavior_as_new_detector() {
+    let mut detector = Detector::new(DetectorConfig::default());
+    let start = Instant::now();
+    let (_, end) = feed_pattern(&mut detector, start, Duration::from_millis(250), Duration::from_secs(1));
+    detector.reset();
+
+    // A single beep after the reset must not be paired with the gap measured before it
+    assert_eq!(feed_beep(&mut detector, end + Duration::from_secs(10), Duration::from_millis(250)), None);
+
+    let mut fresh_detector = Detector::new(DetectorConfig::default());
+    assert_eq!(feed_beep(&mut fresh_detector, end + Duration::from_secs(10), Duration::from_millis(250)), None);
+  }
+
+  #[test]
+  fn long_silence_reports_on_mains_and_clears_history() {
+    let mut detector = Detector::new(DetectorConfig::default());
+    let (_, end) = feed_pattern(&mut detector, Instant::now(), Duration::from_secs(2), Duration::from_secs(2));
+
+    assert_eq!(timeout_status(&mut detector, end + DEFAULT_ON_MAINS_GRACE_DURATION), Some(Status::OnMains));
+    assert!(!detector.beep_durations.is_empty());
+
+    assert_eq!(timeout_status(&mut detector, end + HISTORY_RESET_DURATION), Some(Status::OnMains));
+    assert!(detector.beep_durations.is_empty());
+    assert_eq!(timeout_status(&mut detector, end + HISTORY_RESET_DURATION + TIMEOUT_DURATION), None);
+  }
+
+  #[test]
+  fn long_silence_after_battery_pattern_is_power_off() {
+    let mut detector = Detector::new(DetectorConfig::default());
+    let (_, end) = feed_pattern(&mut detector, Instant::now(), Duration::from_millis(250), Duration::from_secs(1));
+
+    assert_eq!(timeout_status(&mut detector, end + DEFAULT_ON_MAINS_GRACE_DURATION), Some(Status::OnMains));
+    assert_eq!(timeout_status(&mut detector, end + HISTORY_RESET_DURATION), Some(Status::PowerOff));
+    assert_eq!(timeout_status(&mut detector, end + HISTORY_RESET_DURATION + TIMEOUT_DURATION), None);
+  }
+
+  #[test]
+  fn long_silence_after_mains_pattern_is_on_mains() {
+    let mut detector = Detector::new(DetectorConfig::default());
+    let (status, end) = feed_pattern(&mut detector, Instant::now(), Duration::from_secs(2), Duration::from_secs(2));
+    assert_eq!(status, Some(Status::OverloadOrShortCircuitOnMains));
+
+    assert_eq!(timeout_status(&mut detector, end + HISTORY_RESET_DURATION), Some(Status::OnMains));
+  }
+
+  #[test]
+  fn reset_forgets_battery_pattern() {
+    let mut detector = Detector::new(DetectorConfig::default());
+    let (_, end) = feed_pattern(&mut detector, Instant::now(), Duration::from_millis(250), Duration::from_secs(1));
+    detector.reset();
+
+    let (status, end) = feed_pattern(&mut detector, end + Duration::from_secs(10), Duration::from_secs(2), Duration::from_secs(2));
+    assert_eq!(status, Some(Status::OverloadOrShortCircuitOnMains));
+    assert_eq!(timeout_status(&mut detector, end + HISTORY_RESET_DURATION), Some(Status::OnMains));
+  }
+
+  // OnMains is the silence itself, it only comes from a timeout once the grace period has passed since the last beep,
+  // never from an edge and never from silence ahead of the first beep
+  #[test]
+  fn on_mains_is_only_reported_for_sustained_silence() {
+    let mut detector = Detector::new(DetectorConfig::default());
+    let start = Instant::now();
+    assert_eq!(timeout_status(&mut detector, start + HISTORY_RESET_DURATION), None);
+
+    let (status, end) = feed_pattern(&mut detector, start, Duration::from_millis(250), Duration::from_secs(2));
+    assert_eq!(status, Some(Status::OverloadOrShortCircuitOnBattery));
+    assert_eq!(timeout_status(&mut detector, end + DEFAULT_ON_MAINS_GRACE_DURATION - Duration::from_millis(1)), None);
+    let on_mains = detector.on_timeout(end + DEFAULT_ON_MAINS_GRACE_DURATION).unwrap();
+    assert_eq!((on_mains.status, on_mains.confidence), (Status::OnMains, 1.0));
+    assert_eq!((on_mains.beep_duration, on_mains.inter_beep_duration), (ZERO_DURATION, DEFAULT_ON_MAINS_GRACE_DURATION));
+
+    let match_config = MatchConfig { silence_status: None, ..MatchConfig::default() };
+    let mut meaningless_silence_detector = Detector::with_classifier(DetectorConfig::default(), Box::new(BuiltinClassifier::new(match_config)));
+    let (_, end) = feed_pattern(&mut meaningless_silence_detector, start, Duration::from_millis(250), Duration::from_secs(2));
+    assert_eq!(timeout_status(&mut meaningless_silence_detector, end + DEFAULT_ON_MAINS_GRACE_DURATION), Some(Status::Unknown));
+  }
+
+  #[test]
+  fn on_mains_waits_for_grace_window() {
+    let mut detector = Detector::new(DetectorConfig { on_mains_grace_duration: Duration::from_secs(8), ..DetectorConfig::default() });
+    let (_, end) = feed_pattern(&mut detector, Instant::now(), Duration::from_millis(250), Duration::from_secs(2));
+
+    assert_eq!(timeout_status(&mut detector, end + TIMEOUT_DURATION), None);
+    assert_eq!(timeout_status(&mut detector, end + TIMEOUT_DURATION * 2), None);
+    assert_eq!(timeout_status(&mut detector, end + TIMEOUT_DURATION * 3), Some(Status::OnMains));
+  }
+
+  #[test]
+  fn on_mains_waits_for_the_gap_of_the_last_pattern() {
+    let mut detector = Detector::new(DetectorConfig::default());
+    let (status, end) = feed_pattern(&mut detector, Instant::now(), Duration::from_millis(250), Duration::from_secs(60));
+    assert_eq!(status, Some(Status::OnBattery));
+
+    // Well past the grace period but still the wait for the next beep, up to the 60s gap and its 5% tolerance
+    assert_eq!(timeout_status(&mut detector, end + Duration::from_secs(10)), None);
+    assert_eq!(timeout_status(&mut detector, end + Duration::from_secs(50)), None);
+    assert_eq!(timeout_status(&mut detector, end + Duration::from_millis(62_999)), None);
+    assert_eq!(timeout_status(&mut detector, end + Duration::from_secs(63)), Some(Status::OnMains));
+
+    // The next beep coming in time keeps the pattern going without anything inferred in between
+    let mut detector = Detector::new(DetectorConfig::default());
+    let (_, end) = feed_pattern(&mut detector, Instant::now(), Duration::from_millis(250), Duration::from_secs(60));
+    assert_eq!(timeout_status(&mut detector, end + Duration::from_secs(30)), None);
+    assert_eq!(feed_beep(&mut detector, end + Duration::from_secs(60), Duration::from_millis(250)), Some(Status::OnBattery));
+  }
+
+  #[test]
+  fn beeps_within_grace_window_keep_battery_status() {
+    let mut detector = Detector::new(DetectorConfig::default());
+    let (status, end) = feed_pattern(&mut detector, Instant::now(), Duration::from_millis(250), Duration::from_secs(2));
+    assert_eq!(status, Some(Status::OverloadOrShortCircuitOnBattery));
+
+    // A stabilizing beep 4s later neither lets OnMains through before it nor right after it
+    assert_eq!(timeout_status(&mut detector, end + TIMEOUT_DURATION), None);
+    let beep_end = end + Duration::from_secs(4) + Duration::from_millis(250);
+    feed_beep(&mut detector, end + Duration::from_secs(4), Duration::from_millis(250));
+    assert_eq!(timeout_status(&mut detector, beep_end + TIMEOUT_DURATION), None);
+    assert_eq!(timeout_status(&mut detector, beep_end + TIMEOUT_DURATION * 2), Some(Status::OnMains));
+  }
+
+  #[test]
+  fn silence_after_battery_pattern_is_held_as_mute() {
+    let mut detector = Detector::new(DetectorConfig { mute_hold_duration: Duration::from_secs(30), ..DetectorConfig::default() });
+    let (_, end) = feed_pattern(&mut detector, Instant::now(), Duration::from_millis(250), Duration::from_secs(1));
+
+    assert_eq!(timeout_status(&mut detector, end + DEFAULT_ON_MAINS_GRACE_DURATION), None);
+    assert!(detector.is_holding_for_mute);
+    assert_eq!(timeout_status(&mut detector, end + Duration::from_secs(29)), None);
+    assert_eq!(timeout_status(&mut detector, end + Duration::from_secs(30)), Some(Status::OnMains));
+  }
+
+  #[test]
+  fn mains_pattern_ends_mute_hold() {
+    let mut detector = Detector::new(DetectorConfig { mute_hold_duration: Duration::from_secs(30), ..DetectorConfig::default() });
+    let (_, end) = feed_pattern(&mut detector, Instant::now(), Duration::from_millis(250), Duration::from_secs(1));
+    assert_eq!(timeout_status(&mut detector, end + DEFAULT_ON_MAINS_GRACE_DURATION), None);
+
+    let (status, end) = feed_pattern(&mut detector, end + Duration::from_secs(10), Duration::from_secs(2), Duration::from_secs(2));
+    assert_eq!(status, Some(Status::OverloadOrShortCircuitOnMains));
+    assert!(!detector.is_holding_for_mute);
+    assert_eq!(timeout_status(&mut detector, end + DEFAULT_ON_MAINS_GRACE_DURATION), Some(Status::OnMains));
+  }
+
+  #[test]
+  fn pulse_shorter_than_min_beep_is_discarded() {
+    let mut detector = Detector::new(DetectorConfig { min_beep_duration: Duration::from_millis(150), ..DetectorConfig::default() });
+    let start = Instant::now();
+    feed_beep(&mut detector, start, Duration::from_millis(250));
+    let beep_end = start + Duration::from_millis(250);
+
+    // A noise pulse in the middle of the gap must neither count as a beep nor split the gap
+    assert_eq!(feed_beep(&mut detector, beep_end + Duration::from_millis(400), Duration::from_millis(100)), None);
+    assert_eq!(detector.beep_durations.len(), 1);
+    assert!(detector.inter_beep_durations.is_empty());
+
+    assert_eq!(feed_beep(&mut detector, beep_end + Duration::from_secs(1), Duration::from_millis(250)), Some(Status::LowOnBattery));
+  }
+
+  #[test]
+  fn pulse_longer_than_min_beep_is_kept() {
+    let mut detector = Detector::new(DetectorConfig { min_beep_duration: Duration::from_millis(150), ..DetectorConfig::default() });
+    let (status, _) = feed_pattern(&mut detector, Instant::now(), Duration::from_millis(250), Duration::from_secs(1));
+    assert_eq!(status, Some(Status::LowOnBattery));
+  }
+
+  #[test]
+  fn timeout_fires_once_the_clock_passes_it() {
+    let clock = MockClock::new();
+    let mut detector = Detector::new(DetectorConfig { on_mains_grace_duration: TIMEOUT_DURATION, ..DetectorConfig::default() });
+    feed_pattern(&mut detector, clock.now(), Duration::from_millis(250), Duration::from_secs(2));
+    clock.advance(Duration::from_millis(250) * 2 + Duration::from_secs(2));
+
+    clock.advance(TIMEOUT_DURATION - Duration::from_millis(1));
+    assert_eq!(timeout_status(&mut detector, clock.now()), None);
+    clock.advance(Duration::from_millis(1));
+    assert_eq!(timeout_status(&mut detector, clock.now()), Some(Status::OnMains));
+  }
+
+  #[test]
+  fn wall_clock_steps_between_edges_change_nothing() {
+    let clock = MockClock::new();
+    let mut detector = Detector::new(DetectorConfig::default());
+    detector.on_edge(Edge::BeepStart, clock.now());
+    clock.advance(Duration::from_millis(250));
+    detector.on_edge(Edge::BeepEnd, clock.now());
+    clock.set_wall_time(clock.wall_time() - Duration::from_secs(3600));
+    clock.advance(Duration::from_secs(1));
+    detector.on_edge(Edge::BeepStart, clock.now());
+    clock.set_wall_time(clock.wall_time() + Duration::from_secs(7200));
+    clock.advance(Duration::from_millis(250));
+    let classification = detector.on_edge(Edge::BeepEnd, clock.now()).unwrap();
+    assert_eq!((classification.beep_duration, classification.inter_beep_duration), (Duration::from_millis(250), Duration::from_secs(1)));
+    assert_eq!(classification.status, Status::LowOnBattery);
+  }
+
+  #[test]
+  fn continuous_beep_is_reported_without_history() {
+    let mut detector = Detector::new(DetectorConfig::default());
+    let start = Instant::now();
+    detector.on_edge(Edge::BeepStart, start);
+    assert_eq!(timeout_status(&mut detector, start + TIMEOUT_DURATION), Some(Status::OverTemperatureOnBatteryOrInternalError));
+  }
+
+  #[test]
+  fn continuous_beep_is_timed_on_its_own() {
+    let mut detector = Detector::new(DetectorConfig::default());
+    let start = Instant::now();
+    detector.on_edge(Edge::BeepStart, start);
+    // Polls that come sooner than the poll timeout, or pile up well after it, all see the same beep
+    assert_eq!(timeout_status(&mut detector, start + CONTINUOUS_BEEP_DURATION - Duration::from_millis(1)), None);
+    assert_eq!(timeout_status(&mut detector, start + CONTINUOUS_BEEP_DURATION), Some(Status::OverTemperatureOnBatteryOrInternalError));
+    assert_eq!(timeout_status(&mut detector, start + CONTINUOUS_BEEP_DURATION * 5), Some(Status::OverTemperatureOnBatteryOrInternalError));
+  }
+
+  #[test]
+  fn double_beep_is_reported_as_its_group() {
+    let beep_groups = vec![crate::group::parse_beep_group("TestInProgress:beep=100ms,gap=500ms").unwrap()];
+    let mut detector = Detector::new(DetectorConfig { beep_groups, ..DetectorConfig::default() });
+    let start = Instant::now();
+    let beep = Duration::from_millis(100);
+    assert_eq!(feed_pattern(&mut detector, start, beep, Duration::from_millis(500)).0, Some(Status::TestInProgress));
+    // The first beep of the next group is held back instead of being Unknown
+    let next_start = start + Duration::from_secs(10);
+    assert_eq!(feed_beep(&mut detector, next_start, beep), None);
+    assert_eq!(feed_beep(&mut detector, next_start + Duration::from_millis(600), beep), Some(Status::TestInProgress));
+    // A third beep makes it some other pattern
+    assert_eq!(feed_beep(&mut detector, next_start + Duration::from_millis(1200), beep), Some(Status::Unknown));
+    // The table still has every other pattern
+    let (status, end) = feed_pattern(&mut detector, next_start + Duration::from_secs(30), Duration::from_millis(250), Duration::from_secs(1));
+    assert_eq!(status, Some(Status::LowOnBattery));
+    assert_eq!(feed_beep(&mut detector, end + Duration::from_secs(1), Duration::from_millis(250)), Some(Status::LowOnBattery));
+  }
+
+  fn get_chirping_detector() -> Detector {
+    Detector::new(DetectorConfig { avr_chirp: Some((Duration::from_millis(80), None)), ..DetectorConfig::default() })
+  }
+
+  #[test]
+  fn lone_chirp_followed_by_silence_is_voltage_regulation() {
+    let mut detector = get_chirping_detector();
+    let start = Instant::now();
+    assert_eq!(feed_beep(&mut detector, start, Duration::from_millis(90)), None);
+    let chirp_end = start + Duration::from_millis(90);
+    assert_eq!(timeout_status(&mut detector, chirp_end + DEFAULT_ON_MAINS_GRACE_DURATION - Duration::from_millis(1)), None);
+    assert_eq!(timeout_status(&mut detector, chirp_end + DEFAULT_ON_MAINS_GRACE_DURATION), Some(Status::VoltageRegulating));
+    assert!(Status::VoltageRegulating.is_on_mains() && !Status::VoltageRegulating.is_on_battery());
+    // Another chirp a while later makes no pattern with it either
+    let next_chirp_start = start + Duration::from_secs(20);
+    assert_eq!(feed_beep(&mut detector, next_chirp_start, Duration::from_millis(80)), Some(Status::Unknown));
+    assert_eq!(timeout_status(&mut detector, next_chirp_start + Duration::from_secs(10)), Some(Status::VoltageRegulating));
+    // Until the history gets cleared, which is the mains without any regulation
+    assert_eq!(timeout_status(&mut detector, next_chirp_start + HISTORY_RESET_DURATION + Duration::from_secs(1)), Some(Status::OnMains));
+    assert!(detector.beep_durations.is_empty());
+  }
+
+  #[test]
+  fn battery_beeps_are_not_chirps() {
+    let mut detector = get_chirping_detector();
+    let start = Instant::now();
+    // The first beep of OnBattery is too long for a chirp, the silence after it is the wait for the next beep of whatever pattern it starts
+    feed_beep(&mut detector, start, Duration::from_millis(250));
+    assert_eq!(timeout_status(&mut detector, start + Duration::from_secs(10)), None);
+    assert_eq!(timeout_status(&mut detector, start + Duration::from_secs(40)), None);
+    assert_eq!(feed_beep(&mut detector, start + Duration::from_millis(60_250), Duration::from_millis(250)), Some(Status::OnBattery));
+    // Nor is the silence after a battery beep, whatever came before it, the battery status is held all through the gap
+    assert_eq!(timeout_status(&mut detector, start + Duration::from_secs(70)), None);
+    assert_eq!(timeout_status(&mut detector, start + Duration::from_secs(110)), None);
+    assert_eq!(feed_beep(&mut detector, start + Duration::from_millis(120_500), Duration::from_millis(250)), Some(Status::OnBattery));
+  }
+
+  #[test]
+  fn chirps_matched_as_a_pattern_are_not_regulation() {
+    let beep_groups = vec![crate::group::parse_beep_group("TestInProgress:beep=80ms,gap=500ms").unwrap()];
+    let mut detector = Detector::new(DetectorConfig { beep_groups, ..get_chirping_detector().config });
+    let (status, end) = feed_pattern(&mut detector, Instant::now(), Duration::from_millis(80), Duration::from_millis(500));
+    assert_eq!(status, Some(Status::TestInProgress));
+    assert_eq!(timeout_status(&mut detector, end + Duration::from_secs(10)), Some(Status::OnMains));
+  }
+
+  #[test]
+  fn chirps_are_left_alone_without_avr_chirp() {
+    let mut detector = Detector::new(DetectorConfig::default());
+    let start = Instant::now();
+    feed_beep(&mut detector, start, Duration::from_millis(80));
+    // Just a lone beep, which might still be the first of the OnBattery pattern with the longest gap of the table
+    assert_eq!(timeout_status(&mut detector, start + Duration::from_secs(10)), None);
+    assert_eq!(timeout_status(&mut detector, start + Duration::from_secs(64)), Some(Status::OnMains));
+  }
+
+  #[test]
+  fn history_keeps_the_latest_entries_in_order() {
+    let mut detector = Detector::new(DetectorConfig::default());
+    let start = Instant::now();
+    for index in 0..(MAX_ENTRIES as u64 + 5) {
+      feed_beep(&mut detector, start + Duration::from_secs(index * 2), Duration::from_millis(100 + index));
+    }
+    let expected_beeps: Vec<Duration> = (5..(MAX_ENTRIES as u64 + 5)).map(|index| Duration::from_millis(100 + index)).collect();
+    assert_eq!(detector.beep_durations, expected_beeps);
+    let expected_gaps: Vec<Duration> = (5..(MAX_ENTRIES as u64 + 5)).map(|index| Duration::from_millis(2000 - 100 - (index - 1))).collect();
+    assert_eq!(detector.inter_beep_durations, expected_gaps);
+  }
+
+  #[test]
+  fn history_reset_outlasts_every_pattern_gap() {
+    assert!(HISTORY_RESET_DURATION > Duration::from_secs(60));
+  }
+}
@@ -0,0 +1,147 @@
+use std::time::{Duration, Instant};
+
+use crate::report::Origin;
+use crate::status::{Classification, Status, get_status_from_name};
+
+// A status to exit on, confirmed separately from the reporting, which goes on reporting every status as soon as it's detected
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct ExitCondition {
+  pub status: Status,
+  // How long the status has to be detected without interruption
+  pub grace_duration: Duration,
+  // Classifications of the status less confident than this interrupt it just like another status would, 0 to count them all
+  pub min_confidence: f64,
+}
+
+// Ends the process once a status has been detected continuously for its grace duration, so a supervisor can act on the exit
+pub struct ExitPolicy {
+  conditions: Vec<ExitCondition>,
+  // The status of the latest classification and since when it has been detected without interruption
+  current: Option<(Status, Instant)>,
+}
+
+impl ExitPolicy {
+  pub fn new(conditions: Vec<ExitCondition>) -> ExitPolicy {
+    ExitPolicy { conditions, current: None }
+  }
+
+  pub fn update(&mut self, status: Status, confidence: f64, now: Instant) {
+    if self.conditions.iter().any(|condition| condition.status == status && confidence < condition.min_confidence) {
+      self.current = None;
+      return;
+    }
+    if self.current.is_none_or(|current| current.0 != status) {
+      self.current = Some((status, now));
+    }
+  }
+
+  // Returns the status to exit with once its grace duration has passed
+  pub fn check(&self, now: Instant) -> Option<Status> {
+    let (status, since) = self.current?;
+    self.conditions.iter()
+      .any(|condition| condition.status == status && now.duration_since(since) >= condition.grace_duration)
+      .then_some(status)
+  }
+}
+
+// Whether the one-shot mode can exit on a classification, a pattern counts as soon as it completes but a status inferred from the silence
+// only once the silence has outlasted the longest gap of any pattern, until then it may just be the wait for the next beep of one,
+// what gets inferred from a timeout during a beep has no silence to it and counts right away
+pub fn is_one_shot_settled(classification: Classification, origin: Origin, longest_gap: Option<Duration>) -> bool {
+  origin != Origin::Inferred
+    || classification.inter_beep_duration.is_zero()
+    || longest_gap.is_none_or(|longest_gap| classification.inter_beep_duration >= longest_gap)
+}
+
+// Parses a condition written as "<status>:<grace secs>[:<min confidence>]", as in "LowOnBattery:30" or "LowOnBattery:30:0.8"
+pub fn parse_exit_condition(value: &str) -> Result<ExitCondition, String> {
+  let invalid_condition = || format!("invalid exit condition {}, expected <status>:<grace secs>[:<min confidence>]", value);
+
+  let (status_name, grace) = value.split_once(':').ok_or_else(invalid_condition)?;
+  let status = get_status_from_name(status_name).ok_or_else(|| format!("unknown status {} in exit condition {}", status_name, value))?;
+  let (grace_secs, min_confidence) = match grace.split_once(':') {
+    Some((grace_secs, min_confidence)) => (grace_secs, min_confidence.parse().map_err(|_| invalid_condition())?),
+    None => (grace, 0.0),
+  };
+  if !(0.0..=1.0).contains(&min_confidence) {
+    return Err(format!("minimum confidence {} in exit condition {} is not between 0 and 1", min_confidence, value));
+  }
+  let grace_secs = grace_secs.parse().map_err(|_| invalid_condition())?;
+  Ok(ExitCondition { status, grace_duration: Duration::from_secs(grace_secs), min_confidence })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn get_condition(status: Status, grace_secs: u64) -> ExitCondition {
+    ExitCondition { status, grace_duration: Duration::from_secs(grace_secs), min_confidence: 0.0 }
+  }
+
+  #[test]
+  fn parses_conditions() {
+    assert_eq!(parse_exit_condition("LowOnBattery:30"), Ok(get_condition(Status::LowOnBattery, 30)));
+    assert_eq!(parse_exit_condition("LowOnBattery:30:0.8"), Ok(ExitCondition { min_confidence: 0.8, ..get_condition(Status::LowOnBattery, 30) }));
+    assert!(parse_exit_condition("LowOnBattery").is_err());
+    assert!(parse_exit_condition("LowOnBattery:soon").is_err());
+    assert!(parse_exit_condition("LowOnBattery:30:sure").is_err());
+    assert!(parse_exit_condition("LowOnBattery:30:2").unwrap_err().starts_with("minimum confidence 2 in exit condition"));
+    assert!(parse_exit_condition("Low:30").unwrap_err().starts_with("unknown status Low"));
+  }
+
+  #[test]
+  fn exits_once_status_outlasts_grace() {
+    let mut exit_policy = ExitPolicy::new(vec![get_condition(Status::LowOnBattery, 30)]);
+    let start = Instant::now();
+    assert_eq!(exit_policy.check(start), None);
+
+    exit_policy.update(Status::LowOnBattery, 1.0, start);
+    exit_policy.update(Status::LowOnBattery, 0.1, start + Duration::from_secs(20));
+    assert_eq!(exit_policy.check(start + Duration::from_secs(29)), None);
+    assert_eq!(exit_policy.check(start + Duration::from_secs(30)), Some(Status::LowOnBattery));
+  }
+
+  #[test]
+  fn other_status_restarts_grace() {
+    let mut exit_policy = ExitPolicy::new(vec![get_condition(Status::LowOnBattery, 30), get_condition(Status::PowerOff, 0)]);
+    let start = Instant::now();
+    exit_policy.update(Status::LowOnBattery, 1.0, start);
+    exit_policy.update(Status::OnBattery, 1.0, start + Duration::from_secs(20));
+    exit_policy.update(Status::LowOnBattery, 1.0, start + Duration::from_secs(25));
+    assert_eq!(exit_policy.check(start + Duration::from_secs(40)), None);
+    assert_eq!(exit_policy.check(start + Duration::from_secs(55)), Some(Status::LowOnBattery));
+
+    exit_policy.update(Status::PowerOff, 1.0, start + Duration::from_secs(60));
+    assert_eq!(exit_policy.check(start + Duration::from_secs(60)), Some(Status::PowerOff));
+  }
+
+  #[test]
+  fn one_shot_waits_out_the_longest_gap_for_silence() {
+    let longest_gap = Some(Duration::from_secs(63));
+    let get_classification = |status, beep_secs, inter_beep_secs| Classification {
+      status,
+      confidence: 1.0,
+      beep_duration: Duration::from_secs(beep_secs),
+      inter_beep_duration: Duration::from_secs(inter_beep_secs),
+    };
+    assert!(is_one_shot_settled(get_classification(Status::OnBattery, 0, 60), Origin::Observed, longest_gap));
+    // The silence 10s into the gap of OnBattery is no reason to exit as on mains
+    assert!(!is_one_shot_settled(get_classification(Status::OnMains, 0, 10), Origin::Inferred, longest_gap));
+    assert!(is_one_shot_settled(get_classification(Status::OnMains, 0, 63), Origin::Inferred, longest_gap));
+    assert!(is_one_shot_settled(get_classification(Status::OverTemperatureOnBatteryOrInternalError, 3, 0), Origin::Inferred, longest_gap));
+    assert!(is_one_shot_settled(get_classification(Status::Unknown, 0, 10), Origin::Inferred, None));
+  }
+
+  #[test]
+  fn unconfident_classifications_restart_grace() {
+    let mut exit_policy = ExitPolicy::new(vec![ExitCondition { min_confidence: 0.8, ..get_condition(Status::LowOnBattery, 30) }]);
+    let start = Instant::now();
+    exit_policy.update(Status::LowOnBattery, 0.5, start);
+    assert_eq!(exit_policy.check(start + Duration::from_secs(30)), None);
+    exit_policy.update(Status::LowOnBattery, 0.9, start + Duration::from_secs(30));
+    exit_policy.update(Status::LowOnBattery, 0.6, start + Duration::from_secs(45));
+    exit_policy.update(Status::LowOnBattery, 0.9, start + Duration::from_secs(50));
+    assert_eq!(exit_policy.check(start + Duration::from_secs(79)), None);
+    assert_eq!(exit_policy.check(start + Duration::from_secs(80)), Some(Status::LowOnBattery));
+  }
+}
@@ -0,0 +1,130 @@
+use rppal::gpio::{Gpio, InputPin, Trigger};
+use rppal::i2c::I2c;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::detector::Edge;
+use crate::gpio::get_gpio_error_description;
+use crate::source::{EdgeSource, ReadFailures, SourceEvent};
+
+// MCP23017 registers with the default IOCON.BANK = 0 layout, the port B register always follows the port A one
+const IODIRA: u8 = 0x00;
+const GPINTENA: u8 = 0x04;
+const INTCONA: u8 = 0x08;
+const IOCON: u8 = 0x0A;
+const GPIOA: u8 = 0x12;
+
+// Makes INTA fire for changes on either port
+const IOCON_MIRROR: u8 = 0x40;
+
+pub const EXPANDER_CHANNELS: u8 = 16;
+
+// Reads edges of a MCP23017 channel, the expander's INTA output being wired to the interrupt pin,
+// which gets pulled low whenever the channel changes until the port is read
+pub struct ExpanderSource {
+  i2c: I2c,
+  interrupt_pin: InputPin,
+  // Channels 0 to 7 are GPA0 to GPA7 and 8 to 15 are GPB0 to GPB7
+  channel: u8,
+
+  last_level: bool,
+  read_failures: ReadFailures,
+}
+
+impl ExpanderSource {
+  // Fails rather than panics, an expander that doesn't answer at the address is the usual wiring mistake
+  pub fn new(interrupt_pin: u8, address: u16, channel: u8) -> Result<ExpanderSource, String> {
+    let mut i2c = I2c::new().map_err(|error| format!("could not open the I2C bus for the expander: {}", error))?;
+    i2c.set_slave_address(address).map_err(|error| format!("could not address the expander at {:#04x}: {}", address, error))?;
+
+    let (port_offset, mask) = get_channel_port_offset_and_mask(channel);
+    // Sets the channel's bits of a register, or clears them when setting is false, leaving those of the other channels alone
+    let update_register = |i2c: &I2c, register: u8, bits: u8, setting: bool| {
+      let value = i2c.smbus_read_byte(register)?;
+      i2c.smbus_write_byte(register, if setting { value | bits } else { value & !bits })
+    };
+    let setup = update_register(&i2c, IODIRA + port_offset, mask, true)
+      .and_then(|_| update_register(&i2c, IOCON, IOCON_MIRROR, true))
+      // Interrupt on any change compared to the previous value rather than against DEFVAL
+      .and_then(|_| update_register(&i2c, INTCONA + port_offset, mask, false))
+      .and_then(|_| update_register(&i2c, GPINTENA + port_offset, mask, true));
+    setup.map_err(|error| format!("could not set up the expander at {:#04x}: {}", address, error))?;
+
+    let gpio = Gpio::new().map_err(|error| get_gpio_error_description(interrupt_pin, error))?;
+    let mut interrupt_pin_input = gpio.get(interrupt_pin).map_err(|error| get_gpio_error_description(interrupt_pin, error))?.into_input_pullup();
+    interrupt_pin_input.set_interrupt(Trigger::FallingEdge).map_err(|error| get_gpio_error_description(interrupt_pin, error))?;
+
+    let mut expander_source = ExpanderSource {
+      i2c,
+      interrupt_pin: interrupt_pin_input,
+      channel,
+      last_level: false,
+      read_failures: ReadFailures::default(),
+    };
+    // Reading the port also clears any interrupt left pending from before
+    expander_source.last_level = expander_source.read_level()
+      .map_err(|error| format!("could not read the expander at {:#04x}: {}", address, error))?;
+    Ok(expander_source)
+  }
+
+  fn read_level(&self) -> Result<bool, rppal::i2c::Error> {
+    let (port_offset, mask) = get_channel_port_offset_and_mask(self.channel);
+    Ok(self.i2c.smbus_read_byte(GPIOA + port_offset)? & mask != 0)
+  }
+}
+
+impl EdgeSource for ExpanderSource {
+  fn next_event(&mut self, timeout: Duration) -> Option<SourceEvent> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+      let remaining = deadline.saturating_duration_since(Instant::now());
+      // A failed wait or read counts as one that saw no change, after waiting as long as it would have
+      let interrupt = match self.interrupt_pin.poll_interrupt(true, Some(remaining)) {
+        Ok(interrupt) => interrupt,
+        Err(error) => {
+          self.read_failures.on_failure(format!("Could not wait for an interrupt of the expander: {}", error));
+          thread::sleep(remaining);
+          None
+        },
+      };
+      let now = Instant::now();
+      if interrupt.is_none() {
+        return Some(SourceEvent::Timeout(now));
+      }
+
+      // The interrupt may have been raised by another channel on the same port, or by a change that was already undone
+      let level = match self.read_level() {
+        Ok(level) => {
+          self.read_failures.on_success();
+          level
+        },
+        Err(error) => {
+          self.read_failures.on_failure(format!("Could not read the expander: {}", error));
+          self.last_level
+        },
+      };
+      if level != self.last_level {
+        self.last_level = level;
+        return Some(SourceEvent::Edge(if level { Edge::BeepStart } else { Edge::BeepEnd }, now));
+      }
+    }
+  }
+}
+
+fn get_channel_port_offset_and_mask(channel: u8) -> (u8, u8) {
+  (channel / 8, 1 << (channel % 8))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn maps_channels_to_ports() {
+    assert_eq!(get_channel_port_offset_and_mask(0), (0, 0b0000_0001));
+    assert_eq!(get_channel_port_offset_and_mask(7), (0, 0b1000_0000));
+    assert_eq!(get_channel_port_offset_and_mask(8), (1, 0b0000_0001));
+    assert_eq!(get_channel_port_offset_and_mask(15), (1, 0b1000_0000));
+  }
+}
@@ -0,0 +1,39 @@
+// The GPIO library edges are read through
+const BACKEND: &str = "rppal";
+
+// Every optional cargo feature of the crate along with whether it was compiled into this binary
+const FEATURES: [(&str, bool); 5] = [
+  ("http", cfg!(feature = "http")),
+  ("adc", cfg!(feature = "adc")),
+  ("sqlite", cfg!(feature = "sqlite")),
+  ("mqtt", cfg!(feature = "mqtt")),
+  ("amqp", cfg!(feature = "amqp")),
+];
+
+pub fn get_features_description() -> String {
+  let enabled_features: Vec<&str> = FEATURES.iter()
+    .filter(|feature| feature.1)
+    .map(|feature| feature.0)
+    .collect();
+
+  format!(
+    "{} {}\nbackend: {}\nfeatures: {}",
+    env!("CARGO_PKG_NAME"),
+    env!("CARGO_PKG_VERSION"),
+    BACKEND,
+    if enabled_features.is_empty() { "none".to_string() } else { enabled_features.join(", ") },
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn describes_version_and_backend() {
+    let description = get_features_description();
+    assert!(description.starts_with(&format!("ups-power-status-from-beeps {}", env!("CARGO_PKG_VERSION"))));
+    assert!(description.contains("backend: rppal"));
+    assert!(description.contains("features: "));
+  }
+}
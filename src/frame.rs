@@ -0,0 +1,184 @@
+use std::time::{Duration, Instant};
+
+use crate::detector::{BEEP_BOUNCE_MAX_DURATION, Decoder, Edge};
+use crate::status::{Classification, Status, TIMEOUT_DURATION, get_status_from_name};
+
+pub const DEFAULT_FRAME_DELIMITER_DURATION: Duration = Duration::from_secs(1);
+
+// Reads the beeps as frames, each made of a long delimiter beep followed by as many short beeps as the code it carries,
+// a frame ends either with the delimiter of the next one or with silence lasting the timeout
+pub struct FrameDecoder {
+  // Beeps at least this long are delimiters, shorter ones count towards the code
+  delimiter_duration: Duration,
+  codes: Vec<(u32, Status)>,
+
+  current_beep_start_time: Option<Instant>,
+  last_beep_end_time: Option<Instant>,
+  // When the delimiter of the frame in progress started and how many short beeps followed it so far
+  frame: Option<(Instant, u32)>,
+}
+
+impl FrameDecoder {
+  pub fn new(delimiter_duration: Duration, codes: Vec<(u32, Status)>) -> FrameDecoder {
+    FrameDecoder {
+      delimiter_duration,
+      codes,
+      current_beep_start_time: None,
+      last_beep_end_time: None,
+      frame: None,
+    }
+  }
+
+  fn end_frame(&mut self, now: Instant) -> Option<Classification> {
+    let (frame_start_time, code) = self.frame.take()?;
+    let status = self.codes.iter().rev()
+      .find(|code_status| code_status.0 == code)
+      .map(|code_status| code_status.1)
+      .unwrap_or(Status::Unknown);
+
+    Some(Classification {
+      status,
+      confidence: if status == Status::Unknown { 0.0 } else { 1.0 },
+      beep_duration: self.delimiter_duration,
+      inter_beep_duration: now.duration_since(frame_start_time),
+    })
+  }
+}
+
+impl Decoder for FrameDecoder {
+  // Returns the possible power state whenever a delimiter ends the frame before it
+  fn on_edge(&mut self, edge: Edge, now: Instant) -> Option<Classification> {
+    match edge {
+      Edge::BeepStart => {
+        if self.current_beep_start_time.is_none() {
+          self.current_beep_start_time = Some(now);
+        }
+        None
+      },
+      Edge::BeepEnd => {
+        let beep_start_time = self.current_beep_start_time.take()?;
+        let beep_duration = now.duration_since(beep_start_time);
+        if beep_duration <= BEEP_BOUNCE_MAX_DURATION {
+          return None;
+        }
+        self.last_beep_end_time = Some(now);
+
+        if beep_duration >= self.delimiter_duration {
+          let classification = self.end_frame(beep_start_time);
+          self.frame = Some((beep_start_time, 0));
+          classification
+        } else {
+          // Short beeps before the first delimiter can't be placed in a frame and are dropped
+          if let Some((_, code)) = &mut self.frame {
+            *code += 1;
+          }
+          None
+        }
+      },
+    }
+  }
+
+  fn on_timeout(&mut self, now: Instant) -> Option<Classification> {
+    let beep_end_time = self.last_beep_end_time?;
+    if self.current_beep_start_time.is_some() || now.duration_since(beep_end_time) < TIMEOUT_DURATION {
+      return None;
+    }
+    self.end_frame(beep_end_time)
+  }
+
+  fn reset(&mut self) {
+    self.current_beep_start_time = None;
+    self.last_beep_end_time = None;
+    self.frame = None;
+  }
+}
+
+// Parses a code mapping written as "<code>=<status>", as in "3=LowOnBattery"
+pub fn parse_frame_code(value: &str) -> Result<(u32, Status), String> {
+  let invalid_code = || format!("invalid frame code {}, expected <code>=<status>", value);
+
+  let (code, status_name) = value.split_once('=').ok_or_else(invalid_code)?;
+  let code = code.parse().map_err(|_| invalid_code())?;
+  let status = get_status_from_name(status_name).ok_or_else(|| format!("unknown status {} in frame code {}", status_name, value))?;
+  Ok((code, status))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SHORT_BEEP: Duration = Duration::from_millis(200);
+  const SHORT_GAP: Duration = Duration::from_millis(300);
+
+  fn get_decoder() -> FrameDecoder {
+    FrameDecoder::new(DEFAULT_FRAME_DELIMITER_DURATION, vec![
+      parse_frame_code("1=OnBattery").unwrap(),
+      parse_frame_code("3=LowOnBattery").unwrap(),
+    ])
+  }
+
+  fn feed_beep(decoder: &mut FrameDecoder, start: Instant, beep: Duration) -> Option<Status> {
+    decoder.on_edge(Edge::BeepStart, start);
+    decoder.on_edge(Edge::BeepEnd, start + beep).map(|classification| classification.status)
+  }
+
+  // Feeds a delimiter followed by code short beeps, returns the status the delimiter reported along with when the frame ended
+  fn feed_frame(decoder: &mut FrameDecoder, start: Instant, code: u32) -> (Option<Status>, Instant) {
+    let status = feed_beep(decoder, start, DEFAULT_FRAME_DELIMITER_DURATION);
+    let mut at = start + DEFAULT_FRAME_DELIMITER_DURATION;
+    for _ in 0..code {
+      at += SHORT_GAP;
+      feed_beep(decoder, at, SHORT_BEEP);
+      at += SHORT_BEEP;
+    }
+    (status, at)
+  }
+
+  #[test]
+  fn parses_codes() {
+    assert_eq!(parse_frame_code("3=LowOnBattery"), Ok((3, Status::LowOnBattery)));
+    assert!(parse_frame_code("three=LowOnBattery").is_err());
+    assert!(parse_frame_code("3=Low").unwrap_err().starts_with("unknown status Low"));
+  }
+
+  #[test]
+  fn decodes_frames_ended_by_the_next_delimiter() {
+    let mut decoder = get_decoder();
+    let (status, end) = feed_frame(&mut decoder, Instant::now(), 3);
+    assert_eq!(status, None);
+    let (status, end) = feed_frame(&mut decoder, end + Duration::from_secs(1), 1);
+    assert_eq!(status, Some(Status::LowOnBattery));
+    let (status, _) = feed_frame(&mut decoder, end + Duration::from_secs(1), 3);
+    assert_eq!(status, Some(Status::OnBattery));
+  }
+
+  #[test]
+  fn decodes_frame_ended_by_silence() {
+    let mut decoder = get_decoder();
+    let (_, end) = feed_frame(&mut decoder, Instant::now(), 1);
+    assert_eq!(decoder.on_timeout(end + Duration::from_secs(1)), None);
+    assert_eq!(decoder.on_timeout(end + TIMEOUT_DURATION).map(|classification| classification.status), Some(Status::OnBattery));
+    assert_eq!(decoder.on_timeout(end + TIMEOUT_DURATION * 2), None);
+  }
+
+  #[test]
+  fn unmapped_code_is_unknown() {
+    let mut decoder = get_decoder();
+    let (_, end) = feed_frame(&mut decoder, Instant::now(), 2);
+    let classification = decoder.on_timeout(end + TIMEOUT_DURATION).unwrap();
+    assert_eq!(classification.status, Status::Unknown);
+    assert_eq!(classification.confidence, 0.0);
+  }
+
+  #[test]
+  fn short_beeps_before_a_delimiter_are_dropped() {
+    let mut decoder = get_decoder();
+    let start = Instant::now();
+    feed_beep(&mut decoder, start, SHORT_BEEP);
+    feed_beep(&mut decoder, start + Duration::from_secs(1), SHORT_BEEP);
+    assert_eq!(decoder.on_timeout(start + Duration::from_secs(5)), None);
+
+    let (_, end) = feed_frame(&mut decoder, start + Duration::from_secs(6), 1);
+    assert_eq!(decoder.on_timeout(end + TIMEOUT_DURATION).map(|classification| classification.status), Some(Status::OnBattery));
+  }
+}
@@ -0,0 +1,69 @@
+use crate::status::{Status, get_status_from_name};
+
+// Short codes for status bars, anything on battery is a letter, faults that need acting on right away are a !
+const STATUS_GLYPHS: [(Status, &str); 15] = [
+  (Status::OnBattery, "B"),
+  (Status::LowOnBattery, "L"),
+  (Status::NoLoadOnBattery, "N"),
+  (Status::OverloadOrShortCircuitOnBattery, "!"),
+  (Status::OverloadOrShortCircuitOnMains, "O"),
+  (Status::AdvanceLowRuntimeOnMains, "A"),
+  (Status::OverTemperatureOnMains, "T"),
+  (Status::OnMains, "M"),
+  (Status::VoltageRegulating, "V"),
+  (Status::OverTemperatureOnBatteryOrInternalError, "!"),
+  (Status::ReplaceBattery, "R"),
+  (Status::PowerOff, "X"),
+  (Status::TestInProgress, "S"),
+  (Status::SensorConflict, "C"),
+  (Status::Unknown, "?"),
+];
+
+// The default glyphs with any user overrides applied on top
+#[derive(Clone, Default)]
+pub struct GlyphTable {
+  overrides: Vec<(Status, String)>,
+}
+
+impl GlyphTable {
+  pub fn new(overrides: Vec<(Status, String)>) -> GlyphTable {
+    GlyphTable { overrides }
+  }
+
+  pub fn get(&self, status: Status) -> &str {
+    self.overrides.iter().rev()
+      .find(|status_glyph| status_glyph.0 == status)
+      .map(|status_glyph| status_glyph.1.as_str())
+      .unwrap_or_else(|| STATUS_GLYPHS.iter().find(|status_glyph| status_glyph.0 == status).unwrap().1)
+  }
+}
+
+// Parses an override written as "<status>=<glyph>", as in "OnBattery=🔋"
+pub fn parse_glyph_override(value: &str) -> Result<(Status, String), String> {
+  let (status_name, glyph) = value.split_once('=').ok_or_else(|| format!("invalid glyph {}, expected <status>=<glyph>", value))?;
+  let status = get_status_from_name(status_name).ok_or_else(|| format!("unknown status {} in glyph {}", status_name, value))?;
+  if glyph.trim().is_empty() {
+    return Err(format!("empty glyph in {}", value));
+  }
+  Ok((status, glyph.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn overrides_replace_defaults() {
+    let table = GlyphTable::new(vec![parse_glyph_override("OnBattery=🔋").unwrap()]);
+    assert_eq!(table.get(Status::OnBattery), "🔋");
+    assert_eq!(table.get(Status::OnMains), "M");
+    assert_eq!(table.get(Status::LowOnBattery), "L");
+  }
+
+  #[test]
+  fn rejects_invalid_overrides() {
+    assert!(parse_glyph_override("OnBattery").unwrap_err().starts_with("invalid glyph"));
+    assert!(parse_glyph_override("Battery=B").unwrap_err().starts_with("unknown status Battery"));
+    assert!(parse_glyph_override("OnBattery= ").unwrap_err().starts_with("empty glyph"));
+  }
+}
@@ -0,0 +1,108 @@
+// Replays every capture in fixtures/golden and checks the statuses it classifies as against the ones its header expects,
+// so changes to the table or the tolerances that break a real UPS show up as a failing test,
+// a capture is a replay file with these header lines ahead of its edges:
+//   # model: <the UPS it was captured from>
+//   # profile: <built-in profile it is classified with, standard when left out>
+//   # expect: <status> <status>...
+// where the expected statuses are the classifications in order with repeats of the same status collapsed,
+// the silence at the end comes from an idle level repeated at the time the capture stopped, as a replay ends with its last edge,
+// supporting a new UPS means adding a capture of it along with the statuses it should come out as
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::classifier::BuiltinClassifier;
+use crate::detector::{Detector, DetectorConfig};
+use crate::profile::{DEFAULT_PROFILE_NAME, get_builtin_profile};
+use crate::replay::{ReplaySource, parse_events};
+use crate::source::{EdgeSource, SourceEvent};
+use crate::status::{MatchConfig, Status, TIMEOUT_DURATION, get_status_from_name};
+
+const FIXTURE_DIRECTORY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/golden");
+
+struct Golden {
+  model: String,
+  profile_name: String,
+  expected_statuses: Vec<Status>,
+}
+
+fn parse_golden(contents: &str) -> Result<Golden, String> {
+  let mut model = None;
+  let mut profile_name = DEFAULT_PROFILE_NAME.to_string();
+  let mut expected_statuses = None;
+  for line in contents.lines() {
+    let Some((key, value)) = line.strip_prefix('#').and_then(|header| header.split_once(':')) else {
+      continue;
+    };
+    match key.trim() {
+      "model" => model = Some(value.trim().to_string()),
+      "profile" => profile_name = value.trim().to_string(),
+      "expect" => expected_statuses = Some(value.split_whitespace()
+        .map(|name| get_status_from_name(name).ok_or_else(|| format!("unknown status {} in expect", name)))
+        .collect::<Result<Vec<Status>, String>>()?),
+      _ => {},
+    }
+  }
+  Ok(Golden {
+    model: model.ok_or("missing model header")?,
+    profile_name,
+    expected_statuses: expected_statuses.ok_or("missing expect header")?,
+  })
+}
+
+// Classified the same way the detection loop does, timeouts included as that is where the inferred statuses come from
+fn get_replayed_statuses(contents: &str, profile_name: &str) -> Result<Vec<Status>, String> {
+  let profile = get_builtin_profile(profile_name).ok_or_else(|| format!("unknown profile {}", profile_name))?;
+  let match_config = MatchConfig { beep_durations: profile.beep_durations, silence_status: profile.silence_status, ..MatchConfig::default() };
+  let mut detector = Detector::with_classifier(DetectorConfig::default(), Box::new(BuiltinClassifier::new(match_config)));
+  let mut source = ReplaySource::new(parse_events(contents)?, 0.0);
+
+  let mut statuses: Vec<Status> = vec![];
+  while let Some(event) = source.next_event(TIMEOUT_DURATION) {
+    let classification = match event {
+      SourceEvent::Edge(edge, at) => detector.on_edge(if profile.inverted { edge.inverted() } else { edge }, at),
+      SourceEvent::Timeout(at) => detector.on_timeout(at),
+    };
+    if let Some(classification) = classification
+      && statuses.last() != Some(&classification.status) {
+      statuses.push(classification.status);
+    }
+  }
+  Ok(statuses)
+}
+
+fn get_fixture_paths(directory: &Path) -> Vec<PathBuf> {
+  let mut paths: Vec<PathBuf> = fs::read_dir(directory).unwrap()
+    .map(|entry| entry.unwrap().path())
+    .filter(|path| path.extension().is_some_and(|extension| extension == "txt"))
+    .collect();
+  paths.sort();
+  paths
+}
+
+#[test]
+fn captures_classify_as_expected() {
+  let paths = get_fixture_paths(Path::new(FIXTURE_DIRECTORY));
+  assert!(!paths.is_empty(), "no captures in {}", FIXTURE_DIRECTORY);
+
+  let failures: Vec<String> = paths.iter().filter_map(|path| {
+    let contents = fs::read_to_string(path).unwrap();
+    let result = parse_golden(&contents).and_then(|golden| {
+      let statuses = get_replayed_statuses(&contents, &golden.profile_name)?;
+      Ok((golden, statuses))
+    });
+    match result {
+      Ok((golden, statuses)) if statuses == golden.expected_statuses => None,
+      Ok((golden, statuses)) => Some(format!("{} ({}): expected {:?}, got {:?}", path.display(), golden.model, golden.expected_statuses, statuses)),
+      Err(error) => Some(format!("{}: {}", path.display(), error)),
+    }
+  }).collect();
+  assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn rejects_captures_without_headers() {
+  assert_eq!(parse_golden("# model: Test\n0 1\n").err().unwrap(), "missing expect header");
+  assert_eq!(parse_golden("# expect: OnMains\n").err().unwrap(), "missing model header");
+  assert_eq!(parse_golden("# model: Test\n# expect: OnMains Sideways\n").err().unwrap(), "unknown status Sideways in expect");
+  assert_eq!(parse_golden("# model: Test\n# expect: OnBattery OnMains\n").unwrap().expected_statuses, vec![Status::OnBattery, Status::OnMains]);
+}
@@ -0,0 +1,271 @@
+use rppal::gpio::{Error, Gpio, InputPin, Level, Trigger};
+use std::fs::{File, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::detector::Edge;
+use crate::logging::{debug, info};
+use crate::source::{EdgeSource, ReadFailures, SourceEvent};
+use crate::status::Status;
+
+// Pin the status line is wired to unless --pin says otherwise
+pub const DEFAULT_PIN: u8 = 17;
+// Highest BCM number brought out on the Pi's 40 pin header
+pub const MAX_HEADER_PIN: u8 = 27;
+
+// Which edges of the line raise an interrupt, for hardware that only gives a clean pulse on one of them
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum TriggerMode {
+  Both,
+  Rising,
+  Falling,
+}
+
+const TRIGGER_MODE_NAMES: [(TriggerMode, &str); 3] = [
+  (TriggerMode::Both, "both"),
+  (TriggerMode::Rising, "rising"),
+  (TriggerMode::Falling, "falling"),
+];
+
+impl TriggerMode {
+  pub fn from_name(name: &str) -> Option<TriggerMode> {
+    TRIGGER_MODE_NAMES.iter()
+      .find(|trigger_mode_name| trigger_mode_name.1 == name)
+      .map(|trigger_mode_name| trigger_mode_name.0)
+  }
+
+  fn get_trigger(self) -> Trigger {
+    match self {
+      TriggerMode::Both => Trigger::Both,
+      TriggerMode::Rising => Trigger::RisingEdge,
+      TriggerMode::Falling => Trigger::FallingEdge,
+    }
+  }
+
+  // Whether the edge comes with an interrupt, the others are only ever seen by reading the level
+  fn is_triggered_by(self, edge: Edge) -> bool {
+    match self {
+      TriggerMode::Both => true,
+      TriggerMode::Rising => edge == Edge::BeepStart,
+      TriggerMode::Falling => edge == Edge::BeepEnd,
+    }
+  }
+}
+
+// How often the level is read when only one edge raises an interrupt and no sample interval is given,
+// the edge read this way is reported up to this late so the durations it ends grow by as much at most
+const DEFAULT_UNTRIGGERED_SAMPLE_INTERVAL: Duration = Duration::from_millis(5);
+
+pub struct GpioSource {
+  pin: InputPin,
+  trigger_mode: TriggerMode,
+  // How often the level gets read while waiting for an edge, to catch edges whose interrupt got lost
+  // or, with a single edge trigger, the edges that never raise one
+  sample_interval: Option<Duration>,
+  // The level the edges handed out so far leave the line at
+  is_high: bool,
+  read_failures: ReadFailures,
+}
+
+// Where the lock files of exclusively owned pins go
+const LOCK_DIRECTORY: &str = "/run/lock";
+
+// EBUSY, what requesting a line another process already requested fails with
+const BUSY_ERROR_CODE: i32 = 16;
+
+impl GpioSource {
+  // Fails rather than panics, setting up the interrupt requests the line from the kernel which is where a pin taken by another process shows up
+  pub fn new(pin_number: u8, sample_interval: Option<Duration>, trigger_mode: TriggerMode, pull_up: bool) -> Result<GpioSource, String> {
+    let gpio = Gpio::new().map_err(|error| get_gpio_error_description(pin_number, error))?;
+    let pin = gpio.get(pin_number).map_err(|error| get_gpio_error_description(pin_number, error))?;
+    let mut pin = if pull_up { pin.into_input_pullup() } else { pin.into_input() };
+    pin.set_interrupt(trigger_mode.get_trigger()).map_err(|error| get_gpio_error_description(pin_number, error))?;
+
+    let sample_interval = match trigger_mode {
+      TriggerMode::Both => sample_interval,
+      _ => Some(sample_interval.unwrap_or(DEFAULT_UNTRIGGERED_SAMPLE_INTERVAL)),
+    };
+    let is_high = pin.is_high();
+    info!(
+      "Watching GPIO pin {} for edges, {}, the line reads {}",
+      pin_number,
+      if pull_up { "pulled up" } else { "without a pull" },
+      if is_high { "high" } else { "low" },
+    );
+    Ok(GpioSource { pin, trigger_mode, sample_interval, is_high, read_failures: ReadFailures::default() })
+  }
+}
+
+pub fn get_gpio_error_description(pin_number: u8, error: Error) -> String {
+  match error {
+    Error::PermissionDenied(path) => format!("could not open {} for GPIO pin {}, permission denied, run as root or as a user in the gpio group", path, pin_number),
+    Error::PinNotAvailable(_) => format!("GPIO pin {} is not available on this board", pin_number),
+    Error::PinUsed(_) => format!("GPIO pin {} is busy, it is already in use by this process", pin_number),
+    Error::Io(error) if error.raw_os_error() == Some(BUSY_ERROR_CODE) => format!("GPIO pin {} is busy, its line is requested by another process", pin_number),
+    error => format!("could not set up GPIO pin {}: {}", pin_number, error),
+  }
+}
+
+// Held for as long as the returned file is open, so that a second detector on the same pin fails at startup instead of both seeing a share of the edges
+pub fn lock_pin(pin_number: u8) -> Result<File, String> {
+  lock_pin_in(LOCK_DIRECTORY, pin_number)
+}
+
+fn lock_pin_in(directory: &str, pin_number: u8) -> Result<File, String> {
+  let path = format!("{}/ups-power-status-gpio{}.lock", directory, pin_number);
+  let file = File::create(&path).map_err(|error| format!("could not create lock file {}: {}", path, error))?;
+  match file.try_lock() {
+    Ok(()) => Ok(file),
+    Err(TryLockError::WouldBlock) => Err(format!("GPIO pin {} is busy, another process holds {}", pin_number, path)),
+    Err(TryLockError::Error(error)) => Err(format!("could not lock {}: {}", path, error)),
+  }
+}
+
+// The edge that was evidently missed when the line reads at a different level than the edges so far left it at
+fn get_missed_edge(was_high: bool, is_high: bool) -> Option<Edge> {
+  match (was_high, is_high) {
+    (false, true) => Some(Edge::BeepStart),
+    (true, false) => Some(Edge::BeepEnd),
+    _ => None,
+  }
+}
+
+impl EdgeSource for GpioSource {
+  // Without sampling this is a single wait for an interrupt, with it the wait is cut into sample intervals
+  // and the line is read in between, a timeout still only comes once the whole timeout has passed without an edge
+  fn next_event(&mut self, timeout: Duration) -> Option<SourceEvent> {
+    let deadline = Instant::now() + timeout;
+    loop {
+      let wait_duration = deadline.saturating_duration_since(Instant::now());
+      let wait_duration = self.sample_interval.map_or(wait_duration, |sample_interval| sample_interval.min(wait_duration));
+      // A failed wait counts as one that saw no edge, after waiting as long as it would have
+      let level = match self.pin.poll_interrupt(true, Some(wait_duration)) {
+        Ok(level) => {
+          self.read_failures.on_success();
+          level
+        },
+        Err(error) => {
+          self.read_failures.on_failure(format!("Could not wait for an edge of the GPIO pin: {}", error));
+          thread::sleep(wait_duration);
+          None
+        },
+      };
+      let now = Instant::now();
+
+      match level {
+        Some(level) => {
+          self.is_high = level == Level::High;
+          return Some(SourceEvent::Edge(if self.is_high { Edge::BeepStart } else { Edge::BeepEnd }, now));
+        },
+        None => {
+          if self.sample_interval.is_some() {
+            let is_high = self.pin.is_high();
+            if let Some(edge) = get_missed_edge(self.is_high, is_high) {
+              // Edges without an interrupt are always read this way, only a lost interrupt is worth a mention
+              if self.trigger_mode.is_triggered_by(edge) {
+                debug!("Correcting a missed {:?}, the line reads {}", edge, if is_high { "high" } else { "low" });
+              }
+              self.is_high = is_high;
+              return Some(SourceEvent::Edge(edge, now));
+            }
+          }
+          if now >= deadline {
+            return Some(SourceEvent::Timeout(now));
+          }
+        },
+      }
+    }
+  }
+}
+
+// Input of a relay that is high while mains power is present, read on demand rather than polled for edges
+pub struct MainsPin {
+  pin: InputPin,
+}
+
+impl MainsPin {
+  pub fn new(pin: u8) -> Result<MainsPin, String> {
+    let gpio = Gpio::new().map_err(|error| get_gpio_error_description(pin, error))?;
+    Ok(MainsPin { pin: gpio.get(pin).map_err(|error| get_gpio_error_description(pin, error))?.into_input() })
+  }
+
+  pub fn is_mains_present(&self) -> bool {
+    self.pin.is_high()
+  }
+}
+
+// Pins of a status LED, each high while its color is lit, read on demand alongside the mains input
+pub struct LedPins {
+  pins: Vec<(InputPin, Status)>,
+}
+
+impl LedPins {
+  pub fn new(pins: &[(u8, Status)]) -> Result<LedPins, String> {
+    let gpio = Gpio::new().map_err(|error| get_gpio_error_description(pins[0].0, error))?;
+    let pins = pins.iter()
+      .map(|(pin, status)| Ok((gpio.get(*pin).map_err(|error| get_gpio_error_description(*pin, error))?.into_input(), *status)))
+      .collect::<Result<_, String>>()?;
+    Ok(LedPins { pins })
+  }
+
+  // The status of every color lit right now
+  pub fn get_lit_statuses(&self) -> Vec<Status> {
+    self.pins.iter().filter(|(pin, _)| pin.is_high()).map(|(_, status)| *status).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::fs;
+  use std::io;
+
+  #[test]
+  fn describes_busy_pins() {
+    assert_eq!(get_gpio_error_description(17, Error::PinUsed(17)), "GPIO pin 17 is busy, it is already in use by this process");
+    assert_eq!(get_gpio_error_description(17, Error::Io(io::Error::from_raw_os_error(BUSY_ERROR_CODE))), "GPIO pin 17 is busy, its line is requested by another process");
+    assert!(get_gpio_error_description(17, Error::ThreadPanic).starts_with("could not set up GPIO pin 17"));
+  }
+
+  #[test]
+  fn tells_missing_permissions_apart_from_missing_pins() {
+    assert_eq!(
+      get_gpio_error_description(17, Error::PermissionDenied("/dev/gpiomem".to_string())),
+      "could not open /dev/gpiomem for GPIO pin 17, permission denied, run as root or as a user in the gpio group",
+    );
+    assert_eq!(get_gpio_error_description(60, Error::PinNotAvailable(60)), "GPIO pin 60 is not available on this board");
+  }
+
+  #[test]
+  fn level_change_without_an_edge_is_a_missed_edge() {
+    assert_eq!(get_missed_edge(false, true), Some(Edge::BeepStart));
+    assert_eq!(get_missed_edge(true, false), Some(Edge::BeepEnd));
+    assert_eq!(get_missed_edge(true, true), None);
+    assert_eq!(get_missed_edge(false, false), None);
+  }
+
+  #[test]
+  fn single_edge_triggers_leave_the_other_edge_to_the_level() {
+    assert_eq!(TriggerMode::from_name("rising"), Some(TriggerMode::Rising));
+    assert_eq!(TriggerMode::from_name("up"), None);
+    assert!(TriggerMode::Both.is_triggered_by(Edge::BeepEnd));
+    assert!(TriggerMode::Rising.is_triggered_by(Edge::BeepStart));
+    assert!(!TriggerMode::Rising.is_triggered_by(Edge::BeepEnd));
+    assert!(!TriggerMode::Falling.is_triggered_by(Edge::BeepStart));
+  }
+
+  #[test]
+  fn second_lock_on_a_pin_fails() {
+    let directory = env::temp_dir();
+    let directory = directory.to_str().unwrap();
+    let lock = lock_pin_in(directory, 200).unwrap();
+    assert!(lock_pin_in(directory, 200).unwrap_err().starts_with("GPIO pin 200 is busy, another process holds"));
+    assert!(lock_pin_in(directory, 201).is_ok());
+
+    drop(lock);
+    assert!(lock_pin_in(directory, 200).is_ok());
+    fs::remove_file(format!("{}/ups-power-status-gpio200.lock", directory)).unwrap();
+    fs::remove_file(format!("{}/ups-power-status-gpio201.lock", directory)).unwrap();
+  }
+}
@@ -0,0 +1,200 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::detector::{INTER_BEEP_BOUNCE_MAX_DURATION, MAX_ENTRIES};
+use crate::pattern::parse_target_tolerance;
+use crate::status::{Classification, Status, Tolerance, get_closeness, get_status_from_name};
+
+// A few beeps in quick succession followed by a pause, as in the two short beeps some UPSes make when a self test is started,
+// the beeps within it are too close together to be any pair of the table so they are matched as a whole instead
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct BeepGroup {
+  pub status: Status,
+  pub count: usize,
+  pub beep_duration: Duration,
+  // Between the beeps within the group, the pause after it is whatever the next beep makes it
+  pub gap_duration: Duration,
+  // None keeps the margin and floor of the whole table for that duration
+  pub beep_tolerance: Option<Tolerance>,
+  pub gap_tolerance: Option<Tolerance>,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum GroupMatch {
+  // The last beeps are a whole group
+  Complete(Classification),
+  // The last beeps could be the start of a group that is still going on
+  Started,
+}
+
+// Parsed from "<status>:count=<count>,beep=<duration>[±<tolerance>],gap=<duration>[±<tolerance>]", as in "TestInProgress:count=2,beep=100ms,gap=500ms±100ms",
+// the count defaults to 2 and the durations and tolerances are the same as those of --pattern
+pub fn parse_beep_group(value: &str) -> Result<BeepGroup, String> {
+  let invalid_group = || format!("invalid beep group {}, expected <status>:count=<count>,beep=<duration>[±<tolerance>],gap=<duration>[±<tolerance>]", value);
+
+  let (status_name, fields) = value.split_once(':').ok_or_else(invalid_group)?;
+  let status = get_status_from_name(status_name).ok_or_else(|| format!("unknown status {} in beep group {}", status_name, value))?;
+  let mut count = None;
+  let mut beep = None;
+  let mut gap = None;
+  for field in fields.split(',') {
+    let (key, spec) = field.split_once('=').ok_or_else(invalid_group)?;
+    match key {
+      "count" if count.is_none() => count = Some(spec.parse::<usize>().map_err(|_| format!("invalid count {} in beep group {}", spec, value))?),
+      "beep" | "gap" => {
+        let target_tolerance = parse_target_tolerance(spec).ok_or_else(|| format!("invalid {} {} in beep group {}", key, spec, value))?;
+        match key {
+          "beep" if beep.is_none() => beep = Some(target_tolerance),
+          "gap" if gap.is_none() => gap = Some(target_tolerance),
+          _ => return Err(invalid_group()),
+        }
+      },
+      _ => return Err(invalid_group()),
+    }
+  }
+  let ((beep_duration, beep_tolerance), (gap_duration, gap_tolerance)) = beep.zip(gap).ok_or_else(invalid_group)?;
+  let count = count.unwrap_or(2);
+  // A single beep is what the table is for, and the history only goes back so far
+  if !(2..=MAX_ENTRIES).contains(&count) {
+    return Err(format!("invalid count {} in beep group {}, expected 2 to {}", count, value, MAX_ENTRIES));
+  }
+  // Gaps this short are merged away as bounce before anything gets matched
+  if gap_duration <= INTER_BEEP_BOUNCE_MAX_DURATION {
+    return Err(format!("gap of beep group {} has to be longer than {}ms", value, INTER_BEEP_BOUNCE_MAX_DURATION.as_millis()));
+  }
+  Ok(BeepGroup { status, count, beep_duration, gap_duration, beep_tolerance, gap_tolerance })
+}
+
+// Checks the beeps heard last against every group, the first group they complete wins, the nth last gap being the one before the nth last beep,
+// beeps that go on with the same gap for longer than a group are some other pattern and complete none of it
+pub fn get_group_match(groups: &[BeepGroup], default_tolerance: Tolerance, beep_durations: &VecDeque<Duration>, inter_beep_durations: &VecDeque<Duration>) -> Option<GroupMatch> {
+  let mut group_match = None;
+  for group in groups {
+    let Some((length, confidence)) = get_trailing_run(group, default_tolerance, beep_durations, inter_beep_durations) else {
+      continue;
+    };
+    if length == group.count {
+      return Some(GroupMatch::Complete(Classification {
+        status: group.status,
+        confidence,
+        beep_duration: *beep_durations.back().unwrap(),
+        inter_beep_duration: *inter_beep_durations.back().unwrap(),
+      }));
+    }
+    group_match = Some(GroupMatch::Started);
+  }
+  group_match
+}
+
+// How many of the last beeps are the beeps of the group with its gaps between them, along with how close the closest of them was,
+// None when the last beep isn't one of the group or the run is longer than the group
+fn get_trailing_run(group: &BeepGroup, default_tolerance: Tolerance, beep_durations: &VecDeque<Duration>, inter_beep_durations: &VecDeque<Duration>) -> Option<(usize, f64)> {
+  let beep_tolerance = group.beep_tolerance.unwrap_or(default_tolerance);
+  let gap_tolerance = group.gap_tolerance.unwrap_or(default_tolerance);
+  let get_beep_closeness = |beep_duration: Duration| get_closeness(beep_duration, group.beep_duration, beep_tolerance.error_margin, beep_tolerance.min_error_duration);
+  let get_gap_closeness = |gap_duration: Duration| get_closeness(gap_duration, group.gap_duration, gap_tolerance.error_margin, gap_tolerance.min_error_duration);
+
+  let mut confidence = get_beep_closeness(*beep_durations.back()?)?;
+  let mut length = 1;
+  while let Some(gap_closeness) = inter_beep_durations.len().checked_sub(length).and_then(|index| get_gap_closeness(inter_beep_durations[index])) {
+    let Some(beep_closeness) = beep_durations.len().checked_sub(length + 1).and_then(|index| get_beep_closeness(beep_durations[index])) else {
+      break;
+    };
+    if length == group.count {
+      return None;
+    }
+    confidence = confidence.min(gap_closeness).min(beep_closeness);
+    length += 1;
+  }
+  Some((length, confidence))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::status::{ERROR_MARGIN, MIN_ERROR_DURATION};
+
+  const DEFAULT_TOLERANCE: Tolerance = Tolerance { error_margin: ERROR_MARGIN, min_error_duration: MIN_ERROR_DURATION };
+
+  fn get_millis(millis: &[u64]) -> VecDeque<Duration> {
+    millis.iter().map(|millis| Duration::from_millis(*millis)).collect()
+  }
+
+  fn get_match(groups: &[BeepGroup], beeps: &[u64], gaps: &[u64]) -> Option<GroupMatch> {
+    get_group_match(groups, DEFAULT_TOLERANCE, &get_millis(beeps), &get_millis(gaps))
+  }
+
+  #[test]
+  fn parses_beep_groups() {
+    let group = parse_beep_group("TestInProgress:count=3,beep=100ms±20ms,gap=500ms±10%").unwrap();
+    assert_eq!(group, BeepGroup {
+      status: Status::TestInProgress,
+      count: 3,
+      beep_duration: Duration::from_millis(100),
+      gap_duration: Duration::from_millis(500),
+      beep_tolerance: Some(Tolerance { error_margin: 0.0, min_error_duration: Duration::from_millis(20) }),
+      gap_tolerance: Some(Tolerance { error_margin: 0.1, min_error_duration: Duration::ZERO }),
+    });
+
+    let group = parse_beep_group("TestInProgress:gap=1s,beep=100ms").unwrap();
+    assert_eq!((group.count, group.beep_tolerance, group.gap_tolerance), (2, None, None));
+  }
+
+  #[test]
+  fn rejects_invalid_beep_groups() {
+    assert!(parse_beep_group("TestInProgress").unwrap_err().starts_with("invalid beep group TestInProgress"));
+    assert_eq!(parse_beep_group("Test:beep=100ms,gap=500ms").unwrap_err(), "unknown status Test in beep group Test:beep=100ms,gap=500ms");
+    assert!(parse_beep_group("TestInProgress:count=two,beep=100ms,gap=500ms").unwrap_err().starts_with("invalid count two"));
+    assert!(parse_beep_group("TestInProgress:count=1,beep=100ms,gap=500ms").unwrap_err().starts_with("invalid count 1"));
+    assert!(parse_beep_group("TestInProgress:count=11,beep=100ms,gap=500ms").unwrap_err().starts_with("invalid count 11"));
+    assert!(parse_beep_group("TestInProgress:beep=100,gap=500ms").unwrap_err().starts_with("invalid beep 100"));
+    assert!(parse_beep_group("TestInProgress:beep=100ms").unwrap_err().starts_with("invalid beep group"));
+    assert!(parse_beep_group("TestInProgress:beep=100ms,gap=500ms,gap=1s").unwrap_err().starts_with("invalid beep group"));
+    assert_eq!(parse_beep_group("TestInProgress:beep=100ms,gap=300ms").unwrap_err(), "gap of beep group TestInProgress:beep=100ms,gap=300ms has to be longer than 300ms");
+  }
+
+  #[test]
+  fn matches_whole_groups_only() {
+    let groups = [parse_beep_group("TestInProgress:beep=100ms,gap=500ms").unwrap()];
+    assert_eq!(get_match(&groups, &[100], &[]), Some(GroupMatch::Started));
+    assert_eq!(get_match(&groups, &[250, 100], &[30_000]), Some(GroupMatch::Started));
+    assert_eq!(get_match(&groups, &[250], &[30_000]), None);
+    let Some(GroupMatch::Complete(classification)) = get_match(&groups, &[250, 100, 100], &[30_000, 500]) else {
+      panic!("expected a whole group");
+    };
+    assert_eq!(classification.status, Status::TestInProgress);
+    assert_eq!((classification.beep_duration, classification.inter_beep_duration), (Duration::from_millis(100), Duration::from_millis(500)));
+    assert_eq!(classification.confidence, 1.0);
+    // The pause after the group starts the next one
+    assert_eq!(get_match(&groups, &[100, 100, 100], &[500, 10_000]), Some(GroupMatch::Started));
+    // Three beeps are not a double beep
+    assert_eq!(get_match(&groups, &[100, 100, 100], &[500, 500]), None);
+  }
+
+  #[test]
+  fn confidence_is_that_of_the_furthest_duration() {
+    let groups = [parse_beep_group("TestInProgress:beep=100ms±40ms,gap=500ms±100ms").unwrap()];
+    let Some(GroupMatch::Complete(classification)) = get_match(&groups, &[120, 100], &[550]) else {
+      panic!("expected a whole group");
+    };
+    assert!((classification.confidence - 0.5).abs() < 1e-9);
+    assert_eq!(get_match(&groups, &[100, 100], &[650]), Some(GroupMatch::Started));
+  }
+
+  #[test]
+  fn first_group_completed_wins() {
+    let groups = [
+      parse_beep_group("ReplaceBattery:count=3,beep=100ms,gap=500ms").unwrap(),
+      parse_beep_group("TestInProgress:beep=100ms,gap=500ms").unwrap(),
+    ];
+    let Some(GroupMatch::Complete(classification)) = get_match(&groups, &[100, 100], &[500]) else {
+      panic!("expected a whole group");
+    };
+    assert_eq!(classification.status, Status::TestInProgress);
+    let Some(GroupMatch::Complete(classification)) = get_match(&groups, &[100, 100, 100], &[500, 500]) else {
+      panic!("expected a whole group");
+    };
+    assert_eq!(classification.status, Status::ReplaceBattery);
+  }
+}
@@ -0,0 +1,151 @@
+use crate::status::{Status, get_status_from_name};
+
+// Ordered from the least to the most severe
+#[derive(Eq, PartialEq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum Severity {
+  Info,
+  Warning,
+  Critical,
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Action {
+  None,
+  Monitor,
+  PrepareShutdown,
+  ShutdownNow,
+}
+
+// Structured counterpart to the human readable description of a status
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct Guidance {
+  pub severity: Severity,
+  pub action: Action,
+}
+
+const SEVERITY_NAMES: [(Severity, &str); 3] = [
+  (Severity::Info, "info"),
+  (Severity::Warning, "warning"),
+  (Severity::Critical, "critical"),
+];
+
+const ACTION_NAMES: [(Action, &str); 4] = [
+  (Action::None, "none"),
+  (Action::Monitor, "monitor"),
+  (Action::PrepareShutdown, "prepare-shutdown"),
+  (Action::ShutdownNow, "shutdown-now"),
+];
+
+const STATUS_GUIDANCE: [(Status, Guidance); 15] = [
+  (Status::OnBattery, Guidance { severity: Severity::Warning, action: Action::Monitor }),
+  (Status::LowOnBattery, Guidance { severity: Severity::Critical, action: Action::ShutdownNow }),
+  (Status::NoLoadOnBattery, Guidance { severity: Severity::Warning, action: Action::PrepareShutdown }),
+  (Status::OverloadOrShortCircuitOnBattery, Guidance { severity: Severity::Critical, action: Action::PrepareShutdown }),
+  (Status::OverloadOrShortCircuitOnMains, Guidance { severity: Severity::Warning, action: Action::Monitor }),
+  (Status::AdvanceLowRuntimeOnMains, Guidance { severity: Severity::Warning, action: Action::Monitor }),
+  (Status::OverTemperatureOnMains, Guidance { severity: Severity::Warning, action: Action::Monitor }),
+  (Status::OnMains, Guidance { severity: Severity::Info, action: Action::None }),
+  // The mains is still there, the backup is only evening it out
+  (Status::VoltageRegulating, Guidance { severity: Severity::Info, action: Action::None }),
+  (Status::OverTemperatureOnBatteryOrInternalError, Guidance { severity: Severity::Critical, action: Action::PrepareShutdown }),
+  (Status::ReplaceBattery, Guidance { severity: Severity::Warning, action: Action::Monitor }),
+  // The connected devices have already lost power by then, so there is nothing left to shut down
+  (Status::PowerOff, Guidance { severity: Severity::Critical, action: Action::None }),
+  // Started on purpose, nothing needs doing about it
+  (Status::TestInProgress, Guidance { severity: Severity::Info, action: Action::None }),
+  (Status::SensorConflict, Guidance { severity: Severity::Warning, action: Action::Monitor }),
+  (Status::Unknown, Guidance { severity: Severity::Warning, action: Action::Monitor }),
+];
+
+impl Severity {
+  pub fn name(self) -> &'static str {
+    SEVERITY_NAMES.iter().find(|severity_name| severity_name.0 == self).unwrap().1
+  }
+}
+
+impl Action {
+  pub fn name(self) -> &'static str {
+    ACTION_NAMES.iter().find(|action_name| action_name.0 == self).unwrap().1
+  }
+}
+
+// The default guidance with any user overrides applied on top
+#[derive(Clone, Default)]
+pub struct GuidanceTable {
+  overrides: Vec<(Status, Guidance)>,
+}
+
+impl GuidanceTable {
+  pub fn new(overrides: Vec<(Status, Guidance)>) -> GuidanceTable {
+    GuidanceTable { overrides }
+  }
+
+  pub fn get(&self, status: Status) -> Guidance {
+    self.overrides.iter().rev()
+      .chain(STATUS_GUIDANCE.iter())
+      .find(|status_guidance| status_guidance.0 == status)
+      .unwrap()
+      .1
+  }
+}
+
+// Parses an override written as "<status>=<severity>:<action>", as in "OnBattery=critical:prepare-shutdown"
+pub fn parse_guidance_override(value: &str) -> Result<(Status, Guidance), String> {
+  let invalid_override = || format!("invalid guidance {}, expected <status>=<severity>:<action>", value);
+
+  let (status_name, guidance) = value.split_once('=').ok_or_else(invalid_override)?;
+  let (severity_name, action_name) = guidance.split_once(':').ok_or_else(invalid_override)?;
+
+  let status = get_status_from_name(status_name).ok_or_else(|| format!("unknown status {} in guidance {}", status_name, value))?;
+  let severity = SEVERITY_NAMES.iter()
+    .find(|severity_name_pair| severity_name_pair.1 == severity_name)
+    .ok_or_else(|| format!("unknown severity {} in guidance {}", severity_name, value))?
+    .0;
+  let action = ACTION_NAMES.iter()
+    .find(|action_name_pair| action_name_pair.1 == action_name)
+    .ok_or_else(|| format!("unknown action {} in guidance {}", action_name, value))?
+    .0;
+
+  Ok((status, Guidance { severity, action }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn defaults_follow_the_descriptions() {
+    let table = GuidanceTable::new(vec![]);
+    assert_eq!(table.get(Status::OnMains), Guidance { severity: Severity::Info, action: Action::None });
+    assert_eq!(table.get(Status::LowOnBattery), Guidance { severity: Severity::Critical, action: Action::ShutdownNow });
+  }
+
+  #[test]
+  fn overrides_replace_defaults() {
+    let table = GuidanceTable::new(vec![
+      parse_guidance_override("OnBattery=critical:prepare-shutdown").unwrap(),
+      parse_guidance_override("OnBattery=info:none").unwrap(),
+    ]);
+    assert_eq!(table.get(Status::OnBattery), Guidance { severity: Severity::Info, action: Action::None });
+    assert_eq!(table.get(Status::OnMains), Guidance { severity: Severity::Info, action: Action::None });
+  }
+
+  #[test]
+  fn rejects_invalid_overrides() {
+    assert!(parse_guidance_override("OnBattery").unwrap_err().starts_with("invalid guidance"));
+    assert!(parse_guidance_override("OnBattery=critical").unwrap_err().starts_with("invalid guidance"));
+    assert!(parse_guidance_override("Battery=critical:none").unwrap_err().starts_with("unknown status Battery"));
+    assert!(parse_guidance_override("OnBattery=fatal:none").unwrap_err().starts_with("unknown severity fatal"));
+    assert!(parse_guidance_override("OnBattery=critical:panic").unwrap_err().starts_with("unknown action panic"));
+  }
+
+  #[test]
+  fn names_round_trip() {
+    for severity_name in SEVERITY_NAMES {
+      assert_eq!(severity_name.0.name(), severity_name.1);
+    }
+    for action_name in ACTION_NAMES {
+      assert_eq!(action_name.0.name(), action_name.1);
+    }
+  }
+}
@@ -0,0 +1,25 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// Adds to a history of durations kept oldest first, dropping the oldest once there are more than capacity of them,
+// which takes the same time however long the history is
+pub fn push_capped(history: &mut VecDeque<Duration>, duration: Duration, capacity: usize) {
+  history.push_back(duration);
+  if history.len() > capacity {
+    history.pop_front();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn drops_the_oldest_past_the_capacity() {
+    let mut history = VecDeque::new();
+    for millis in 1..=4 {
+      push_capped(&mut history, Duration::from_millis(millis), 3);
+    }
+    assert_eq!(history, [Duration::from_millis(2), Duration::from_millis(3), Duration::from_millis(4)]);
+  }
+}
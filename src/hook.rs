@@ -0,0 +1,299 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::json::get_transition_json;
+use crate::logging::{error, warn};
+use crate::notifier::{Notifier, StatusEvent};
+use crate::status::get_status_description;
+
+// How long a webhook gets to connect and then to answer, it's on a thread of its own either way
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Runs a shell command on every reported transition, with {status} and {description} replaced by the new status and its description,
+// each run waited on by a thread of its own so a hung command never holds up detection, a failing one is only logged
+pub struct CommandNotifier {
+  command: String,
+}
+
+impl CommandNotifier {
+  pub fn new(command: String) -> CommandNotifier {
+    CommandNotifier { command }
+  }
+}
+
+impl Notifier for CommandNotifier {
+  fn notify(&mut self, event: &StatusEvent) {
+    let StatusEvent::Transition { transition, .. } = event else {
+      return;
+    };
+    let command = get_command_line(&self.command, &format!("{:?}", transition.to), get_status_description(transition.to));
+    let mut child = match Command::new("sh").arg("-c").arg(&command).stdin(Stdio::null()).spawn() {
+      Ok(child) => child,
+      Err(error) => {
+        error!("Could not run {}: {}", command, error);
+        return;
+      }
+    };
+    thread::spawn(move || match child.wait() {
+      Ok(exit_status) if !exit_status.success() => warn!("{} failed with {}", command, exit_status),
+      Ok(_) => {},
+      Err(error) => error!("Could not wait for {}: {}", command, error),
+    });
+  }
+}
+
+// The values are quoted for the shell, the descriptions have spaces and commas of their own
+fn get_command_line(command: &str, status: &str, description: &str) -> String {
+  command
+    .replace("{status}", &get_shell_quoted(status))
+    .replace("{description}", &get_shell_quoted(description))
+}
+
+fn get_shell_quoted(value: &str) -> String {
+  format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+// Hands a transition on only once its status has been reported for the delay with no other transition or clearing in between,
+// so a glitch that is reported and gone again never gets as far as a hook shutting something down, the sinks still reporting it right away
+pub struct DelayedNotifier<N> {
+  notifier: Arc<Mutex<N>>,
+  delay: Duration,
+  // Counts the events that have come along, a transition waiting out its delay is handed on only if it is still the latest of them
+  event_count: Arc<AtomicU64>,
+}
+
+impl<N: Notifier + Send + 'static> DelayedNotifier<N> {
+  pub fn new(notifier: N, delay: Duration) -> DelayedNotifier<N> {
+    DelayedNotifier { notifier: Arc::new(Mutex::new(notifier)), delay, event_count: Arc::new(AtomicU64::new(0)) }
+  }
+}
+
+impl<N: Notifier + Send + 'static> Notifier for DelayedNotifier<N> {
+  fn notify(&mut self, event: &StatusEvent) {
+    let StatusEvent::Transition { transition } = event else {
+      // Clearing a status is as much the end of it as the next transition, anything else has nothing to confirm
+      if let StatusEvent::Cleared { .. } = event {
+        self.event_count.fetch_add(1, Ordering::SeqCst);
+      }
+      self.notifier.lock().unwrap().notify(event);
+      return;
+    };
+    let count = self.event_count.fetch_add(1, Ordering::SeqCst) + 1;
+    let (transition, notifier, event_count, delay) = (**transition, self.notifier.clone(), self.event_count.clone(), self.delay);
+    thread::spawn(move || {
+      thread::sleep(delay);
+      if event_count.load(Ordering::SeqCst) == count {
+        notifier.lock().unwrap().notify(&StatusEvent::Transition { transition: &transition });
+      }
+    });
+  }
+}
+
+// Parses a hook given with the delay before acting on a transition as "<delay secs>:<hook>", the hook being anything after the first colon
+pub fn parse_delayed_hook(value: &str) -> Result<(Duration, &str), String> {
+  let invalid_hook = || format!("invalid delayed hook {}, expected <delay secs>:<hook>", value);
+  let (delay_secs, hook) = value.split_once(':').ok_or_else(invalid_hook)?;
+  let delay_secs = delay_secs.parse().map_err(|_| invalid_hook())?;
+  if hook.is_empty() {
+    return Err(invalid_hook());
+  }
+  Ok((Duration::from_secs(delay_secs), hook))
+}
+
+// Where a webhook is POSTed to, parsed from "http://<host>[:<port>][/<path>]"
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct WebhookUrl {
+  pub host: String,
+  pub port: u16,
+  pub path: String,
+}
+
+// Only plain HTTP, there is nothing to speak TLS with
+pub fn parse_webhook_url(value: &str) -> Result<WebhookUrl, String> {
+  let rest = value.strip_prefix("http://").ok_or_else(|| format!("invalid webhook URL {}, only http:// URLs are supported", value))?;
+  let (authority, path) = rest.find('/').map_or((rest, "/"), |index| rest.split_at(index));
+  let (host, port) = match authority.rsplit_once(':') {
+    Some((host, port)) => (host, port.parse().map_err(|_| format!("invalid port {} in webhook URL {}", port, value))?),
+    None => (authority, 80),
+  };
+  if host.is_empty() {
+    return Err(format!("no host in webhook URL {}", value));
+  }
+  Ok(WebhookUrl { host: host.to_string(), port, path: path.to_string() })
+}
+
+// POSTs the JSON object of every reported transition to the URL, each on a thread of its own for the same reason as commands
+pub struct WebhookNotifier {
+  url: WebhookUrl,
+}
+
+impl WebhookNotifier {
+  pub fn new(url: WebhookUrl) -> WebhookNotifier {
+    WebhookNotifier { url }
+  }
+}
+
+impl Notifier for WebhookNotifier {
+  fn notify(&mut self, event: &StatusEvent) {
+    let StatusEvent::Transition { transition, .. } = event else {
+      return;
+    };
+    let url = self.url.clone();
+    let body = get_transition_json(transition);
+    thread::spawn(move || {
+      if let Err(error) = post_webhook(&url, &body) {
+        warn!("Webhook to http://{}:{}{} failed: {}", url.host, url.port, url.path, error);
+      }
+    });
+  }
+}
+
+// Anything but a 2xx status line is a failure, the rest of the answer is of no interest
+fn post_webhook(url: &WebhookUrl, body: &str) -> Result<(), String> {
+  let address = (url.host.as_str(), url.port).to_socket_addrs()
+    .map_err(|error| error.to_string())?
+    .next()
+    .ok_or_else(|| format!("{} has no address", url.host))?;
+  let mut stream = TcpStream::connect_timeout(&address, WEBHOOK_TIMEOUT).map_err(|error| error.to_string())?;
+  stream.set_read_timeout(Some(WEBHOOK_TIMEOUT)).map_err(|error| error.to_string())?;
+  write!(
+    stream,
+    "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    url.path, url.host, body.len(), body,
+  ).and_then(|_| stream.flush()).map_err(|error| error.to_string())?;
+
+  let mut status_line = String::new();
+  BufReader::new(stream).read_line(&mut status_line).map_err(|error| error.to_string())?;
+  match status_line.split_whitespace().nth(1) {
+    Some(code) if code.starts_with('2') => Ok(()),
+    _ => Err(format!("answered {}", status_line.trim())),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Read;
+  use std::net::TcpListener;
+  use std::time::{Instant, SystemTime};
+  use std::{env, fs, process};
+
+  use crate::guidance::GuidanceTable;
+  use crate::report::Origin;
+  use crate::state::Transition;
+  use crate::status::Status;
+
+  fn get_transition(to: Status) -> Transition {
+    Transition {
+      from: None,
+      to,
+      guidance: GuidanceTable::new(vec![]).get(to),
+      cleared: false,
+      confidence: 1.0,
+      beep_duration: Duration::from_millis(250),
+      inter_beep_duration: Duration::from_secs(60),
+      origin: Origin::Observed,
+      at: SystemTime::now(),
+      at_instant: Instant::now(),
+      sequence: 1,
+      in_maintenance_window: false,
+    }
+  }
+
+  #[test]
+  fn substitutes_quoted_placeholders() {
+    assert_eq!(get_command_line("notify {status} {description}", "OnBattery", "On battery power"), "notify 'OnBattery' 'On battery power'");
+    assert_eq!(get_command_line("echo {description}", "PowerOff", "it's off"), "echo 'it'\\''s off'");
+  }
+
+  #[test]
+  fn runs_the_command_on_transitions_only() {
+    let path = env::temp_dir().join(format!("ups-power-status-hook-{}.txt", process::id()));
+    let mut notifier = CommandNotifier::new(format!("echo {{status}} >> {}", path.display()));
+    notifier.notify(&StatusEvent::Alert { alert: "Wear", at: SystemTime::now() });
+    notifier.notify(&StatusEvent::Transition { transition: &get_transition(Status::LowOnBattery) });
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while fs::read_to_string(&path).unwrap_or_default().is_empty() && Instant::now() < deadline {
+      thread::sleep(Duration::from_millis(10));
+    }
+    let contents = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(contents, "LowOnBattery\n");
+  }
+
+  // Every transition it is handed, by status
+  struct RecordingNotifier {
+    statuses: Arc<Mutex<Vec<Status>>>,
+  }
+
+  impl Notifier for RecordingNotifier {
+    fn notify(&mut self, event: &StatusEvent) {
+      if let StatusEvent::Transition { transition } = event {
+        self.statuses.lock().unwrap().push(transition.to);
+      }
+    }
+  }
+
+  #[test]
+  fn acts_only_on_transitions_outlasting_the_delay() {
+    let statuses = Arc::new(Mutex::new(vec![]));
+    let mut notifier = DelayedNotifier::new(RecordingNotifier { statuses: statuses.clone() }, Duration::from_millis(100));
+
+    // A glitch, gone again before its delay is up, and a status cleared before it is
+    notifier.notify(&StatusEvent::Transition { transition: &get_transition(Status::LowOnBattery) });
+    notifier.notify(&StatusEvent::Transition { transition: &get_transition(Status::OnMains) });
+    thread::sleep(Duration::from_millis(200));
+    notifier.notify(&StatusEvent::Transition { transition: &get_transition(Status::PowerOff) });
+    notifier.notify(&StatusEvent::Cleared { cleared_status: Status::PowerOff, by: Status::OnBattery, at: SystemTime::now() });
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(*statuses.lock().unwrap(), vec![Status::OnMains]);
+  }
+
+  #[test]
+  fn parses_delayed_hooks() {
+    assert_eq!(parse_delayed_hook("60:shutdown -h now"), Ok((Duration::from_secs(60), "shutdown -h now")));
+    assert_eq!(parse_delayed_hook("0:http://nas.local:8123/ups"), Ok((Duration::ZERO, "http://nas.local:8123/ups")));
+    assert!(parse_delayed_hook("shutdown -h now").unwrap_err().starts_with("invalid delayed hook"));
+    assert!(parse_delayed_hook("soon:shutdown").unwrap_err().starts_with("invalid delayed hook"));
+    assert!(parse_delayed_hook("60:").unwrap_err().starts_with("invalid delayed hook"));
+  }
+
+  #[test]
+  fn parses_webhook_urls() {
+    assert_eq!(parse_webhook_url("http://nas.local:8123/api/ups"), Ok(WebhookUrl { host: "nas.local".to_string(), port: 8123, path: "/api/ups".to_string() }));
+    assert_eq!(parse_webhook_url("http://10.0.0.2"), Ok(WebhookUrl { host: "10.0.0.2".to_string(), port: 80, path: "/".to_string() }));
+    assert!(parse_webhook_url("https://example.com/").unwrap_err().contains("only http://"));
+    assert!(parse_webhook_url("http://example.com:http/").unwrap_err().starts_with("invalid port http"));
+    assert!(parse_webhook_url("http:///path").unwrap_err().starts_with("no host"));
+  }
+
+  #[test]
+  fn posts_transitions_as_json() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let mut notifier = WebhookNotifier::new(parse_webhook_url(&format!("http://127.0.0.1:{}/ups", port)).unwrap());
+    let transition = get_transition(Status::OnBattery);
+    notifier.notify(&StatusEvent::Transition { transition: &transition });
+
+    let (mut stream, _) = listener.accept().unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let expected_body = get_transition_json(&transition);
+    let mut request = vec![];
+    let mut buffer = [0; 1024];
+    while !String::from_utf8_lossy(&request).ends_with(&expected_body) {
+      let count = stream.read(&mut buffer).unwrap();
+      assert!(count > 0);
+      request.extend_from_slice(&buffer[..count]);
+    }
+    stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+    let request = String::from_utf8(request).unwrap();
+    assert!(request.starts_with("POST /ups HTTP/1.1\r\n"));
+    assert!(request.contains(&format!("Content-Length: {}\r\n", expected_body.len())));
+  }
+}
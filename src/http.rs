@@ -0,0 +1,293 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::json::{get_measurement_json, get_timeline_json, get_totals_json, get_transition_json};
+use crate::logging::{debug, warn};
+use crate::state::SharedState;
+
+const INDEX_HTML: &str = include_str!("web/index.html");
+
+// Idle event streams get a comment this often, which is also how a client that went away gets noticed
+const EVENTS_KEEP_ALIVE_DURATION: Duration = Duration::from_secs(15);
+
+// Serves the page, the current status and the recent transitions on its own thread so requests never hold up beep timing,
+// failing to bind only warns as the detector itself is still useful without it
+pub fn start_http_server(address: &str, state: SharedState, raw_token: Option<String>) {
+  let listener = match TcpListener::bind(address) {
+    Ok(listener) => listener,
+    Err(error) => {
+      warn!("Could not start the HTTP server on {}: {}", address, error);
+      return;
+    }
+  };
+
+  thread::spawn(move || {
+    for stream in listener.incoming().flatten() {
+      // Every connection gets its own thread as event streams stay open for as long as the client wants
+      let state = state.clone();
+      let raw_token = raw_token.clone();
+      thread::spawn(move || {
+        if let Err(error) = handle_connection(stream, &state, raw_token.as_deref()) {
+          debug!("HTTP request failed: {}", error);
+        }
+      });
+    }
+  });
+}
+
+fn handle_connection(mut stream: TcpStream, state: &SharedState, raw_token: Option<&str>) -> std::io::Result<()> {
+  let mut reader = BufReader::new(stream.try_clone()?);
+  let mut request_line = String::new();
+  reader.read_line(&mut request_line)?;
+  // Only the bearer token is of any use, the other headers are read so that the client doesn't see the connection reset under it
+  let mut bearer_token = None;
+  let mut header = String::new();
+  while reader.read_line(&mut header)? > 2 {
+    if let Some((name, value)) = header.split_once(':')
+      && name.eq_ignore_ascii_case("authorization") {
+      bearer_token = value.trim().strip_prefix("Bearer ").map(str::to_string);
+    }
+    header.clear();
+  }
+
+  let mut request_parts = request_line.split_whitespace();
+  let (method, target) = (request_parts.next().unwrap_or(""), request_parts.next().unwrap_or(""));
+  let (path, query) = target.split_once('?').unwrap_or((target, ""));
+  if (method, path) == ("GET", "/events") {
+    return stream_events(stream, state);
+  }
+  // The token can also come in the query, as EventSource in browsers can't set headers
+  let query_token = query.split('&').find_map(|parameter| parameter.strip_prefix("token="));
+  let is_raw_allowed = raw_token.is_some_and(|raw_token| bearer_token.as_deref() == Some(raw_token) || query_token == Some(raw_token));
+  if (method, path) == ("GET", "/raw") && is_raw_allowed {
+    return stream_measurements(stream, state);
+  }
+
+  let (status_line, content_type, body) = match (method, path) {
+    ("GET", "/") => ("200 OK", "text/html; charset=utf-8", INDEX_HTML.to_string()),
+    ("GET", "/status") => ("200 OK", "application/json", get_status_json(state)),
+    ("GET", "/history") => ("200 OK", "application/json", get_history_json(state)),
+    ("GET", "/timeline") => ("200 OK", "application/json", get_timeline_json(&state.lock().unwrap().get_timeline(SystemTime::now()))),
+    ("GET", "/stats") => ("200 OK", "application/json", get_totals_json(&state.lock().unwrap().get_totals(Instant::now()))),
+    // Not served at all without a token configured, so there is no telling it apart from any path that doesn't exist
+    ("GET", "/raw") if raw_token.is_some() => ("401 Unauthorized", "text/plain", "Unauthorized".to_string()),
+    ("GET", _) => ("404 Not Found", "text/plain", "Not found".to_string()),
+    _ => ("405 Method Not Allowed", "text/plain", "Method not allowed".to_string()),
+  };
+
+  write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}", status_line, content_type, body.len(), body)?;
+  stream.flush()
+}
+
+// Server-Sent Events stream of every transition from now on
+fn stream_events(mut stream: TcpStream, state: &SharedState) -> std::io::Result<()> {
+  let receiver = state.lock().unwrap().subscribe();
+  write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\nConnection: keep-alive\r\n\r\n")?;
+  stream.flush()?;
+
+  loop {
+    match receiver.recv_timeout(EVENTS_KEEP_ALIVE_DURATION) {
+      Ok(transition) => {
+        let transition_json = get_transition_json(&transition);
+        // Clearing a critical status gets an event of its own ahead of the status one, for clients that only listen for that
+        if transition.cleared {
+          write!(stream, "event: cleared\ndata: {}\n\n", transition_json)?;
+        }
+        write!(stream, "event: status\ndata: {}\n\n", transition_json)?
+      },
+      Err(RecvTimeoutError::Timeout) => write!(stream, ": keep-alive\n\n")?,
+      Err(RecvTimeoutError::Disconnected) => return Ok(()),
+    }
+    stream.flush()?;
+  }
+}
+
+// Server-Sent Events stream of every measured beep and inter beep pair from now on
+fn stream_measurements(mut stream: TcpStream, state: &SharedState) -> std::io::Result<()> {
+  let receiver = state.lock().unwrap().subscribe_to_measurements();
+  write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\nConnection: keep-alive\r\n\r\n")?;
+  stream.flush()?;
+
+  loop {
+    match receiver.recv_timeout(EVENTS_KEEP_ALIVE_DURATION) {
+      Ok((beep, inter_beep)) => write!(stream, "event: measurement\ndata: {}\n\n", get_measurement_json(beep, inter_beep))?,
+      Err(RecvTimeoutError::Timeout) => write!(stream, ": keep-alive\n\n")?,
+      Err(RecvTimeoutError::Disconnected) => return Ok(()),
+    }
+    stream.flush()?;
+  }
+}
+
+fn get_status_json(state: &SharedState) -> String {
+  let state = state.lock().unwrap();
+  match &state.current {
+    Some(current) => {
+      let transition_json = get_transition_json(current);
+      // Splice the time in state into the transition object
+      format!("{},\"in_state_secs\":{}}}", &transition_json[..transition_json.len() - 1], current.at_instant.elapsed().as_secs())
+    },
+    None => "{\"status\":null}".to_string(),
+  }
+}
+
+fn get_history_json(state: &SharedState) -> String {
+  let state = state.lock().unwrap();
+  let transitions: Vec<String> = state.transitions.iter().map(get_transition_json).collect();
+  format!("[{}]", transitions.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Read;
+  use std::time::Duration;
+  use std::time::{Instant, SystemTime};
+
+  use crate::guidance::GuidanceTable;
+  use crate::report::Origin;
+  use crate::state::Transition;
+  use crate::status::Status;
+
+  fn request(state: &SharedState, request: &str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    client.write_all(request.as_bytes()).unwrap();
+    let (server_stream, _) = listener.accept().unwrap();
+    handle_connection(server_stream, state, Some("s3cret")).unwrap();
+
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    response
+  }
+
+  fn get_transition(to: Status) -> Transition {
+    Transition {
+      from: None,
+      to,
+      guidance: GuidanceTable::new(vec![]).get(to),
+      cleared: false,
+      confidence: 1.0,
+      beep_duration: Duration::from_millis(250),
+      inter_beep_duration: Duration::from_secs(60),
+      origin: Origin::Observed,
+      at: SystemTime::now(),
+      at_instant: Instant::now(),
+      sequence: 0,
+      in_maintenance_window: false,
+    }
+  }
+
+  #[test]
+  fn streams_transitions_as_events() {
+    let state = SharedState::default();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    client.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+    let (server_stream, _) = listener.accept().unwrap();
+    let server_state = state.clone();
+    thread::spawn(move || handle_connection(server_stream, &server_state, None));
+
+    let mut reader = BufReader::new(client);
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "HTTP/1.1 200 OK\r\n");
+    while line != "\r\n" {
+      line.clear();
+      reader.read_line(&mut line).unwrap();
+    }
+
+    while state.lock().unwrap().subscribers_count() == 0 {
+      thread::yield_now();
+    }
+    state.lock().unwrap().record(get_transition(Status::LowOnBattery));
+
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "event: status\n");
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    assert!(line.starts_with("data: {\"schema_version\":1,\"from\":null,\"status\":\"LowOnBattery\""));
+    assert!(line.contains("\"beep_ms\":250,\"inter_beep_ms\":60000"));
+
+    state.lock().unwrap().record(Transition { from: Some(Status::LowOnBattery), cleared: true, ..get_transition(Status::OnMains) });
+    let mut lines = vec![];
+    for _ in 0..6 {
+      line.clear();
+      reader.read_line(&mut line).unwrap();
+      lines.push(line.clone());
+    }
+    assert_eq!(lines[0], "\n");
+    assert_eq!(lines[1], "event: cleared\n");
+    assert!(lines[2].contains("\"cleared\":true"));
+    assert_eq!(lines[4], "event: status\n");
+  }
+
+  #[test]
+  fn streams_measurements_with_the_token() {
+    let state = SharedState::default();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    client.write_all(b"GET /raw HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n").unwrap();
+    let (server_stream, _) = listener.accept().unwrap();
+    let server_state = state.clone();
+    thread::spawn(move || handle_connection(server_stream, &server_state, Some("s3cret")));
+
+    let mut reader = BufReader::new(client);
+    let mut line = String::new();
+    while line != "\r\n" {
+      line.clear();
+      reader.read_line(&mut line).unwrap();
+    }
+    while state.lock().unwrap().measurement_subscribers_count() == 0 {
+      thread::yield_now();
+    }
+    state.lock().unwrap().publish_measurement(Duration::from_millis(250), Duration::from_millis(1020));
+
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "event: measurement\n");
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "data: {\"beep_ms\":250,\"inter_beep_ms\":1020}\n");
+  }
+
+  #[test]
+  fn refuses_measurements_without_the_token() {
+    let state = SharedState::default();
+    assert!(request(&state, "GET /raw HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 401"));
+    assert!(request(&state, "GET /raw?token=guess HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 401"));
+    assert!(request(&state, "GET /raw HTTP/1.1\r\nAuthorization: Bearer guess\r\n\r\n").starts_with("HTTP/1.1 401"));
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    client.write_all(b"GET /raw?token=s3cret HTTP/1.1\r\n\r\n").unwrap();
+    let (server_stream, _) = listener.accept().unwrap();
+    handle_connection(server_stream, &state, None).unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 404"));
+  }
+
+  #[test]
+  fn serves_status_and_page() {
+    let state = SharedState::default();
+    assert!(request(&state, "GET /status HTTP/1.1\r\nHost: ups\r\n\r\n").ends_with("{\"status\":null}"));
+
+    state.lock().unwrap().record(get_transition(Status::OnBattery));
+    let response = request(&state, "GET /status HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains("\"status\":\"OnBattery\""));
+    assert!(response.ends_with("\"in_state_secs\":0}"));
+    assert!(request(&state, "GET /history HTTP/1.1\r\n\r\n").contains("[{\"schema_version\":1,\"from\":null,\"status\":\"OnBattery\""));
+
+    assert!(request(&state, "GET /stats HTTP/1.1\r\n\r\n").contains("{\"totals_ms\":{\"OnBattery\":"));
+    assert!(request(&state, "GET /timeline HTTP/1.1\r\n\r\n").contains("[{\"status\":\"OnBattery\",\"start\":"));
+
+    assert!(request(&state, "GET / HTTP/1.1\r\n\r\n").contains("<!DOCTYPE html>"));
+    assert!(request(&state, "GET /missing HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+    assert!(request(&state, "POST /status HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+  }
+}
@@ -0,0 +1,289 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "http")]
+use crate::state::StatusInterval;
+use crate::state::Transition;
+#[cfg(feature = "http")]
+use crate::stats::StatusTotals;
+use crate::summary::Summary;
+use crate::status::{Status, get_status_description};
+
+// Carried by every transition, alert and summary object, bumped whenever a field is renamed, removed or changes meaning,
+// so consumers can tell which layout they are reading, fields only ever being added doesn't need a bump
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+pub fn escape_json_string(value: &str) -> String {
+  let mut escaped = String::with_capacity(value.len() + 2);
+  escaped.push('"');
+  for character in value.chars() {
+    match character {
+      '"' => escaped.push_str("\\\""),
+      '\\' => escaped.push_str("\\\\"),
+      '\n' => escaped.push_str("\\n"),
+      '\r' => escaped.push_str("\\r"),
+      '\t' => escaped.push_str("\\t"),
+      character if (character as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", character as u32)),
+      character => escaped.push(character),
+    }
+  }
+  escaped.push('"');
+  escaped
+}
+
+pub fn get_unix_millis(time: SystemTime) -> u128 {
+  time.duration_since(UNIX_EPOCH).map(|duration| duration.as_millis()).unwrap_or(0)
+}
+
+pub fn get_transition_json(transition: &Transition) -> String {
+  format!(
+    "{{\"schema_version\":{},\"from\":{},\"status\":{},\"description\":{},\"severity\":{},\"action\":{},\"cleared\":{},\"confidence\":{},\"beep_ms\":{},\"inter_beep_ms\":{},\"origin\":{},\"at\":{},\"sequence\":{},\"maintenance\":{}}}",
+    JSON_SCHEMA_VERSION,
+    transition.from.map(|from| escape_json_string(&format!("{:?}", from))).unwrap_or_else(|| "null".to_string()),
+    escape_json_string(&format!("{:?}", transition.to)),
+    escape_json_string(get_status_description(transition.to)),
+    escape_json_string(transition.guidance.severity.name()),
+    escape_json_string(transition.guidance.action.name()),
+    transition.cleared,
+    transition.confidence,
+    transition.beep_duration.as_millis(),
+    transition.inter_beep_duration.as_millis(),
+    escape_json_string(transition.origin.name()),
+    get_unix_millis(transition.at),
+    transition.sequence,
+    transition.in_maintenance_window,
+  )
+}
+
+// The current status repeated while it holds, with since when, how long that is and the sequence number of the transition to it,
+// kept apart from transitions so nothing counting them counts this
+pub fn get_heartbeat_json(current: &Transition, at: SystemTime) -> String {
+  format!(
+    "{{\"schema_version\":{},\"heartbeat\":{{\"status\":{},\"description\":{},\"severity\":{},\"since\":{},\"since_sequence\":{},\"in_state_ms\":{},\"at\":{}}}}}",
+    JSON_SCHEMA_VERSION,
+    escape_json_string(&format!("{:?}", current.to)),
+    escape_json_string(get_status_description(current.to)),
+    escape_json_string(current.guidance.severity.name()),
+    get_unix_millis(current.at),
+    current.sequence,
+    at.duration_since(current.at).unwrap_or_default().as_millis(),
+    get_unix_millis(at),
+  )
+}
+
+// How long it has been on battery since the mains went, once that is past the warning threshold, and the threshold
+pub fn get_on_battery_warning_json(elapsed: Duration, threshold: Duration, at: SystemTime) -> String {
+  format!(
+    "{{\"schema_version\":{},\"on_battery_warning\":{{\"elapsed_ms\":{},\"threshold_ms\":{},\"at\":{}}}}}",
+    JSON_SCHEMA_VERSION,
+    elapsed.as_millis(),
+    threshold.as_millis(),
+    get_unix_millis(at),
+  )
+}
+
+// The critical status left and the one classified that left it, which is reported on its own once it is past its hold-backs
+pub fn get_cleared_json(cleared_status: Status, by: Status, at: SystemTime) -> String {
+  format!(
+    "{{\"schema_version\":{},\"cleared\":{{\"status\":{},\"by\":{},\"at\":{}}}}}",
+    JSON_SCHEMA_VERSION,
+    escape_json_string(&format!("{:?}", cleared_status)),
+    escape_json_string(&format!("{:?}", by)),
+    get_unix_millis(at),
+  )
+}
+
+// Alerts have no status of their own, such as the battery wear warning, so they are just the message
+pub fn get_alert_json(alert: &str) -> String {
+  format!("{{\"schema_version\":{},\"alert\":{}}}", JSON_SCHEMA_VERSION, escape_json_string(alert))
+}
+
+pub fn get_summary_json(summary: &Summary) -> String {
+  format!(
+    "{{\"schema_version\":{},\"summary\":{{\"period_s\":{},\"outages\":{},\"on_battery_s\":{},\"longest_on_battery_s\":{},\"replace_battery_warnings\":{}}}}}",
+    JSON_SCHEMA_VERSION,
+    summary.period.as_secs(),
+    summary.outages,
+    summary.on_battery_duration.as_secs(),
+    summary.longest_on_battery_duration.as_secs(),
+    summary.replace_battery_warnings,
+  )
+}
+
+// An array of {status, start, end} objects with times in Unix milliseconds, the layout Grafana's state timeline panel takes as it is
+#[cfg(feature = "http")]
+pub fn get_timeline_json(intervals: &[StatusInterval]) -> String {
+  let interval_objects: Vec<String> = intervals.iter()
+    .map(|interval| format!(
+      "{{\"status\":{},\"start\":{},\"end\":{}}}",
+      escape_json_string(&format!("{:?}", interval.status)),
+      get_unix_millis(interval.start),
+      get_unix_millis(interval.end),
+    ))
+    .collect();
+  format!("[{}]", interval_objects.join(","))
+}
+
+// Only ever streamed on /raw
+#[cfg(feature = "http")]
+pub fn get_measurement_json(beep: Duration, inter_beep: Duration) -> String {
+  format!("{{\"beep_ms\":{},\"inter_beep_ms\":{}}}", beep.as_millis(), inter_beep.as_millis())
+}
+
+// Milliseconds per status along with the availability as a fraction, or null when there is nothing to compute it from
+#[cfg(feature = "http")]
+pub fn get_totals_json(totals: &StatusTotals) -> String {
+  let status_totals: Vec<String> = totals.iter()
+    .map(|status_total| format!("{}:{}", escape_json_string(&format!("{:?}", status_total.0)), status_total.1.as_millis()))
+    .collect();
+  format!(
+    "{{\"totals_ms\":{{{}}},\"availability\":{}}}",
+    status_totals.join(","),
+    totals.get_availability().map(|availability| availability.to_string()).unwrap_or_else(|| "null".to_string()),
+  )
+}
+
+// Spreads a compact object over indented lines for people reading it, the strings are kept as they are
+pub fn get_pretty_json(json: &str) -> String {
+  let mut pretty = String::with_capacity(json.len() * 2);
+  let mut depth = 0;
+  let mut is_in_string = false;
+  let mut is_escaped = false;
+  let mut characters = json.chars().peekable();
+  while let Some(character) = characters.next() {
+    if is_in_string {
+      pretty.push(character);
+      match character {
+        _ if is_escaped => is_escaped = false,
+        '\\' => is_escaped = true,
+        '"' => is_in_string = false,
+        _ => {},
+      }
+      continue;
+    }
+    match character {
+      '"' => {
+        is_in_string = true;
+        pretty.push(character);
+      },
+      // Empty objects and arrays stay on one line
+      '{' | '[' if matches!(characters.peek(), Some('}' | ']')) => {
+        pretty.push(character);
+        pretty.push(characters.next().unwrap());
+      },
+      '{' | '[' => {
+        depth += 1;
+        pretty.push(character);
+        push_json_line_break(&mut pretty, depth);
+      },
+      '}' | ']' => {
+        depth -= 1;
+        push_json_line_break(&mut pretty, depth);
+        pretty.push(character);
+      },
+      ',' => {
+        pretty.push(character);
+        push_json_line_break(&mut pretty, depth);
+      },
+      ':' => pretty.push_str(": "),
+      character => pretty.push(character),
+    }
+  }
+  pretty
+}
+
+fn push_json_line_break(pretty: &mut String, depth: usize) {
+  pretty.push('\n');
+  pretty.push_str(&"  ".repeat(depth));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  use crate::guidance::GuidanceTable;
+  use crate::report::Origin;
+
+  #[cfg(feature = "http")]
+  #[test]
+  fn formats_totals() {
+    assert_eq!(get_totals_json(&StatusTotals::default()), "{\"totals_ms\":{},\"availability\":null}");
+
+    let mut totals = StatusTotals::default();
+    totals.add(Status::OnMains, Duration::from_secs(3));
+    totals.add(Status::OnBattery, Duration::from_secs(1));
+    assert_eq!(get_totals_json(&totals), "{\"totals_ms\":{\"OnMains\":3000,\"OnBattery\":1000},\"availability\":0.75}");
+  }
+
+  #[cfg(feature = "http")]
+  #[test]
+  fn formats_timelines() {
+    assert_eq!(get_timeline_json(&[]), "[]");
+    let start = UNIX_EPOCH + Duration::from_secs(1000);
+    let intervals = [
+      StatusInterval { status: Status::OnBattery, start, end: start + Duration::from_secs(60) },
+      StatusInterval { status: Status::OnMains, start: start + Duration::from_secs(60), end: start + Duration::from_secs(90) },
+    ];
+    assert_eq!(
+      get_timeline_json(&intervals),
+      "[{\"status\":\"OnBattery\",\"start\":1000000,\"end\":1060000},{\"status\":\"OnMains\",\"start\":1060000,\"end\":1090000}]",
+    );
+  }
+
+  #[test]
+  fn pretty_prints_nested_objects() {
+    assert_eq!(
+      get_pretty_json("{\"schema_version\":1,\"summary\":{\"outages\":2},\"totals_ms\":{},\"alert\":\"a {\\\"b\\\"}, c: d\"}"),
+      "{\n  \"schema_version\": 1,\n  \"summary\": {\n    \"outages\": 2\n  },\n  \"totals_ms\": {},\n  \"alert\": \"a {\\\"b\\\"}, c: d\"\n}",
+    );
+  }
+
+  #[test]
+  fn versions_streamed_objects() {
+    assert_eq!(get_alert_json("Replace the battery"), format!("{{\"schema_version\":{},\"alert\":\"Replace the battery\"}}", JSON_SCHEMA_VERSION));
+  }
+
+  #[test]
+  fn formats_heartbeats() {
+    let current = Transition {
+      from: None,
+      to: Status::OnMains,
+      guidance: GuidanceTable::new(vec![]).get(Status::OnMains),
+      cleared: false,
+      confidence: 1.0,
+      beep_duration: Duration::ZERO,
+      inter_beep_duration: Duration::from_secs(3),
+      origin: Origin::Inferred,
+      at: UNIX_EPOCH + Duration::from_secs(1000),
+      at_instant: std::time::Instant::now(),
+      sequence: 4,
+      in_maintenance_window: false,
+    };
+    assert_eq!(
+      get_heartbeat_json(&current, UNIX_EPOCH + Duration::from_secs(1300)),
+      format!("{{\"schema_version\":{},\"heartbeat\":{{\"status\":\"OnMains\",\"description\":{},\"severity\":\"info\",\"since\":1000000,\"since_sequence\":4,\"in_state_ms\":300000,\"at\":1300000}}}}",
+        JSON_SCHEMA_VERSION, escape_json_string(get_status_description(Status::OnMains))),
+    );
+  }
+
+  #[test]
+  fn on_battery_warning_carries_the_time_on_battery() {
+    assert_eq!(
+      get_on_battery_warning_json(Duration::from_secs(630), Duration::from_secs(600), UNIX_EPOCH + Duration::from_secs(2000)),
+      format!("{{\"schema_version\":{},\"on_battery_warning\":{{\"elapsed_ms\":630000,\"threshold_ms\":600000,\"at\":2000000}}}}", JSON_SCHEMA_VERSION),
+    );
+  }
+
+  #[test]
+  fn cleared_names_both_statuses() {
+    assert_eq!(
+      get_cleared_json(Status::LowOnBattery, Status::OnMains, UNIX_EPOCH + Duration::from_secs(2000)),
+      format!("{{\"schema_version\":{},\"cleared\":{{\"status\":\"LowOnBattery\",\"by\":\"OnMains\",\"at\":2000000}}}}", JSON_SCHEMA_VERSION),
+    );
+  }
+
+  #[test]
+  fn escapes_strings() {
+    assert_eq!(escape_json_string("a \"b\"\\\n\u{1}"), "\"a \\\"b\\\"\\\\\\n\\u0001\"");
+  }
+}
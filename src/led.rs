@@ -0,0 +1,68 @@
+use crate::status::{Status, get_status_from_name};
+
+// Parses a pin of the status LED written as "<pin>=<status>", as in "5=OnMains" for the pin driving its green half,
+// only statuses that tell mains from battery can be checked against so any other is rejected
+pub fn parse_led_pin(value: &str) -> Result<(u8, Status), String> {
+  let invalid_pin = || format!("invalid LED pin {}, expected <pin>=<status>", value);
+
+  let (pin, status_name) = value.split_once('=').ok_or_else(invalid_pin)?;
+  let pin = pin.parse().map_err(|_| invalid_pin())?;
+  let status = get_status_from_name(status_name).ok_or_else(|| format!("unknown status {} in LED pin {}", status_name, value))?;
+  if !status.is_on_mains() && !status.is_on_battery() {
+    return Err(format!("status {} in LED pin {} is neither on mains nor on battery", status_name, value));
+  }
+  Ok((pin, status))
+}
+
+// What the LED shows given the statuses of its lit pins, nothing when it's dark or lit in more than one color at once,
+// which is as likely to be a blink caught halfway as anything the UPS means
+pub fn get_led_status(lit_statuses: &[Status]) -> Option<Status> {
+  match lit_statuses {
+    [status] => Some(*status),
+    _ => None,
+  }
+}
+
+// Only whether each side has the UPS on mains or on battery is compared, the LED has no way of telling any finer,
+// a status that says neither can't disagree with it
+pub fn get_led_disagreement(status: Status, led_status: Status) -> Option<String> {
+  let disagrees = (led_status.is_on_mains() && (status.is_on_battery() || status == Status::PowerOff)) || (led_status.is_on_battery() && status.is_on_mains());
+  disagrees.then(|| format!(
+    "Classified {:?} from the beeps but the status LED shows {}",
+    status,
+    if led_status.is_on_mains() { "mains" } else { "battery" },
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_pins() {
+    assert_eq!(parse_led_pin("5=OnMains").unwrap(), (5, Status::OnMains));
+    assert_eq!(parse_led_pin("6=LowOnBattery").unwrap(), (6, Status::LowOnBattery));
+    assert!(parse_led_pin("5").unwrap_err().starts_with("invalid LED pin 5"));
+    assert!(parse_led_pin("green=OnMains").unwrap_err().starts_with("invalid LED pin"));
+    assert!(parse_led_pin("5=Mains").unwrap_err().starts_with("unknown status Mains"));
+    assert_eq!(parse_led_pin("5=ReplaceBattery").unwrap_err(), "status ReplaceBattery in LED pin 5=ReplaceBattery is neither on mains nor on battery");
+  }
+
+  #[test]
+  fn only_a_single_color_is_a_reading() {
+    assert_eq!(get_led_status(&[]), None);
+    assert_eq!(get_led_status(&[Status::OnBattery]), Some(Status::OnBattery));
+    assert_eq!(get_led_status(&[Status::OnMains, Status::OnBattery]), None);
+  }
+
+  #[test]
+  fn compares_mains_against_battery() {
+    assert_eq!(get_led_disagreement(Status::OnMains, Status::OnMains), None);
+    assert_eq!(get_led_disagreement(Status::AdvanceLowRuntimeOnMains, Status::OnMains), None);
+    assert_eq!(get_led_disagreement(Status::LowOnBattery, Status::OnBattery), None);
+    assert_eq!(get_led_disagreement(Status::ReplaceBattery, Status::OnBattery), None);
+    assert_eq!(get_led_disagreement(Status::Unknown, Status::OnMains), None);
+    assert_eq!(get_led_disagreement(Status::OnMains, Status::OnBattery).as_deref(), Some("Classified OnMains from the beeps but the status LED shows battery"));
+    assert_eq!(get_led_disagreement(Status::PowerOff, Status::OnMains).as_deref(), Some("Classified PowerOff from the beeps but the status LED shows mains"));
+  }
+}
@@ -0,0 +1,7 @@
+// The statuses and the matching of measured beeps to them, which needs no GPIO or anything else of the Pi
+// so it can be depended on and tested anywhere, the binary reads the beeps and reports what these make of them,
+// an embedder reading the beeps itself can follow the changes of status with the observers of a tracker
+pub mod guidance;
+pub mod history;
+pub mod observer;
+pub mod status;
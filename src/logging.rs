@@ -0,0 +1,137 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::SystemTime;
+
+use crate::clock::get_utc_timestamp;
+
+// How much goes to stderr, each level including the ones above it, reported statuses go to the sinks whatever the level
+#[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Debug)]
+pub enum Level {
+  Error,
+  Warn,
+  Info,
+  // Every edge and every classification of a measured pair
+  Debug,
+  // Every timeout and what the silence was inferred as too
+  Trace,
+}
+
+const LEVEL_NAMES: [(Level, &str); 5] = [
+  (Level::Error, "error"),
+  (Level::Warn, "warn"),
+  (Level::Info, "info"),
+  (Level::Debug, "debug"),
+  (Level::Trace, "trace"),
+];
+
+// Everything that was ever written to stderr is still written by default
+pub const DEFAULT_LEVEL: Level = Level::Info;
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(DEFAULT_LEVEL as u8);
+
+impl Level {
+  pub fn from_name(name: &str) -> Option<Level> {
+    LEVEL_NAMES.iter()
+      .find(|level_name| level_name.1.eq_ignore_ascii_case(name))
+      .map(|level_name| level_name.0)
+  }
+
+  fn name(self) -> &'static str {
+    LEVEL_NAMES.iter().find(|level_name| level_name.0 == self).map_or("", |level_name| level_name.1)
+  }
+}
+
+// Read the way RUST_LOG is elsewhere, only a plain level or the level of a "<target>=<level>" directive means anything here,
+// as everything logged is this program's own, the last one understood wins
+pub fn get_level_from_filter(filter: &str) -> Option<Level> {
+  filter.split(',')
+    .filter_map(|directive| Level::from_name(directive.rsplit_once('=').map_or(directive, |(_, level)| level).trim()))
+    .next_back()
+}
+
+// Each -v goes one level further than the default, without any RUST_LOG picks the level instead
+pub fn get_level(verbosity: u8, filter: Option<&str>) -> Level {
+  match verbosity {
+    0 => filter.and_then(get_level_from_filter).unwrap_or(DEFAULT_LEVEL),
+    1 => Level::Debug,
+    _ => Level::Trace,
+  }
+}
+
+pub fn set_max_level(level: Level) {
+  MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn is_enabled(level: Level) -> bool {
+  level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+// One line per message, led by the time it was written to the millisecond and its level
+pub fn write(level: Level, message: fmt::Arguments) {
+  eprintln!("{} {:<5} {}", get_utc_timestamp(SystemTime::now()), level.name(), message);
+}
+
+// Formatted like println!, the arguments are only formatted at all when the level is enabled
+macro_rules! log {
+  ($level:expr, $($arg:tt)*) => {{
+    let level = $level;
+    if $crate::logging::is_enabled(level) {
+      $crate::logging::write(level, format_args!($($arg)*));
+    }
+  }};
+}
+
+macro_rules! error {
+  ($($arg:tt)*) => { $crate::logging::log!($crate::logging::Level::Error, $($arg)*) };
+}
+
+// Named apart from the warn attribute, which the name would be ambiguous with where the macro is defined
+macro_rules! warning {
+  ($($arg:tt)*) => { $crate::logging::log!($crate::logging::Level::Warn, $($arg)*) };
+}
+
+macro_rules! info {
+  ($($arg:tt)*) => { $crate::logging::log!($crate::logging::Level::Info, $($arg)*) };
+}
+
+macro_rules! debug {
+  ($($arg:tt)*) => { $crate::logging::log!($crate::logging::Level::Debug, $($arg)*) };
+}
+
+macro_rules! trace {
+  ($($arg:tt)*) => { $crate::logging::log!($crate::logging::Level::Trace, $($arg)*) };
+}
+
+pub(crate) use {debug, error, info, log, trace, warning as warn};
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_filters() {
+    assert_eq!(get_level_from_filter("debug"), Some(Level::Debug));
+    assert_eq!(get_level_from_filter("WARN"), Some(Level::Warn));
+    assert_eq!(get_level_from_filter("ups_power_status_from_beeps=trace"), Some(Level::Trace));
+    assert_eq!(get_level_from_filter("info,ups_power_status_from_beeps=debug"), Some(Level::Debug));
+    assert_eq!(get_level_from_filter("loud"), None);
+    assert_eq!(get_level_from_filter(""), None);
+  }
+
+  #[test]
+  fn verbosity_goes_before_the_filter() {
+    assert_eq!(get_level(0, None), Level::Info);
+    assert_eq!(get_level(0, Some("error")), Level::Error);
+    assert_eq!(get_level(0, Some("loud")), Level::Info);
+    assert_eq!(get_level(1, Some("error")), Level::Debug);
+    assert_eq!(get_level(2, None), Level::Trace);
+    assert_eq!(get_level(3, None), Level::Trace);
+  }
+
+  #[test]
+  fn levels_include_the_ones_above_them() {
+    assert!(Level::Error < Level::Warn && Level::Debug < Level::Trace);
+    assert_eq!(Level::from_name("trace"), Some(Level::Trace));
+    assert_eq!(Level::Warn.name(), "warn");
+  }
+}
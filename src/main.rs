@@ -1,174 +1,648 @@
-use rppal::gpio::{Gpio, InputPin, Trigger, Level};
+#[cfg(feature = "adc")]
+mod adc;
+#[cfg(feature = "amqp")]
+mod amqp;
+mod calibrate;
+mod chardev;
+mod classifier;
+mod cli;
+mod clock;
+mod config;
+#[cfg(any(feature = "mqtt", feature = "amqp"))]
+mod delivery;
+mod detector;
+mod exit;
+mod expander;
+mod features;
+mod frame;
+mod glyph;
+#[cfg(test)]
+mod golden;
+mod gpio;
+mod group;
+mod hook;
+#[cfg(feature = "http")]
+mod http;
+mod json;
+mod logging;
+mod led;
+mod mains;
+mod maintenance;
+#[cfg(feature = "http")]
+mod metrics;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod noise;
+mod notifier;
+mod output;
+mod pattern;
+mod profile;
+mod pwm;
+mod ratelimit;
+mod remap;
+mod replay;
+mod report;
+mod rules;
+mod shadow;
+mod signals;
+mod socket;
+mod source;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod state;
+mod stats;
+mod summary;
+mod suspend;
+mod sweep;
+mod symbols;
+mod table;
+mod udp;
+mod validate;
+mod watchdog;
+mod wear;
+
+use std::fs;
+use std::process;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
 
-const PIN: u8 = 17;
-const MAX_ENTRIES: usize = 10;
-const ERROR_MARGIN: f64 = 0.05;
-
-const TIMEOUT_DURATION = Duration::from_secs(3);
-const ZERO_DURATION = Duration::from_millis(0);
-
-const TARGET_NORMAL_BEEP_DURATION: Duration = Duration::from_millis(250);
-const TARGET_LONG_BEEP_DURATION: Duration = Duration::from_secs(2);
-
-const BEEP_BOUNCE_MAX_DURATION: Duration = Duration::from_millis(50);
-const INTER_BEEP_BOUNCE_MAX_DURATION: Duration = Duration::from_millis(300);
-
-#[derive(Hash, Eq, PartialEq, Debug)]
-enum Status {
-  OnMains,
-  OnBattery,
-  LowOnBattery,
-  NoLoadOnBattery,
-  OverloadOrShortCircuitOnBattery,
-  OverloadOrShortCircuitOnMains,
-  AdvanceLowRuntimeOnMains,
-  OverTemperatureOnMains,
-  OverTemperatureOnBatteryOrInternalError,
-  ReplaceBattery,
-  Unknown,
-}
+// The statuses, how beeps are matched to them and how their history is capped are the library's,
+// every module here reaches them as crate::status, crate::guidance and crate::history
+use ups_power_status_from_beeps::{guidance, history, status};
+
+#[cfg(feature = "adc")]
+use adc::AdcSource;
+use calibrate::Calibrator;
+use chardev::ChardevSource;
+use clock::{Clock, EventClock, SystemClock};
+use classifier::{BuiltinClassifier, Classifier, ExternalClassifier, MeasurementPublisher};
 
-const  STATUS_DESCRIPTIONS: HashMap<Status, &str> = vec![
-  (Status::OnBattery, "On battery power, no issues detected"),
-  (Status::LowOnBattery, "Low battery, power backup will shut down in 1 minute"),
-  (Status::NoLoadOnBattery, "Battery saver mode is enabled and power load is below 30W, power backup will shut down in 2 minutes"),
-  (Status::OverloadOrShortCircuitOnBattery, "Overload or short circuit has occured on battery power, power backup will shut down in 5 minutes"),
-  (Status::OverloadOrShortCircuitOnMains, "Overload or short circuit has occured on mains power"),
-  (Status::AdvanceLowRuntimeOnMains, "Battery is on mains power and will have low runtime if it has to shift to battery power"),
-  (Status::OverTemperatureOnMains, "Battery is over temperature on mains power"),
-  (Status::OnMains, "On mains power, no issues detected"),
-  (Status::OverTemperatureOnBatteryOrInternalError, "Battery is either over temperature on battery power or an internal error has occured"),
-  (Status::ReplaceBattery, "Battery needs replacement"),
-  (Status::Unknown, "Appropriate state could not be detected"),
-].into_iter().collect();
-
-const STATUS_BEEP_DURATIONS = [
-  (Status::OnBattery, [TARGET_NORMAL_BEEP_DURATION, Duration::from_secs(60)]),
-  (Status::LowOnBattery, [TARGET_NORMAL_BEEP_DURATION, Duration::from_secs(1)]),
-  (Status::NoLoadOnBattery, [TARGET_NORMAL_BEEP_DURATION, Duration::from_secs(10)]),
-  (Status::OverloadOrShortCircuitOnBattery, [TARGET_NORMAL_BEEP_DURATION, Duration::from_secs(2)]),
-  (Status::OverloadOrShortCircuitOnMains, [TARGET_LONG_BEEP_DURATION, Duration::from_secs(2)]),
-  (Status::AdvanceLowRuntimeOnMains, [TARGET_LONG_BEEP_DURATION, Duration::from_secs(13)]),
-  (Status::OverTemperatureOnMains, [TARGET_NORMAL_BEEP_DURATION, Duration::from_secs(4)]),
-  (Status::OnMains, [ZERO_DURATION, TIMEOUT_DURATION]),
-  (Status::OverTemperatureOnBatteryOrInternalError, [TIMEOUT_DURATION, ZERO_DURATION]),
-  (Status::ReplaceBattery, [TARGET_LONG_BEEP_DURATION, Duration::from_secs(40)]),
-];
+use cli::Encoding;
+use detector::{Decoder, Detector, DetectorConfig};
+use exit::ExitPolicy;
+use expander::ExpanderSource;
+use frame::FrameDecoder;
+use glyph::GlyphTable;
+use gpio::{GpioSource, LedPins, MainsPin, TriggerMode};
+use guidance::GuidanceTable;
+use hook::{CommandNotifier, DelayedNotifier, WebhookNotifier, WebhookUrl};
+use logging::{debug, info, trace, warn};
+use notifier::{LineStyle, Notifier};
+use output::{Output, OutputTarget};
+use profile::Profile;
+use pwm::PwmDecoder;
+use ratelimit::RateLimitedSource;
+use replay::ReplaySource;
+use report::{Format, Origin, ReportConfig, Reporter};
+use shadow::ShadowClassifier;
+use source::{EdgeSource, SourceEvent};
+use state::{SharedState, StatusState};
+use stats::StatusTotals;
+use status::{Classification, DEFAULT_LONG_BEEP_THRESHOLD_DURATION, DEFAULT_SILENCE_STATUS, MatchConfig, NO_STATUS_EXIT_CODE, Tolerance, ZERO_DURATION, get_status_exit_code};
+use suspend::SuspendWatcher;
+use symbols::SymbolPrinter;
+use table::Table;
+use watchdog::ActivityWatchdog;
 
 fn main() {
-  let gpio = Gpio::new().unwrap();
-  let pin = gpio.get(PIN).unwrap().into_input();
-  pin.set_interrupt(Trigger::Both).unwrap();
-  
-  let mut beep_durations = vec![];
-  let mut inter_beep_durations = vec![];
-
-  let mut current_beep_start_time: Option<Instant> = None;
-  let mut last_beep_end_time: Option<Instant> = None;
-
-  let mut last_status: Option<Status>  = None;
-
-  loop {
-    let level = pin.poll_interrupt(true, Some(TIMEOUT_DURATION)).unwrap();
-    
-    if let Some(level) = level {
-      let now = Instant::now();
-
-      if level == Level::Low {
-        // Don't update last_beep_end_time if it was already set previously so that on detecting another subsequent beep end without detecting a beep start first,
-        // the original beep end still gets considered as the beep end
-        if let None = last_beep_end_time {
-          last_beep_end_time = now;
-        }
+  let options = match cli::parse_args(env::args().skip(1)) {
+    Ok(options) => options,
+    Err(error) => {
+      eprintln!("{}", error);
+      process::exit(2);
+    }
+  };
 
-        // Detect beep end only if we had previously detected a beep start,
-        // because we need to calculate the duration of the beep as the time difference between now (beep end) and current_beep_start_time
-        if let Some(current_beep_start_time) = current_beep_start_time {
-          let beep_duration = now.duration_since(current_beep_start_time);
-          // If the beep end happened too quickly since the beep start then just ignore the last beep start
-          if beep_duration > MAX_BOUNCE_DURATION {
-            beep_durations.push(beep_duration);
-            if beep_durations.len() > MAX_ENTRIES {
-              beep_durations.remove(0);
-            }
+  logging::set_max_level(logging::get_level(options.verbosity, env::var("RUST_LOG").ok().as_deref()));
 
-            // After every detected beep, check for patterns and report the possible power state
-            if !beep_durations.is_empty() && !inter_beep_durations.is_empty() {
-              update_and_report_status(get_status_from_beep_durations(beep_durations.last().unwrap(), inter_beep_durations.last().unwrap()));
-            }
+  if options.show_features {
+    println!("{}", features::get_features_description());
+    return;
+  }
+  if options.list_models {
+    match profile::get_profile_names_description() {
+      Ok(description) => println!("{}", description),
+      Err(error) => {
+        eprintln!("{}", error);
+        process::exit(1);
+      }
+    }
+    return;
+  }
 
-          } else if inter_beep_durations.len() > 0 {
-            inter_beep_durations.pop();
-          }
+  let profile = match &options.model {
+    Some(model) => profile::load_model_profile(model),
+    None => profile::load_profile(&options.profile),
+  };
+  let mut profile = match profile {
+    Ok(profile) => profile,
+    Err(error) => {
+      eprintln!("{}", error);
+      process::exit(1);
+    }
+  };
+  if let Some(table) = &options.config {
+    profile.beep_durations = table.beep_durations.clone();
+    profile.inverted = table.active_low.unwrap_or(profile.inverted);
+  }
+  if options.active_low {
+    profile.inverted = true;
+  }
+  // Its polarity is left out, the edges have been flipped or not by the time there is anything to classify
+  let shadow_profile = match options.shadow_profile.as_deref().map(profile::load_profile).transpose() {
+    Ok(shadow_profile) => shadow_profile,
+    Err(error) => {
+      eprintln!("{}", error);
+      process::exit(1);
+    }
+  };
+  let guidance = GuidanceTable::new(options.guidance_overrides);
+  let mut match_config = MatchConfig {
+    beep_durations: profile.beep_durations,
+    silence_status: profile.silence_status,
+    error_margin: options.error_margin,
+    min_error_duration: options.min_error_duration,
+    status_tolerances: options.config.as_ref().map_or(vec![], |table| table.status_tolerances.clone()),
+    on_ambiguous: options.on_ambiguous,
+    metric: options.match_metric,
+    long_beep_threshold: options.long_beep_threshold,
+    guidance: guidance.clone(),
+  };
+  pattern::apply_pattern_overrides(&mut match_config, &options.pattern_overrides);
+  // Profiles can't have these, only a --pattern giving one status the pattern of another can
+  for (index, winning_status) in status::get_unreachable_entries(&match_config) {
+    warn!("{:?} has the same pattern as {:?} and is never reported, see --show-windows", match_config.beep_durations[index].0, winning_status);
+  }
+  if options.show_windows {
+    println!("{}", status::get_windows_description(&match_config));
+    return;
+  }
+  if let Some(dump_profile_path) = &options.dump_profile_path {
+    let effective_profile = Profile {
+      name: profile.name.clone(),
+      inverted: profile.inverted,
+      beep_durations: match_config.beep_durations.clone(),
+      silence_status: match_config.silence_status,
+    };
+    if let Err(error) = fs::write(dump_profile_path, profile::get_profile_file(&effective_profile)) {
+      eprintln!("Could not write the profile to {}: {}", dump_profile_path, error);
+      process::exit(1);
+    }
+    // Nothing else of the command line goes in a profile, the tolerances of single statuses included
+    if !match_config.status_tolerances.is_empty() {
+      eprintln!("The tolerances of single statuses are not part of a profile, they have to be given along with it or kept with --dump-config");
+    }
+    return;
+  }
+  if let Some(dump_config_path) = &options.dump_config_path {
+    let effective_config = Table {
+      pin: Some(options.pin),
+      active_low: Some(profile.inverted),
+      error_margin: Some(options.error_margin),
+      min_error_duration: Some(options.min_error_duration),
+      sinks: Some(options.sinks.clone()),
+      beep_durations: match_config.beep_durations.clone(),
+      status_tolerances: match_config.status_tolerances.clone(),
+    };
+    if let Err(error) = fs::write(dump_config_path, table::get_table_file(&effective_config)) {
+      eprintln!("Could not write the config to {}: {}", dump_config_path, error);
+      process::exit(1);
+    }
+    // A config keeps the silence of the profile or model it is given along with, so only that of the default profile goes without saying
+    if match_config.silence_status != Some(DEFAULT_SILENCE_STATUS) {
+      eprintln!("The silence of the {} profile is not part of a config, the profile has to be given along with it", profile.name);
+    }
+    return;
+  }
+
+  let detector_config = DetectorConfig {
+    min_beep_duration: options.min_beep_duration,
+    on_mains_grace_duration: options.on_mains_grace_duration,
+    mute_hold_duration: options.mute_hold_duration,
+    max_measured_duration: options.max_measured_duration,
+    gap_basis: options.gap_basis,
+    first_edge_policy: options.first_edge_policy,
+    beep_groups: options.beep_groups,
+    avr_chirp: options.avr_chirp,
+    table_tolerance: Tolerance { error_margin: options.error_margin, min_error_duration: options.min_error_duration },
+  };
+  // Replayed as fast as possible, the instants handed to the detector keep the recorded spacing anyway
+  if let Some((validation_status, capture_path)) = &options.validation {
+    let mut capture = match ReplaySource::open(capture_path, 0.0) {
+      Ok(capture) => capture,
+      Err(error) => {
+        eprintln!("{}", error);
+        process::exit(1);
+      }
+    };
+    let mut detector = Detector::with_classifier(detector_config, Box::new(BuiltinClassifier::new(match_config)));
+    let validation = validate::validate_capture(&mut capture, &mut detector, profile.inverted, *validation_status);
+    println!("{}", validation.get_description());
+    process::exit(if validation.is_passing() { 0 } else { 1 });
+  }
+  if let Some(threshold_sweep_path) = &options.threshold_sweep_path {
+    let sweep = sweep::load_labeled_capture(threshold_sweep_path)
+      .and_then(|capture| sweep::sweep_capture(&capture, detector_config, profile.inverted, &match_config));
+    match sweep {
+      Ok(sweep) => println!("{}", sweep.get_description()),
+      Err(error) => {
+        eprintln!("{}", error);
+        process::exit(1);
+      }
+    }
+    return;
+  }
+
+  let totals = match &options.stats_path {
+    Some(stats_path) => match StatusTotals::load(stats_path) {
+      Ok(totals) => totals,
+      Err(error) => {
+        eprintln!("{}", error);
+        process::exit(1);
+      }
+    },
+    None => StatusTotals::default(),
+  };
+  if options.dump_stats {
+    println!("{}", totals.get_description());
+    return;
+  }
+
+  let mut status_state = StatusState::new(options.history_size);
+  status_state.totals = totals;
+  let state = Arc::new(Mutex::new(status_state));
+  signals::start_history_dump_on_signal(state.clone());
+  #[cfg(feature = "http")]
+  if let Some(http_address) = &options.http_address {
+    http::start_http_server(http_address, state.clone(), options.raw_token.clone());
+  }
+  #[cfg(feature = "http")]
+  if let Some(metrics_address) = &options.metrics_address {
+    metrics::start_metrics_server(metrics_address, state.clone());
+  }
+  // Kept until the process ends, dropping it removes the socket file
+  let status_socket = match options.status_socket_path.as_deref().map(|path| socket::start_status_socket(path, state.clone())).transpose() {
+    Ok(status_socket) => status_socket,
+    Err(error) => {
+      eprintln!("{}", error);
+      process::exit(1);
+    }
+  };
+  if let Some(udp_raw_address) = &options.udp_raw_address {
+    udp::start_udp_raw(udp_raw_address, state.clone());
+  }
+  // The threads writing the history out, what was already sent to them is only ever lost when the process is killed
+  #[cfg_attr(not(any(feature = "mqtt", feature = "amqp", feature = "sqlite")), allow(unused_mut))]
+  let mut writers: Vec<JoinHandle<()>> = vec![];
+  #[cfg(feature = "mqtt")]
+  if let Some(mqtt) = options.mqtt.clone() {
+    writers.push(mqtt::start_mqtt_publisher(mqtt, state.clone()));
+  }
+  #[cfg(feature = "amqp")]
+  if let Some(amqp) = options.amqp.clone() {
+    writers.push(amqp::start_amqp_publisher(amqp, state.clone()));
+  }
+  #[cfg(feature = "sqlite")]
+  if let Some(sqlite_path) = &options.sqlite_path {
+    match sqlite::start_sqlite_history(sqlite_path, state.clone()) {
+      Ok(writer) => writers.push(writer),
+      Err(error) => {
+        eprintln!("{}", error);
+        process::exit(1);
+      }
+    }
+  }
+
+  let sinks = match open_sinks(&options.sinks) {
+    Ok(sinks) => sinks,
+    Err(error) => {
+      eprintln!("{}", error);
+      process::exit(1);
+    }
+  };
+  let line_style = LineStyle {
+    glyphs: GlyphTable::new(options.glyph_overrides),
+    show_confidence: options.show_confidence,
+    show_guidance: options.show_guidance,
+    show_origin: options.show_origin,
+    show_timestamps: options.show_timestamps,
+    pretty_json: options.pretty_json,
+  };
+  // A replay times everything by its recorded instants, however fast it goes
+  let event_clock = options.replay_path.is_some().then(EventClock::new);
+  let clock: Box<dyn Clock> = match &event_clock {
+    Some(event_clock) => Box::new(event_clock.clone()),
+    None => Box::new(SystemClock),
+  };
+  let mut reporter = Reporter::with_clock(ReportConfig {
+    guidance,
+    replace_battery_escalation_score: options.replace_battery_escalation_score,
+    stats_path: options.stats_path,
+    unknown_debounce_duration: options.unknown_debounce_duration,
+    outage_corroboration_window: options.outage_corroboration_window,
+    confirmations: options.confirmations,
+    warmup_duration: options.warmup_duration,
+    heartbeat_interval: options.heartbeat_interval,
+    on_battery_warn_duration: options.on_battery_warn_duration,
+    maintenance_windows: options.maintenance_windows,
+    restart_policy: options.restart_policy,
+  }, state.clone(), get_notifiers(sinks, &line_style, options.on_change_commands, options.webhook_urls), clock);
 
-          // Reset the current_beep_start_time variable to prevent detecting another subsequent beep end without detecting a beep start first,
-          current_beep_start_time = None;
+  signals::start_pause_toggle_on_signal(reporter.get_paused());
+
+  // A test event goes through the whole reporting path and ends it, the origin marks it as such wherever it gets reported
+  if let Some(emit_status) = options.emit_status {
+    reporter.update_and_report_status(Classification {
+      status: emit_status,
+      confidence: 1.0,
+      beep_duration: ZERO_DURATION,
+      inter_beep_duration: ZERO_DURATION,
+    }, Origin::Test);
+    stop_writers(&state, writers);
+    return;
+  }
+
+  if let Some(summary_period) = options.summary_period {
+    let summary_sinks = if options.summary_sinks.is_empty() { &options.sinks } else { &options.summary_sinks };
+    match open_sinks(summary_sinks) {
+      Ok(summary_sinks) => summary::start_summaries(state.clone(), summary_period, summary_sinks, options.pretty_json),
+      Err(error) => {
+        eprintln!("{}", error);
+        process::exit(1);
+      }
+    }
+  }
+  reporter.restore_last_status();
+
+  // Kept open until the process ends, which is what holds the lock
+  let pin_lock = if options.exclusive_gpio {
+    match gpio::lock_pin(options.pin) {
+      Ok(pin_lock) => Some(pin_lock),
+      Err(error) => {
+        eprintln!("{}", error);
+        process::exit(1);
+      }
+    }
+  } else {
+    None
+  };
+
+  let source: Box<dyn EdgeSource> = match options.replay_path {
+    Some(replay_path) => match ReplaySource::open(&replay_path, options.replay_speed) {
+      Ok(mut replay_source) => {
+        if let Some(replay_noise) = &options.replay_noise {
+          info!("Injecting noise into the replay with seed {}", replay_noise.seed);
+          replay_source.add_noise(replay_noise);
         }
-      } else {
-        // Don't update current_beep_start_time if it was already set previously so that on detecting another subsequent beep start without detecting a beep end first,
-        // the original beep start still gets considered as the beep start
-        if let None = current_beep_start_time {
-          current_beep_start_time = now;
+        Box::new(replay_source)
+      },
+      Err(error) => {
+        eprintln!("{}", error);
+        process::exit(1);
+      }
+    },
+    None => match options.expander {
+      // The pin is then the one the expander's interrupt output is wired to
+      Some((address, channel)) => match ExpanderSource::new(options.pin, address, channel) {
+        Ok(expander_source) => Box::new(expander_source),
+        Err(error) => {
+          eprintln!("{}", error);
+          process::exit(1);
         }
-
-        // Detect beep start only if we had previously detected a beep end,
-        // because we need to calculate the duration between this and the last beep as the time difference between now (beep start) and  last_beep_end_time
-        if let Some(last_beep_end_time) = last_beep_end_time {
-          let inter_beep_duration = now.duration_since(last_beep_end_time);
-          // If the beep start happened too quickly since the beep end then just ignore the last beep end
-          if inter_beep_duration > MAX_BOUNCE_DURATION {
-            inter_beep_durations.push(inter_beep_duration);
-            if inter_beep_durations.len() > MAX_ENTRIES {
-              inter_beep_durations.remove(0);
+      },
+      None => match &options.gpiochip_path {
+        // The pin number is then the offset of its line on that chip, the same as on the Pi's own chip
+        Some(gpiochip_path) => match ChardevSource::new(gpiochip_path, options.pin) {
+          Ok(chardev_source) => Box::new(chardev_source),
+          Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+          }
+        },
+        // The ADC channel the tap is wired to when there is one, the pin itself otherwise
+        None => {
+          #[cfg(feature = "adc")]
+          let pin_source = open_pin_source(options.pin, options.sample_interval, options.trigger_mode, profile.inverted, options.adc);
+          #[cfg(not(feature = "adc"))]
+          let pin_source = open_pin_source_directly(options.pin, options.sample_interval, options.trigger_mode, profile.inverted);
+          match pin_source {
+            Ok(pin_source) => pin_source,
+            Err(error) => {
+              eprintln!("{}", error);
+              process::exit(1);
             }
-          } else if beep_durations.len() > 0 {
-            beep_durations.pop();
           }
+        },
+      },
+    },
+  };
+  let mut source = match options.min_edge_interval {
+    Some(min_edge_interval) => Box::new(RateLimitedSource::new(source, min_edge_interval)),
+    None => source,
+  };
+
+  // Only the table of the beeps has any gaps for the one-shot mode to wait out
+  let one_shot_longest_gap = if options.encoding == Encoding::Beep { status::get_longest_gap(&match_config) } else { None };
+  let mut decoder: Box<dyn Decoder> = match options.encoding {
+    Encoding::Beep => {
+      let shadow_match_config = shadow_profile.map(|shadow_profile| MatchConfig {
+        beep_durations: shadow_profile.beep_durations,
+        silence_status: shadow_profile.silence_status,
+        // The tolerances given with --pattern tune the active table only
+        status_tolerances: vec![],
+        ..match_config.clone()
+      });
+      let builtin_classifier = BuiltinClassifier::new(match_config);
+      let classifier: Box<dyn Classifier> = match &options.classifier_command {
+        // Checked to come without a classifier command or shadow profile
+        _ if options.calibrate => Box::new(Calibrator::new(options.error_margin, options.min_error_duration)),
+        Some(classifier_command) => match ExternalClassifier::spawn(classifier_command, builtin_classifier) {
+          Ok(external_classifier) => Box::new(external_classifier),
+          Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+          }
+        },
+        None => Box::new(builtin_classifier),
+      };
+      let classifier: Box<dyn Classifier> = match shadow_match_config {
+        Some(shadow_match_config) => Box::new(ShadowClassifier::new(classifier, BuiltinClassifier::new(shadow_match_config))),
+        None => classifier,
+      };
+      let is_publishing_measurements = options.udp_raw_address.is_some();
+      #[cfg(feature = "http")]
+      let is_publishing_measurements = is_publishing_measurements || options.raw_token.is_some();
+      #[cfg(feature = "sqlite")]
+      let is_publishing_measurements = is_publishing_measurements || options.sqlite_path.is_some();
+      let classifier: Box<dyn Classifier> = if is_publishing_measurements { Box::new(MeasurementPublisher::new(classifier, state.clone())) } else { classifier };
+      let long_beep_threshold = options.long_beep_threshold.unwrap_or(DEFAULT_LONG_BEEP_THRESHOLD_DURATION);
+      let classifier: Box<dyn Classifier> = if options.show_symbols { Box::new(SymbolPrinter::new(classifier, long_beep_threshold)) } else { classifier };
+      Box::new(Detector::with_classifier(detector_config, classifier))
+    },
+    Encoding::Pwm => Box::new(PwmDecoder::new(options.duty_cycle_bands)),
+    Encoding::Frame => Box::new(FrameDecoder::new(options.frame_delimiter_duration, options.frame_codes)),
+  };
+
+  let mains_pin = match options.mains_pin.map(MainsPin::new).transpose() {
+    Ok(mains_pin) => mains_pin,
+    Err(error) => {
+      eprintln!("{}", error);
+      process::exit(1);
+    }
+  };
+  let led_pins = match (!options.led_pins.is_empty()).then(|| LedPins::new(&options.led_pins)).transpose() {
+    Ok(led_pins) => led_pins,
+    Err(error) => {
+      eprintln!("{}", error);
+      process::exit(1);
+    }
+  };
+
+  let mut exit_policy = ExitPolicy::new(options.exit_conditions);
+
+  let mut activity_watchdog = options.activity_interval.map(|activity_interval| ActivityWatchdog::new(activity_interval, options.warmup_duration, Instant::now()));
+
+  let mut suspend_watcher = options.is_watching_suspend.then(SuspendWatcher::new);
+
+  let shutdown = signals::get_shutdown_flag();
 
-          // Reset the last_beep_end_time variable to prevent detecting another subsequent beep start without detecting a beep end first,
-          last_beep_end_time = None;
+  let once_deadline = Instant::now() + options.once_timeout_duration;
+  while let Some(event) = source.next_event(options.timeout_duration) {
+    // Events come at least every timeout, so stopping never takes longer than that
+    if shutdown.load(Ordering::Relaxed) {
+      // Releases the pin and its interrupt, the lock on it and the status socket, before saying so
+      drop(source);
+      drop(pin_lock);
+      drop(status_socket);
+      info!("Shutting down");
+      stop_writers(&state, writers);
+      return;
+    }
+    // Checked ahead of the event, which is the first one after resuming and would otherwise be timed against the edges before the suspend
+    if let Some(suspended_duration) = suspend_watcher.as_mut().and_then(SuspendWatcher::check) {
+      reporter.report_alert(&suspend::get_suspend_description(suspended_duration));
+      decoder.reset();
+    }
+    let (classification, origin, at) = match event {
+      SourceEvent::Edge(edge, at) => {
+        debug!("Edge {:?}", edge);
+        if let Some(activity_watchdog) = &mut activity_watchdog {
+          activity_watchdog.on_edge(at);
         }
+        (decoder.on_edge(if profile.inverted { edge.inverted() } else { edge }, at), Origin::Observed, at)
+      },
+      SourceEvent::Timeout(at) => {
+        trace!("Timed out waiting for an edge");
+        (decoder.on_timeout(at), Origin::Inferred, at)
+      },
+    };
+    if let Some(event_clock) = &event_clock {
+      event_clock.set(at);
+    }
+    if let Some(alert) = activity_watchdog.as_mut().and_then(|activity_watchdog| activity_watchdog.check(at)) {
+      reporter.report_alert(&alert);
+    }
+
+    // Nothing measured while calibrating is a status
+    // What silence is inferred as comes again on every timeout, only measured pairs are worth a line each
+    if let Some(classification) = classification {
+      logging::log!(
+        if origin == Origin::Observed { logging::Level::Debug } else { logging::Level::Trace },
+        "Classified beep {}ms, inter beep {}ms as {:?} with confidence {:.2}",
+        classification.beep_duration.as_millis(),
+        classification.inter_beep_duration.as_millis(),
+        classification.status,
+        classification.confidence,
+      );
+    }
+    if let Some(classification) = classification
+      && !options.calibrate {
+      // Checked against what was decoded from the beeps alone, before the rules and the mains input have had their say
+      let led_status = led_pins.as_ref().and_then(|led_pins| led::get_led_status(&led_pins.get_lit_statuses()));
+      if let Some(disagreement) = led_status.and_then(|led_status| led::get_led_disagreement(classification.status, led_status)) {
+        warn!("{}", disagreement);
       }
-    } else {
-      // When a timeout happens waiting for an interrupt then also check for patterns and report the possible power state
-      if !beep_durations.is_empty() && !inter_beep_durations.is_empty() {
-        if let Some(current_beep_start_time) = current_beep_start_time && let None = last_beep_end_time {
-          // Timeout happened during a beep
-          update_and_report_status(get_status_from_beep_durations(TIMEOUT_DURATION, ZERO_DURATION));
-        } else if let None = current_beep_start_time && let Some(last_beep_end_time) = last_beep_end_time {
-          // Timeout did not happen during a beep
-          update_and_report_status(get_status_from_beep_durations(ZERO_DURATION, TIMEOUT_DURATION));
-        } else {
-          // THis case should not be possible
-          update_and_report_status(Status::Unknown);
-        }
+      let is_mains_present = mains_pin.as_ref().map(MainsPin::is_mains_present);
+      let classification = match (rules::apply_rules(&options.rules, classification, is_mains_present), is_mains_present) {
+        (Some(classification), _) => classification,
+        (None, Some(is_mains_present)) => {
+          let (classification, conflict) = mains::reconcile_with_mains(classification, is_mains_present);
+          if let Some(conflict) = conflict {
+            warn!("{}", conflict);
+          }
+          classification
+        },
+        (None, None) => classification,
+      };
+      let classification = remap::remap_classification(&options.status_maps, classification);
+      reporter.update_and_report_status(classification, origin);
+      exit_policy.update(classification.status, classification.confidence, at);
+      if options.once && exit::is_one_shot_settled(classification, origin, one_shot_longest_gap) {
+        stop_writers(&state, writers);
+        process::exit(get_status_exit_code(classification.status));
       }
     }
+
+    // Events come at least every timeout, so neither is ever late by more than that
+    reporter.report_unknown_if_due();
+    reporter.report_heartbeat_if_due();
+    reporter.report_on_battery_warning_if_due();
+
+    if let Some(exit_status) = exit_policy.check(at) {
+      info!("Exiting on {:?} as requested", exit_status);
+      stop_writers(&state, writers);
+      process::exit(get_status_exit_code(exit_status));
+    }
+    if options.once && at >= once_deadline {
+      break;
+    }
   }
-}
 
-fn update_and_report_status(new_status: Status) {
-  if last_status != new_status {
-      last_status = new_status;
-      println!(STATUS_DESCRIPTIONS[last_status]);
+  stop_writers(&state, writers);
+  if options.once {
+    process::exit(NO_STATUS_EXIT_CODE);
   }
 }
 
-fn get_status_from_beep_durations(beep: Duration, inter_beep: Duration) -> Status {
-  for status_beep_duration in STATUS_BEEP_DURATIONS {
-      if (
-        close_enough(beep, status_beep_duration.1[0], BEEP_BOUNCE_MAX_DURATION) &&
-        close_enough(inter_beep, status_beep_duration.1[1], INTER_BEEP_BOUNCE_MAX_DURATION)
-       ) {
-          return status_beep_duration.0;
-      }
+// Has the writers finish what was sent to them before the process ends, which would cut them off wherever they were
+fn stop_writers(state: &SharedState, writers: Vec<JoinHandle<()>>) {
+  state.lock().unwrap().close_subscriptions();
+  for writer in writers {
+    let _ = writer.join();
   }
+}
+
+// The sinks first, so a transition is written down before any hook gets to act on it
+fn get_notifiers(sinks: Vec<(Output, Format)>, line_style: &LineStyle, on_change_commands: Vec<(String, Duration)>, webhook_urls: Vec<(WebhookUrl, Duration)>) -> Vec<Box<dyn Notifier>> {
+  let mut notifiers = notifier::get_sink_notifiers(sinks, line_style);
+  notifiers.extend(on_change_commands.into_iter().map(|(command, delay)| get_delayed_notifier(CommandNotifier::new(command), delay)));
+  notifiers.extend(webhook_urls.into_iter().map(|(url, delay)| get_delayed_notifier(WebhookNotifier::new(url), delay)));
+  notifiers
+}
 
-  return Status::Unknown;
+fn get_delayed_notifier<N: Notifier + Send + 'static>(notifier: N, delay: Duration) -> Box<dyn Notifier> {
+  if delay.is_zero() { Box::new(notifier) } else { Box::new(DelayedNotifier::new(notifier, delay)) }
+}
+
+fn open_sinks(sinks: &[(OutputTarget, Format)]) -> Result<Vec<(Output, Format)>, String> {
+  sinks.iter()
+    .map(|(target, format)| Output::open(target).map(|output| (output, *format)))
+    .collect()
+}
+
+// The ADC channel is only there with the adc feature, so without it the pin is opened directly
+#[cfg(feature = "adc")]
+fn open_pin_source(pin: u8, sample_interval: Option<Duration>, trigger_mode: TriggerMode, pull_up: bool, adc: Option<(u8, u16, u16)>) -> Result<Box<dyn EdgeSource>, String> {
+  match adc {
+    Some((channel, threshold, hysteresis)) => Ok(Box::new(AdcSource::new(channel, threshold, hysteresis)?)),
+    None => open_pin_source_directly(pin, sample_interval, trigger_mode, pull_up),
+  }
 }
 
-fn close_enough(duration: &Duration, target: Duration, error_margin: f64) -> bool {
-  let error_range = target.as_micros() as f64 * error_margin;
-  (duration.as_micros() as f64 - target.as_micros() as f64).abs() < error_range
+// An active-low line is pulled up to stay high in between beeps, an active-high one is left floating as it always was
+fn open_pin_source_directly(pin: u8, sample_interval: Option<Duration>, trigger_mode: TriggerMode, pull_up: bool) -> Result<Box<dyn EdgeSource>, String> {
+  Ok(Box::new(GpioSource::new(pin, sample_interval, trigger_mode, pull_up)?))
 }
@@ -0,0 +1,56 @@
+use crate::status::{Classification, Status};
+
+// Checks a classification against the mains present input, which is authoritative whenever the two disagree,
+// returns the classification to report along with a description of the conflict if there was one
+pub fn reconcile_with_mains(classification: Classification, is_mains_present: bool) -> (Classification, Option<String>) {
+  let status = classification.status;
+  let conflicts = (is_mains_present && (status.is_on_battery() || status == Status::PowerOff)) || (!is_mains_present && status.is_on_mains());
+  if !conflicts && status != Status::Unknown {
+    return (classification, None);
+  }
+
+  let mains_status = if is_mains_present { Status::OnMains } else { Status::OnBattery };
+  let conflict = conflicts.then(|| format!(
+    "Classified {:?} from the beeps but mains is {}, reporting {:?} instead",
+    status,
+    if is_mains_present { "present" } else { "absent" },
+    mains_status,
+  ));
+  (Classification { status: mains_status, confidence: 1.0, ..classification }, conflict)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  fn get_classification(status: Status) -> Classification {
+    Classification { status, confidence: 0.5, beep_duration: Duration::from_millis(250), inter_beep_duration: Duration::from_secs(60) }
+  }
+
+  #[test]
+  fn agreeing_or_unrelated_status_is_kept() {
+    for (status, is_mains_present) in [(Status::OnBattery, false), (Status::AdvanceLowRuntimeOnMains, true), (Status::ReplaceBattery, true), (Status::ReplaceBattery, false)] {
+      assert_eq!(reconcile_with_mains(get_classification(status), is_mains_present), (get_classification(status), None));
+    }
+  }
+
+  #[test]
+  fn conflicting_status_is_overridden() {
+    let (classification, conflict) = reconcile_with_mains(get_classification(Status::LowOnBattery), true);
+    assert_eq!(classification.status, Status::OnMains);
+    assert_eq!(classification.confidence, 1.0);
+    assert_eq!(conflict.as_deref(), Some("Classified LowOnBattery from the beeps but mains is present, reporting OnMains instead"));
+
+    let (classification, conflict) = reconcile_with_mains(get_classification(Status::OnMains), false);
+    assert_eq!(classification.status, Status::OnBattery);
+    assert!(conflict.is_some());
+    assert_eq!(reconcile_with_mains(get_classification(Status::PowerOff), true).0.status, Status::OnMains);
+  }
+
+  #[test]
+  fn unknown_status_is_disambiguated_without_conflict() {
+    assert_eq!(reconcile_with_mains(get_classification(Status::Unknown), true), (Classification { status: Status::OnMains, confidence: 1.0, ..get_classification(Status::Unknown) }, None));
+    assert_eq!(reconcile_with_mains(get_classification(Status::Unknown), false).0.status, Status::OnBattery);
+  }
+}
@@ -0,0 +1,214 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// What the times of a window are in
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum WindowZone {
+  // The system's time zone, daylight saving time included, as libc sees it
+  Local,
+  // A fixed offset from UTC, in seconds east of it
+  Offset(i64),
+}
+
+// A stretch of the week during which nothing is reported, such as the hour a self test is scheduled for every Sunday,
+// one ending earlier in the day than it starts runs past midnight into the next day
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct MaintenanceWindow {
+  // Indexed by the day of the week from Sunday, the day a window starts on
+  pub days: [bool; 7],
+  // Minutes since midnight, the start included and the end not
+  pub start_minute: u32,
+  pub end_minute: u32,
+  pub zone: WindowZone,
+}
+
+impl MaintenanceWindow {
+  // The day of the week from Sunday and the minutes since midnight
+  fn contains(&self, weekday: usize, minute: u32) -> bool {
+    if self.start_minute < self.end_minute {
+      self.days[weekday] && (self.start_minute..self.end_minute).contains(&minute)
+    } else {
+      (self.days[weekday] && minute >= self.start_minute) || (self.days[(weekday + 6) % 7] && minute < self.end_minute)
+    }
+  }
+}
+
+// Parsed from "<days>@<HH:MM>-<HH:MM>[Z|+HH:MM|-HH:MM]", as in "sun@03:00-04:00" or "mon-fri@23:30-00:30+05:30",
+// the days are "daily", a day name, a range of them or several of those separated by commas, the times are local without a zone
+pub fn parse_maintenance_window(value: &str) -> Result<MaintenanceWindow, String> {
+  let invalid_window = || format!("invalid maintenance window {}, expected <days>@<HH:MM>-<HH:MM>[Z|+HH:MM|-HH:MM]", value);
+
+  let (days_spec, times) = value.split_once('@').ok_or_else(invalid_window)?;
+  let days = parse_days(days_spec).ok_or_else(|| format!("invalid days {} in maintenance window {}", days_spec, value))?;
+  let (start, end_and_zone) = times.split_once('-').ok_or_else(invalid_window)?;
+  let (end, zone) = end_and_zone.split_at_checked(5).ok_or_else(invalid_window)?;
+  let start_minute = parse_minute(start).ok_or_else(invalid_window)?;
+  let end_minute = parse_minute(end).ok_or_else(invalid_window)?;
+  let zone = parse_zone(zone).ok_or_else(|| format!("invalid zone {} in maintenance window {}", zone, value))?;
+  if start_minute == end_minute {
+    return Err(format!("maintenance window {} is empty", value));
+  }
+  Ok(MaintenanceWindow { days, start_minute, end_minute, zone })
+}
+
+fn parse_days(days_spec: &str) -> Option<[bool; 7]> {
+  let get_day = |name: &str| DAY_NAMES.iter().position(|day_name| *day_name == name);
+  let mut days = [false; 7];
+  for part in days_spec.split(',') {
+    if part == "daily" {
+      days = [true; 7];
+      continue;
+    }
+    // A range wraps around the end of the week, "fri-mon" being the long weekend
+    let (first, last) = match part.split_once('-') {
+      Some((first, last)) => (get_day(first)?, get_day(last)?),
+      None => (get_day(part)?, get_day(part)?),
+    };
+    let mut day = first;
+    loop {
+      days[day] = true;
+      if day == last {
+        break;
+      }
+      day = (day + 1) % 7;
+    }
+  }
+  Some(days)
+}
+
+fn parse_minute(time: &str) -> Option<u32> {
+  let (hours, minutes) = time.split_once(':')?;
+  if hours.len() != 2 || minutes.len() != 2 {
+    return None;
+  }
+  let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+  (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+fn parse_zone(zone: &str) -> Option<WindowZone> {
+  let (sign, offset) = match zone.chars().next() {
+    None => return Some(WindowZone::Local),
+    Some('Z') if zone.len() == 1 => return Some(WindowZone::Offset(0)),
+    Some('+') => (1, &zone[1..]),
+    Some('-') => (-1, &zone[1..]),
+    _ => return None,
+  };
+  // Offsets go up to 14 hours either way
+  let offset_minutes = parse_minute(offset).filter(|offset_minutes| *offset_minutes <= 14 * 60)?;
+  Some(WindowZone::Offset(sign * offset_minutes as i64 * 60))
+}
+
+// The day of the week from Sunday and the minutes since midnight in the zone, times before 1970 are taken as 1970
+fn get_civil_time(time: SystemTime, zone: WindowZone) -> (usize, u32) {
+  let unix_secs = time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs() as i64);
+  let offset_secs = match zone {
+    WindowZone::Offset(offset_secs) => offset_secs,
+    WindowZone::Local => get_local_offset(unix_secs),
+  };
+  let secs = unix_secs + offset_secs;
+  // 1970-01-01 was a Thursday
+  let weekday = (secs.div_euclid(86_400) + 4).rem_euclid(7) as usize;
+  let minute = (secs.rem_euclid(86_400) / 60) as u32;
+  (weekday, minute)
+}
+
+// The offset from UTC of the system's time zone at that moment, which changes with daylight saving time,
+// a time libc can't convert is taken as UTC
+fn get_local_offset(unix_secs: i64) -> i64 {
+  let time = unix_secs as libc::time_t;
+  let mut local_time: libc::tm = unsafe { std::mem::zeroed() };
+  let result = unsafe { libc::localtime_r(&time, &mut local_time) };
+  if result.is_null() { 0 } else { local_time.tm_gmtoff as i64 }
+}
+
+// Whether the time falls within any of the windows, each one judged in its own zone
+pub fn is_in_maintenance_window(windows: &[MaintenanceWindow], time: SystemTime) -> bool {
+  windows.iter().any(|window| {
+    let (weekday, minute) = get_civil_time(time, window.zone);
+    window.contains(weekday, minute)
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  // 2023-11-12, a Sunday, at midnight UTC
+  const SUNDAY_SECS: u64 = 1_699_747_200;
+
+  fn get_time(day_offset: u64, hours: u64, minutes: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(SUNDAY_SECS + day_offset * 86_400 + hours * 3600 + minutes * 60)
+  }
+
+  fn is_in(value: &str, time: SystemTime) -> bool {
+    is_in_maintenance_window(&[parse_maintenance_window(value).unwrap()], time)
+  }
+
+  #[test]
+  fn parses_maintenance_windows() {
+    assert_eq!(parse_maintenance_window("sun@03:00-04:00").unwrap(), MaintenanceWindow {
+      days: [true, false, false, false, false, false, false],
+      start_minute: 180,
+      end_minute: 240,
+      zone: WindowZone::Local,
+    });
+    assert_eq!(parse_maintenance_window("mon-fri@23:30-00:30+05:30").unwrap().zone, WindowZone::Offset(19_800));
+    assert_eq!(parse_maintenance_window("daily@12:00-13:00Z").unwrap().days, [true; 7]);
+    assert_eq!(parse_maintenance_window("fri-mon,wed@12:00-13:00-08:00").unwrap().days, [true, true, false, true, false, true, true]);
+    assert_eq!(parse_maintenance_window("sat@12:00-13:00-08:00").unwrap().zone, WindowZone::Offset(-28_800));
+  }
+
+  #[test]
+  fn rejects_invalid_maintenance_windows() {
+    assert!(parse_maintenance_window("sun").unwrap_err().starts_with("invalid maintenance window sun"));
+    assert!(parse_maintenance_window("sunday@03:00-04:00").unwrap_err().starts_with("invalid days sunday"));
+    assert!(parse_maintenance_window("sun@3:00-04:00").is_err());
+    assert!(parse_maintenance_window("sun@03:00-24:00").is_err());
+    assert!(parse_maintenance_window("sun@03:00-04:60").is_err());
+    assert!(parse_maintenance_window("sun@03:00").is_err());
+    assert!(parse_maintenance_window("sun@03:00-04:00+15:00").unwrap_err().starts_with("invalid zone +15:00"));
+    assert!(parse_maintenance_window("sun@03:00-04:00UTC").unwrap_err().starts_with("invalid zone UTC"));
+    assert_eq!(parse_maintenance_window("sun@03:00-03:00Z").unwrap_err(), "maintenance window sun@03:00-03:00Z is empty");
+  }
+
+  #[test]
+  fn covers_the_days_and_times_given() {
+    assert!(!is_in("sun@03:00-04:00Z", get_time(0, 2, 59)));
+    assert!(is_in("sun@03:00-04:00Z", get_time(0, 3, 0)));
+    assert!(is_in("sun@03:00-04:00Z", get_time(0, 3, 59)));
+    assert!(!is_in("sun@03:00-04:00Z", get_time(0, 4, 0)));
+    // The same time on another day
+    assert!(!is_in("sun@03:00-04:00Z", get_time(1, 3, 30)));
+    assert!(is_in("mon-fri@09:00-17:00Z", get_time(5, 12, 0)));
+    assert!(!is_in("mon-fri@09:00-17:00Z", get_time(6, 12, 0)));
+  }
+
+  #[test]
+  fn windows_past_midnight_belong_to_the_day_they_start() {
+    // Saturday night into Sunday morning
+    assert!(is_in("sat@23:00-01:00Z", get_time(6, 23, 30)));
+    assert!(is_in("sat@23:00-01:00Z", get_time(7, 0, 30)));
+    assert!(!is_in("sat@23:00-01:00Z", get_time(7, 1, 0)));
+    // Friday night's window doesn't spill into Saturday morning
+    assert!(!is_in("sat@23:00-01:00Z", get_time(6, 0, 30)));
+  }
+
+  #[test]
+  fn offsets_move_the_window_across_days() {
+    // 03:00 on Sunday at +05:30 is 21:30 on Saturday in UTC
+    assert!(is_in("sun@03:00-04:00+05:30", get_time(6, 21, 30)));
+    assert!(!is_in("sun@03:00-04:00+05:30", get_time(0, 3, 30)));
+    // And at -08:00 it is 11:00 on Sunday in UTC
+    assert!(is_in("sun@03:00-04:00-08:00", get_time(0, 11, 0)));
+  }
+
+  #[test]
+  fn local_windows_follow_the_system_zone() {
+    let time = get_time(0, 12, 0);
+    let offset_secs = get_local_offset(SUNDAY_SECS as i64 + 12 * 3600);
+    let (weekday, minute) = get_civil_time(time, WindowZone::Offset(offset_secs));
+    assert_eq!(get_civil_time(time, WindowZone::Local), (weekday, minute));
+  }
+}
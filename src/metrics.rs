@@ -0,0 +1,196 @@
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::logging::{debug, warn};
+use crate::state::SharedState;
+use crate::status::get_all_statuses;
+
+// A scraper sends its request right away and reads the answer as it comes, one that doesn't is given up on after this
+const CONNECTION_TIMEOUT_DURATION: Duration = Duration::from_secs(5);
+// Far more than the request line and headers of any scraper, the rest of a request any longer is left unread
+const MAX_REQUEST_BYTES: u64 = 16 * 1024;
+
+// Serves /metrics in the Prometheus text format, or in OpenMetrics to scrapers asking for it, on its own thread so scrapes never hold up beep timing,
+// failing to bind only warns as the detector itself is still useful without it
+pub fn start_metrics_server(address: &str, state: SharedState) {
+  let listener = match TcpListener::bind(address) {
+    Ok(listener) => listener,
+    Err(error) => {
+      warn!("Could not start the metrics server on {}: {}", address, error);
+      return;
+    }
+  };
+
+  thread::spawn(move || {
+    for stream in listener.incoming().flatten() {
+      // Every connection gets its own thread so a client that connects and goes quiet never holds up the scrapes after it
+      let state = state.clone();
+      thread::spawn(move || {
+        if let Err(error) = handle_connection(stream, &state) {
+          debug!("Metrics request failed: {}", error);
+        }
+      });
+    }
+  });
+}
+
+fn handle_connection(mut stream: TcpStream, state: &SharedState) -> std::io::Result<()> {
+  stream.set_read_timeout(Some(CONNECTION_TIMEOUT_DURATION))?;
+  stream.set_write_timeout(Some(CONNECTION_TIMEOUT_DURATION))?;
+  let mut reader = BufReader::new(stream.try_clone()?.take(MAX_REQUEST_BYTES));
+  let mut request_line = String::new();
+  reader.read_line(&mut request_line)?;
+  // The headers are read so that the client doesn't see the connection reset under it, only Accept says anything to the answer
+  let mut header = String::new();
+  let mut is_openmetrics = false;
+  while reader.read_line(&mut header)? > 2 {
+    if let Some((name, value)) = header.split_once(':') && name.eq_ignore_ascii_case("accept") {
+      is_openmetrics = value.contains("application/openmetrics-text");
+    }
+    header.clear();
+  }
+
+  let mut request_parts = request_line.split_whitespace();
+  let (method, target) = (request_parts.next().unwrap_or(""), request_parts.next().unwrap_or(""));
+  let path = target.split_once('?').map_or(target, |(path, _)| path);
+  let (status_line, content_type, body) = match (method, path) {
+    ("GET", "/metrics") if is_openmetrics => ("200 OK", "application/openmetrics-text; version=1.0.0; charset=utf-8", get_metrics_text(state, true)),
+    ("GET", "/metrics") => ("200 OK", "text/plain; version=0.0.4", get_metrics_text(state, false)),
+    ("GET", _) => ("404 Not Found", "text/plain", "Not found".to_string()),
+    _ => ("405 Method Not Allowed", "text/plain", "Method not allowed".to_string()),
+  };
+
+  write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status_line, content_type, body.len(), body)?;
+  stream.flush()
+}
+
+// Every status has its sample so a query for one never comes up empty, the durations are left out until a pair has been measured,
+// in OpenMetrics the transitions counter carries the durations of the pair behind the current transition as its exemplar,
+// which the Prometheus text format has no room for
+fn get_metrics_text(state: &SharedState, is_openmetrics: bool) -> String {
+  let state = state.lock().unwrap();
+  let current_status = state.current.map(|current| current.to);
+  let mut text = String::new();
+
+  text.push_str("# HELP ups_status Whether the UPS is in the status, 1 for the current one and 0 for every other\n# TYPE ups_status gauge\n");
+  for status in get_all_statuses() {
+    let _ = writeln!(text, "ups_status{{status=\"{:?}\"}} {}", status, u8::from(current_status == Some(status)));
+  }
+  // The sequence number of the current transition is how many there have been, OpenMetrics names the counter without its suffix
+  let transitions_name = if is_openmetrics { "ups_status_transitions" } else { "ups_status_transitions_total" };
+  let _ = write!(
+    text,
+    "# HELP {0} Status transitions since starting\n# TYPE {0} counter\nups_status_transitions_total {1}",
+    transitions_name, state.current.map_or(0, |current| current.sequence),
+  );
+  if is_openmetrics && let Some(current) = state.current {
+    let at = current.at.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let _ = write!(
+      text,
+      " # {{beep_ms=\"{}\",gap_ms=\"{}\"}} 1 {}.{:03}",
+      current.beep_duration.as_millis(), current.inter_beep_duration.as_millis(), at.as_secs(), at.subsec_millis(),
+    );
+  }
+  text.push('\n');
+  if let Some((beep, inter_beep)) = state.last_measurement {
+    let _ = writeln!(
+      text,
+      "# HELP ups_last_beep_milliseconds Duration of the last measured beep\n# TYPE ups_last_beep_milliseconds gauge\nups_last_beep_milliseconds {}",
+      beep.as_millis(),
+    );
+    let _ = writeln!(
+      text,
+      "# HELP ups_last_inter_beep_milliseconds Duration of the gap after the last measured beep\n# TYPE ups_last_inter_beep_milliseconds gauge\nups_last_inter_beep_milliseconds {}",
+      inter_beep.as_millis(),
+    );
+  }
+  if is_openmetrics {
+    text.push_str("# EOF\n");
+  }
+  text
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Read;
+  use std::time::{Duration, Instant};
+
+  use crate::guidance::GuidanceTable;
+  use crate::report::Origin;
+  use crate::state::Transition;
+  use crate::status::Status;
+
+  fn request(state: &SharedState, request: &str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    client.write_all(request.as_bytes()).unwrap();
+    let (server_stream, _) = listener.accept().unwrap();
+    handle_connection(server_stream, state).unwrap();
+
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    response
+  }
+
+  fn get_transition(to: Status) -> Transition {
+    Transition {
+      from: None,
+      to,
+      guidance: GuidanceTable::new(vec![]).get(to),
+      cleared: false,
+      confidence: 1.0,
+      beep_duration: Duration::from_millis(250),
+      inter_beep_duration: Duration::from_secs(60),
+      origin: Origin::Observed,
+      at: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+      at_instant: Instant::now(),
+      sequence: 0,
+      in_maintenance_window: false,
+    }
+  }
+
+  #[test]
+  fn serves_the_current_status_and_the_last_measurement() {
+    let state = SharedState::default();
+    let response = request(&state, "GET /metrics HTTP/1.1\r\nHost: ups\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains("ups_status{status=\"OnMains\"} 0\n"));
+    assert!(response.contains("ups_status_transitions_total 0\n"));
+    assert!(!response.contains("ups_last_beep_milliseconds"));
+
+    state.lock().unwrap().record(get_transition(Status::OnMains));
+    state.lock().unwrap().record(get_transition(Status::OnBattery));
+    state.lock().unwrap().last_measurement = Some((Duration::from_millis(248), Duration::from_millis(60_012)));
+    let response = request(&state, "GET /metrics HTTP/1.1\r\n\r\n");
+    assert!(response.contains("ups_status{status=\"OnMains\"} 0\n"));
+    assert!(response.contains("ups_status{status=\"OnBattery\"} 1\n"));
+    assert_eq!(response.matches("ups_status{").count(), get_all_statuses().count());
+    assert!(response.contains("ups_status_transitions_total 2\n"));
+    assert!(response.contains("ups_last_beep_milliseconds 248\n"));
+    assert!(response.contains("ups_last_inter_beep_milliseconds 60012\n"));
+
+    assert!(!response.contains("# EOF"));
+
+    assert!(request(&state, "GET /status HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+    assert!(request(&state, "POST /metrics HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+  }
+
+  #[test]
+  fn tags_transitions_with_their_durations_for_openmetrics() {
+    let state = SharedState::default();
+    let openmetrics_request = "GET /metrics HTTP/1.1\r\nAccept: application/openmetrics-text;version=1.0.0,text/plain;q=0.5\r\n\r\n";
+    let response = request(&state, openmetrics_request);
+    assert!(response.contains("Content-Type: application/openmetrics-text; version=1.0.0; charset=utf-8\r\n"));
+    assert!(response.contains("# TYPE ups_status_transitions counter\nups_status_transitions_total 0\n"));
+    assert!(response.ends_with("# EOF\n"));
+
+    state.lock().unwrap().record(Transition { beep_duration: Duration::from_millis(248), inter_beep_duration: Duration::from_millis(1012), ..get_transition(Status::LowOnBattery) });
+    let response = request(&state, openmetrics_request);
+    assert!(response.contains("ups_status_transitions_total 1 # {beep_ms=\"248\",gap_ms=\"1012\"} 1 1700000000.123\n"));
+    assert!(!request(&state, "GET /metrics HTTP/1.1\r\n\r\n").contains(" # {"));
+  }
+}
@@ -0,0 +1,313 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::delivery::{Broker, start_publisher};
+use crate::json::get_transition_json;
+use crate::state::{SharedState, Transition};
+
+pub const DEFAULT_MQTT_PORT: u16 = 1883;
+pub const DEFAULT_MQTT_TOPIC: &str = "ups/status";
+
+// How long the broker waits for a packet before taking the connection for dead, a ping is sent halfway through it
+const KEEP_ALIVE_DURATION: Duration = Duration::from_secs(60);
+// For connecting and for every packet written or read, a broker slower than that is treated as unreachable
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PINGREQ: u8 = 0xc0;
+const PINGRESP: u8 = 0xd0;
+const DISCONNECT: u8 = 0xe0;
+const RETAIN_FLAG: u8 = 0x01;
+const CLEAN_SESSION_FLAG: u8 = 0x02;
+const PASSWORD_FLAG: u8 = 0x40;
+const USERNAME_FLAG: u8 = 0x80;
+// MQTT 3.1.1, which every broker still speaks
+const PROTOCOL_LEVEL: u8 = 4;
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct MqttConfig {
+  pub host: String,
+  pub port: u16,
+  pub topic: String,
+  // The username along with the password, if there is one, MQTT 3.1.1 has no password without a username
+  pub credentials: Option<(String, Option<String>)>,
+  // Has the broker keep the last status for whoever subscribes later, such as Home Assistant after a restart
+  pub retain: bool,
+}
+
+// Publishes every transition as the same JSON object as the json format to the topic, see start_publisher for the delivery
+pub fn start_mqtt_publisher(config: MqttConfig, state: SharedState) -> JoinHandle<()> {
+  start_publisher(config, state)
+}
+
+impl Broker for MqttConfig {
+  type Connection = TcpStream;
+
+  fn describe(&self) -> String {
+    format!("the MQTT broker at {}:{}", self.host, self.port)
+  }
+
+  fn connect(&self) -> io::Result<TcpStream> {
+    connect(self)
+  }
+
+  fn publish_transitions(&self, stream: &mut TcpStream, current: Option<Transition>, transitions: &Receiver<Transition>) -> io::Result<()> {
+    if let Some(current) = current {
+      stream.write_all(&get_publish_packet(&self.topic, get_transition_json(&current).as_bytes(), self.retain))?;
+    }
+    loop {
+      match transitions.recv_timeout(KEEP_ALIVE_DURATION / 2) {
+        Ok(transition) => stream.write_all(&get_publish_packet(&self.topic, get_transition_json(&transition).as_bytes(), self.retain))?,
+        // The answer is read right away so it can't pile up, not getting one is how a dead connection shows up
+        Err(RecvTimeoutError::Timeout) => {
+          stream.write_all(&[PINGREQ, 0])?;
+          let mut response = [0; 2];
+          stream.read_exact(&mut response)?;
+          if response != [PINGRESP, 0] {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected answer to a ping"));
+          }
+        },
+        Err(RecvTimeoutError::Disconnected) => return stream.write_all(&[DISCONNECT, 0]),
+      }
+    }
+  }
+}
+
+fn connect(config: &MqttConfig) -> io::Result<TcpStream> {
+  let address = (config.host.as_str(), config.port).to_socket_addrs()?.next()
+    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host has no address"))?;
+  let mut stream = TcpStream::connect_timeout(&address, IO_TIMEOUT)?;
+  stream.set_read_timeout(Some(IO_TIMEOUT))?;
+  stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+  // Two instances with the same client identifier would keep taking the connection from each other
+  let client_id = format!("ups-power-status-from-beeps-{}", process::id());
+  stream.write_all(&get_connect_packet(&client_id, config.credentials.as_ref()))?;
+  let mut connack = [0; 4];
+  stream.read_exact(&mut connack)?;
+  if connack[..2] != [CONNACK, 2] {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected answer to connecting"));
+  }
+  match connack[3] {
+    0 => Ok(stream),
+    return_code => Err(io::Error::new(io::ErrorKind::ConnectionRefused, get_refusal_description(return_code))),
+  }
+}
+
+fn get_refusal_description(return_code: u8) -> String {
+  match return_code {
+    1 => "the broker doesn't speak MQTT 3.1.1".to_string(),
+    2 => "the client identifier was rejected".to_string(),
+    3 => "the broker is unavailable".to_string(),
+    4 => "bad username or password".to_string(),
+    5 => "not authorized".to_string(),
+    return_code => format!("connection refused with return code {}", return_code),
+  }
+}
+
+fn get_connect_packet(client_id: &str, credentials: Option<&(String, Option<String>)>) -> Vec<u8> {
+  let mut flags = CLEAN_SESSION_FLAG;
+  let mut body = vec![];
+  push_string(&mut body, b"MQTT");
+  body.push(PROTOCOL_LEVEL);
+  let flags_index = body.len();
+  body.push(0);
+  body.extend_from_slice(&(KEEP_ALIVE_DURATION.as_secs() as u16).to_be_bytes());
+  push_string(&mut body, client_id.as_bytes());
+  if let Some((username, password)) = credentials {
+    flags |= USERNAME_FLAG;
+    push_string(&mut body, username.as_bytes());
+    if let Some(password) = password {
+      flags |= PASSWORD_FLAG;
+      push_string(&mut body, password.as_bytes());
+    }
+  }
+  body[flags_index] = flags;
+  get_packet(CONNECT, &body)
+}
+
+// At most once delivery, which takes no packet identifier
+fn get_publish_packet(topic: &str, payload: &[u8], retain: bool) -> Vec<u8> {
+  let mut body = vec![];
+  push_string(&mut body, topic.as_bytes());
+  body.extend_from_slice(payload);
+  get_packet(if retain { PUBLISH | RETAIN_FLAG } else { PUBLISH }, &body)
+}
+
+fn get_packet(header: u8, body: &[u8]) -> Vec<u8> {
+  let mut packet = vec![header];
+  push_remaining_length(&mut packet, body.len());
+  packet.extend_from_slice(body);
+  packet
+}
+
+// Seven bits at a time from the lowest, the top bit of every byte but the last set
+fn push_remaining_length(packet: &mut Vec<u8>, mut length: usize) {
+  loop {
+    let byte = (length % 128) as u8;
+    length /= 128;
+    if length == 0 {
+      packet.push(byte);
+      return;
+    }
+    packet.push(byte | 0x80);
+  }
+}
+
+fn push_string(body: &mut Vec<u8>, string: &[u8]) {
+  body.extend_from_slice(&(string.len() as u16).to_be_bytes());
+  body.extend_from_slice(string);
+}
+
+// Topics are published to, not subscribed to, so they can't have wildcards, and a string holds at most 65535 bytes
+pub fn is_valid_topic(topic: &str) -> bool {
+  !topic.is_empty() && topic.len() <= u16::MAX as usize && !topic.contains(['+', '#', '\0'])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::net::TcpListener;
+  use std::sync::{Arc, Mutex};
+  use std::thread;
+  use std::time::{Instant, SystemTime};
+
+  use crate::guidance::GuidanceTable;
+  use crate::report::Origin;
+  use crate::state::StatusState;
+  use crate::status::Status;
+
+  fn get_config(port: u16) -> MqttConfig {
+    MqttConfig { host: "127.0.0.1".to_string(), port, topic: "ups/status".to_string(), credentials: None, retain: true }
+  }
+
+  fn get_transition(status: Status) -> Transition {
+    Transition {
+      from: None,
+      to: status,
+      guidance: GuidanceTable::new(vec![]).get(status),
+      cleared: false,
+      confidence: 1.0,
+      beep_duration: Duration::from_millis(250),
+      inter_beep_duration: Duration::from_secs(60),
+      origin: Origin::Observed,
+      at: SystemTime::now(),
+      at_instant: Instant::now(),
+      sequence: 0,
+      in_maintenance_window: false,
+    }
+  }
+
+  // The fixed header and the body of the next packet
+  fn read_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut header = [0; 1];
+    stream.read_exact(&mut header).unwrap();
+    let (mut length, mut multiplier) = (0, 1);
+    loop {
+      let mut byte = [0; 1];
+      stream.read_exact(&mut byte).unwrap();
+      length += (byte[0] & 0x7f) as usize * multiplier;
+      multiplier *= 128;
+      if byte[0] & 0x80 == 0 {
+        break;
+      }
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).unwrap();
+    (header[0], body)
+  }
+
+  #[test]
+  fn encodes_remaining_lengths() {
+    let get_length = |length: usize| {
+      let mut packet = vec![];
+      push_remaining_length(&mut packet, length);
+      packet
+    };
+    assert_eq!(get_length(0), vec![0]);
+    assert_eq!(get_length(127), vec![0x7f]);
+    assert_eq!(get_length(128), vec![0x80, 0x01]);
+    assert_eq!(get_length(321), vec![0xc1, 0x02]);
+    assert_eq!(get_length(16_384), vec![0x80, 0x80, 0x01]);
+  }
+
+  #[test]
+  fn encodes_connect_packets() {
+    assert_eq!(get_connect_packet("ups", None), vec![
+      CONNECT, 15, 0, 4, b'M', b'Q', b'T', b'T', PROTOCOL_LEVEL, CLEAN_SESSION_FLAG, 0, 60, 0, 3, b'u', b'p', b's',
+    ]);
+    let packet = get_connect_packet("ups", Some(&("ha".to_string(), Some("pw".to_string()))));
+    assert_eq!(packet[9], CLEAN_SESSION_FLAG | USERNAME_FLAG | PASSWORD_FLAG);
+    assert!(packet.ends_with(&[0, 2, b'h', b'a', 0, 2, b'p', b'w']));
+    let packet = get_connect_packet("ups", Some(&("ha".to_string(), None)));
+    assert_eq!(packet[9], CLEAN_SESSION_FLAG | USERNAME_FLAG);
+    assert!(packet.ends_with(&[0, 2, b'h', b'a']));
+  }
+
+  #[test]
+  fn encodes_publish_packets() {
+    assert_eq!(get_publish_packet("a/b", b"on", false), vec![PUBLISH, 7, 0, 3, b'a', b'/', b'b', b'o', b'n']);
+    assert_eq!(get_publish_packet("a/b", b"on", true)[0], PUBLISH | RETAIN_FLAG);
+  }
+
+  #[test]
+  fn validates_topics() {
+    assert!(is_valid_topic("ups/status"));
+    assert!(!is_valid_topic(""));
+    assert!(!is_valid_topic("ups/+"));
+    assert!(!is_valid_topic("ups/#"));
+  }
+
+  #[test]
+  fn publishes_the_current_status_and_every_transition() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let state = Arc::new(Mutex::new(StatusState::new(10)));
+    state.lock().unwrap().record(get_transition(Status::OnMains));
+    let publisher = start_mqtt_publisher(get_config(listener.local_addr().unwrap().port()), state.clone());
+
+    let (mut stream, _) = listener.accept().unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let (header, body) = read_packet(&mut stream);
+    assert_eq!(header, CONNECT);
+    assert_eq!(&body[..7], &[0, 4, b'M', b'Q', b'T', b'T', PROTOCOL_LEVEL]);
+    stream.write_all(&[CONNACK, 2, 0, 0]).unwrap();
+
+    let (header, body) = read_packet(&mut stream);
+    assert_eq!(header, PUBLISH | RETAIN_FLAG);
+    assert_eq!(&body[..12], b"\0\x0aups/status");
+    assert!(String::from_utf8_lossy(&body[12..]).contains("\"status\":\"OnMains\""));
+
+    state.lock().unwrap().record(get_transition(Status::OnBattery));
+    let (_, body) = read_packet(&mut stream);
+    let payload = String::from_utf8_lossy(&body[12..]).to_string();
+    assert!(payload.contains("\"status\":\"OnBattery\""));
+    assert!(payload.contains("\"description\":"));
+
+    // Stopping publishes what was recorded before it, then disconnects
+    state.lock().unwrap().record(get_transition(Status::OnMains));
+    state.lock().unwrap().close_subscriptions();
+    let (_, body) = read_packet(&mut stream);
+    assert!(String::from_utf8_lossy(&body[12..]).contains("\"status\":\"OnMains\""));
+    assert_eq!(read_packet(&mut stream), (DISCONNECT, vec![]));
+    publisher.join().unwrap();
+  }
+
+  #[test]
+  fn refused_connections_are_described() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let broker = thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      read_packet(&mut stream);
+      stream.write_all(&[CONNACK, 2, 0, 4]).unwrap();
+    });
+    assert_eq!(connect(&get_config(port)).unwrap_err().to_string(), "bad username or password");
+    broker.join().unwrap();
+  }
+}
@@ -0,0 +1,129 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::detector::{BEEP_BOUNCE_MAX_DURATION, Edge};
+
+// Degrades a clean capture the way a noisy line would, to check the detector still classifies it correctly,
+// the same seed always degrades the same capture the same way
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct NoiseConfig {
+  // Chance of every edge bouncing, as a fraction
+  pub bounce_probability: f64,
+  // Edges are moved by up to this much either way
+  pub jitter: Duration,
+  pub seed: u64,
+}
+
+// For when no seed is given, printed along so the run can be repeated
+pub fn get_time_seed() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64
+}
+
+// SplitMix64, plenty for noise and no dependency for it
+struct NoiseRandom {
+  state: u64,
+}
+
+impl NoiseRandom {
+  fn next(&mut self) -> u64 {
+    self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut value = self.state;
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+    value ^ (value >> 31)
+  }
+
+  // Uniform in [0, 1)
+  fn next_fraction(&mut self) -> f64 {
+    (self.next() >> 11) as f64 / (1u64 << 53) as f64
+  }
+
+  // Uniform in [0, max]
+  fn next_up_to(&mut self, max: u64) -> u64 {
+    if max == 0 { 0 } else { self.next() % (max + 1) }
+  }
+}
+
+// Jitters every edge first, never past the edge before it so the order is kept, then follows some of them with a bounce,
+// a flip to the other level and back, each half no longer than BEEP_BOUNCE_MAX_DURATION so it is below both bounce thresholds
+// of the detector, a bounce that wouldn't fit before the next edge is left out
+pub fn add_noise(events: &[(Duration, Edge)], config: &NoiseConfig) -> Vec<(Duration, Edge)> {
+  let mut random = NoiseRandom { state: config.seed };
+  let jitter_millis = config.jitter.as_millis() as u64;
+
+  let mut jittered_events: Vec<(Duration, Edge)> = Vec::with_capacity(events.len());
+  for (offset, edge) in events {
+    let shift = Duration::from_millis(random.next_up_to(2 * jitter_millis));
+    let offset = (*offset + shift).saturating_sub(config.jitter);
+    let previous_offset = jittered_events.last().map_or(Duration::ZERO, |event| event.0);
+    jittered_events.push((offset.max(previous_offset), *edge));
+  }
+
+  let bounce_max_millis = BEEP_BOUNCE_MAX_DURATION.as_millis() as u64;
+  let mut noisy_events = Vec::with_capacity(jittered_events.len());
+  for (index, (offset, edge)) in jittered_events.iter().enumerate() {
+    noisy_events.push((*offset, *edge));
+    if random.next_fraction() >= config.bounce_probability {
+      continue;
+    }
+    let flip_offset = *offset + Duration::from_millis(1 + random.next_up_to(bounce_max_millis - 1));
+    let back_offset = flip_offset + Duration::from_millis(1 + random.next_up_to(bounce_max_millis - 1));
+    if jittered_events.get(index + 1).is_some_and(|next_event| next_event.0 <= back_offset) {
+      continue;
+    }
+    noisy_events.push((flip_offset, edge.inverted()));
+    noisy_events.push((back_offset, *edge));
+  }
+  noisy_events
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn get_capture() -> Vec<(Duration, Edge)> {
+    (0..20u64)
+      .flat_map(|index| [
+        (Duration::from_millis(index * 30_250), Edge::BeepStart),
+        (Duration::from_millis(index * 30_250 + 250), Edge::BeepEnd),
+      ])
+      .collect()
+  }
+
+  #[test]
+  fn same_seed_gives_the_same_noise() {
+    let config = NoiseConfig { bounce_probability: 0.5, jitter: Duration::from_millis(20), seed: 7 };
+    assert_eq!(add_noise(&get_capture(), &config), add_noise(&get_capture(), &config));
+    assert_ne!(add_noise(&get_capture(), &config), add_noise(&get_capture(), &NoiseConfig { seed: 8, ..config }));
+  }
+
+  #[test]
+  fn no_noise_leaves_the_capture_alone() {
+    let config = NoiseConfig { bounce_probability: 0.0, jitter: Duration::ZERO, seed: 1 };
+    assert_eq!(add_noise(&get_capture(), &config), get_capture());
+  }
+
+  #[test]
+  fn jitter_stays_within_bounds_and_keeps_the_order() {
+    let config = NoiseConfig { bounce_probability: 0.0, jitter: Duration::from_millis(100), seed: 3 };
+    let noisy_events = add_noise(&get_capture(), &config);
+    assert_ne!(noisy_events, get_capture());
+    for ((noisy_offset, noisy_edge), (offset, edge)) in noisy_events.iter().zip(get_capture()) {
+      assert_eq!(*noisy_edge, edge);
+      assert!(noisy_offset.abs_diff(offset) <= Duration::from_millis(100));
+    }
+    assert!(noisy_events.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+  }
+
+  #[test]
+  fn bounces_flip_the_level_and_back_below_the_threshold() {
+    let config = NoiseConfig { bounce_probability: 1.0, jitter: Duration::ZERO, seed: 5 };
+    let noisy_events = add_noise(&get_capture(), &config);
+    assert_eq!(noisy_events.len(), get_capture().len() * 3);
+    for bounce in noisy_events.chunks(3) {
+      assert_eq!(bounce[1].1, bounce[0].1.inverted());
+      assert_eq!(bounce[2].1, bounce[0].1);
+      assert!(bounce[1].0 - bounce[0].0 <= BEEP_BOUNCE_MAX_DURATION);
+      assert!(bounce[2].0 - bounce[1].0 <= BEEP_BOUNCE_MAX_DURATION);
+    }
+  }
+}
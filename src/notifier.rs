@@ -0,0 +1,291 @@
+use std::time::{Duration, SystemTime};
+
+use crate::clock::get_utc_timestamp;
+use crate::glyph::GlyphTable;
+use crate::guidance::Severity;
+use crate::json::{get_alert_json, get_cleared_json, get_heartbeat_json, get_on_battery_warning_json, get_pretty_json, get_transition_json};
+use crate::output::{Output, SyslogData};
+use crate::report::{Format, Origin};
+use crate::state::Transition;
+use crate::status::{Status, get_status_description};
+
+// Everything the reporter delivers, once it has decided it's to be reported at all, the hold-backs and pausing are all behind it
+#[derive(Debug)]
+pub enum StatusEvent<'a> {
+  // A new status
+  Transition { transition: &'a Transition },
+  // Leaving a critical status for the one classified by, which may not be reported yet, and when it was
+  Cleared { cleared_status: Status, by: Status, at: SystemTime },
+  // Something to warn about that doesn't come with a status, such as the battery wear warning, and when it came up
+  Alert { alert: &'a str, at: SystemTime },
+  // The current status repeated while it holds, as of the given time
+  Heartbeat { current: &'a Transition, at: SystemTime },
+  // Having been on battery for longer than the warning threshold, however many battery statuses that took since the mains went
+  OnBatteryWarning { elapsed: Duration, threshold: Duration, at: SystemTime },
+}
+
+// Where reported events go, the reporter hands every event to every notifier in the order they were registered,
+// each one decides for itself what to make of it, delivering somewhere new only takes another implementation of this
+pub trait Notifier {
+  fn notify(&mut self, event: &StatusEvent);
+}
+
+// How the lines of a sink look beyond its format, the same for every sink
+#[derive(Clone, Default)]
+pub struct LineStyle {
+  // Only used by sinks in the char format
+  pub glyphs: GlyphTable,
+  // Appends the confidence of the classification to every reported status
+  pub show_confidence: bool,
+  // Appends the severity and suggested action of every reported status
+  pub show_guidance: bool,
+  // Appends whether every reported status was observed or inferred
+  pub show_origin: bool,
+  // Starts every text line with the time it is about, to the millisecond, and transitions with their sequence number too
+  pub show_timestamps: bool,
+  // Spreads the objects of json sinks over indented lines instead of one line each
+  pub pretty_json: bool,
+}
+
+// Writes events as lines to an output in one of the formats, what --output and --sink set up
+pub struct SinkNotifier {
+  output: Output,
+  format: Format,
+  style: LineStyle,
+}
+
+impl SinkNotifier {
+  pub fn new(output: Output, format: Format, style: LineStyle) -> SinkNotifier {
+    SinkNotifier { output, format, style }
+  }
+}
+
+impl Notifier for SinkNotifier {
+  fn notify(&mut self, event: &StatusEvent) {
+    match (event, self.format) {
+      (StatusEvent::Transition { transition }, format) => {
+        let line = get_status_line(&self.style, format, transition);
+        let line = if format == Format::Text { get_timestamped_line(&self.style, transition.at, Some(transition.sequence), line) } else { line };
+        let data = SyslogData { message_id: "transition", parameters: get_transition_parameters(transition) };
+        self.output.write_line_with_data(&line, transition.guidance.severity, Some(&data));
+      },
+      // A status bar only shows the glyph of the current status, which changes once the new one is reported
+      (StatusEvent::Cleared { .. }, Format::Char) => {},
+      (StatusEvent::Cleared { cleared_status, by, at }, format) => {
+        let line = match format {
+          Format::Json => get_json_line(&self.style, get_cleared_json(*cleared_status, *by, *at)),
+          _ => get_timestamped_line(&self.style, *at, None, format!("Cleared: {}", get_status_description(*cleared_status))),
+        };
+        let data = SyslogData { message_id: "cleared", parameters: vec![("status", format!("{:?}", cleared_status)), ("by", format!("{:?}", by))] };
+        self.output.write_line_with_data(&line, Severity::Info, Some(&data));
+      },
+      // An alert line would just replace the glyph of the current status
+      (StatusEvent::Alert { .. }, Format::Char) => {},
+      (StatusEvent::Alert { alert, at }, format) => {
+        let line = match format {
+          Format::Json => get_json_line(&self.style, get_alert_json(alert)),
+          _ => get_timestamped_line(&self.style, *at, None, alert.to_string()),
+        };
+        self.output.write_line_with_data(&line, Severity::Warning, Some(&SyslogData { message_id: "alert", parameters: vec![] }));
+      },
+      (StatusEvent::Heartbeat { current, at }, format) => {
+        let line = match format {
+          Format::Text => get_timestamped_line(&self.style, *at, None, format!("Heartbeat: {}", get_status_line(&self.style, format, current))),
+          // A status bar has nothing to mark it with, the same glyph again at least shows the line is alive
+          Format::Char => get_status_line(&self.style, format, current),
+          Format::Json => get_json_line(&self.style, get_heartbeat_json(current, *at)),
+        };
+        let mut parameters = get_transition_parameters(current);
+        parameters.retain(|parameter| matches!(parameter.0, "status" | "severity" | "action" | "sequence"));
+        self.output.write_line_with_data(&line, Severity::Info, Some(&SyslogData { message_id: "heartbeat", parameters }));
+      },
+      // The glyph of the battery status already shows it isn't over
+      (StatusEvent::OnBatteryWarning { .. }, Format::Char) => {},
+      (StatusEvent::OnBatteryWarning { elapsed, threshold, at }, format) => {
+        let line = match format {
+          Format::Json => get_json_line(&self.style, get_on_battery_warning_json(*elapsed, *threshold, *at)),
+          _ => get_timestamped_line(&self.style, *at, None, format!("Still on battery after {}s, past the {}s warning threshold", elapsed.as_secs(), threshold.as_secs())),
+        };
+        let parameters = vec![("elapsed_ms", elapsed.as_millis().to_string()), ("threshold_ms", threshold.as_millis().to_string())];
+        self.output.write_line_with_data(&line, Severity::Critical, Some(&SyslogData { message_id: "on_battery_warning", parameters }));
+      },
+    }
+  }
+}
+
+// Every sink as a notifier, in the order given
+pub fn get_sink_notifiers(sinks: Vec<(Output, Format)>, style: &LineStyle) -> Vec<Box<dyn Notifier>> {
+  sinks.into_iter()
+    .map(|(output, format)| Box::new(SinkNotifier::new(output, format, style.clone())) as Box<dyn Notifier>)
+    .collect()
+}
+
+// The same fields as the JSON object of a transition, for the structured data of syslog messages
+fn get_transition_parameters(transition: &Transition) -> Vec<(&'static str, String)> {
+  let mut parameters = vec![
+    ("status", format!("{:?}", transition.to)),
+    ("severity", transition.guidance.severity.name().to_string()),
+    ("action", transition.guidance.action.name().to_string()),
+    ("cleared", transition.cleared.to_string()),
+    ("confidence", transition.confidence.to_string()),
+    ("beep_ms", transition.beep_duration.as_millis().to_string()),
+    ("inter_beep_ms", transition.inter_beep_duration.as_millis().to_string()),
+    ("origin", transition.origin.name().to_string()),
+    ("sequence", transition.sequence.to_string()),
+    ("maintenance", transition.in_maintenance_window.to_string()),
+  ];
+  if let Some(from) = transition.from {
+    parameters.insert(0, ("from", format!("{:?}", from)));
+  }
+  parameters
+}
+
+// Only a transition has a sequence number of its own, a heartbeat repeats the one of the transition it follows
+fn get_timestamped_line(style: &LineStyle, at: SystemTime, sequence: Option<u64>, line: String) -> String {
+  if !style.show_timestamps {
+    return line;
+  }
+  match sequence {
+    Some(sequence) => format!("{} #{} {}", get_utc_timestamp(at), sequence, line),
+    None => format!("{} {}", get_utc_timestamp(at), line),
+  }
+}
+
+fn get_json_line(style: &LineStyle, json: String) -> String {
+  if style.pretty_json { get_pretty_json(&json) } else { json }
+}
+
+// The line a transition is reported with in the given format, the JSON object already carries the origin and everything the options add
+pub fn get_status_line(style: &LineStyle, format: Format, transition: &Transition) -> String {
+  if format == Format::Json {
+    return get_json_line(style, get_transition_json(transition));
+  }
+
+  // Test events are always marked, regardless of show_origin, so they can never pass for a real one
+  let mut line = if transition.origin == Origin::Test { "TEST: ".to_string() } else { String::new() };
+  if format == Format::Char {
+    line.push_str(style.glyphs.get(transition.to));
+    return line;
+  }
+  line.push_str(get_status_description(transition.to));
+  if style.show_confidence {
+    line.push_str(&format!(" (confidence {:.2})", transition.confidence));
+  }
+  if style.show_guidance {
+    line.push_str(&format!(" [{}, {}]", transition.guidance.severity.name(), transition.guidance.action.name()));
+  }
+  if style.show_origin {
+    line.push_str(&format!(" ({})", transition.origin.name()));
+  }
+  // Only ever written late, once the window is over
+  if transition.in_maintenance_window {
+    line.push_str(" (during maintenance)");
+  }
+  line
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::{Duration, Instant, UNIX_EPOCH};
+  use std::{env, fs, process};
+
+  use crate::glyph::parse_glyph_override;
+  use crate::guidance::GuidanceTable;
+
+  fn get_transition(to: Status, confidence: f64, origin: Origin) -> Transition {
+    Transition {
+      from: None,
+      to,
+      guidance: GuidanceTable::new(vec![]).get(to),
+      cleared: false,
+      confidence,
+      beep_duration: Duration::from_millis(250),
+      inter_beep_duration: Duration::from_secs(60),
+      origin,
+      at: SystemTime::now(),
+      at_instant: Instant::now(),
+      sequence: 0,
+      in_maintenance_window: false,
+    }
+  }
+
+  #[test]
+  fn appends_confidence_when_enabled() {
+    let style = LineStyle { show_confidence: true, ..LineStyle::default() };
+    let line = get_status_line(&style, Format::Text, &get_transition(Status::OnBattery, 0.456, Origin::Observed));
+    assert!(line.ends_with("(confidence 0.46)"));
+  }
+
+  #[test]
+  fn appends_guidance_when_enabled() {
+    let style = LineStyle { show_confidence: true, show_guidance: true, ..LineStyle::default() };
+    let line = get_status_line(&style, Format::Text, &get_transition(Status::LowOnBattery, 1.0, Origin::Observed));
+    assert!(line.ends_with("(confidence 1.00) [critical, shutdown-now]"));
+  }
+
+  #[test]
+  fn appends_origin_when_enabled() {
+    let style = LineStyle { show_origin: true, ..LineStyle::default() };
+    let line = get_status_line(&style, Format::Text, &get_transition(Status::OnMains, 1.0, Origin::Inferred));
+    assert_eq!(line, format!("{} (inferred)", get_status_description(Status::OnMains)));
+  }
+
+  #[test]
+  fn char_format_is_only_the_glyph() {
+    let style = LineStyle {
+      glyphs: GlyphTable::new(vec![parse_glyph_override("OnMains=⚡").unwrap()]),
+      show_confidence: true,
+      show_guidance: true,
+      ..LineStyle::default()
+    };
+    assert_eq!(get_status_line(&style, Format::Char, &get_transition(Status::LowOnBattery, 1.0, Origin::Observed)), "L");
+    assert_eq!(get_status_line(&style, Format::Char, &get_transition(Status::OnMains, 1.0, Origin::Observed)), "⚡");
+    assert_eq!(get_status_line(&style, Format::Char, &get_transition(Status::OnBattery, 1.0, Origin::Test)), "TEST: B");
+  }
+
+  #[test]
+  fn timestamps_text_lines_when_enabled() {
+    let path = env::temp_dir().join(format!("ups-power-status-timestamps-{}.log", process::id()));
+    let output = Output::open(&crate::output::OutputTarget::File(path.to_str().unwrap().to_string())).unwrap();
+    let mut notifier = SinkNotifier::new(output, Format::Text, LineStyle { show_timestamps: true, ..LineStyle::default() });
+    let at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+    let transition = Transition { sequence: 3, at, ..get_transition(Status::OnMains, 1.0, Origin::Inferred) };
+    notifier.notify(&StatusEvent::Cleared { cleared_status: Status::LowOnBattery, by: Status::OnMains, at });
+    notifier.notify(&StatusEvent::Transition { transition: &transition });
+    notifier.notify(&StatusEvent::Alert { alert: "Replace the battery", at: at + Duration::from_secs(1) });
+    notifier.notify(&StatusEvent::Heartbeat { current: &transition, at: at + Duration::from_secs(60) });
+    notifier.notify(&StatusEvent::OnBatteryWarning { elapsed: Duration::from_secs(600), threshold: Duration::from_secs(300), at: at + Duration::from_secs(90) });
+
+    let contents = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let description = get_status_description(Status::OnMains);
+    assert_eq!(contents.lines().collect::<Vec<_>>(), vec![
+      format!("2023-11-14T22:13:20.123Z Cleared: {}", get_status_description(Status::LowOnBattery)),
+      format!("2023-11-14T22:13:20.123Z #3 {}", description),
+      "2023-11-14T22:13:21.123Z Replace the battery".to_string(),
+      format!("2023-11-14T22:14:20.123Z Heartbeat: {}", description),
+      "2023-11-14T22:14:50.123Z Still on battery after 600s, past the 300s warning threshold".to_string(),
+    ]);
+  }
+
+  #[test]
+  fn transitions_carry_their_fields_as_syslog_parameters() {
+    let transition = Transition { from: Some(Status::OnMains), sequence: 2, ..get_transition(Status::OnBattery, 0.5, Origin::Observed) };
+    let parameters = get_transition_parameters(&transition);
+    assert_eq!(parameters[..3], [
+      ("from", "OnMains".to_string()),
+      ("status", "OnBattery".to_string()),
+      ("severity", transition.guidance.severity.name().to_string()),
+    ]);
+    assert!(parameters.contains(&("confidence", "0.5".to_string())));
+    assert_eq!(parameters[parameters.len() - 2..], [("sequence", "2".to_string()), ("maintenance", "false".to_string())]);
+    assert_eq!(get_transition_parameters(&get_transition(Status::OnMains, 1.0, Origin::Inferred))[0].0, "status");
+  }
+
+  #[test]
+  fn test_events_are_always_marked() {
+    let line = get_status_line(&LineStyle::default(), Format::Text, &get_transition(Status::LowOnBattery, 1.0, Origin::Test));
+    assert_eq!(line, format!("TEST: {}", get_status_description(Status::LowOnBattery)));
+  }
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use crate::status::{Classification, Status};
+
+// What came with a status change besides the statuses themselves, the durations being the ones the new status was classified from
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct ChangeMeta {
+  pub confidence: f64,
+  pub beep_duration: Duration,
+  pub inter_beep_duration: Duration,
+  // How many changes there have been, this one included
+  pub sequence: u64,
+}
+
+type Observer = Box<dyn FnMut(Option<Status>, Status, &ChangeMeta) + Send>;
+
+// Follows the classifications handed to it and calls every registered observer whenever the status changes, for embedding the matching
+// without the binary, its reporter does the same with everything it holds back and writes out on top, a classification of the status
+// already current calls nobody
+#[derive(Default)]
+pub struct StatusTracker {
+  current: Option<Status>,
+  sequence: u64,
+  observers: Vec<Observer>,
+}
+
+impl StatusTracker {
+  pub fn new() -> StatusTracker {
+    StatusTracker::default()
+  }
+
+  // Observers are called in the order they were registered, with the status before the change, None for the first one, and the one after it
+  pub fn on_status_change(&mut self, observer: impl FnMut(Option<Status>, Status, &ChangeMeta) + Send + 'static) {
+    self.observers.push(Box::new(observer));
+  }
+
+  pub fn current(&self) -> Option<Status> {
+    self.current
+  }
+
+  // Returns whether the status changed, which is when the observers got called
+  pub fn update(&mut self, classification: Classification) -> bool {
+    if self.current == Some(classification.status) {
+      return false;
+    }
+    let from = self.current.replace(classification.status);
+    self.sequence += 1;
+    let meta = ChangeMeta {
+      confidence: classification.confidence,
+      beep_duration: classification.beep_duration,
+      inter_beep_duration: classification.inter_beep_duration,
+      sequence: self.sequence,
+    };
+    for observer in &mut self.observers {
+      observer(from, classification.status, &meta);
+    }
+    true
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::{Arc, Mutex};
+
+  use crate::status::{MatchConfig, get_status_from_beep_durations};
+
+  #[test]
+  fn calls_every_observer_on_every_change() {
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    let mut tracker = StatusTracker::new();
+    for name in ["first", "second"] {
+      let changes = changes.clone();
+      tracker.on_status_change(move |from, to, meta| changes.lock().unwrap().push(format!("{} {:?} {:?} #{}", name, from, to, meta.sequence)));
+    }
+
+    let config = MatchConfig::default();
+    let on_battery = get_status_from_beep_durations(Duration::from_millis(250), Duration::from_secs(60), &config);
+    let low_on_battery = get_status_from_beep_durations(Duration::from_millis(250), Duration::from_secs(1), &config);
+    assert!(tracker.update(on_battery));
+    assert!(!tracker.update(on_battery));
+    assert!(tracker.update(low_on_battery));
+    assert_eq!(tracker.current(), Some(Status::LowOnBattery));
+
+    assert_eq!(*changes.lock().unwrap(), vec![
+      "first None OnBattery #1",
+      "second None OnBattery #1",
+      "first Some(OnBattery) LowOnBattery #2",
+      "second Some(OnBattery) LowOnBattery #2",
+    ]);
+  }
+}
@@ -0,0 +1,213 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::time::SystemTime;
+
+use crate::clock::get_utc_timestamp;
+use crate::guidance::Severity;
+use crate::logging::error;
+
+const SYSLOG_SOCKET_PATH: &str = "/dev/log";
+// LOG_DAEMON
+const SYSLOG_FACILITY: u8 = 3;
+const SYSLOG_TAG: &str = env!("CARGO_PKG_NAME");
+// The SD-ID of the structured data element, 32473 being the enterprise number RFC 5612 sets aside for examples,
+// as there is no number registered for this
+const SYSLOG_SD_ID: &str = "ups@32473";
+
+// The wire format of the messages sent to syslog, the plain one is just the tag and the line
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum SyslogFormat {
+  Plain,
+  // RFC 5424, with the fields of the event as structured data so the syslog daemon can parse them without parsing the line
+  Rfc5424,
+}
+
+// The fields of an event for the structured data of RFC 5424 syslog messages, the line is the message either way
+pub struct SyslogData<'a> {
+  // Says which kind of event it is, as in transition or alert
+  pub message_id: &'a str,
+  pub parameters: Vec<(&'static str, String)>,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum OutputTarget {
+  Stdout,
+  Stderr,
+  Syslog(SyslogFormat),
+  // Appended to, created if missing
+  File(String),
+}
+
+impl OutputTarget {
+  // Anything other than the fixed names is taken as the path of a file
+  pub fn from_name(name: &str) -> OutputTarget {
+    match name {
+      "stdout" => OutputTarget::Stdout,
+      "stderr" => OutputTarget::Stderr,
+      "syslog" => OutputTarget::Syslog(SyslogFormat::Plain),
+      "syslog-rfc5424" => OutputTarget::Syslog(SyslogFormat::Rfc5424),
+      path => OutputTarget::File(path.to_string()),
+    }
+  }
+
+  pub fn name(&self) -> &str {
+    match self {
+      OutputTarget::Stdout => "stdout",
+      OutputTarget::Stderr => "stderr",
+      OutputTarget::Syslog(SyslogFormat::Plain) => "syslog",
+      OutputTarget::Syslog(SyslogFormat::Rfc5424) => "syslog-rfc5424",
+      OutputTarget::File(path) => path,
+    }
+  }
+}
+
+// Where the human readable status lines go, diagnostics always go to stderr regardless
+pub enum Output {
+  Stdout,
+  Stderr,
+  Syslog(UnixDatagram, SyslogFormat),
+  File(File),
+}
+
+impl Output {
+  pub fn open(target: &OutputTarget) -> Result<Output, String> {
+    Ok(match target {
+      OutputTarget::Stdout => Output::Stdout,
+      OutputTarget::Stderr => Output::Stderr,
+      OutputTarget::Syslog(format) => {
+        let socket = UnixDatagram::unbound().map_err(|error| format!("could not open syslog socket: {}", error))?;
+        socket.connect(SYSLOG_SOCKET_PATH).map_err(|error| format!("could not connect to syslog at {}: {}", SYSLOG_SOCKET_PATH, error))?;
+        Output::Syslog(socket, *format)
+      },
+      OutputTarget::File(path) => Output::File(
+        OpenOptions::new().create(true).append(true).open(path).map_err(|error| format!("could not open {}: {}", path, error))?,
+      ),
+    })
+  }
+
+  pub fn write_line(&mut self, line: &str, severity: Severity) {
+    self.write_line_with_data(line, severity, None);
+  }
+
+  // Only RFC 5424 syslog has anywhere to put the data, every other output writes just the line,
+  // failing to write only warns, as losing a line is better than stopping detection
+  pub fn write_line_with_data(&mut self, line: &str, severity: Severity, data: Option<&SyslogData>) {
+    let result = match self {
+      // Flushed on every line rather than trusting the buffering to, a pipeline such as jq reading the JSON lines sees each as it happens
+      Output::Stdout => {
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{}", line).and_then(|_| stdout.flush())
+      },
+      Output::Stderr => writeln!(std::io::stderr(), "{}", line),
+      Output::Syslog(socket, SyslogFormat::Plain) => socket.send(get_syslog_message(line, severity).as_bytes()).map(|_| ()),
+      Output::Syslog(socket, SyslogFormat::Rfc5424) => {
+        socket.send(get_rfc5424_syslog_message(line, severity, data, SystemTime::now()).as_bytes()).map(|_| ())
+      },
+      Output::File(file) => writeln!(file, "{}", line).and_then(|_| file.flush()),
+    };
+    if let Err(error) = result {
+      error!("Could not write status: {}", error);
+    }
+  }
+}
+
+fn get_syslog_priority(severity: Severity) -> u8 {
+  let level = match severity {
+    Severity::Info => 6,
+    Severity::Warning => 4,
+    Severity::Critical => 2,
+  };
+  SYSLOG_FACILITY * 8 + level
+}
+
+fn get_syslog_message(line: &str, severity: Severity) -> String {
+  format!("<{}>{}: {}", get_syslog_priority(severity), SYSLOG_TAG, line)
+}
+
+// The hostname is left for the syslog daemon to fill in, lines without data have nil structured data
+fn get_rfc5424_syslog_message(line: &str, severity: Severity, data: Option<&SyslogData>, at: SystemTime) -> String {
+  let structured_data = match data {
+    Some(data) if !data.parameters.is_empty() => {
+      let parameters: Vec<String> = data.parameters.iter()
+        .map(|(name, value)| format!(" {}=\"{}\"", name, escape_sd_param_value(value)))
+        .collect();
+      format!("[{}{}]", SYSLOG_SD_ID, parameters.concat())
+    },
+    _ => "-".to_string(),
+  };
+  format!(
+    "<{}>1 {} - {} {} {} {} {}",
+    get_syslog_priority(severity),
+    get_utc_timestamp(at),
+    SYSLOG_TAG,
+    process::id(),
+    data.map_or("-", |data| data.message_id),
+    structured_data,
+    line,
+  )
+}
+
+// The only characters a parameter value has to escape
+fn escape_sd_param_value(value: &str) -> String {
+  let mut escaped = String::with_capacity(value.len());
+  for character in value.chars() {
+    if matches!(character, '"' | '\\' | ']') {
+      escaped.push('\\');
+    }
+    escaped.push(character);
+  }
+  escaped
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::fs;
+  use std::process;
+
+  #[test]
+  fn parses_targets() {
+    assert_eq!(OutputTarget::from_name("stdout"), OutputTarget::Stdout);
+    assert_eq!(OutputTarget::from_name("syslog"), OutputTarget::Syslog(SyslogFormat::Plain));
+    assert_eq!(OutputTarget::from_name("syslog-rfc5424"), OutputTarget::Syslog(SyslogFormat::Rfc5424));
+    assert_eq!(OutputTarget::from_name("status.log"), OutputTarget::File("status.log".to_string()));
+  }
+
+  #[test]
+  fn syslog_priority_follows_severity() {
+    assert_eq!(get_syslog_message("On battery", Severity::Critical), format!("<26>{}: On battery", SYSLOG_TAG));
+    assert_eq!(get_syslog_priority(Severity::Warning), 28);
+    assert_eq!(get_syslog_priority(Severity::Info), 30);
+  }
+
+  #[test]
+  fn formats_rfc5424_messages_with_structured_data() {
+    let at = std::time::UNIX_EPOCH + std::time::Duration::from_millis(1_700_000_000_123);
+    let data = SyslogData {
+      message_id: "transition",
+      parameters: vec![("status", "OnBattery".to_string()), ("note", "say \"hi\" [x]\\".to_string())],
+    };
+    assert_eq!(
+      get_rfc5424_syslog_message("On battery", Severity::Warning, Some(&data), at),
+      format!("<28>1 2023-11-14T22:13:20.123Z - {} {} transition [ups@32473 status=\"OnBattery\" note=\"say \\\"hi\\\" [x\\]\\\\\"] On battery", SYSLOG_TAG, process::id()),
+    );
+    assert_eq!(
+      get_rfc5424_syslog_message("Summary", Severity::Info, None, at),
+      format!("<30>1 2023-11-14T22:13:20.123Z - {} {} - - Summary", SYSLOG_TAG, process::id()),
+    );
+  }
+
+  #[test]
+  fn appends_lines_to_file() {
+    let path = env::temp_dir().join(format!("ups-power-status-output-{}.log", process::id()));
+    let target = OutputTarget::File(path.to_str().unwrap().to_string());
+    Output::open(&target).unwrap().write_line("first", Severity::Info);
+    Output::open(&target).unwrap().write_line("second", Severity::Critical);
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "first\nsecond\n");
+    fs::remove_file(&path).unwrap();
+  }
+}
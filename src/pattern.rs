@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use crate::status::{MatchConfig, Status, Tolerance, get_status_from_name};
+
+// A table entry given on the command line, taking the place of whatever the table has for its status
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct PatternOverride {
+  pub status: Status,
+  pub beep_duration: Duration,
+  pub inter_beep_duration: Duration,
+  // None keeps the margin and floor of the whole table for that duration
+  pub beep_tolerance: Option<Tolerance>,
+  pub inter_beep_tolerance: Option<Tolerance>,
+}
+
+// Parsed from "<status>:beep=<duration>[±<tolerance>],gap=<duration>[±<tolerance>]", as in "LowOnBattery:beep=250ms±10%,gap=1s±300ms",
+// durations are in ms or s and a tolerance is either a percentage of the target or a duration either side of it, "+-" works as well as "±"
+pub fn parse_pattern_override(value: &str) -> Result<PatternOverride, String> {
+  let invalid_pattern = || format!("invalid pattern {}, expected <status>:beep=<duration>[±<tolerance>],gap=<duration>[±<tolerance>]", value);
+
+  let (status_name, fields) = value.split_once(':').ok_or_else(invalid_pattern)?;
+  let status = get_status_from_name(status_name).ok_or_else(|| format!("unknown status {} in pattern {}", status_name, value))?;
+  let mut beep = None;
+  let mut inter_beep = None;
+  for field in fields.split(',') {
+    let (key, spec) = field.split_once('=').ok_or_else(invalid_pattern)?;
+    let target_tolerance = parse_target_tolerance(spec).ok_or_else(|| format!("invalid {} {} in pattern {}", key, spec, value))?;
+    match key {
+      "beep" if beep.is_none() => beep = Some(target_tolerance),
+      "gap" if inter_beep.is_none() => inter_beep = Some(target_tolerance),
+      _ => return Err(invalid_pattern()),
+    }
+  }
+  let ((beep_duration, beep_tolerance), (inter_beep_duration, inter_beep_tolerance)) = beep.zip(inter_beep).ok_or_else(invalid_pattern)?;
+  Ok(PatternOverride { status, beep_duration, inter_beep_duration, beep_tolerance, inter_beep_tolerance })
+}
+
+pub fn parse_target_tolerance(spec: &str) -> Option<(Duration, Option<Tolerance>)> {
+  match spec.split_once('±').or_else(|| spec.split_once("+-")) {
+    Some((target, tolerance)) => Some((parse_duration(target)?, Some(parse_tolerance(tolerance)?))),
+    None => Some((parse_duration(spec)?, None)),
+  }
+}
+
+// A percentage scales with the target alone, a duration is the same either side of any target
+fn parse_tolerance(tolerance: &str) -> Option<Tolerance> {
+  match tolerance.strip_suffix('%') {
+    Some(percentage) => Some(Tolerance { error_margin: parse_amount(percentage)? / 100.0, min_error_duration: Duration::ZERO }),
+    None => Some(Tolerance { error_margin: 0.0, min_error_duration: parse_duration(tolerance)? }),
+  }
+}
+
+fn parse_duration(duration: &str) -> Option<Duration> {
+  match duration.strip_suffix("ms") {
+    Some(millis) => Duration::try_from_secs_f64(parse_amount(millis)? / 1e3).ok(),
+    None => Duration::try_from_secs_f64(parse_amount(duration.strip_suffix('s')?)?).ok(),
+  }
+}
+
+fn parse_amount(amount: &str) -> Option<f64> {
+  amount.parse().ok().filter(|amount: &f64| *amount >= 0.0 && amount.is_finite())
+}
+
+// The first entry of the status is replaced where it stands so it keeps its place for the ambiguity policies, any others of it are dropped,
+// a status the table doesn't have yet is added at the end, a later override of the same status replaces an earlier one
+pub fn apply_pattern_overrides(config: &mut MatchConfig, pattern_overrides: &[PatternOverride]) {
+  for pattern_override in pattern_overrides {
+    let entry = (pattern_override.status, [pattern_override.beep_duration, pattern_override.inter_beep_duration]);
+    match config.beep_durations.iter().position(|status_beep_duration| status_beep_duration.0 == pattern_override.status) {
+      Some(index) => {
+        config.beep_durations[index] = entry;
+        let mut index = index + 1;
+        while index < config.beep_durations.len() {
+          if config.beep_durations[index].0 == pattern_override.status {
+            config.beep_durations.remove(index);
+          } else {
+            index += 1;
+          }
+        }
+      },
+      None => config.beep_durations.push(entry),
+    }
+
+    let [beep_tolerance, inter_beep_tolerance] = config.get_tolerances(pattern_override.status);
+    let tolerances = [pattern_override.beep_tolerance.unwrap_or(beep_tolerance), pattern_override.inter_beep_tolerance.unwrap_or(inter_beep_tolerance)];
+    config.status_tolerances.retain(|status_tolerance| status_tolerance.0 != pattern_override.status);
+    config.status_tolerances.push((pattern_override.status, tolerances));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::status::{ERROR_MARGIN, MIN_ERROR_DURATION, STATUS_BEEP_DURATIONS, get_status_from_beep_durations};
+
+  #[test]
+  fn parses_patterns() {
+    let pattern_override = parse_pattern_override("LowOnBattery:beep=250ms±10%,gap=1s±300ms").unwrap();
+    assert_eq!(pattern_override, PatternOverride {
+      status: Status::LowOnBattery,
+      beep_duration: Duration::from_millis(250),
+      inter_beep_duration: Duration::from_secs(1),
+      beep_tolerance: Some(Tolerance { error_margin: 0.1, min_error_duration: Duration::ZERO }),
+      inter_beep_tolerance: Some(Tolerance { error_margin: 0.0, min_error_duration: Duration::from_millis(300) }),
+    });
+
+    let pattern_override = parse_pattern_override("ReplaceBattery:gap=40.5s+-5%,beep=2s").unwrap();
+    assert_eq!((pattern_override.beep_duration, pattern_override.inter_beep_duration), (Duration::from_secs(2), Duration::from_millis(40500)));
+    assert_eq!(pattern_override.beep_tolerance, None);
+    assert_eq!(pattern_override.inter_beep_tolerance.unwrap().error_margin, 0.05);
+  }
+
+  #[test]
+  fn rejects_invalid_patterns() {
+    assert!(parse_pattern_override("LowOnBattery").unwrap_err().starts_with("invalid pattern LowOnBattery"));
+    assert_eq!(parse_pattern_override("Low:beep=250ms,gap=1s").unwrap_err(), "unknown status Low in pattern Low:beep=250ms,gap=1s");
+    assert_eq!(parse_pattern_override("LowOnBattery:beep=250,gap=1s").unwrap_err(), "invalid beep 250 in pattern LowOnBattery:beep=250,gap=1s");
+    assert!(parse_pattern_override("LowOnBattery:beep=250ms±-5%,gap=1s").is_err());
+    assert!(parse_pattern_override("LowOnBattery:beep=250ms,gap=1e30s").is_err());
+    assert!(parse_pattern_override("LowOnBattery:beep=250ms").unwrap_err().starts_with("invalid pattern"));
+    assert!(parse_pattern_override("LowOnBattery:beep=250ms,beep=300ms,gap=1s").unwrap_err().starts_with("invalid pattern"));
+    assert!(parse_pattern_override("LowOnBattery:beep=250ms,gap=1s,period=2s").unwrap_err().starts_with("invalid pattern"));
+  }
+
+  #[test]
+  fn overrides_only_the_given_status() {
+    let mut config = MatchConfig::default();
+    apply_pattern_overrides(&mut config, &[
+      parse_pattern_override("LowOnBattery:beep=250ms,gap=1s±300ms").unwrap(),
+      parse_pattern_override("OnBattery:beep=500ms±20%,gap=30s").unwrap(),
+      parse_pattern_override("OnBattery:beep=300ms±20%,gap=30s").unwrap(),
+    ]);
+    assert_eq!(config.beep_durations.len(), STATUS_BEEP_DURATIONS.len());
+    assert_eq!(config.beep_durations[0], (Status::OnBattery, [Duration::from_millis(300), Duration::from_secs(30)]));
+
+    let get_status = |beep: u64, inter_beep: u64| get_status_from_beep_durations(Duration::from_millis(beep), Duration::from_millis(inter_beep), &config).status;
+    assert_eq!(get_status(250, 1300), Status::LowOnBattery);
+    assert_eq!(get_status(250, 1301), Status::Unknown);
+    // The floor of the table still applies to the beep left without a tolerance
+    assert_eq!(get_status(280, 1000), Status::LowOnBattery);
+    assert_eq!(get_status(360, 30_000), Status::OnBattery);
+    assert_eq!(get_status(250, 60_000), Status::Unknown);
+    // Every other status is as it was
+    assert_eq!(get_status(250, 10_000), Status::NoLoadOnBattery);
+    assert_eq!(config.get_tolerances(Status::NoLoadOnBattery)[0], Tolerance { error_margin: ERROR_MARGIN, min_error_duration: MIN_ERROR_DURATION });
+  }
+
+  #[test]
+  fn adds_statuses_the_table_lacks() {
+    let mut config = MatchConfig { beep_durations: vec![], ..MatchConfig::default() };
+    apply_pattern_overrides(&mut config, &[parse_pattern_override("OnMains:beep=100ms,gap=10s").unwrap()]);
+    assert_eq!(config.beep_durations, vec![(Status::OnMains, [Duration::from_millis(100), Duration::from_secs(10)])]);
+  }
+}
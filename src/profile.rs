@@ -0,0 +1,305 @@
+use std::fs;
+use std::time::Duration;
+
+use crate::config::ConfigError;
+use crate::status::{CONTINUOUS_BEEP_DURATION, DEFAULT_SILENCE_STATUS, STATUS_BEEP_DURATIONS, Status, ZERO_DURATION, get_status_from_name};
+
+pub const DEFAULT_PROFILE_NAME: &str = "standard";
+
+// Profiles of the UPS models contributed so far, compiled into the binary so they work without any file around
+const MODEL_DATABASE: &str = include_str!("models.txt");
+const MODEL_DATABASE_SOURCE: &str = "model database";
+
+// Everything that differs between UPS models in how they signal their status
+#[derive(PartialEq, Clone, Debug)]
+pub struct Profile {
+  pub name: String,
+  // The line is low while beeping instead of high, every edge is flipped before it reaches the decoder
+  pub inverted: bool,
+  // Target beep and inter beep durations of every status, including the synthetic pair of a timeout during a beep
+  pub beep_durations: Vec<(Status, [Duration; 2])>,
+  // What sustained silence means, None when it means nothing to the model
+  pub silence_status: Option<Status>,
+}
+
+// Silent on mains and beeping in patterns on battery, which is what the built-in table describes
+fn get_standard_profile() -> Profile {
+  Profile {
+    name: DEFAULT_PROFILE_NAME.to_string(),
+    inverted: false,
+    beep_durations: STATUS_BEEP_DURATIONS.to_vec(),
+    silence_status: Some(DEFAULT_SILENCE_STATUS),
+  }
+}
+
+// The same, for sound sensors whose output goes low while they hear the beep
+fn get_active_low_profile() -> Profile {
+  Profile {
+    name: "active-low".to_string(),
+    inverted: true,
+    ..get_standard_profile()
+  }
+}
+
+// Beeping continuously on mains and silent on battery, without any patterns
+fn get_beeps_on_mains_profile() -> Profile {
+  Profile {
+    name: "beeps-on-mains".to_string(),
+    inverted: false,
+    beep_durations: vec![
+      (Status::OnMains, [CONTINUOUS_BEEP_DURATION, ZERO_DURATION]),
+    ],
+    silence_status: Some(Status::OnBattery),
+  }
+}
+
+fn get_builtin_profiles() -> [Profile; 3] {
+  [get_standard_profile(), get_active_low_profile(), get_beeps_on_mains_profile()]
+}
+
+pub fn get_builtin_profile(name: &str) -> Option<Profile> {
+  get_builtin_profiles().into_iter().find(|profile| profile.name == name)
+}
+
+// What --profile and --model can be given, one name per line under a heading for each
+pub fn get_profile_names_description() -> Result<String, ConfigError> {
+  let profile_names: Vec<String> = get_builtin_profiles().iter().map(|profile| format!("  {}", profile.name)).collect();
+  let model_names: Vec<String> = parse_model_database(MODEL_DATABASE)?.iter().map(|profile| format!("  {}", profile.name)).collect();
+  Ok(format!("Built-in profiles, for --profile:\n{}\nModels, for --model:\n{}", profile_names.join("\n"), model_names.join("\n")))
+}
+
+// A built-in profile by name, or else a profile file at that path
+pub fn load_profile(name_or_path: &str) -> Result<Profile, ConfigError> {
+  match get_builtin_profile(name_or_path) {
+    Some(profile) => Ok(profile),
+    None => {
+      let contents = fs::read_to_string(name_or_path)
+        .map_err(|error| ConfigError::MissingFile { source: format!("profile {}", name_or_path), error })?;
+      parse_profile(name_or_path, &contents)
+    },
+  }
+}
+
+// The profile of a model in the bundled database, an unknown model is an error listing the known ones,
+// a misspelt name silently running on some other table would only show up as statuses that never come
+pub fn load_model_profile(model: &str) -> Result<Profile, ConfigError> {
+  let profiles = parse_model_database(MODEL_DATABASE)?;
+  match profiles.iter().find(|profile| profile.name.eq_ignore_ascii_case(model)) {
+    Some(profile) => Ok(profile.clone()),
+    None => {
+      let model_names: Vec<&str> = profiles.iter().map(|profile| profile.name.as_str()).collect();
+      Err(ConfigError::Invalid { source: MODEL_DATABASE_SOURCE.to_string(), reason: format!("has no model {}, known models are: {}", model, model_names.join(", ")) })
+    },
+  }
+}
+
+// Entries start with "[<model name>]" and go on with the lines of a profile file, up to the next entry,
+// errors are located by the line of the whole database rather than of the entry
+fn parse_model_database(contents: &str) -> Result<Vec<Profile>, ConfigError> {
+  // The name, the number of lines ahead of the entry's own and its lines
+  let mut entries: Vec<(&str, usize, String)> = vec![];
+  for (index, line) in contents.lines().enumerate() {
+    match line.trim().strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+      Some(model_name) => entries.push((model_name.trim(), index + 1, String::new())),
+      None => match entries.last_mut() {
+        Some(entry) => {
+          entry.2.push_str(line);
+          entry.2.push('\n');
+        },
+        None if line.trim().is_empty() || line.trim().starts_with('#') => {},
+        None => return Err(ConfigError::Parse { source: MODEL_DATABASE_SOURCE.to_string(), line: index + 1, text: line.to_string() }),
+      },
+    }
+  }
+  // Models are looked up regardless of case, two names differing only in case could never both be chosen
+  for (index, entry) in entries.iter().enumerate() {
+    if entries[..index].iter().any(|other_entry| other_entry.0.eq_ignore_ascii_case(entry.0)) {
+      return Err(ConfigError::Invalid { source: MODEL_DATABASE_SOURCE.to_string(), reason: format!("lists model {} more than once", entry.0) });
+    }
+  }
+  entries.iter()
+    .map(|(model_name, line_offset, contents)| parse_profile(model_name, contents)
+      .map_err(|error| error.within(&format!("{} entry {}", MODEL_DATABASE_SOURCE, model_name), *line_offset)))
+    .collect()
+}
+
+// Profile files have one setting per line, blank lines and lines starting with # are skipped:
+//   polarity active-high|active-low
+//   silence <status>             status of sustained silence
+//   continuous <status>          status of a timeout during a beep
+//   pattern <status> <beep ms> <inter beep ms>
+pub fn parse_profile(name: &str, contents: &str) -> Result<Profile, ConfigError> {
+  let mut profile = Profile { name: name.to_string(), inverted: false, beep_durations: vec![], silence_status: None };
+
+  for (index, line) in contents.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+
+    let invalid_line = || ConfigError::Parse { source: format!("profile {}", name), line: index + 1, text: line.to_string() };
+    let get_status = |status_name: Option<&str>| status_name.and_then(get_status_from_name).ok_or_else(invalid_line);
+    let get_duration = |millis: Option<&str>| millis.and_then(|millis| millis.parse().ok()).map(Duration::from_millis).ok_or_else(invalid_line);
+
+    let mut fields = line.split_whitespace();
+    match fields.next() {
+      Some("polarity") => profile.inverted = match fields.next() {
+        Some("active-high") => false,
+        Some("active-low") => true,
+        _ => return Err(invalid_line()),
+      },
+      Some("silence") => profile.silence_status = Some(get_status(fields.next())?),
+      Some("continuous") => profile.beep_durations.push((get_status(fields.next())?, [CONTINUOUS_BEEP_DURATION, ZERO_DURATION])),
+      Some("pattern") => {
+        let status = get_status(fields.next())?;
+        profile.beep_durations.push((status, [get_duration(fields.next())?, get_duration(fields.next())?]));
+      },
+      _ => return Err(invalid_line()),
+    }
+    if fields.next().is_some() {
+      return Err(invalid_line());
+    }
+  }
+
+  if profile.beep_durations.is_empty() && profile.silence_status.is_none() {
+    return Err(ConfigError::Invalid { source: format!("profile {}", name), reason: "has no statuses".to_string() });
+  }
+  check_distinct_patterns(&format!("profile {}", name), &profile.beep_durations)?;
+  Ok(profile)
+}
+
+// The matcher could never tell two statuses with the same pattern apart, so one of them would silently never be reported
+pub fn check_distinct_patterns(source: &str, beep_durations: &[(Status, [Duration; 2])]) -> Result<(), ConfigError> {
+  for (index, status_beep_duration) in beep_durations.iter().enumerate() {
+    if let Some(other) = beep_durations[..index].iter().find(|other| other.0 != status_beep_duration.0 && other.1 == status_beep_duration.1) {
+      return Err(ConfigError::Invalid {
+        source: source.to_string(),
+        reason: format!("gives {:?} the same pattern as {:?}, the two could never be told apart", status_beep_duration.0, other.0),
+      });
+    }
+  }
+  Ok(())
+}
+
+// The lines of a profile file parse_profile reads back as the same profile, as far as a profile file can say it,
+// durations are whole milliseconds in a profile file so anything finer is cut off
+pub fn get_profile_file(profile: &Profile) -> String {
+  let mut lines = vec![
+    format!("# The {} profile", profile.name),
+    format!("polarity {}", if profile.inverted { "active-low" } else { "active-high" }),
+  ];
+  if let Some(silence_status) = profile.silence_status {
+    lines.push(format!("silence {:?}", silence_status));
+  }
+  for (status, [beep_duration, inter_beep_duration]) in &profile.beep_durations {
+    if [*beep_duration, *inter_beep_duration] == [CONTINUOUS_BEEP_DURATION, ZERO_DURATION] {
+      lines.push(format!("continuous {:?}", status));
+    } else {
+      lines.push(format!("pattern {:?} {} {}", status, beep_duration.as_millis(), inter_beep_duration.as_millis()));
+    }
+  }
+  lines.push(String::new());
+  lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::status::{MatchConfig, get_status_from_beep_durations};
+
+  fn get_status(profile: &Profile, beep: Duration, inter_beep: Duration) -> Status {
+    let config = MatchConfig { beep_durations: profile.beep_durations.clone(), ..MatchConfig::default() };
+    get_status_from_beep_durations(beep, inter_beep, &config).status
+  }
+
+  #[test]
+  fn builtin_profiles_flip_the_assumptions() {
+    let standard = load_profile(DEFAULT_PROFILE_NAME).unwrap();
+    assert!(!standard.inverted);
+    assert_eq!(standard.silence_status, Some(Status::OnMains));
+    assert!(load_profile("active-low").unwrap().inverted);
+
+    let beeps_on_mains = load_profile("beeps-on-mains").unwrap();
+    assert_eq!(get_status(&beeps_on_mains, CONTINUOUS_BEEP_DURATION, ZERO_DURATION), Status::OnMains);
+    assert_eq!(beeps_on_mains.silence_status, Some(Status::OnBattery));
+  }
+
+  #[test]
+  fn parses_profile_files() {
+    let profile = parse_profile("custom", "# A made up model\npolarity active-low\n\nsilence OnBattery\ncontinuous OnMains\npattern LowOnBattery 500 500\n").unwrap();
+    assert!(profile.inverted);
+    assert_eq!(profile.beep_durations.len(), 2);
+    assert_eq!(get_status(&profile, Duration::from_millis(500), Duration::from_millis(500)), Status::LowOnBattery);
+    assert_eq!(profile.silence_status, Some(Status::OnBattery));
+    // A model whose silence means nothing leaves it out
+    assert_eq!(parse_profile("custom", "pattern LowOnBattery 500 500").unwrap().silence_status, None);
+    assert_eq!(parse_profile("custom", "silence OnMains").unwrap().beep_durations, vec![]);
+  }
+
+  #[test]
+  fn profile_files_read_back_as_the_same_profile() {
+    for profile in [get_standard_profile(), get_active_low_profile(), get_beeps_on_mains_profile()] {
+      assert_eq!(parse_profile(&profile.name, &get_profile_file(&profile)).unwrap(), profile);
+    }
+    let profile = parse_profile("custom", "pattern LowOnBattery 500 500").unwrap();
+    assert_eq!(get_profile_file(&profile), "# The custom profile\npolarity active-high\npattern LowOnBattery 500 500\n");
+    assert_eq!(parse_profile("custom", &get_profile_file(&profile)).unwrap(), profile);
+  }
+
+  #[test]
+  fn bundled_models_parse() {
+    let profiles = parse_model_database(MODEL_DATABASE).unwrap();
+    assert!(!profiles.is_empty());
+    let generic = load_model_profile("generic LINE-interactive").unwrap();
+    assert_eq!(generic.beep_durations, get_standard_profile().beep_durations);
+    assert_eq!(generic.silence_status, get_standard_profile().silence_status);
+    assert!(!generic.inverted);
+    assert!(load_model_profile("Generic line-interactive active-low").unwrap().inverted);
+  }
+
+  #[test]
+  fn unknown_models_are_rejected_with_the_known_ones() {
+    assert_eq!(
+      load_model_profile("Acme 9000").unwrap_err().to_string(),
+      "model database has no model Acme 9000, known models are: Generic line-interactive, Generic line-interactive active-low, Generic beeping on mains",
+    );
+  }
+
+  #[test]
+  fn lists_profiles_and_models() {
+    let description = get_profile_names_description().unwrap();
+    assert!(description.starts_with("Built-in profiles, for --profile:\n  standard\n  active-low\n  beeps-on-mains\nModels, for --model:\n"));
+    assert!(description.ends_with("\n  Generic beeping on mains"));
+  }
+
+  #[test]
+  fn parses_model_databases() {
+    let profiles = parse_model_database("# Models\n\n[Acme 1]\nsilence OnMains\n[Acme 2]\npolarity active-low\nsilence OnBattery\n").unwrap();
+    assert_eq!(profiles.iter().map(|profile| profile.name.as_str()).collect::<Vec<_>>(), vec!["Acme 1", "Acme 2"]);
+    assert!(profiles[1].inverted);
+    assert_eq!(parse_model_database("# Models\nsilence OnMains").unwrap_err().to_string(), "invalid line 2 in model database: silence OnMains");
+    // Counted from the top of the database, not from the entry
+    assert!(matches!(parse_model_database("[Acme 1]\nsilence OnMains\n[Acme 2]\nsilence Mains").unwrap_err(), ConfigError::Parse { line: 4, .. }));
+    assert_eq!(parse_model_database("[Acme 1]\nsilence Mains").unwrap_err().to_string(), "invalid line 2 in model database entry Acme 1: silence Mains");
+    assert_eq!(parse_model_database("[Acme 1]\npolarity active-low").unwrap_err().to_string(), "model database entry Acme 1 has no statuses");
+    assert_eq!(parse_model_database("[Acme 1]\nsilence OnMains\n[ACME 1]\nsilence OnMains").unwrap_err().to_string(), "model database lists model ACME 1 more than once");
+  }
+
+  #[test]
+  fn rejects_invalid_profile_files() {
+    assert_eq!(parse_profile("custom", "polarity sideways").unwrap_err().to_string(), "invalid line 1 in profile custom: polarity sideways");
+    assert!(matches!(parse_profile("custom", "silence OnMains\npattern LowOnBattery 500").unwrap_err(), ConfigError::Parse { line: 2, .. }));
+    assert!(parse_profile("custom", "silence Mains").is_err());
+    assert!(parse_profile("custom", "silence OnMains now").is_err());
+    assert!(matches!(parse_profile("custom", "polarity active-low").unwrap_err(), ConfigError::Invalid { .. }));
+    assert_eq!(parse_profile("custom", "polarity active-low").unwrap_err().to_string(), "profile custom has no statuses");
+    assert_eq!(
+      parse_profile("custom", "pattern LowOnBattery 250 1000\npattern LowOnBattery 250 1000\npattern ReplaceBattery 250 1000").unwrap_err().to_string(),
+      "profile custom gives ReplaceBattery the same pattern as LowOnBattery, the two could never be told apart",
+    );
+    let missing_file = load_profile("/nonexistent/profile").unwrap_err();
+    assert!(matches!(missing_file, ConfigError::MissingFile { .. }));
+    assert!(missing_file.to_string().starts_with("could not read profile /nonexistent/profile"));
+  }
+}
@@ -0,0 +1,215 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::detector::{Decoder, Edge};
+use crate::status::{Classification, Status, TIMEOUT_DURATION, get_status_from_name};
+
+// How many of the most recent complete periods the duty cycle is averaged over
+const MAX_PERIODS: usize = 8;
+
+// A range of duty cycles, as fractions between 0 and 1 with both ends included, that stands for a status
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct DutyCycleBand {
+  pub status: Status,
+  pub min: f64,
+  pub max: f64,
+}
+
+// Reads the status line as a PWM signal, a beep start being a rising edge and a beep end a falling one,
+// and maps the duty cycle of the last few periods to the status of the band it falls in
+pub struct PwmDecoder {
+  bands: Vec<DutyCycleBand>,
+
+  // High time and length of each of the last complete periods, measured from one rising edge to the next
+  periods: VecDeque<(Duration, Duration)>,
+
+  last_rise_time: Option<Instant>,
+  last_fall_time: Option<Instant>,
+  is_high: bool,
+}
+
+impl PwmDecoder {
+  pub fn new(bands: Vec<DutyCycleBand>) -> PwmDecoder {
+    PwmDecoder {
+      bands,
+      periods: VecDeque::new(),
+      last_rise_time: None,
+      last_fall_time: None,
+      is_high: false,
+    }
+  }
+
+  // Confidence is 1 at the middle of the band and falls off to 0 at its ends, the first band the duty cycle falls in wins
+  fn classify(&self, duty_cycle: f64, high_duration: Duration, low_duration: Duration) -> Classification {
+    let (status, confidence) = self.bands.iter()
+      .find(|band| band.min <= duty_cycle && duty_cycle <= band.max)
+      .map(|band| {
+        let half_width = (band.max - band.min) / 2.0;
+        let error = (duty_cycle - (band.min + half_width)).abs();
+        (band.status, if half_width == 0.0 { 1.0 } else { 1.0 - error / half_width })
+      })
+      .unwrap_or((Status::Unknown, 0.0));
+
+    Classification {
+      status,
+      confidence,
+      beep_duration: high_duration,
+      inter_beep_duration: low_duration,
+    }
+  }
+}
+
+impl Decoder for PwmDecoder {
+  // Returns the possible power state whenever a rising edge completes a period
+  fn on_edge(&mut self, edge: Edge, now: Instant) -> Option<Classification> {
+    let mut classification = None;
+
+    if edge == Edge::BeepStart && !self.is_high {
+      if let (Some(rise_time), Some(fall_time)) = (self.last_rise_time, self.last_fall_time) {
+        self.periods.push_back((fall_time.duration_since(rise_time), now.duration_since(rise_time)));
+        if self.periods.len() > MAX_PERIODS {
+          self.periods.pop_front();
+        }
+
+        let high_duration: Duration = self.periods.iter().map(|period| period.0).sum();
+        let period_duration: Duration = self.periods.iter().map(|period| period.1).sum();
+        if !period_duration.is_zero() {
+          let duty_cycle = high_duration.as_secs_f64() / period_duration.as_secs_f64();
+          classification = Some(self.classify(duty_cycle, high_duration, period_duration - high_duration));
+        }
+      }
+      self.last_rise_time = Some(now);
+      self.is_high = true;
+    } else if edge == Edge::BeepEnd && self.is_high {
+      self.last_fall_time = Some(now);
+      self.is_high = false;
+    }
+
+    classification
+  }
+
+  // A line that stays at one level for the whole timeout is at a duty cycle of either 0 or 1,
+  // the periods measured before it are dropped so the next classification is based only on what comes after
+  fn on_timeout(&mut self, now: Instant) -> Option<Classification> {
+    let last_edge_time = if self.is_high { self.last_rise_time } else { self.last_fall_time }?;
+    let level_duration = now.duration_since(last_edge_time);
+    if level_duration < TIMEOUT_DURATION {
+      return None;
+    }
+
+    self.periods.clear();
+    Some(if self.is_high {
+      self.classify(1.0, level_duration, Duration::ZERO)
+    } else {
+      self.classify(0.0, Duration::ZERO, level_duration)
+    })
+  }
+
+  // The level stays as it was, the next edge is still read against it
+  fn reset(&mut self) {
+    self.periods.clear();
+    self.last_rise_time = None;
+    self.last_fall_time = None;
+  }
+}
+
+// Parses a band written as "<status>=<min>-<max>" with the duty cycles in percent, as in "OnBattery=40-60"
+pub fn parse_duty_cycle_band(value: &str) -> Result<DutyCycleBand, String> {
+  let invalid_band = || format!("invalid duty cycle band {}, expected <status>=<min>-<max> in percent", value);
+
+  let (status_name, range) = value.split_once('=').ok_or_else(invalid_band)?;
+  let (min, max) = range.split_once('-').ok_or_else(invalid_band)?;
+
+  let status = get_status_from_name(status_name).ok_or_else(|| format!("unknown status {} in duty cycle band {}", status_name, value))?;
+  let min: f64 = min.parse().map_err(|_| invalid_band())?;
+  let max: f64 = max.parse().map_err(|_| invalid_band())?;
+  if !(0.0 <= min && min <= max && max <= 100.0) {
+    return Err(invalid_band());
+  }
+
+  Ok(DutyCycleBand { status, min: min / 100.0, max: max / 100.0 })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn get_decoder() -> PwmDecoder {
+    PwmDecoder::new(vec![
+      parse_duty_cycle_band("OnMains=0-10").unwrap(),
+      parse_duty_cycle_band("OnBattery=40-60").unwrap(),
+      parse_duty_cycle_band("LowOnBattery=90-100").unwrap(),
+    ])
+  }
+
+  // Feeds count periods of the given high and low times and returns the classification of the last one, along with when the next period would start
+  fn feed_periods(decoder: &mut PwmDecoder, start: Instant, high: Duration, low: Duration, count: u32) -> (Option<Classification>, Instant) {
+    let mut classification = None;
+    let mut at = start;
+    for _ in 0..=count {
+      classification = decoder.on_edge(Edge::BeepStart, at);
+      decoder.on_edge(Edge::BeepEnd, at + high);
+      at += high + low;
+    }
+    (classification, at)
+  }
+
+  #[test]
+  fn parses_bands() {
+    assert_eq!(parse_duty_cycle_band("OnBattery=40-60").unwrap(), DutyCycleBand { status: Status::OnBattery, min: 0.4, max: 0.6 });
+    assert!(parse_duty_cycle_band("OnBattery=60-40").is_err());
+    assert!(parse_duty_cycle_band("OnBattery=40-120").is_err());
+    assert!(parse_duty_cycle_band("OnBattery").is_err());
+    assert!(parse_duty_cycle_band("Battery=40-60").unwrap_err().starts_with("unknown status Battery"));
+  }
+
+  #[test]
+  fn maps_duty_cycle_to_band() {
+    let mut decoder = get_decoder();
+    let (classification, _) = feed_periods(&mut decoder, Instant::now(), Duration::from_millis(50), Duration::from_millis(50), 3);
+    let classification = classification.unwrap();
+    assert_eq!(classification.status, Status::OnBattery);
+    assert!((classification.confidence - 1.0).abs() < 1e-9);
+  }
+
+  #[test]
+  fn duty_cycle_outside_every_band_is_unknown() {
+    let mut decoder = get_decoder();
+    let (classification, _) = feed_periods(&mut decoder, Instant::now(), Duration::from_millis(25), Duration::from_millis(75), 3);
+    assert_eq!(classification.unwrap().status, Status::Unknown);
+  }
+
+  #[test]
+  fn duty_cycle_follows_the_recent_periods() {
+    let mut decoder = get_decoder();
+    let (_, end) = feed_periods(&mut decoder, Instant::now(), Duration::from_millis(50), Duration::from_millis(50), 3);
+    let (classification, _) = feed_periods(&mut decoder, end, Duration::from_millis(95), Duration::from_millis(5), MAX_PERIODS as u32);
+    assert_eq!(classification.unwrap().status, Status::LowOnBattery);
+  }
+
+  #[test]
+  fn stuck_line_is_zero_or_full_duty_cycle() {
+    let mut decoder = get_decoder();
+    let start = Instant::now();
+    assert!(decoder.on_timeout(start + TIMEOUT_DURATION).is_none());
+
+    decoder.on_edge(Edge::BeepStart, start);
+    assert!(decoder.on_timeout(start + Duration::from_secs(1)).is_none());
+    assert_eq!(decoder.on_timeout(start + TIMEOUT_DURATION).unwrap().status, Status::LowOnBattery);
+
+    let fall = start + TIMEOUT_DURATION;
+    decoder.on_edge(Edge::BeepEnd, fall);
+    assert_eq!(decoder.on_timeout(fall + TIMEOUT_DURATION).unwrap().status, Status::OnMains);
+    assert!(decoder.periods.is_empty());
+  }
+
+  #[test]
+  fn reset_measures_afresh() {
+    let mut decoder = get_decoder();
+    let (_, end) = feed_periods(&mut decoder, Instant::now(), Duration::from_millis(95), Duration::from_millis(5), 3);
+    decoder.reset();
+    // The first rise after a reset only starts a period, it isn't measured against the rise before
+    assert!(decoder.on_edge(Edge::BeepStart, end + Duration::from_secs(60)).is_none());
+    assert!(decoder.periods.is_empty());
+  }
+}
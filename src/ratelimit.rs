@@ -0,0 +1,118 @@
+use std::time::{Duration, Instant};
+
+use crate::logging::warn;
+use crate::source::{EdgeSource, SourceEvent};
+
+// Drops edges arriving sooner than min_interval after the last edge let through, so a chattering line can't keep the loop spinning,
+// unlike the bounce filtering in the detector this isn't about correctness, the dropped edges are simply never seen
+pub struct RateLimitedSource {
+  source: Box<dyn EdgeSource>,
+  min_interval: Duration,
+
+  last_edge_time: Option<Instant>,
+  // When the last event of either kind was handed out, which the timeout is counted from
+  last_event_time: Option<Instant>,
+  dropped_edge_count: u64,
+  // Dropped since the last edge let through, reported once the flood subsides
+  recently_dropped_edge_count: u64,
+}
+
+impl RateLimitedSource {
+  pub fn new(source: Box<dyn EdgeSource>, min_interval: Duration) -> RateLimitedSource {
+    RateLimitedSource {
+      source,
+      min_interval,
+      last_edge_time: None,
+      last_event_time: None,
+      dropped_edge_count: 0,
+      recently_dropped_edge_count: 0,
+    }
+  }
+
+  #[cfg(test)]
+  pub fn dropped_edge_count(&self) -> u64 {
+    self.dropped_edge_count
+  }
+}
+
+impl EdgeSource for RateLimitedSource {
+  // Still times out after timeout even while every edge gets dropped, so the detector keeps getting its timeouts during a flood
+  fn next_event(&mut self, timeout: Duration) -> Option<SourceEvent> {
+    let mut remaining_timeout = timeout;
+    loop {
+      let event = self.source.next_event(remaining_timeout)?;
+      let at = match event {
+        SourceEvent::Edge(_, at) => at,
+        SourceEvent::Timeout(at) => {
+          self.last_event_time = Some(at);
+          return Some(event);
+        },
+      };
+
+      if self.last_edge_time.is_none_or(|last_edge_time| at.duration_since(last_edge_time) >= self.min_interval) {
+        if self.recently_dropped_edge_count > 0 {
+          warn!("Dropped {} edges arriving within {}ms of each other, {} in total", self.recently_dropped_edge_count, self.min_interval.as_millis(), self.dropped_edge_count);
+          self.recently_dropped_edge_count = 0;
+        }
+        self.last_edge_time = Some(at);
+        self.last_event_time = Some(at);
+        return Some(event);
+      }
+
+      self.dropped_edge_count += 1;
+      self.recently_dropped_edge_count += 1;
+      let waited = self.last_event_time.map(|last_event_time| at.duration_since(last_event_time)).unwrap_or_default();
+      remaining_timeout = timeout.saturating_sub(waited);
+      if remaining_timeout.is_zero() {
+        self.last_event_time = Some(at);
+        return Some(SourceEvent::Timeout(at));
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::detector::Edge;
+  use crate::replay::ReplaySource;
+
+  fn get_events(offsets_ms: &[u64]) -> Vec<(Duration, Edge)> {
+    offsets_ms.iter().enumerate()
+      .map(|(index, offset_ms)| (Duration::from_millis(*offset_ms), if index % 2 == 0 { Edge::BeepStart } else { Edge::BeepEnd }))
+      .collect()
+  }
+
+  // Whether each event handed out is an edge, along with its offset from the first one
+  fn get_offsets(source: &mut RateLimitedSource, timeout: Duration) -> Vec<(bool, u128)> {
+    let mut offsets = vec![];
+    let mut start = None;
+    while let Some(event) = source.next_event(timeout) {
+      let (is_edge, at) = match event {
+        SourceEvent::Edge(_, at) => (true, at),
+        SourceEvent::Timeout(at) => (false, at),
+      };
+      offsets.push((is_edge, at.duration_since(*start.get_or_insert(at)).as_millis()));
+    }
+    offsets
+  }
+
+  #[test]
+  fn drops_edges_within_min_interval() {
+    let replay_source = ReplaySource::new(get_events(&[0, 5, 10, 15, 250, 1250]), 0.0);
+    let mut source = RateLimitedSource::new(Box::new(replay_source), Duration::from_millis(20));
+    assert_eq!(get_offsets(&mut source, Duration::from_secs(3)), vec![(true, 0), (true, 250), (true, 1250)]);
+    assert_eq!(source.dropped_edge_count(), 3);
+  }
+
+  #[test]
+  fn times_out_during_a_flood() {
+    let flood_offsets: Vec<u64> = (0..40).map(|index| index * 10).collect();
+    let replay_source = ReplaySource::new(get_events(&flood_offsets), 0.0);
+    let mut source = RateLimitedSource::new(Box::new(replay_source), Duration::from_secs(1));
+    let offsets = get_offsets(&mut source, Duration::from_millis(100));
+    assert_eq!(offsets[0], (true, 0));
+    assert_eq!(offsets[1], (false, 100));
+  }
+}
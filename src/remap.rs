@@ -0,0 +1,45 @@
+use crate::status::{Classification, Status, get_status_from_name};
+
+// Parsed from "<status>=<status>", the classification's status on the left gets reported as the one on the right
+pub fn parse_status_map(value: &str) -> Result<(Status, Status), String> {
+  let (from_name, to_name) = value.split_once('=').ok_or_else(|| format!("invalid map {}, expected <status>=<status>", value))?;
+  let from = get_status_from_name(from_name).ok_or_else(|| format!("unknown status {} in map {}", from_name, value))?;
+  let to = get_status_from_name(to_name).ok_or_else(|| format!("unknown status {} in map {}", to_name, value))?;
+  Ok((from, to))
+}
+
+// Applied once right before reporting so every sink and the exit conditions see the same status,
+// maps aren't chained, the first one for the status is the one used
+pub fn remap_classification(status_maps: &[(Status, Status)], classification: Classification) -> Classification {
+  match status_maps.iter().find(|status_map| status_map.0 == classification.status) {
+    Some(status_map) => Classification { status: status_map.1, ..classification },
+    None => classification,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  fn get_classification(status: Status) -> Classification {
+    Classification { status, confidence: 0.8, beep_duration: Duration::from_millis(250), inter_beep_duration: Duration::from_secs(10) }
+  }
+
+  #[test]
+  fn parses_maps() {
+    assert_eq!(parse_status_map("NoLoadOnBattery=OnBattery"), Ok((Status::NoLoadOnBattery, Status::OnBattery)));
+    assert_eq!(parse_status_map("NoLoadOnBattery").unwrap_err(), "invalid map NoLoadOnBattery, expected <status>=<status>");
+    assert_eq!(parse_status_map("NoLoad=OnBattery").unwrap_err(), "unknown status NoLoad in map NoLoad=OnBattery");
+    assert_eq!(parse_status_map("NoLoadOnBattery=Off").unwrap_err(), "unknown status Off in map NoLoadOnBattery=Off");
+  }
+
+  #[test]
+  fn remaps_only_the_mapped_status_without_chaining() {
+    let status_maps = [(Status::NoLoadOnBattery, Status::OnBattery), (Status::OnBattery, Status::LowOnBattery)];
+    let classification = remap_classification(&status_maps, get_classification(Status::NoLoadOnBattery));
+    assert_eq!(classification.status, Status::OnBattery);
+    assert_eq!((classification.confidence, classification.inter_beep_duration), (0.8, Duration::from_secs(10)));
+    assert_eq!(remap_classification(&status_maps, get_classification(Status::OnMains)).status, Status::OnMains);
+  }
+}
@@ -0,0 +1,189 @@
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::detector::Edge;
+use crate::noise::{NoiseConfig, add_noise};
+use crate::source::{EdgeSource, SourceEvent};
+
+// Replays edges recorded one per line as "<milliseconds since start> <level>", the level being 1 for a beep start or 0 for a beep end,
+// or beeps written as "<beep ms>,<inter beep ms>", the beep starting that long after the last edge, the two kinds of line can be mixed,
+// blank lines and lines starting with # are skipped
+pub struct ReplaySource {
+  events: Vec<(Duration, Edge)>,
+  next_event_index: usize,
+  // A speed of 0 replays as fast as possible
+  speed: f64,
+
+  start_time: Instant,
+  // Offset of the last edge or timeout handed out, timeouts are counted from it just like the GPIO poll does
+  last_offset: Duration,
+}
+
+impl ReplaySource {
+  pub fn open(path: &str, speed: f64) -> Result<ReplaySource, String> {
+    let contents = fs::read_to_string(path).map_err(|error| format!("could not read {}: {}", path, error))?;
+    Ok(ReplaySource::new(parse_events(&contents)?, speed))
+  }
+
+  pub fn new(events: Vec<(Duration, Edge)>, speed: f64) -> ReplaySource {
+    ReplaySource {
+      events,
+      next_event_index: 0,
+      speed,
+      start_time: Instant::now(),
+      last_offset: Duration::ZERO,
+    }
+  }
+
+  // Degrades the recording before it starts being replayed
+  pub fn add_noise(&mut self, config: &NoiseConfig) {
+    self.events = add_noise(&self.events, config);
+  }
+
+  // How far into the recording an instant handed out by this source is
+  pub fn get_offset(&self, at: Instant) -> Duration {
+    at.duration_since(self.start_time)
+  }
+
+  // Only the wall clock waiting is scaled, the instants handed to the detector keep the recorded spacing regardless of speed
+  fn wait_until(&mut self, offset: Duration) -> Instant {
+    if self.speed > 0.0 {
+      thread::sleep((offset - self.last_offset).div_f64(self.speed));
+    }
+    self.last_offset = offset;
+    self.start_time + offset
+  }
+}
+
+impl EdgeSource for ReplaySource {
+  fn next_event(&mut self, timeout: Duration) -> Option<SourceEvent> {
+    let (offset, edge) = *self.events.get(self.next_event_index)?;
+
+    if offset - self.last_offset > timeout {
+      let timeout_offset = self.last_offset + timeout;
+      return Some(SourceEvent::Timeout(self.wait_until(timeout_offset)));
+    }
+
+    self.next_event_index += 1;
+    Some(SourceEvent::Edge(edge, self.wait_until(offset)))
+  }
+}
+
+pub fn parse_events(contents: &str) -> Result<Vec<(Duration, Edge)>, String> {
+  let mut events: Vec<(Duration, Edge)> = vec![];
+
+  for (index, line) in contents.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+
+    let invalid_line = || format!("invalid replay line {}: {}", index + 1, line);
+    if let Some((beep, inter_beep)) = line.split_once(',') {
+      let get_duration = |millis: &str| millis.trim().parse().ok().map(Duration::from_millis).ok_or_else(invalid_line);
+      let (beep_duration, inter_beep_duration) = (get_duration(beep)?, get_duration(inter_beep)?);
+      // A beep can only start once the one before it has ended
+      if events.last().is_some_and(|event| event.1 == Edge::BeepStart) {
+        return Err(invalid_line());
+      }
+      let start_offset = events.last().map_or(Duration::ZERO, |event| event.0) + inter_beep_duration;
+      events.push((start_offset, Edge::BeepStart));
+      events.push((start_offset + beep_duration, Edge::BeepEnd));
+      continue;
+    }
+    let mut fields = line.split_whitespace();
+    let offset = fields.next()
+      .and_then(|field| field.parse().ok())
+      .map(Duration::from_millis)
+      .ok_or_else(invalid_line)?;
+    let edge = match fields.next() {
+      Some("1") => Edge::BeepStart,
+      Some("0") => Edge::BeepEnd,
+      _ => return Err(invalid_line()),
+    };
+    if fields.next().is_some() || events.last().is_some_and(|event| event.0 > offset) {
+      return Err(invalid_line());
+    }
+
+    events.push((offset, edge));
+  }
+
+  Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TIMEOUT: Duration = Duration::from_secs(3);
+
+  fn collect(source: &mut ReplaySource) -> Vec<(Option<Edge>, Duration)> {
+    let mut events = vec![];
+    while let Some(event) = source.next_event(TIMEOUT) {
+      events.push(match event {
+        SourceEvent::Edge(edge, at) => (Some(edge), at - source.start_time),
+        SourceEvent::Timeout(at) => (None, at - source.start_time),
+      });
+    }
+    events
+  }
+
+  #[test]
+  fn parses_events_skipping_comments() {
+    let events = parse_events("# capture\n0 1\n\n250 0\n").unwrap();
+    assert_eq!(events, vec![(Duration::ZERO, Edge::BeepStart), (Duration::from_millis(250), Edge::BeepEnd)]);
+  }
+
+  #[test]
+  fn parses_beep_and_inter_beep_pairs() {
+    let events = parse_events("250,0\n250, 1000\n5000 1\n5250 0\n2000,60000\n").unwrap();
+    assert_eq!(events, vec![
+      (Duration::ZERO, Edge::BeepStart),
+      (Duration::from_millis(250), Edge::BeepEnd),
+      (Duration::from_millis(1250), Edge::BeepStart),
+      (Duration::from_millis(1500), Edge::BeepEnd),
+      (Duration::from_millis(5000), Edge::BeepStart),
+      (Duration::from_millis(5250), Edge::BeepEnd),
+      (Duration::from_millis(65_250), Edge::BeepStart),
+      (Duration::from_millis(67_250), Edge::BeepEnd),
+    ]);
+    assert_eq!(parse_events("250,1000ms").unwrap_err(), "invalid replay line 1: 250,1000ms");
+    assert!(parse_events("0 1\n250,1000").is_err());
+  }
+
+  #[test]
+  fn rejects_invalid_lines() {
+    assert_eq!(parse_events("0 1\nabc 0").unwrap_err(), "invalid replay line 2: abc 0");
+    assert!(parse_events("0 2").is_err());
+    assert!(parse_events("0 1 extra").is_err());
+    assert!(parse_events("500 1\n250 0").is_err());
+  }
+
+  #[test]
+  fn synthesizes_timeouts_during_long_gaps() {
+    let mut source = ReplaySource::new(parse_events("0 1\n250 0\n7000 1").unwrap(), 0.0);
+    assert_eq!(collect(&mut source), vec![
+      (Some(Edge::BeepStart), Duration::ZERO),
+      (Some(Edge::BeepEnd), Duration::from_millis(250)),
+      (None, Duration::from_millis(3250)),
+      (None, Duration::from_millis(6250)),
+      (Some(Edge::BeepStart), Duration::from_millis(7000)),
+    ]);
+  }
+
+  #[test]
+  fn speed_does_not_change_replayed_timings() {
+    let events = parse_events("0 1\n20 0\n40 1\n60 0").unwrap();
+    let as_fast_as_possible = collect(&mut ReplaySource::new(events.clone(), 0.0));
+    let sped_up = collect(&mut ReplaySource::new(events, 10.0));
+    assert_eq!(as_fast_as_possible, sped_up);
+  }
+
+  #[test]
+  fn speed_scales_wall_clock_waiting() {
+    let started = Instant::now();
+    collect(&mut ReplaySource::new(parse_events("0 1\n200 0").unwrap(), 10.0));
+    assert!(started.elapsed() < Duration::from_millis(150));
+  }
+}
@@ -0,0 +1,1315 @@
+use std::mem;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
+use crate::guidance::{GuidanceTable, Severity};
+use crate::logging::{error, info};
+use crate::maintenance::{self, MaintenanceWindow};
+use crate::notifier::{Notifier, StatusEvent};
+use crate::output::OutputTarget;
+use crate::state::{SharedState, Transition};
+use crate::status::{Classification, Status, get_status_description, get_status_dwell_bounds};
+use crate::wear::BatteryWearTracker;
+
+const PERSISTENT_REPLACE_BATTERY_DESCRIPTION: &str = "Battery replacement has been requested persistently, the battery is likely failing and should be replaced soon";
+
+// Whether a status was matched from a beep pattern the edges actually showed, or inferred on a timeout from the lack of them,
+// or else injected on request to test whatever consumes the reports, or carried over from before a restart
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Origin {
+  Observed,
+  Inferred,
+  Test,
+  Restored,
+}
+
+impl Origin {
+  pub fn name(self) -> &'static str {
+    match self {
+      Origin::Observed => "observed",
+      Origin::Inferred => "inferred",
+      Origin::Test => "test",
+      Origin::Restored => "restored",
+    }
+  }
+}
+
+// What to make of the first patterns after starting, which may be measured from edges seen halfway through a beep or gap
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum RestartPolicy {
+  // Every pattern is reported as it comes
+  Report,
+  // The status saved in the stats file carries over and the first matched pattern is held back,
+  // only a whole beep, gap and beep seen since the restart can change the status
+  WaitForCycle,
+}
+
+const RESTART_POLICY_NAMES: [(RestartPolicy, &str); 2] = [
+  (RestartPolicy::Report, "report"),
+  (RestartPolicy::WaitForCycle, "wait-for-cycle"),
+];
+
+impl RestartPolicy {
+  pub fn from_name(name: &str) -> Option<RestartPolicy> {
+    RESTART_POLICY_NAMES.iter()
+      .find(|restart_policy_name| restart_policy_name.1 == name)
+      .map(|restart_policy_name| restart_policy_name.0)
+  }
+}
+
+// How the lines of a sink are written, the full description, just a glyph for status bars, or a JSON object per line for log pipelines
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Format {
+  Text,
+  Char,
+  Json,
+}
+
+const FORMAT_NAMES: [(Format, &str); 4] = [
+  (Format::Text, "text"),
+  (Format::Text, "human"),
+  (Format::Char, "char"),
+  (Format::Json, "json"),
+];
+
+impl Format {
+  pub fn from_name(name: &str) -> Option<Format> {
+    FORMAT_NAMES.iter()
+      .find(|format_name| format_name.1 == name)
+      .map(|format_name| format_name.0)
+  }
+
+  pub fn name(self) -> &'static str {
+    FORMAT_NAMES.iter().find(|format_name| format_name.0 == self).unwrap().1
+  }
+}
+
+// Split on the last colon so file paths can have colons of their own, as in /var/log/ups:main.jsonl:json
+pub fn parse_sink(value: &str) -> Result<(OutputTarget, Format), String> {
+  let (target_name, format_name) = value.rsplit_once(':').ok_or_else(|| format!("invalid sink {}, expected <target>:<format>", value))?;
+  if target_name.is_empty() {
+    return Err(format!("empty target in sink {}", value));
+  }
+  let format = Format::from_name(format_name).ok_or_else(|| format!("unknown format {} in sink {}", format_name, value))?;
+  Ok((OutputTarget::from_name(target_name), format))
+}
+
+// The <target>:<format> parse_sink reads back as the same sink
+pub fn get_sink_name(sink: &(OutputTarget, Format)) -> String {
+  format!("{}:{}", sink.0.name(), sink.1.name())
+}
+
+pub struct ReportConfig {
+  pub guidance: GuidanceTable,
+  // Score of decaying ReplaceBattery sightings past which the persistent replacement alert is reported
+  pub replace_battery_escalation_score: f64,
+  // File the time spent in every status is saved to on every transition, so it adds up across restarts
+  pub stats_path: Option<String>,
+  // How long Unknown has to persist with nothing else classified before it is reported, the partial beeps and odd gaps of a transition between two real statuses
+  // rarely last that long, zero reports it right away
+  pub unknown_debounce_duration: Duration,
+  // When set, leaving OnMains or VoltageRegulating for a battery status takes a second matched battery pattern within this long of the first,
+  // returning to OnMains stays immediate as a false outage alert is worse than one arriving a pattern late
+  pub outage_corroboration_window: Option<Duration>,
+  // How many times in a row a matched pattern has to come up before the status it is of is reported, 1 reports it the first time
+  pub confirmations: usize,
+  // How long after starting only critical statuses get reported, giving the detector time to see enough of a pattern
+  pub warmup_duration: Duration,
+  // When set, the current status is written again, marked as a heartbeat, whenever no status has been written for this long,
+  // for monitors that take a quiet line for a dead one
+  pub heartbeat_interval: Option<Duration>,
+  // When set, being on battery for longer than this since the mains went is warned about once, as a critical event of its own
+  pub on_battery_warn_duration: Option<Duration>,
+  pub restart_policy: RestartPolicy,
+  // Stretches of the week during which nothing gets reported, detection and recording go on and reporting picks up again after them
+  // the way it does after resuming from a pause
+  pub maintenance_windows: Vec<MaintenanceWindow>,
+}
+
+pub struct Reporter {
+  config: ReportConfig,
+  state: SharedState,
+  // Every reported event goes to all of them
+  notifiers: Vec<Box<dyn Notifier>>,
+  clock: Box<dyn Clock>,
+  started_at: Instant,
+  battery_wear: BatteryWearTracker,
+
+  last_status: Option<Status>,
+  // The last of the Unknown classifications currently being held back, and when the first of them came
+  held_back_unknown: Option<(Classification, Instant)>,
+  // When the battery pattern waiting for a corroborating one while on mains was matched
+  uncorroborated_outage_since: Option<Instant>,
+  // The status last matched that isn't the reported one, and how many times in a row it has been
+  unconfirmed_status: Option<(Status, usize)>,
+  // The status being held back until it has lasted its minimum dwell, and since when
+  dwell_pending_since: Option<(Status, Instant)>,
+  // Whether the current status has already been flagged for outlasting its maximum dwell
+  is_overstay_flagged: bool,
+  // When the first battery status since last being on mains was recorded, kept through a change to another battery status or to Unknown
+  on_battery_since: Option<Instant>,
+  // Whether being on battery since then has already been warned about
+  is_on_battery_warned: bool,
+  // While set, statuses are still tracked but nothing gets written to the sinks, toggled from outside the detection loop
+  paused: Arc<AtomicBool>,
+  // Set when something went unreported while paused, so the current status gets written again after resuming even if unchanged
+  is_resuming: bool,
+  // When a status was last written to the sinks, as a transition or a heartbeat
+  last_reported_at: Instant,
+  // Set until the first matched pattern after starting has been held back, when waiting for a whole cycle
+  is_awaiting_clean_cycle: bool,
+  // The critical status whose clearing has been reported while the status clearing it is still held back
+  reported_clear: Option<Status>,
+}
+
+impl Reporter {
+  // Timed by the system clock
+  #[cfg(test)]
+  pub fn new(config: ReportConfig, state: SharedState, notifiers: Vec<Box<dyn Notifier>>) -> Reporter {
+    Reporter::with_clock(config, state, notifiers, Box::new(crate::clock::SystemClock))
+  }
+
+  pub fn with_clock(config: ReportConfig, state: SharedState, notifiers: Vec<Box<dyn Notifier>>, clock: Box<dyn Clock>) -> Reporter {
+    let started_at = clock.now();
+    Reporter {
+      started_at,
+      battery_wear: BatteryWearTracker::new(config.replace_battery_escalation_score),
+      state,
+      notifiers,
+      clock,
+      last_status: None,
+      held_back_unknown: None,
+      uncorroborated_outage_since: None,
+      unconfirmed_status: None,
+      dwell_pending_since: None,
+      is_overstay_flagged: false,
+      on_battery_since: None,
+      is_on_battery_warned: false,
+      paused: Arc::default(),
+      is_resuming: false,
+      last_reported_at: started_at,
+      is_awaiting_clean_cycle: config.restart_policy == RestartPolicy::WaitForCycle,
+      reported_clear: None,
+      config,
+    }
+  }
+
+  pub fn get_paused(&self) -> Arc<AtomicBool> {
+    self.paused.clone()
+  }
+
+  // Reports the status saved in the stats file before the restart, as if it had just been seen again, so the same status
+  // being matched afterwards isn't a transition, none of the hold-backs apply as it was already reported once before,
+  // does nothing unless waiting for a whole cycle or without a saved status
+  pub fn restore_last_status(&mut self) {
+    if self.config.restart_policy != RestartPolicy::WaitForCycle {
+      return;
+    }
+    let Some(last_status) = self.state.lock().unwrap().totals.last_status else {
+      return;
+    };
+    info!("Carrying {:?} over from before the restart until a whole cycle has been seen", last_status);
+    let classification = Classification {
+      status: last_status,
+      confidence: 1.0,
+      beep_duration: Duration::ZERO,
+      inter_beep_duration: Duration::ZERO,
+    };
+    if let Some(transition) = self.update_status(classification, Origin::Restored) {
+      self.last_reported_at = self.clock.now();
+      self.notify(&StatusEvent::Transition { transition: &transition });
+    }
+  }
+
+  pub fn update_and_report_status(&mut self, classification: Classification, origin: Origin) {
+    // Only measured patterns say anything about the tolerances, whether or not they end up reported
+    if origin == Origin::Observed {
+      let mut state = self.state.lock().unwrap();
+      state.last_measurement = Some((classification.beep_duration, classification.inter_beep_duration));
+      if classification.status != Status::Unknown {
+        state.totals.add_match_distance(classification.status, 1.0 - classification.confidence);
+      }
+    }
+    if self.is_awaiting_clean_cycle(origin) {
+      info!("Holding back {:?} as the first pattern since starting may be measured from part of a beep or gap", classification.status);
+      return;
+    }
+    self.report_clear_if_due(classification.status);
+    if self.is_unknown_held_back(classification, self.clock.now()) {
+      info!(
+        "Holding back Unknown (beep {}ms, inter beep {}ms)",
+        classification.beep_duration.as_millis(),
+        classification.inter_beep_duration.as_millis(),
+      );
+      return;
+    }
+    if self.is_warming_up(classification.status, origin, self.clock.now()) {
+      return;
+    }
+    if self.is_outage_uncorroborated(classification.status, origin, self.clock.now()) {
+      info!("Holding back {:?} until another battery pattern corroborates it", classification.status);
+      return;
+    }
+    if let Some(count) = self.get_unconfirmed_count(classification.status, origin) {
+      info!("Holding back {:?} until it is matched {} times in a row, {} so far", classification.status, self.config.confirmations, count);
+      return;
+    }
+    if origin != Origin::Test && self.is_held_back_for_dwell(classification.status, self.clock.now()) {
+      return;
+    }
+
+    let transition = self.update_status(classification, origin);
+    let alerts: Vec<String> = [
+      self.track_battery_wear(classification, origin, self.clock.now()),
+      self.get_overstay_alert(transition.is_some(), self.clock.now()),
+    ].into_iter().flatten().collect();
+    if let Some(hold_reason) = self.get_hold_reason() {
+      if let Some(transition) = &transition {
+        info!("{}, not reporting {}", hold_reason, get_status_description(transition.to));
+      }
+      self.is_resuming = true;
+      return;
+    }
+    let transition = if mem::take(&mut self.is_resuming) { transition.or(self.state.lock().unwrap().current) } else { transition };
+    if transition.is_some() {
+      self.last_reported_at = self.clock.now();
+    }
+
+    if let Some(transition) = &transition {
+      self.notify(&StatusEvent::Transition { transition });
+    }
+    let at = self.clock.wall_time();
+    for alert in &alerts {
+      self.notify(&StatusEvent::Alert { alert, at });
+    }
+  }
+
+  // Alerts that don't come with a status, written as they would be alongside one
+  pub fn report_alert(&mut self, alert: &str) {
+    if let Some(hold_reason) = self.get_hold_reason() {
+      info!("{}, not reporting {}", hold_reason, alert);
+      return;
+    }
+    self.notify(&StatusEvent::Alert { alert, at: self.clock.wall_time() });
+  }
+
+  // Reports the held back Unknown once it has lasted the whole debounce with nothing classified since, as a pattern with long gaps
+  // may not classify again for a while, and the Unknown was the last thing classified either way
+  pub fn report_unknown_if_due(&mut self) {
+    let Some((classification, unknown_since)) = self.held_back_unknown else {
+      return;
+    };
+    if self.clock.now().duration_since(unknown_since) >= self.config.unknown_debounce_duration {
+      self.update_and_report_status(classification, Origin::Observed);
+    }
+  }
+
+  // Writes the current status again once none has been written for the heartbeat interval, as its own kind of line so it can never
+  // pass for a transition, nothing is written before there is a status or while paused, same as for everything else
+  pub fn report_heartbeat_if_due(&mut self) {
+    let Some(heartbeat_interval) = self.config.heartbeat_interval else {
+      return;
+    };
+    let now = self.clock.now();
+    if now.duration_since(self.last_reported_at) < heartbeat_interval || self.get_hold_reason().is_some() {
+      return;
+    }
+    let Some(current) = self.state.lock().unwrap().current else {
+      return;
+    };
+    self.last_reported_at = now;
+    let at = self.clock.wall_time();
+    self.notify(&StatusEvent::Heartbeat { current: &current, at });
+  }
+
+  // How long it has been on battery since the mains went, None while on mains or before any status
+  pub fn get_on_battery_duration(&self) -> Option<Duration> {
+    self.on_battery_since.map(|on_battery_since| self.clock.now().duration_since(on_battery_since))
+  }
+
+  // Warns once per outage when it has lasted past the threshold, a pattern can take minutes to come around again so this is checked
+  // on every timeout rather than only as statuses come, nothing is warned about while paused and it comes once reporting picks up again
+  pub fn report_on_battery_warning_if_due(&mut self) {
+    let Some(threshold) = self.config.on_battery_warn_duration else {
+      return;
+    };
+    let Some(elapsed) = self.get_on_battery_duration() else {
+      return;
+    };
+    if self.is_on_battery_warned || elapsed < threshold || self.get_hold_reason().is_some() {
+      return;
+    }
+    self.is_on_battery_warned = true;
+    let at = self.clock.wall_time();
+    self.notify(&StatusEvent::OnBatteryWarning { elapsed, threshold, at });
+  }
+
+  // Reports leaving a critical status as soon as a status that isn't critical is classified, whether or not that one goes on to be
+  // held back, so whoever acted on the critical status can stand down in time, Unknown says nothing about it being over and clears nothing,
+  // the critical status being classified again before the other one is reported has it reported again for them to act on once more
+  fn report_clear_if_due(&mut self, status: Status) {
+    if self.get_hold_reason().is_some() {
+      return;
+    }
+    if self.reported_clear == Some(status) {
+      self.reported_clear = None;
+      let current = self.state.lock().unwrap().current;
+      if let Some(current) = current {
+        info!("{:?} is back before what cleared it was reported", status);
+        self.last_reported_at = self.clock.now();
+        self.notify(&StatusEvent::Transition { transition: &current });
+      }
+      return;
+    }
+    if status == Status::Unknown || self.reported_clear.is_some() {
+      return;
+    }
+    let Some(cleared_status) = self.get_cleared_status(status) else {
+      return;
+    };
+    self.reported_clear = Some(cleared_status);
+    let at = self.clock.wall_time();
+    self.notify(&StatusEvent::Cleared { cleared_status, by: status, at });
+  }
+
+  // Why nothing is to be reported right now, whether paused by hand or within a maintenance window
+  fn get_hold_reason(&self) -> Option<&'static str> {
+    if self.paused.load(Ordering::Relaxed) {
+      Some("Paused")
+    } else if maintenance::is_in_maintenance_window(&self.config.maintenance_windows, self.clock.wall_time()) {
+      Some("In a maintenance window")
+    } else {
+      None
+    }
+  }
+
+  fn notify(&mut self, event: &StatusEvent) {
+    for notifier in &mut self.notifiers {
+      notifier.notify(event);
+    }
+  }
+
+  // Only the first matched pattern is held back, silence long enough to infer a status from started after the restart anyway
+  fn is_awaiting_clean_cycle(&mut self, origin: Origin) -> bool {
+    match origin {
+      Origin::Observed => mem::take(&mut self.is_awaiting_clean_cycle),
+      Origin::Inferred => {
+        self.is_awaiting_clean_cycle = false;
+        false
+      },
+      Origin::Test | Origin::Restored => false,
+    }
+  }
+
+  // Test events are asked for explicitly and are never held back
+  fn is_warming_up(&self, status: Status, origin: Origin, now: Instant) -> bool {
+    origin != Origin::Test
+      && now.duration_since(self.started_at) < self.config.warmup_duration
+      && self.config.guidance.get(status).severity < Severity::Critical
+  }
+
+  // Any other status ends the hold, Unknown is already reported and nothing to hold back when it was the last status too,
+  // one that has lasted the whole debounce is reported, right then or once report_unknown_if_due notices if no classification comes along,
+  // and once it is being confirmed the debounce is over for the rest of the run
+  fn is_unknown_held_back(&mut self, classification: Classification, now: Instant) -> bool {
+    if classification.status != Status::Unknown
+      || self.last_status == Some(Status::Unknown)
+      || self.unconfirmed_status.is_some_and(|unconfirmed_status| unconfirmed_status.0 == Status::Unknown) {
+      self.held_back_unknown = None;
+      return false;
+    }
+    let unknown_since = self.held_back_unknown.map_or(now, |held_back_unknown| held_back_unknown.1);
+    if now.duration_since(unknown_since) >= self.config.unknown_debounce_duration {
+      self.held_back_unknown = None;
+      return false;
+    }
+    self.held_back_unknown = Some((classification, unknown_since));
+    true
+  }
+
+  // Only matched patterns take corroborating, a status inferred from the mains pin or a rule is as sure as it gets,
+  // any battery pattern corroborates any other as the first of them may well have been misread,
+  // the second one is reported as soon as it is matched
+  fn is_outage_uncorroborated(&mut self, status: Status, origin: Origin, now: Instant) -> bool {
+    let Some(outage_corroboration_window) = self.config.outage_corroboration_window else {
+      return false;
+    };
+    // The silence in between the patterns of an outage is never classified, anything else coming after the first one forgets it,
+    // the mains inferred again means its gap went by without another beep, the chirps of voltage regulation are the mains as much as the silence is
+    if !matches!(self.last_status, Some(Status::OnMains | Status::VoltageRegulating)) || origin != Origin::Observed || !status.is_on_battery() {
+      self.uncorroborated_outage_since = None;
+      return false;
+    }
+    match self.uncorroborated_outage_since.take().filter(|since| now.duration_since(*since) <= outage_corroboration_window) {
+      Some(_) => false,
+      None => {
+        self.uncorroborated_outage_since = Some(now);
+        true
+      },
+    }
+  }
+
+  // How many times in a row the status has been matched when that is still short of the confirmations, None once it is reported,
+  // Unknown takes them like any other status, only the ones still held back by its debounce neither count nor break the run,
+  // a status that isn't matched is as sure as it gets, and the silence in between the patterns keeps being classified
+  // as the reported status and doesn't break the run either
+  fn get_unconfirmed_count(&mut self, status: Status, origin: Origin) -> Option<usize> {
+    if self.config.confirmations <= 1 {
+      return None;
+    }
+    match (origin == Origin::Observed, self.last_status == Some(status)) {
+      (true, false) => {},
+      // The silence in between the patterns
+      (false, true) => return None,
+      _ => {
+        self.unconfirmed_status = None;
+        return None;
+      },
+    }
+    let count = match self.unconfirmed_status {
+      Some((unconfirmed_status, count)) if unconfirmed_status == status => count + 1,
+      _ => 1,
+    };
+    if count >= self.config.confirmations {
+      self.unconfirmed_status = None;
+      return None;
+    }
+    self.unconfirmed_status = Some((status, count));
+    Some(count)
+  }
+
+  // A status with a minimum dwell only gets reported once it has been classified for that long, one replaced sooner is dropped as implausible
+  fn is_held_back_for_dwell(&mut self, status: Status, now: Instant) -> bool {
+    if let Some((pending_status, pending_since)) = self.dwell_pending_since && pending_status != status {
+      info!("Dropping {:?} as implausible, it only lasted {}ms", pending_status, now.duration_since(pending_since).as_millis());
+      self.dwell_pending_since = None;
+    }
+    let Some(min_dwell) = get_status_dwell_bounds(status).min else {
+      return false;
+    };
+    if self.last_status == Some(status) {
+      return false;
+    }
+
+    let pending_since = self.dwell_pending_since.get_or_insert((status, now)).1;
+    let is_held_back = now.duration_since(pending_since) < min_dwell;
+    if !is_held_back {
+      self.dwell_pending_since = None;
+    }
+    is_held_back
+  }
+
+  // Flagged once per stay in the status, counted from when it was recorded
+  fn get_overstay_alert(&mut self, has_changed: bool, now: Instant) -> Option<String> {
+    if has_changed {
+      self.is_overstay_flagged = false;
+    }
+    let current = self.state.lock().unwrap().current?;
+    let max_dwell = get_status_dwell_bounds(current.to).max?;
+    let dwell = now.duration_since(current.at_instant);
+    if self.is_overstay_flagged || dwell <= max_dwell {
+      return None;
+    }
+    self.is_overstay_flagged = true;
+    Some(format!("{:?} has lasted {}s, longer than the {}s it is expected to", current.to, dwell.as_secs(), max_dwell.as_secs()))
+  }
+
+  // Every observed ReplaceBattery counts towards the wear, even while the status itself stays unchanged
+  fn track_battery_wear(&mut self, classification: Classification, origin: Origin, now: Instant) -> Option<String> {
+    if classification.status == Status::ReplaceBattery && origin == Origin::Observed && self.battery_wear.record_sighting(now) {
+      Some(PERSISTENT_REPLACE_BATTERY_DESCRIPTION.to_string())
+    } else {
+      None
+    }
+  }
+
+  // Only a mains status ends an outage, a status that is neither says nothing about whether the mains are back,
+  // the silence between the beeps of a battery pattern never comes as OnMains as the detector waits out the gap of the pattern first
+  fn track_time_on_battery(&mut self, status: Status) {
+    if status.is_on_mains() {
+      self.on_battery_since = None;
+    } else if status.is_on_battery() && self.on_battery_since.is_none() {
+      self.on_battery_since = Some(self.clock.now());
+      self.is_on_battery_warned = false;
+    }
+  }
+
+  // Returns the last status when it was critical and the given one no longer is
+  fn get_cleared_status(&self, status: Status) -> Option<Status> {
+    self.last_status.filter(|last_status| {
+      self.config.guidance.get(*last_status).severity == Severity::Critical && self.config.guidance.get(status).severity < Severity::Critical
+    })
+  }
+
+  // Records and returns the transition to report when the status has changed
+  fn update_status(&mut self, classification: Classification, origin: Origin) -> Option<Transition> {
+    if self.last_status == Some(classification.status) {
+      return None;
+    }
+
+    let transition = Transition {
+      from: self.last_status,
+      to: classification.status,
+      guidance: self.config.guidance.get(classification.status),
+      cleared: self.get_cleared_status(classification.status).is_some(),
+      confidence: classification.confidence,
+      beep_duration: classification.beep_duration,
+      inter_beep_duration: classification.inter_beep_duration,
+      origin,
+      at: self.clock.wall_time(),
+      at_instant: self.clock.now(),
+      // Numbered once recorded
+      sequence: 0,
+      in_maintenance_window: maintenance::is_in_maintenance_window(&self.config.maintenance_windows, self.clock.wall_time()),
+    };
+    let mut state = self.state.lock().unwrap();
+    let transition = state.record(transition);
+    info!(
+      "Status #{} is {:?}, was {}, {} with confidence {:.2}",
+      transition.sequence,
+      transition.to,
+      transition.from.map(|from| format!("{:?}", from)).unwrap_or_else(|| "none".to_string()),
+      origin.name(),
+      transition.confidence,
+    );
+    // The totals are up to date right after a transition, the time in the new status is only counted once it ends
+    if let Some(stats_path) = &self.config.stats_path
+      && let Err(error) = state.totals.save(stats_path) {
+      error!("{}", error);
+    }
+    drop(state);
+    self.last_status = Some(classification.status);
+    self.reported_clear = None;
+    self.track_time_on_battery(classification.status);
+    Some(transition)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::{env, fs, process};
+  use std::path::Path;
+  use std::sync::Mutex;
+  use std::time::{Duration, UNIX_EPOCH};
+
+  use crate::clock::{Clock, MockClock};
+  use crate::detector::{Detector, DetectorConfig, Edge};
+  use crate::guidance::parse_guidance_override;
+  use crate::notifier::{LineStyle, get_sink_notifiers};
+  use crate::output::Output;
+  use crate::state::{DEFAULT_MAX_TRANSITIONS, StatusState};
+  use crate::status::TIMEOUT_DURATION;
+
+  fn get_classification(status: Status, confidence: f64) -> Classification {
+    Classification { status, confidence, beep_duration: Duration::from_millis(250), inter_beep_duration: Duration::from_secs(60) }
+  }
+
+  fn get_config() -> ReportConfig {
+    ReportConfig {
+      guidance: GuidanceTable::new(vec![]),
+      replace_battery_escalation_score: 3.0,
+      stats_path: None,
+      unknown_debounce_duration: Duration::ZERO,
+      outage_corroboration_window: None,
+      confirmations: 1,
+      warmup_duration: Duration::ZERO,
+      heartbeat_interval: None,
+      on_battery_warn_duration: None,
+      restart_policy: RestartPolicy::Report,
+      maintenance_windows: vec![],
+    }
+  }
+
+  fn get_file_notifiers(path: &Path, format: Format) -> Vec<Box<dyn Notifier>> {
+    let output = Output::open(&OutputTarget::File(path.to_str().unwrap().to_string())).unwrap();
+    get_sink_notifiers(vec![(output, format)], &LineStyle::default())
+  }
+
+  fn get_stdout_notifiers() -> Vec<Box<dyn Notifier>> {
+    get_sink_notifiers(vec![(Output::Stdout, Format::Text)], &LineStyle::default())
+  }
+
+  fn get_reporter() -> Reporter {
+    Reporter::new(get_config(), SharedState::default(), get_stdout_notifiers())
+  }
+
+  #[test]
+  fn reports_only_changes() {
+    let mut reporter = get_reporter();
+    let on_battery = get_classification(Status::OnBattery, 0.5);
+    assert_eq!(reporter.update_status(on_battery, Origin::Observed).map(|transition| transition.to), Some(Status::OnBattery));
+    assert!(reporter.update_status(on_battery, Origin::Observed).is_none());
+  }
+
+  #[test]
+  fn test_events_are_recorded_as_such() {
+    let mut reporter = get_reporter();
+    reporter.update_status(get_classification(Status::LowOnBattery, 1.0), Origin::Test);
+    assert_eq!(reporter.state.lock().unwrap().current.unwrap().origin, Origin::Test);
+  }
+
+  #[test]
+  fn origin_change_alone_is_not_reported() {
+    let mut reporter = get_reporter();
+    let on_battery = get_classification(Status::OnBattery, 1.0);
+    assert!(reporter.update_status(on_battery, Origin::Observed).is_some());
+    assert!(reporter.update_status(on_battery, Origin::Inferred).is_none());
+  }
+
+  #[test]
+  fn fans_out_to_every_sink_in_its_format() {
+    let text_path = env::temp_dir().join(format!("ups-power-status-sink-text-{}.log", process::id()));
+    let json_path = env::temp_dir().join(format!("ups-power-status-sink-json-{}.jsonl", process::id()));
+    let notifiers = [get_file_notifiers(&text_path, Format::Text), get_file_notifiers(&json_path, Format::Json)].into_iter().flatten().collect();
+    let mut reporter = Reporter::new(get_config(), SharedState::default(), notifiers);
+    reporter.update_and_report_status(get_classification(Status::LowOnBattery, 1.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+
+    let text = fs::read_to_string(&text_path).unwrap();
+    let json = fs::read_to_string(&json_path).unwrap();
+    fs::remove_file(&text_path).unwrap();
+    fs::remove_file(&json_path).unwrap();
+    assert_eq!(text.lines().collect::<Vec<_>>(), vec![
+      get_status_description(Status::LowOnBattery).to_string(),
+      format!("Cleared: {}", get_status_description(Status::LowOnBattery)),
+      get_status_description(Status::OnMains).to_string(),
+    ]);
+    let json_lines: Vec<_> = json.lines().collect();
+    assert_eq!(json_lines.len(), 3);
+    assert!(json_lines[0].starts_with("{\"schema_version\":1,\"from\":null,\"status\":\"LowOnBattery\""));
+    assert!(json_lines[1].starts_with("{\"schema_version\":1,\"cleared\":{\"status\":\"LowOnBattery\",\"by\":\"OnMains\""));
+    assert!(json_lines[2].contains("\"status\":\"OnMains\""));
+    assert!(json_lines[2].contains("\"cleared\":true"));
+  }
+
+  // Records a short tag for every event it's handed
+  struct RecordingNotifier {
+    name: &'static str,
+    events: Arc<Mutex<Vec<String>>>,
+  }
+
+  impl Notifier for RecordingNotifier {
+    fn notify(&mut self, event: &StatusEvent) {
+      let tag = match event {
+        StatusEvent::Transition { transition } => format!("{:?}", transition.to),
+        StatusEvent::Cleared { cleared_status, by, .. } => format!("{:?} cleared by {:?}", cleared_status, by),
+        StatusEvent::Alert { alert, .. } => format!("alert {}", alert),
+        StatusEvent::Heartbeat { current, .. } => format!("heartbeat {:?}", current.to),
+        StatusEvent::OnBatteryWarning { elapsed, .. } => format!("on battery for {}s", elapsed.as_secs()),
+      };
+      self.events.lock().unwrap().push(format!("{} {}", self.name, tag));
+    }
+  }
+
+  #[test]
+  fn every_notifier_is_handed_every_event_in_order() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let notifiers: Vec<Box<dyn Notifier>> = ["first", "second"].into_iter()
+      .map(|name| Box::new(RecordingNotifier { name, events: events.clone() }) as Box<dyn Notifier>)
+      .collect();
+    let clock = MockClock::new();
+    let config = ReportConfig { heartbeat_interval: Some(Duration::from_secs(60)), ..get_config() };
+    let mut reporter = Reporter::with_clock(config, SharedState::default(), notifiers, Box::new(clock.clone()));
+
+    reporter.update_and_report_status(get_classification(Status::LowOnBattery, 1.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+    reporter.report_alert("Wear");
+    clock.advance(Duration::from_secs(60));
+    reporter.report_heartbeat_if_due();
+    reporter.get_paused().store(true, Ordering::Relaxed);
+    reporter.report_alert("Unheard");
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+
+    assert_eq!(*events.lock().unwrap(), vec![
+      "first LowOnBattery",
+      "second LowOnBattery",
+      "first LowOnBattery cleared by OnMains",
+      "second LowOnBattery cleared by OnMains",
+      "first OnMains",
+      "second OnMains",
+      "first alert Wear",
+      "second alert Wear",
+      "first heartbeat OnMains",
+      "second heartbeat OnMains",
+    ]);
+  }
+
+  #[test]
+  fn parses_sinks() {
+    assert_eq!(parse_sink("stdout:human"), Ok((OutputTarget::Stdout, Format::Text)));
+    assert_eq!(parse_sink("/var/log/ups:main.jsonl:json"), Ok((OutputTarget::File("/var/log/ups:main.jsonl".to_string()), Format::Json)));
+    assert!(parse_sink("syslog").unwrap_err().starts_with("invalid sink syslog"));
+    assert!(parse_sink(":json").unwrap_err().starts_with("empty target"));
+    assert!(parse_sink("stdout:xml").unwrap_err().starts_with("unknown format xml"));
+    for name in ["stdout:text", "stderr:char", "syslog-rfc5424:json", "/var/log/ups:main.jsonl:json"] {
+      assert_eq!(get_sink_name(&parse_sink(name).unwrap()), name);
+    }
+  }
+
+  #[test]
+  fn records_transitions_in_shared_state() {
+    let mut reporter = get_reporter();
+    reporter.update_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    reporter.update_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    reporter.update_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+
+    let state = reporter.state.lock().unwrap();
+    assert_eq!(state.transitions.len(), 2);
+    assert_eq!(state.transitions[1].from, Some(Status::OnBattery));
+    assert_eq!(state.current.unwrap().to, Status::OnMains);
+  }
+
+  #[test]
+  fn only_observed_pairs_are_the_last_measurement() {
+    let mut reporter = get_reporter();
+    reporter.update_and_report_status(Classification { status: Status::Unknown, confidence: 0.0, beep_duration: Duration::from_millis(400), inter_beep_duration: Duration::from_secs(7) }, Origin::Observed);
+    reporter.update_and_report_status(Classification { status: Status::OnMains, confidence: 1.0, beep_duration: Duration::ZERO, inter_beep_duration: Duration::from_secs(3) }, Origin::Inferred);
+    assert_eq!(reporter.state.lock().unwrap().last_measurement, Some((Duration::from_millis(400), Duration::from_secs(7))));
+  }
+
+  #[test]
+  fn transitions_are_timed_by_the_clock() {
+    let clock = MockClock::new();
+    let mut reporter = Reporter::with_clock(get_config(), SharedState::default(), get_stdout_notifiers(), Box::new(clock.clone()));
+    reporter.update_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    clock.advance(Duration::from_secs(90));
+    reporter.update_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+
+    let state = reporter.state.lock().unwrap();
+    assert_eq!(state.transitions[1].at_instant.duration_since(state.transitions[0].at_instant), Duration::from_secs(90));
+  }
+
+  #[test]
+  fn wall_clock_steps_only_move_the_timestamps() {
+    let clock = MockClock::new();
+    let mut reporter = Reporter::with_clock(get_config(), SharedState::default(), get_stdout_notifiers(), Box::new(clock.clone()));
+    reporter.update_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    clock.advance(Duration::from_secs(90));
+    clock.set_wall_time(clock.wall_time() - Duration::from_secs(3600));
+    reporter.update_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+
+    let state = reporter.state.lock().unwrap();
+    assert!(state.transitions[1].at < state.transitions[0].at);
+    assert_eq!(state.totals.get(Status::OnBattery), Duration::from_secs(90));
+    assert_eq!(state.transitions[1].beep_duration, Duration::from_millis(250));
+    assert_eq!(state.transitions[1].inter_beep_duration, Duration::from_secs(60));
+  }
+
+  #[test]
+  fn voltage_regulation_is_no_outage() {
+    let clock = MockClock::new();
+    let mut config = get_config();
+    config.outage_corroboration_window = Some(Duration::from_secs(90));
+    let mut reporter = Reporter::with_clock(config, SharedState::default(), get_stdout_notifiers(), Box::new(clock.clone()));
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+    reporter.update_and_report_status(get_classification(Status::VoltageRegulating, 1.0), Origin::Inferred);
+    assert_eq!(reporter.last_status, Some(Status::VoltageRegulating));
+
+    // Leaving it for a battery status takes corroboration as leaving OnMains does
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::VoltageRegulating));
+    clock.advance(Duration::from_secs(60));
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::OnBattery));
+  }
+
+  #[test]
+  fn leaving_on_mains_takes_corroboration() {
+    let clock = MockClock::new();
+    let mut config = get_config();
+    config.outage_corroboration_window = Some(Duration::from_secs(90));
+    let mut reporter = Reporter::with_clock(config, SharedState::default(), get_stdout_notifiers(), Box::new(clock.clone()));
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+
+    // A lone pattern is forgotten once the window runs out
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::OnMains));
+    clock.advance(Duration::from_secs(91));
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::OnMains));
+    // Or as soon as the silence after it is the mains again, which only comes once its gap has gone by without another beep
+    clock.advance(Duration::from_secs(65));
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+    clock.advance(Duration::from_secs(10));
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::OnMains));
+
+    // A second pattern within the window is reported right away, even a different battery one
+    clock.advance(Duration::from_secs(60));
+    reporter.update_and_report_status(get_classification(Status::LowOnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::LowOnBattery));
+
+    // Power coming back is reported without waiting, as are inferred battery statuses and statuses on mains
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+    assert_eq!(reporter.last_status, Some(Status::OnMains));
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Inferred);
+    assert_eq!(reporter.last_status, Some(Status::OnBattery));
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+    reporter.update_and_report_status(get_classification(Status::OverTemperatureOnMains, 1.0), Origin::Test);
+    assert_eq!(reporter.last_status, Some(Status::OverTemperatureOnMains));
+  }
+
+  #[test]
+  fn new_statuses_take_their_confirmations() {
+    let config = ReportConfig { confirmations: 3, unknown_debounce_duration: Duration::from_secs(60), ..get_config() };
+    let mut reporter = Reporter::new(config, SharedState::default(), get_stdout_notifiers());
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+
+    // A misread breaks the run, Unknown and the silence in between neither break nor advance it
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::LowOnBattery, 1.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::Unknown, 1.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::OnMains));
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::OnBattery));
+
+    // Once reported, a single misread pattern doesn't flip it back
+    reporter.update_and_report_status(get_classification(Status::LowOnBattery, 1.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::LowOnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::OnBattery));
+
+    // Inferred statuses are reported right away
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+    assert_eq!(reporter.last_status, Some(Status::OnMains));
+  }
+
+  #[test]
+  fn unknown_takes_its_confirmations_without_a_debounce() {
+    let config = ReportConfig { confirmations: 3, ..get_config() };
+    let mut reporter = Reporter::new(config, SharedState::default(), get_stdout_notifiers());
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+
+    // A single Unknown never replaces the reported status, and it breaks a run like any misread
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::Unknown, 0.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::OnMains));
+
+    reporter.update_and_report_status(get_classification(Status::Unknown, 0.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::Unknown, 0.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::OnMains));
+    reporter.update_and_report_status(get_classification(Status::Unknown, 0.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::Unknown));
+  }
+
+  #[test]
+  fn unknown_is_confirmed_after_its_debounce() {
+    let clock = MockClock::new();
+    let config = ReportConfig { confirmations: 2, unknown_debounce_duration: Duration::from_secs(10), ..get_config() };
+    let mut reporter = Reporter::with_clock(config, SharedState::default(), get_stdout_notifiers(), Box::new(clock.clone()));
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+
+    reporter.update_and_report_status(get_classification(Status::Unknown, 0.0), Origin::Observed);
+    clock.advance(Duration::from_secs(10));
+    reporter.report_unknown_if_due();
+    assert_eq!(reporter.last_status, Some(Status::OnMains));
+    // The debounce doesn't start over for the next one of the same run
+    reporter.update_and_report_status(get_classification(Status::Unknown, 0.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::Unknown));
+  }
+
+  #[test]
+  fn unknown_is_held_back_until_it_persists() {
+    let clock = MockClock::new();
+    let mut config = get_config();
+    config.unknown_debounce_duration = Duration::from_secs(10);
+    let mut reporter = Reporter::with_clock(config, SharedState::default(), get_stdout_notifiers(), Box::new(clock.clone()));
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+
+    // A blip on the way to a real status is never reported
+    reporter.update_and_report_status(get_classification(Status::Unknown, 0.0), Origin::Observed);
+    clock.advance(Duration::from_secs(5));
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::OnBattery));
+
+    // The hold starts over after the real status
+    reporter.update_and_report_status(get_classification(Status::Unknown, 0.0), Origin::Observed);
+    clock.advance(Duration::from_secs(9));
+    reporter.update_and_report_status(get_classification(Status::Unknown, 0.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::OnBattery));
+    clock.advance(Duration::from_secs(1));
+    reporter.update_and_report_status(get_classification(Status::Unknown, 0.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::Unknown));
+    assert_eq!(reporter.state.lock().unwrap().transitions.len(), 3);
+  }
+
+  #[test]
+  fn unknown_is_reported_once_the_debounce_runs_out_without_another_classification() {
+    let clock = MockClock::new();
+    let mut config = get_config();
+    config.unknown_debounce_duration = Duration::from_secs(10);
+    let mut reporter = Reporter::with_clock(config, SharedState::default(), get_stdout_notifiers(), Box::new(clock.clone()));
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+
+    reporter.update_and_report_status(Classification { beep_duration: Duration::from_millis(700), ..get_classification(Status::Unknown, 0.0) }, Origin::Observed);
+    clock.advance(Duration::from_secs(6));
+    reporter.update_and_report_status(Classification { beep_duration: Duration::from_millis(900), ..get_classification(Status::Unknown, 0.0) }, Origin::Observed);
+    clock.advance(Duration::from_millis(3_999));
+    reporter.report_unknown_if_due();
+    assert_eq!(reporter.last_status, Some(Status::OnMains));
+    // Timed from the first Unknown, reporting the last one
+    clock.advance(Duration::from_millis(1));
+    reporter.report_unknown_if_due();
+    assert_eq!(reporter.last_status, Some(Status::Unknown));
+    assert_eq!(reporter.state.lock().unwrap().current.unwrap().beep_duration, Duration::from_millis(900));
+    reporter.report_unknown_if_due();
+    assert_eq!(reporter.state.lock().unwrap().transitions.len(), 2);
+
+    // A real status in the meantime leaves nothing to report
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+    reporter.update_and_report_status(get_classification(Status::Unknown, 0.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    clock.advance(Duration::from_secs(20));
+    reporter.report_unknown_if_due();
+    assert_eq!(reporter.last_status, Some(Status::OnBattery));
+  }
+
+  #[test]
+  fn unknown_is_reported_right_away_without_debounce() {
+    let mut reporter = get_reporter();
+    reporter.update_and_report_status(get_classification(Status::Unknown, 0.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::Unknown));
+  }
+
+  #[test]
+  fn paused_statuses_are_tracked_and_reported_fresh_on_resume() {
+    let path = env::temp_dir().join(format!("ups-power-status-paused-{}.log", process::id()));
+    let mut reporter = Reporter::new(get_config(), SharedState::default(), get_file_notifiers(&path, Format::Text));
+
+    reporter.get_paused().store(true, Ordering::Relaxed);
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.state.lock().unwrap().current.unwrap().to, Status::OnBattery);
+    reporter.get_paused().store(false, Ordering::Relaxed);
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+
+    let contents = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(contents.lines().collect::<Vec<_>>(), vec![get_status_description(Status::OnBattery)]);
+    assert_eq!(reporter.state.lock().unwrap().transitions.len(), 1);
+  }
+
+  #[test]
+  fn maintenance_windows_hold_back_reporting_and_tag_what_happened_during_them() {
+    let path = env::temp_dir().join(format!("ups-power-status-maintenance-{}.log", process::id()));
+    let clock = MockClock::new();
+    // 2023-11-12 02:59 UTC, a Sunday
+    clock.set_wall_time(UNIX_EPOCH + Duration::from_secs(1_699_757_940));
+    let config = ReportConfig { maintenance_windows: vec![maintenance::parse_maintenance_window("sun@03:00-04:00Z").unwrap()], ..get_config() };
+    let mut reporter = Reporter::with_clock(config, SharedState::default(), get_file_notifiers(&path, Format::Text), Box::new(clock.clone()));
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+
+    // Recorded and tagged, but not written, and neither are alerts
+    clock.advance(Duration::from_secs(60));
+    reporter.update_and_report_status(get_classification(Status::TestInProgress, 1.0), Origin::Observed);
+    reporter.report_alert("Self test alert");
+    let current = reporter.state.lock().unwrap().current.unwrap();
+    assert_eq!((current.to, current.in_maintenance_window), (Status::TestInProgress, true));
+
+    // Written late once the window is over, as it happened during it
+    clock.advance(Duration::from_secs(3600));
+    reporter.update_and_report_status(get_classification(Status::TestInProgress, 1.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+
+    let contents = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(contents.lines().collect::<Vec<_>>(), vec![
+      get_status_description(Status::OnMains).to_string(),
+      format!("{} (during maintenance)", get_status_description(Status::TestInProgress)),
+      get_status_description(Status::OnMains).to_string(),
+    ]);
+    assert!(!reporter.state.lock().unwrap().current.unwrap().in_maintenance_window);
+  }
+
+  #[test]
+  fn heartbeats_repeat_the_current_status_once_quiet_for_the_interval() {
+    let path = env::temp_dir().join(format!("ups-power-status-heartbeat-{}.log", process::id()));
+    let clock = MockClock::new();
+    let config = ReportConfig { heartbeat_interval: Some(Duration::from_secs(60)), ..get_config() };
+    let mut reporter = Reporter::with_clock(config, SharedState::default(), get_file_notifiers(&path, Format::Text), Box::new(clock.clone()));
+
+    // Nothing to repeat yet
+    clock.advance(Duration::from_secs(90));
+    reporter.report_heartbeat_if_due();
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+    clock.advance(Duration::from_secs(59));
+    reporter.report_heartbeat_if_due();
+    clock.advance(Duration::from_secs(1));
+    reporter.report_heartbeat_if_due();
+    reporter.report_heartbeat_if_due();
+    // A transition counts as the status having been written
+    clock.advance(Duration::from_secs(50));
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    clock.advance(Duration::from_secs(50));
+    reporter.report_heartbeat_if_due();
+    clock.advance(Duration::from_secs(10));
+    reporter.report_heartbeat_if_due();
+
+    let contents = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(contents.lines().collect::<Vec<_>>(), vec![
+      get_status_description(Status::OnMains).to_string(),
+      format!("Heartbeat: {}", get_status_description(Status::OnMains)),
+      get_status_description(Status::OnBattery).to_string(),
+      format!("Heartbeat: {}", get_status_description(Status::OnBattery)),
+    ]);
+    assert_eq!(reporter.state.lock().unwrap().transitions.len(), 2);
+  }
+
+  #[test]
+  fn being_on_battery_past_the_threshold_is_warned_about_once_per_outage() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(RecordingNotifier { name: "sink", events: events.clone() })];
+    let clock = MockClock::new();
+    let config = ReportConfig { on_battery_warn_duration: Some(Duration::from_secs(600)), ..get_config() };
+    let mut reporter = Reporter::with_clock(config, SharedState::default(), notifiers, Box::new(clock.clone()));
+
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+    assert_eq!(reporter.get_on_battery_duration(), None);
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    // Neither running low nor an odd pattern starts the count over
+    clock.advance(Duration::from_secs(300));
+    reporter.update_and_report_status(get_classification(Status::Unknown, 1.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::LowOnBattery, 1.0), Origin::Observed);
+    clock.advance(Duration::from_secs(299));
+    reporter.report_on_battery_warning_if_due();
+    assert_eq!(reporter.get_on_battery_duration(), Some(Duration::from_secs(599)));
+    clock.advance(Duration::from_secs(1));
+    reporter.report_on_battery_warning_if_due();
+    clock.advance(Duration::from_secs(60));
+    reporter.report_on_battery_warning_if_due();
+    // Back on mains, the next outage gets warned about in its own time
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+    assert_eq!(reporter.get_on_battery_duration(), None);
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    clock.advance(Duration::from_secs(599));
+    reporter.report_on_battery_warning_if_due();
+    clock.advance(Duration::from_secs(2));
+    reporter.report_on_battery_warning_if_due();
+
+    assert_eq!(*events.lock().unwrap(), vec![
+      "sink OnMains",
+      "sink OnBattery",
+      "sink Unknown",
+      "sink LowOnBattery",
+      "sink on battery for 600s",
+      "sink LowOnBattery cleared by OnMains",
+      "sink OnMains",
+      "sink OnBattery",
+      "sink on battery for 601s",
+    ]);
+  }
+
+  // Through the detector as the detection loop drives it, a timeout every TIMEOUT_DURATION and a beep every minute,
+  // the silence in between must never pass for the mains coming back and start the count over
+  #[test]
+  fn a_steady_outage_is_warned_about_once_it_lasts_past_the_threshold() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(RecordingNotifier { name: "sink", events: events.clone() })];
+    let clock = MockClock::new();
+    let config = ReportConfig { on_battery_warn_duration: Some(Duration::from_secs(120)), ..get_config() };
+    let mut reporter = Reporter::with_clock(config, SharedState::default(), notifiers, Box::new(clock.clone()));
+    let mut detector = Detector::new(DetectorConfig::default());
+
+    let beep_duration = Duration::from_millis(250);
+    let started_at = clock.now();
+    let mut next_beep_at = started_at;
+    while clock.now().duration_since(started_at) < Duration::from_secs(3 * 60) {
+      let (classification, origin) = if clock.now() >= next_beep_at {
+        detector.on_edge(Edge::BeepStart, clock.now());
+        clock.advance(beep_duration);
+        next_beep_at = clock.now() + Duration::from_secs(60);
+        (detector.on_edge(Edge::BeepEnd, clock.now()), Origin::Observed)
+      } else {
+        clock.advance(TIMEOUT_DURATION.min(next_beep_at.duration_since(clock.now())));
+        (detector.on_timeout(clock.now()), Origin::Inferred)
+      };
+      if let Some(classification) = classification {
+        reporter.update_and_report_status(classification, origin);
+      }
+      reporter.report_on_battery_warning_if_due();
+    }
+
+    assert_eq!(*events.lock().unwrap(), vec!["sink OnBattery", "sink on battery for 120s"]);
+  }
+
+  #[test]
+  fn restart_carries_the_saved_status_over_until_a_whole_cycle() {
+    let mut state = StatusState::new(DEFAULT_MAX_TRANSITIONS);
+    state.totals.last_status = Some(Status::OnBattery);
+    let state = Arc::new(Mutex::new(state));
+    let config = ReportConfig { restart_policy: RestartPolicy::WaitForCycle, ..get_config() };
+    let mut reporter = Reporter::new(config, state.clone(), get_stdout_notifiers());
+
+    reporter.restore_last_status();
+    assert_eq!(state.lock().unwrap().current.unwrap().origin, Origin::Restored);
+    // The first pattern may be measured from a beep that started before the restart
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::OnBattery));
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    assert_eq!(state.lock().unwrap().transitions.len(), 1);
+    reporter.update_and_report_status(get_classification(Status::LowOnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::LowOnBattery));
+    assert_eq!(state.lock().unwrap().totals.last_status, Some(Status::LowOnBattery));
+  }
+
+  #[test]
+  fn silence_ends_the_wait_for_a_whole_cycle() {
+    let config = ReportConfig { restart_policy: RestartPolicy::WaitForCycle, ..get_config() };
+    let mut reporter = Reporter::new(config, SharedState::default(), get_stdout_notifiers());
+    // Nothing saved to carry over
+    reporter.restore_last_status();
+    assert_eq!(reporter.last_status, None);
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::OnBattery));
+  }
+
+  #[test]
+  fn saved_status_is_ignored_when_reporting_every_pattern() {
+    let mut state = StatusState::new(DEFAULT_MAX_TRANSITIONS);
+    state.totals.last_status = Some(Status::OnBattery);
+    let mut reporter = Reporter::new(get_config(), Arc::new(Mutex::new(state)), get_stdout_notifiers());
+    reporter.restore_last_status();
+    assert_eq!(reporter.last_status, None);
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::OnMains));
+  }
+
+  #[test]
+  fn test_events_are_not_carried_over() {
+    let mut reporter = get_reporter();
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+    reporter.update_and_report_status(get_classification(Status::LowOnBattery, 1.0), Origin::Test);
+    assert_eq!(reporter.state.lock().unwrap().totals.last_status, Some(Status::OnMains));
+  }
+
+  #[test]
+  fn statuses_are_held_back_until_their_minimum_dwell() {
+    let clock = MockClock::new();
+    let mut reporter = Reporter::with_clock(get_config(), SharedState::default(), get_stdout_notifiers(), Box::new(clock.clone()));
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+
+    // A lone match replaced right away is dropped
+    reporter.update_and_report_status(get_classification(Status::OverTemperatureOnMains, 1.0), Origin::Observed);
+    clock.advance(Duration::from_millis(200));
+    reporter.update_and_report_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+    assert_eq!(reporter.dwell_pending_since, None);
+
+    reporter.update_and_report_status(get_classification(Status::OverTemperatureOnMains, 1.0), Origin::Observed);
+    clock.advance(Duration::from_secs(4));
+    reporter.update_and_report_status(get_classification(Status::OverTemperatureOnMains, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::OnMains));
+    clock.advance(Duration::from_secs(4));
+    reporter.update_and_report_status(get_classification(Status::OverTemperatureOnMains, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::OverTemperatureOnMains));
+    assert_eq!(reporter.state.lock().unwrap().transitions.len(), 2);
+  }
+
+  #[test]
+  fn status_outlasting_its_maximum_dwell_is_flagged_once() {
+    let clock = MockClock::new();
+    let mut reporter = Reporter::with_clock(get_config(), SharedState::default(), get_stdout_notifiers(), Box::new(clock.clone()));
+    reporter.update_status(get_classification(Status::LowOnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.get_overstay_alert(true, clock.now()), None);
+
+    clock.advance(Duration::from_secs(601));
+    assert_eq!(reporter.get_overstay_alert(false, clock.now()).as_deref(), Some("LowOnBattery has lasted 601s, longer than the 600s it is expected to"));
+    assert_eq!(reporter.get_overstay_alert(false, clock.now()), None);
+  }
+
+  #[test]
+  fn only_critical_statuses_are_reported_during_warmup() {
+    let clock = MockClock::new();
+    let mut config = get_config();
+    config.warmup_duration = Duration::from_secs(30);
+    let mut reporter = Reporter::with_clock(config, SharedState::default(), get_stdout_notifiers(), Box::new(clock.clone()));
+
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, None);
+    reporter.update_and_report_status(get_classification(Status::ReplaceBattery, 1.0), Origin::Test);
+    assert_eq!(reporter.last_status, Some(Status::ReplaceBattery));
+    reporter.update_and_report_status(get_classification(Status::LowOnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::LowOnBattery));
+
+    clock.advance(Duration::from_secs(30));
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::OnBattery));
+  }
+
+  #[test]
+  fn clearing_is_reported_while_the_status_clearing_it_is_held_back() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(RecordingNotifier { name: "sink", events: events.clone() })];
+    let config = ReportConfig { confirmations: 3, ..get_config() };
+    let mut reporter = Reporter::new(config, SharedState::default(), notifiers);
+    reporter.update_and_report_status(get_classification(Status::LowOnBattery, 1.0), Origin::Inferred);
+
+    // Unknown clears nothing, the first status that isn't critical does, once however long it is held back
+    reporter.update_and_report_status(get_classification(Status::Unknown, 0.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.last_status, Some(Status::LowOnBattery));
+    // Coming back before that status was reported has it reported again
+    reporter.update_and_report_status(get_classification(Status::LowOnBattery, 1.0), Origin::Observed);
+    for _ in 0..3 {
+      reporter.update_and_report_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    }
+    assert_eq!(reporter.last_status, Some(Status::OnBattery));
+
+    assert_eq!(*events.lock().unwrap(), vec![
+      "sink LowOnBattery",
+      "sink LowOnBattery cleared by OnBattery",
+      "sink LowOnBattery",
+      "sink LowOnBattery cleared by OnBattery",
+      "sink OnBattery",
+    ]);
+    assert!(reporter.state.lock().unwrap().current.unwrap().cleared);
+  }
+
+  #[test]
+  fn leaving_critical_status_is_cleared() {
+    let mut reporter = get_reporter();
+    reporter.update_status(get_classification(Status::LowOnBattery, 1.0), Origin::Observed);
+    // PowerOff is just as critical, OnMains isn't
+    assert_eq!(reporter.get_cleared_status(Status::PowerOff), None);
+    assert_eq!(reporter.get_cleared_status(Status::OnMains), Some(Status::LowOnBattery));
+
+    reporter.update_status(get_classification(Status::OnMains, 1.0), Origin::Inferred);
+    assert_eq!(reporter.get_cleared_status(Status::OnBattery), None);
+
+    let state = reporter.state.lock().unwrap();
+    assert!(!state.transitions[0].cleared);
+    assert!(state.transitions[1].cleared);
+  }
+
+  #[test]
+  fn cleared_follows_guidance_overrides() {
+    let mut reporter = get_reporter();
+    reporter.config.guidance = GuidanceTable::new(vec![parse_guidance_override("OnBattery=critical:prepare-shutdown").unwrap()]);
+    reporter.update_status(get_classification(Status::OnBattery, 1.0), Origin::Observed);
+    assert_eq!(reporter.get_cleared_status(Status::OnMains), Some(Status::OnBattery));
+  }
+
+  #[test]
+  fn escalates_persistent_replace_battery() {
+    let mut reporter = get_reporter();
+    let replace_battery = get_classification(Status::ReplaceBattery, 1.0);
+    let now = Instant::now();
+
+    assert_eq!(reporter.track_battery_wear(replace_battery, Origin::Observed, now), None);
+    assert_eq!(reporter.track_battery_wear(replace_battery, Origin::Inferred, now), None);
+    assert_eq!(reporter.track_battery_wear(get_classification(Status::OnBattery, 1.0), Origin::Observed, now), None);
+    assert_eq!(reporter.track_battery_wear(replace_battery, Origin::Observed, now), None);
+    assert_eq!(reporter.track_battery_wear(replace_battery, Origin::Observed, now).as_deref(), Some(PERSISTENT_REPLACE_BATTERY_DESCRIPTION));
+    assert_eq!(reporter.track_battery_wear(replace_battery, Origin::Observed, now), None);
+  }
+}
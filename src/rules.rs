@@ -0,0 +1,93 @@
+use crate::status::{Classification, Status, get_status_from_name};
+
+// What a rule can look at, which is only ever the inputs the tool has
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Condition {
+  // Whether the mains present input is high, never met without one
+  MainsPresent(bool),
+  // The status classified from the beeps
+  Status(Status),
+}
+
+// Reports the status whenever all of the conditions are met
+#[derive(PartialEq, Clone, Debug)]
+pub struct Rule {
+  pub conditions: Vec<Condition>,
+  pub status: Status,
+}
+
+impl Rule {
+  pub fn uses_mains(&self) -> bool {
+    self.conditions.iter().any(|condition| matches!(condition, Condition::MainsPresent(_)))
+  }
+}
+
+// The status of the first rule whose conditions are all met replaces the classified one, None when no rule applies
+pub fn apply_rules(rules: &[Rule], classification: Classification, is_mains_present: Option<bool>) -> Option<Classification> {
+  rules.iter()
+    .find(|rule| rule.conditions.iter().all(|condition| match *condition {
+      Condition::MainsPresent(is_present) => is_mains_present == Some(is_present),
+      Condition::Status(status) => classification.status == status,
+    }))
+    .map(|rule| Classification { status: rule.status, ..classification })
+}
+
+// Parses a rule written as "<condition>[,<condition>]...=<status>", a condition being either "mains:present", "mains:absent" or "status:<status>",
+// as in "mains:present,status:OnBattery=SensorConflict"
+pub fn parse_rule(value: &str) -> Result<Rule, String> {
+  let invalid_rule = || format!("invalid rule {}, expected <condition>[,<condition>]...=<status>", value);
+
+  let (conditions, status_name) = value.rsplit_once('=').ok_or_else(invalid_rule)?;
+  let status = get_status_from_name(status_name).ok_or_else(|| format!("unknown status {} in rule {}", status_name, value))?;
+  let conditions = conditions.split(',')
+    .map(|condition| match condition.split_once(':') {
+      Some(("mains", "present")) => Ok(Condition::MainsPresent(true)),
+      Some(("mains", "absent")) => Ok(Condition::MainsPresent(false)),
+      Some(("status", status_name)) => get_status_from_name(status_name)
+        .map(Condition::Status)
+        .ok_or_else(|| format!("unknown status {} in rule {}", status_name, value)),
+      _ => Err(format!("invalid condition {} in rule {}", condition, value)),
+    })
+    .collect::<Result<Vec<Condition>, String>>()?;
+
+  Ok(Rule { conditions, status })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  fn get_classification(status: Status) -> Classification {
+    Classification { status, confidence: 0.5, beep_duration: Duration::from_millis(250), inter_beep_duration: Duration::from_secs(60) }
+  }
+
+  #[test]
+  fn first_rule_met_wins() {
+    let rules = vec![
+      parse_rule("mains:present,status:OnBattery=SensorConflict").unwrap(),
+      parse_rule("status:OnBattery=LowOnBattery").unwrap(),
+    ];
+    let classification = apply_rules(&rules, get_classification(Status::OnBattery), Some(true)).unwrap();
+    assert_eq!(classification, Classification { status: Status::SensorConflict, ..get_classification(Status::OnBattery) });
+    assert_eq!(apply_rules(&rules, get_classification(Status::OnBattery), Some(false)).unwrap().status, Status::LowOnBattery);
+    assert_eq!(apply_rules(&rules, get_classification(Status::OnMains), Some(true)), None);
+  }
+
+  #[test]
+  fn mains_conditions_need_the_input() {
+    let rules = vec![parse_rule("mains:absent=OnBattery").unwrap()];
+    assert!(rules[0].uses_mains());
+    assert_eq!(apply_rules(&rules, get_classification(Status::Unknown), None), None);
+    assert_eq!(apply_rules(&rules, get_classification(Status::Unknown), Some(false)).unwrap().status, Status::OnBattery);
+  }
+
+  #[test]
+  fn rejects_invalid_rules() {
+    assert!(parse_rule("mains:present").unwrap_err().starts_with("invalid rule"));
+    assert!(parse_rule("mains:present=Conflict").unwrap_err().starts_with("unknown status Conflict"));
+    assert!(parse_rule("mains:maybe=SensorConflict").unwrap_err().starts_with("invalid condition mains:maybe"));
+    assert!(parse_rule("status:Battery=SensorConflict").unwrap_err().starts_with("unknown status Battery"));
+    assert!(parse_rule("load:high=SensorConflict").unwrap_err().starts_with("invalid condition load:high"));
+  }
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use crate::classifier::{BuiltinClassifier, Classifier};
+use crate::logging::info;
+use crate::status::Classification;
+
+// Classifies every pair with a candidate table as well and logs where it disagrees with the active one, so a table can be tried on live beeps
+// before switching to it, only the classification of the active one is ever handed on
+pub struct ShadowClassifier {
+  classifier: Box<dyn Classifier>,
+  candidate: BuiltinClassifier,
+  classification_count: u64,
+  disagreement_count: u64,
+}
+
+impl ShadowClassifier {
+  pub fn new(classifier: Box<dyn Classifier>, candidate: BuiltinClassifier) -> ShadowClassifier {
+    ShadowClassifier { classifier, candidate, classification_count: 0, disagreement_count: 0 }
+  }
+
+  fn compare(&mut self, active: Classification, candidate: Classification) -> Classification {
+    self.classification_count += 1;
+    if let Some(disagreement) = get_disagreement_description(active, candidate) {
+      self.disagreement_count += 1;
+      info!("{} ({} of {} classifications so far)", disagreement, self.disagreement_count, self.classification_count);
+    }
+    active
+  }
+}
+
+// Only the statuses are compared, a candidate with other tolerances is bound to be more or less confident about the same one
+pub fn get_disagreement_description(active: Classification, candidate: Classification) -> Option<String> {
+  if active.status == candidate.status {
+    return None;
+  }
+  Some(format!(
+    "Shadow table disagrees: {:?} with the active table, {:?} with the candidate (beep {}ms, inter beep {}ms)",
+    active.status,
+    candidate.status,
+    active.beep_duration.as_millis(),
+    active.inter_beep_duration.as_millis(),
+  ))
+}
+
+impl Classifier for ShadowClassifier {
+  fn classify(&mut self, beep: Duration, inter_beep: Duration) -> Classification {
+    let active = self.classifier.classify(beep, inter_beep);
+    let candidate = self.candidate.classify(beep, inter_beep);
+    self.compare(active, candidate)
+  }
+
+  fn classify_silence(&mut self, silence: Duration) -> Classification {
+    let active = self.classifier.classify_silence(silence);
+    let candidate = self.candidate.classify_silence(silence);
+    self.compare(active, candidate)
+  }
+
+  // Only the active classifier decides what gets reported
+  fn get_longest_gap(&self) -> Option<Duration> {
+    self.classifier.get_longest_gap()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::status::{MatchConfig, STATUS_BEEP_DURATIONS, Status};
+
+  #[test]
+  fn hands_on_the_active_classification_and_counts_disagreements() {
+    // The candidate moves LowOnBattery to a 1.4s gap and says nothing about silence
+    let mut beep_durations = STATUS_BEEP_DURATIONS.to_vec();
+    beep_durations.retain(|status_beep_duration| status_beep_duration.0 != Status::LowOnBattery);
+    beep_durations.push((Status::LowOnBattery, [Duration::from_millis(250), Duration::from_millis(1400)]));
+    let candidate = BuiltinClassifier::new(MatchConfig { beep_durations, silence_status: None, ..MatchConfig::default() });
+    let mut classifier = ShadowClassifier::new(Box::new(BuiltinClassifier::default()), candidate);
+
+    assert_eq!(classifier.classify(Duration::from_millis(250), Duration::from_secs(60)).status, Status::OnBattery);
+    assert_eq!(classifier.classify(Duration::from_millis(250), Duration::from_secs(1)).status, Status::LowOnBattery);
+    assert_eq!(classifier.classify(Duration::from_millis(250), Duration::from_millis(1400)).status, Status::Unknown);
+    assert_eq!(classifier.classify_silence(Duration::from_secs(5)).status, Status::OnMains);
+    assert_eq!((classifier.disagreement_count, classifier.classification_count), (3, 4));
+  }
+
+  #[test]
+  fn describes_disagreements() {
+    let active = Classification { status: Status::LowOnBattery, confidence: 1.0, beep_duration: Duration::from_millis(250), inter_beep_duration: Duration::from_secs(1) };
+    assert_eq!(get_disagreement_description(active, Classification { confidence: 0.5, ..active }), None);
+    assert_eq!(
+      get_disagreement_description(active, Classification { status: Status::Unknown, ..active }).unwrap(),
+      "Shadow table disagrees: LowOnBattery with the active table, Unknown with the candidate (beep 250ms, inter beep 1000ms)",
+    );
+  }
+}
@@ -0,0 +1,61 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Instant;
+
+use signal_hook::consts::{SIGINT, SIGTERM, SIGUSR1, SIGUSR2};
+use signal_hook::flag;
+use signal_hook::iterator::Signals;
+
+use crate::logging::{info, warn};
+use crate::state::SharedState;
+
+// Dumps the recent transitions and the time per status to stdout on every SIGUSR2, on its own thread so it works even while the detection loop waits on edges,
+// failing to install the handler only warns as detection works the same without it
+pub fn start_history_dump_on_signal(state: SharedState) {
+  let mut signals = match Signals::new([SIGUSR2]) {
+    Ok(signals) => signals,
+    Err(error) => {
+      warn!("Could not listen for SIGUSR2: {}", error);
+      return;
+    }
+  };
+
+  thread::spawn(move || {
+    for _ in signals.forever() {
+      let state = state.lock().unwrap();
+      println!("Recent transitions:\n{}\nTime per status:\n{}", state.get_history_description(), state.get_totals(Instant::now()).get_description());
+    }
+  });
+}
+
+// Pauses reporting on a SIGUSR1 and resumes it on the next one, for maintenance where the expected statuses shouldn't alert anyone
+pub fn start_pause_toggle_on_signal(paused: Arc<AtomicBool>) {
+  let mut signals = match Signals::new([SIGUSR1]) {
+    Ok(signals) => signals,
+    Err(error) => {
+      warn!("Could not listen for SIGUSR1: {}", error);
+      return;
+    }
+  };
+
+  thread::spawn(move || {
+    for _ in signals.forever() {
+      let was_paused = paused.fetch_xor(true, Ordering::Relaxed);
+      info!("{}", if was_paused { "Reporting resumed" } else { "Reporting paused, send SIGUSR1 again to resume" });
+    }
+  });
+}
+
+// Set on a SIGTERM or SIGINT, what systemctl stop and Ctrl+C send, for the detection loop to stop at its next event and return,
+// a second one while it does exits right away, same as without the handler, which failing to install only warns about
+pub fn get_shutdown_flag() -> Arc<AtomicBool> {
+  let shutdown = Arc::new(AtomicBool::new(false));
+  for signal in [SIGTERM, SIGINT] {
+    // The conditional exit is registered first so it only sees the flag as it was before this signal set it
+    if let Err(error) = flag::register_conditional_shutdown(signal, 1, shutdown.clone()).and_then(|_| flag::register(signal, shutdown.clone())) {
+      warn!("Could not listen for signal {}: {}", signal, error);
+    }
+  }
+  shutdown
+}
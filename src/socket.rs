@@ -0,0 +1,129 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::thread;
+use std::time::Instant;
+
+use crate::logging::debug;
+use crate::state::SharedState;
+use crate::status::get_status_description;
+
+// The socket file of a running status socket, removed again when this is dropped
+#[derive(Debug)]
+pub struct StatusSocket {
+  path: String,
+}
+
+impl Drop for StatusSocket {
+  fn drop(&mut self) {
+    let _ = fs::remove_file(&self.path);
+  }
+}
+
+// Answers every connection with the last reported status, its description and how long it has been in it, then closes it,
+// on its own thread so queries never hold up beep timing, one at a time as answering is only a lock and a write
+pub fn start_status_socket(path: &str, state: SharedState) -> Result<StatusSocket, String> {
+  remove_stale_socket(path)?;
+  let listener = UnixListener::bind(path).map_err(|error| format!("could not bind the status socket {}: {}", path, error))?;
+
+  thread::spawn(move || {
+    for mut stream in listener.incoming().flatten() {
+      if let Err(error) = stream.write_all(get_status_text(&state, Instant::now()).as_bytes()) {
+        debug!("Status socket query failed: {}", error);
+      }
+    }
+  });
+  Ok(StatusSocket { path: path.to_string() })
+}
+
+// A socket left behind by a process that didn't shut down cleanly is in the way of binding, one that still answers isn't stale
+fn remove_stale_socket(path: &str) -> Result<(), String> {
+  match fs::symlink_metadata(path) {
+    Ok(metadata) if metadata.file_type().is_socket() => {
+      if UnixStream::connect(path).is_ok() {
+        return Err(format!("the status socket {} is being served by another process", path));
+      }
+      fs::remove_file(path).map_err(|error| format!("could not remove the stale status socket {}: {}", path, error))
+    },
+    Ok(_) => Err(format!("{} is in the way of the status socket, it isn't a socket", path)),
+    Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+    Err(error) => Err(format!("could not check the status socket {}: {}", path, error)),
+  }
+}
+
+// One key=value line each, for reading with a shell as easily as anything else, the status is None until one has been reported
+fn get_status_text(state: &SharedState, now: Instant) -> String {
+  let state = state.lock().unwrap();
+  let Some(current) = &state.current else {
+    return "status=None\n".to_string();
+  };
+  let mut text = String::new();
+  let _ = writeln!(text, "status={:?}", current.to);
+  let _ = writeln!(text, "description={}", get_status_description(current.to));
+  let _ = writeln!(text, "in_state_ms={}", now.saturating_duration_since(current.at_instant).as_millis());
+  text
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Read;
+  use std::time::{Duration, SystemTime};
+  use std::{env, process};
+
+  use crate::guidance::GuidanceTable;
+  use crate::report::Origin;
+  use crate::state::Transition;
+  use crate::status::Status;
+
+  fn get_transition(to: Status, at_instant: Instant) -> Transition {
+    Transition {
+      from: None,
+      to,
+      guidance: GuidanceTable::new(vec![]).get(to),
+      cleared: false,
+      confidence: 1.0,
+      beep_duration: Duration::from_millis(250),
+      inter_beep_duration: Duration::from_secs(60),
+      origin: Origin::Observed,
+      at: SystemTime::now(),
+      at_instant,
+      sequence: 0,
+      in_maintenance_window: false,
+    }
+  }
+
+  #[test]
+  fn describes_the_current_status() {
+    let state = SharedState::default();
+    let now = Instant::now();
+    assert_eq!(get_status_text(&state, now), "status=None\n");
+
+    state.lock().unwrap().record(get_transition(Status::OnBattery, now));
+    assert_eq!(
+      get_status_text(&state, now + Duration::from_millis(1500)),
+      format!("status=OnBattery\ndescription={}\nin_state_ms=1500\n", get_status_description(Status::OnBattery)),
+    );
+  }
+
+  #[test]
+  fn serves_until_dropped_and_replaces_stale_sockets() {
+    let path = env::temp_dir().join(format!("ups-power-status-{}.sock", process::id())).display().to_string();
+    // What a process that was killed leaves behind
+    drop(UnixListener::bind(&path).unwrap());
+
+    let state = SharedState::default();
+    state.lock().unwrap().record(get_transition(Status::OnMains, Instant::now()));
+    let status_socket = start_status_socket(&path, state.clone()).unwrap();
+    assert!(start_status_socket(&path, state).unwrap_err().contains("being served by another process"));
+
+    let mut response = String::new();
+    UnixStream::connect(&path).unwrap().read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("status=OnMains\n"));
+
+    drop(status_socket);
+    assert!(fs::symlink_metadata(&path).is_err());
+  }
+}
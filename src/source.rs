@@ -0,0 +1,40 @@
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use crate::detector::Edge;
+use crate::logging::{info, warn};
+
+pub enum SourceEvent {
+  Edge(Edge, Instant),
+  // No edge arrived within the timeout, carries when the wait ended
+  Timeout(Instant),
+}
+
+// Anything edges can be read from, be it the GPIO pin or a recording of it
+pub trait EdgeSource {
+  // Waits up to timeout for the next edge, returns None once the source has no more edges to give
+  fn next_event(&mut self, timeout: Duration) -> Option<SourceEvent>;
+}
+
+// Warns once when reading a source starts failing and once when it works again, rather than on every read of a line that keeps failing,
+// a failed read is treated as one that found no edge so detection carries on through a spurious error
+#[derive(Default)]
+pub struct ReadFailures {
+  is_failing: bool,
+}
+
+impl ReadFailures {
+  pub fn on_failure(&mut self, error: impl Display) {
+    if !self.is_failing {
+      warn!("{}, carrying on without edges until it works again", error);
+      self.is_failing = true;
+    }
+  }
+
+  pub fn on_success(&mut self) {
+    if self.is_failing {
+      info!("Reading edges works again");
+      self.is_failing = false;
+    }
+  }
+}
@@ -0,0 +1,387 @@
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::ptr;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, channel};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::logging::error;
+use crate::state::{SharedState, Transition};
+
+// Bound by hand against the system's libsqlite3 like everything else talking to the outside here, only the handful of calls the history needs
+#[link(name = "sqlite3")]
+unsafe extern "C" {
+  fn sqlite3_open_v2(filename: *const c_char, db: *mut *mut c_void, flags: c_int, vfs: *const c_char) -> c_int;
+  fn sqlite3_close(db: *mut c_void) -> c_int;
+  fn sqlite3_errmsg(db: *mut c_void) -> *const c_char;
+  fn sqlite3_exec(db: *mut c_void, sql: *const c_char, callback: *const c_void, argument: *mut c_void, error: *mut *mut c_char) -> c_int;
+  fn sqlite3_prepare_v2(db: *mut c_void, sql: *const c_char, length: c_int, statement: *mut *mut c_void, tail: *mut *const c_char) -> c_int;
+  fn sqlite3_bind_int64(statement: *mut c_void, index: c_int, value: i64) -> c_int;
+  fn sqlite3_bind_double(statement: *mut c_void, index: c_int, value: f64) -> c_int;
+  fn sqlite3_bind_text(statement: *mut c_void, index: c_int, value: *const c_char, length: c_int, destructor: isize) -> c_int;
+  fn sqlite3_bind_null(statement: *mut c_void, index: c_int) -> c_int;
+  fn sqlite3_step(statement: *mut c_void) -> c_int;
+  fn sqlite3_reset(statement: *mut c_void) -> c_int;
+  fn sqlite3_column_int64(statement: *mut c_void, column: c_int) -> i64;
+  fn sqlite3_finalize(statement: *mut c_void) -> c_int;
+}
+
+const SQLITE_OK: c_int = 0;
+const SQLITE_ROW: c_int = 100;
+const SQLITE_DONE: c_int = 101;
+const SQLITE_OPEN_READWRITE: c_int = 0x2;
+const SQLITE_OPEN_CREATE: c_int = 0x4;
+// Has SQLite copy a bound text right away, so it doesn't have to outlive the binding
+const SQLITE_TRANSIENT: isize = -1;
+
+// Applied in order to bring a database up to date, the user_version pragma of a database counts how many it has had already,
+// a later version only ever adds to the end of this
+const MIGRATIONS: [&str; 2] = [
+  "CREATE TABLE transitions (
+    at_ms INTEGER NOT NULL,
+    from_status TEXT,
+    to_status TEXT NOT NULL,
+    confidence REAL NOT NULL,
+    beep_ms INTEGER NOT NULL,
+    inter_beep_ms INTEGER NOT NULL,
+    origin TEXT NOT NULL
+  );
+  CREATE INDEX transitions_at_ms ON transitions (at_ms);
+  CREATE TABLE samples (
+    at_ms INTEGER NOT NULL,
+    beep_ms INTEGER NOT NULL,
+    inter_beep_ms INTEGER NOT NULL
+  );
+  CREATE INDEX samples_at_ms ON samples (at_ms);",
+  // Numbered per process, so only telling apart transitions written within the same run, null for those from before
+  "ALTER TABLE transitions ADD COLUMN sequence INTEGER;",
+];
+
+// Records are written in one transaction once this many are waiting or the oldest of them has waited this long,
+// a transaction per beep would have the SD card of a Raspberry Pi syncing all day
+const BATCH_SIZE: usize = 64;
+const BATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Connection {
+  db: *mut c_void,
+}
+
+// Only ever used from the one thread writing the history
+unsafe impl Send for Connection {}
+
+impl Connection {
+  fn open(path: &str) -> Result<Connection, String> {
+    let filename = CString::new(path).map_err(|_| format!("invalid database path {}", path))?;
+    let mut db = ptr::null_mut();
+    let result = unsafe { sqlite3_open_v2(filename.as_ptr(), &mut db, SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE, ptr::null()) };
+    let connection = Connection { db };
+    if result != SQLITE_OK {
+      return Err(format!("could not open database {}: {}", path, connection.get_error()));
+    }
+    Ok(connection)
+  }
+
+  fn get_error(&self) -> String {
+    if self.db.is_null() {
+      return "out of memory".to_string();
+    }
+    unsafe { CStr::from_ptr(sqlite3_errmsg(self.db)) }.to_string_lossy().into_owned()
+  }
+
+  fn execute(&self, sql: &str) -> Result<(), String> {
+    let sql = CString::new(sql).unwrap();
+    let result = unsafe { sqlite3_exec(self.db, sql.as_ptr(), ptr::null(), ptr::null_mut(), ptr::null_mut()) };
+    if result != SQLITE_OK {
+      return Err(self.get_error());
+    }
+    Ok(())
+  }
+
+  fn prepare(&self, sql: &str) -> Result<Statement<'_>, String> {
+    let sql = CString::new(sql).unwrap();
+    let mut statement = ptr::null_mut();
+    let result = unsafe { sqlite3_prepare_v2(self.db, sql.as_ptr(), -1, &mut statement, ptr::null_mut()) };
+    if result != SQLITE_OK {
+      return Err(self.get_error());
+    }
+    Ok(Statement { connection: self, statement })
+  }
+
+  // The first column of the first row, for the pragmas and counts that only ever have the one
+  fn query_integer(&self, sql: &str) -> Result<i64, String> {
+    let statement = self.prepare(sql)?;
+    match unsafe { sqlite3_step(statement.statement) } {
+      SQLITE_ROW => Ok(unsafe { sqlite3_column_int64(statement.statement, 0) }),
+      _ => Err(self.get_error()),
+    }
+  }
+
+  // Runs every migration the database hasn't had yet, each in a transaction of its own along with bumping the version,
+  // a database from a later version of this is left alone rather than written rows it may not expect
+  fn migrate(&self) -> Result<(), String> {
+    let version = self.query_integer("PRAGMA user_version")?;
+    if version > MIGRATIONS.len() as i64 {
+      return Err(format!("database is at schema version {}, newer than the {} this version knows", version, MIGRATIONS.len()));
+    }
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+      self.execute(&format!("BEGIN; {} PRAGMA user_version = {}; COMMIT;", migration, index + 1)).map_err(|error| {
+        let _ = self.execute("ROLLBACK");
+        format!("could not migrate database to schema version {}: {}", index + 1, error)
+      })?;
+    }
+    Ok(())
+  }
+}
+
+impl Drop for Connection {
+  fn drop(&mut self) {
+    unsafe { sqlite3_close(self.db) };
+  }
+}
+
+struct Statement<'a> {
+  connection: &'a Connection,
+  statement: *mut c_void,
+}
+
+// A value bound to a parameter of a statement, numbered from 1 in the order they are given
+enum Value<'a> {
+  Integer(i64),
+  Real(f64),
+  Text(Option<&'a str>),
+}
+
+impl Statement<'_> {
+  fn execute(&self, values: &[Value]) -> Result<(), String> {
+    for (index, value) in values.iter().enumerate() {
+      let index = index as c_int + 1;
+      unsafe {
+        match value {
+          Value::Integer(value) => sqlite3_bind_int64(self.statement, index, *value),
+          Value::Real(value) => sqlite3_bind_double(self.statement, index, *value),
+          Value::Text(Some(value)) => sqlite3_bind_text(self.statement, index, value.as_ptr() as *const c_char, value.len() as c_int, SQLITE_TRANSIENT),
+          Value::Text(None) => sqlite3_bind_null(self.statement, index),
+        };
+      }
+    }
+    let result = unsafe { sqlite3_step(self.statement) };
+    unsafe { sqlite3_reset(self.statement) };
+    if result != SQLITE_DONE {
+      return Err(self.connection.get_error());
+    }
+    Ok(())
+  }
+}
+
+impl Drop for Statement<'_> {
+  fn drop(&mut self) {
+    unsafe { sqlite3_finalize(self.statement) };
+  }
+}
+
+// Everything the history gets written, merged from the transition and measurement subscriptions into one channel
+enum Record {
+  Transition(Transition),
+  Sample(SystemTime, Duration, Duration),
+}
+
+// Keeps every transition and, when measurements are published, every measured beep and inter beep pair in a SQLite database,
+// creating or migrating its schema right away so a database that can't be used fails the startup instead of the first write,
+// the returned thread writes the batch still waiting and ends once the subscriptions are closed, only when the process is killed is it lost
+pub fn start_sqlite_history(path: &str, state: SharedState) -> Result<JoinHandle<()>, String> {
+  let connection = Connection::open(path)?;
+  connection.migrate().map_err(|error| format!("{} in {}", error, path))?;
+
+  let (sender, records) = channel();
+  let (transitions, measurements) = {
+    let mut state = state.lock().unwrap();
+    (state.subscribe(), state.subscribe_to_measurements())
+  };
+  forward(transitions, sender.clone(), Record::Transition);
+  forward(measurements, sender, |(beep, inter_beep)| Record::Sample(SystemTime::now(), beep, inter_beep));
+
+  let path = path.to_string();
+  Ok(thread::spawn(move || write_history(&connection, &path, records)))
+}
+
+fn forward<T: Send + 'static>(receiver: Receiver<T>, sender: Sender<Record>, to_record: fn(T) -> Record) {
+  thread::spawn(move || {
+    for item in receiver {
+      if sender.send(to_record(item)).is_err() {
+        return;
+      }
+    }
+  });
+}
+
+fn write_history(connection: &Connection, path: &str, records: Receiver<Record>) {
+  let mut batch = Vec::new();
+  let mut batch_started_at = Instant::now();
+  loop {
+    let timeout = if batch.is_empty() { BATCH_INTERVAL } else { BATCH_INTERVAL.saturating_sub(batch_started_at.elapsed()) };
+    let is_disconnected = match records.recv_timeout(timeout) {
+      Ok(record) => {
+        if batch.is_empty() {
+          batch_started_at = Instant::now();
+        }
+        batch.push(record);
+        false
+      },
+      Err(RecvTimeoutError::Timeout) => false,
+      Err(RecvTimeoutError::Disconnected) => true,
+    };
+    if !batch.is_empty() && (is_disconnected || batch.len() >= BATCH_SIZE || batch_started_at.elapsed() >= BATCH_INTERVAL) {
+      // A batch that fails to be written is dropped rather than retried, so a full disk doesn't pile everything up in memory
+      if let Err(error) = write_batch(connection, &batch) {
+        error!("Could not write {} records to {}: {}", batch.len(), path, error);
+      }
+      batch.clear();
+    }
+    if is_disconnected {
+      return;
+    }
+  }
+}
+
+fn write_batch(connection: &Connection, batch: &[Record]) -> Result<(), String> {
+  connection.execute("BEGIN")?;
+  let result = insert_records(connection, batch);
+  connection.execute(if result.is_ok() { "COMMIT" } else { "ROLLBACK" })?;
+  result
+}
+
+fn insert_records(connection: &Connection, batch: &[Record]) -> Result<(), String> {
+  let insert_transition = connection.prepare(
+    "INSERT INTO transitions (at_ms, from_status, to_status, confidence, beep_ms, inter_beep_ms, origin, sequence) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+  )?;
+  let insert_sample = connection.prepare("INSERT INTO samples (at_ms, beep_ms, inter_beep_ms) VALUES (?, ?, ?)")?;
+  for record in batch {
+    match record {
+      Record::Transition(transition) => {
+        let from_status = transition.from.map(|status| format!("{:?}", status));
+        insert_transition.execute(&[
+          Value::Integer(get_unix_millis(transition.at)),
+          Value::Text(from_status.as_deref()),
+          Value::Text(Some(&format!("{:?}", transition.to))),
+          Value::Real(transition.confidence),
+          Value::Integer(transition.beep_duration.as_millis() as i64),
+          Value::Integer(transition.inter_beep_duration.as_millis() as i64),
+          Value::Text(Some(transition.origin.name())),
+          Value::Integer(transition.sequence as i64),
+        ])?;
+      },
+      Record::Sample(at, beep, inter_beep) => insert_sample.execute(&[
+        Value::Integer(get_unix_millis(*at)),
+        Value::Integer(beep.as_millis() as i64),
+        Value::Integer(inter_beep.as_millis() as i64),
+      ])?,
+    }
+  }
+  Ok(())
+}
+
+fn get_unix_millis(time: SystemTime) -> i64 {
+  time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::fs;
+  use std::sync::{Arc, Mutex};
+
+  use crate::guidance::GuidanceTable;
+  use crate::report::Origin;
+  use crate::state::StatusState;
+  use crate::status::Status;
+
+  fn get_database_path(name: &str) -> String {
+    let path = env::temp_dir().join(format!("ups-power-status-from-beeps-{}-{}.sqlite", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path.to_str().unwrap().to_string()
+  }
+
+  fn get_transition(from: Option<Status>, to: Status) -> Transition {
+    Transition {
+      from,
+      to,
+      guidance: GuidanceTable::new(vec![]).get(to),
+      cleared: false,
+      confidence: 0.75,
+      beep_duration: Duration::from_millis(250),
+      inter_beep_duration: Duration::from_secs(30),
+      origin: Origin::Observed,
+      at: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+      at_instant: Instant::now(),
+      sequence: 0,
+      in_maintenance_window: false,
+    }
+  }
+
+  #[test]
+  fn creates_the_schema_once() {
+    let path = get_database_path("schema");
+    let connection = Connection::open(&path).unwrap();
+    connection.migrate().unwrap();
+    assert_eq!(connection.query_integer("PRAGMA user_version").unwrap(), MIGRATIONS.len() as i64);
+    // Opening it again finds it up to date instead of creating the tables twice
+    connection.migrate().unwrap();
+    assert_eq!(connection.query_integer("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table'").unwrap(), 2);
+
+    connection.execute("PRAGMA user_version = 99").unwrap();
+    assert!(connection.migrate().unwrap_err().starts_with("database is at schema version 99"));
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn migrates_databases_of_older_versions() {
+    let path = get_database_path("migrate");
+    let connection = Connection::open(&path).unwrap();
+    connection.execute(&format!("{} PRAGMA user_version = 1;", MIGRATIONS[0])).unwrap();
+    connection.execute("INSERT INTO transitions (at_ms, to_status, confidence, beep_ms, inter_beep_ms, origin) VALUES (1, 'OnMains', 1.0, 0, 0, 'inferred')").unwrap();
+    connection.migrate().unwrap();
+    write_batch(&connection, &[Record::Transition(Transition { sequence: 7, ..get_transition(None, Status::OnBattery) })]).unwrap();
+    assert_eq!(connection.query_integer("SELECT COUNT(*) FROM transitions WHERE sequence IS NULL").unwrap(), 1);
+    assert_eq!(connection.query_integer("SELECT sequence FROM transitions WHERE to_status = 'OnBattery'").unwrap(), 7);
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn writes_batches_in_one_transaction() {
+    let path = get_database_path("batch");
+    let connection = Connection::open(&path).unwrap();
+    connection.migrate().unwrap();
+    write_batch(&connection, &[
+      Record::Transition(get_transition(None, Status::OnMains)),
+      Record::Sample(UNIX_EPOCH + Duration::from_secs(1_700_000_030), Duration::from_millis(250), Duration::from_secs(30)),
+      Record::Transition(get_transition(Some(Status::OnMains), Status::OnBattery)),
+    ]).unwrap();
+    assert_eq!(connection.query_integer("SELECT COUNT(*) FROM transitions WHERE from_status IS NULL AND to_status = 'OnMains'").unwrap(), 1);
+    assert_eq!(connection.query_integer("SELECT COUNT(*) FROM transitions WHERE from_status = 'OnMains' AND origin = 'observed'").unwrap(), 1);
+    assert_eq!(connection.query_integer("SELECT at_ms FROM samples WHERE beep_ms = 250 AND inter_beep_ms = 30000").unwrap(), 1_700_000_030_000);
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn writes_what_the_state_records() {
+    let path = get_database_path("state");
+    let state = Arc::new(Mutex::new(StatusState::new(10)));
+    let writer = start_sqlite_history(&path, state.clone()).unwrap();
+    state.lock().unwrap().record(get_transition(None, Status::LowOnBattery));
+    state.lock().unwrap().publish_measurement(Duration::from_millis(250), Duration::from_secs(1));
+
+    // Nowhere near a full batch or its interval, so it only lands as the writer finishes
+    state.lock().unwrap().close_subscriptions();
+    writer.join().unwrap();
+    let connection = Connection::open(&path).unwrap();
+    assert_eq!(connection.query_integer("SELECT COUNT(*) FROM transitions WHERE to_status = 'LowOnBattery' AND sequence = 1").unwrap(), 1);
+    assert_eq!(connection.query_integer("SELECT COUNT(*) FROM samples").unwrap(), 1);
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn unusable_databases_fail_the_startup() {
+    let state = Arc::new(Mutex::new(StatusState::new(10)));
+    assert!(start_sqlite_history("/nonexistent/history.sqlite", state).unwrap_err().starts_with("could not open database /nonexistent/history.sqlite"));
+  }
+}
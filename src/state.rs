@@ -0,0 +1,288 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::guidance::Guidance;
+use crate::report::Origin;
+use crate::stats::StatusTotals;
+use crate::status::Status;
+
+pub const DEFAULT_MAX_TRANSITIONS: usize = 50;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Transition {
+  pub from: Option<Status>,
+  pub to: Status,
+  pub guidance: Guidance,
+  // Whether this transition leaves a critical status for one that isn't
+  pub cleared: bool,
+  pub confidence: f64,
+  pub beep_duration: Duration,
+  pub inter_beep_duration: Duration,
+  pub origin: Origin,
+  // Wall clock time for displaying, durations are only ever measured from the Instant alongside
+  pub at: SystemTime,
+  pub at_instant: Instant,
+  // Numbered from 1 in the order recorded, a consumer of a stream of them can tell one went missing or came out of order,
+  // starts over with the process
+  pub sequence: u64,
+  // Recorded during a maintenance window, and so not reported as it happened
+  pub in_maintenance_window: bool,
+}
+
+// A stretch of time spent in one status, as drawn by a state timeline
+#[cfg(feature = "http")]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct StatusInterval {
+  pub status: Status,
+  pub start: SystemTime,
+  pub end: SystemTime,
+}
+
+// What the reporter has committed so far, shared with anything serving it outside of the detection loop
+pub struct StatusState {
+  pub current: Option<Transition>,
+  // Oldest first, at most max_transitions of them
+  pub transitions: VecDeque<Transition>,
+  max_transitions: usize,
+  // Time spent in every status up to the current one, whichever way it was detected
+  pub totals: StatusTotals,
+  // Given to the last transition recorded
+  last_sequence: u64,
+  // The beep and inter beep durations of the last pair the edges actually showed, whatever it classified as
+  pub last_measurement: Option<(Duration, Duration)>,
+
+  subscribers: Vec<Sender<Transition>>,
+  measurement_subscribers: Vec<Sender<(Duration, Duration)>>,
+}
+
+pub type SharedState = Arc<Mutex<StatusState>>;
+
+impl Default for StatusState {
+  fn default() -> StatusState {
+    StatusState::new(DEFAULT_MAX_TRANSITIONS)
+  }
+}
+
+impl StatusState {
+  pub fn new(max_transitions: usize) -> StatusState {
+    StatusState {
+      current: None,
+      transitions: VecDeque::with_capacity(max_transitions),
+      max_transitions,
+      totals: StatusTotals::default(),
+      last_sequence: 0,
+      last_measurement: None,
+      subscribers: vec![],
+      measurement_subscribers: vec![],
+    }
+  }
+
+  // Returns the transition as recorded, numbered in sequence
+  pub fn record(&mut self, mut transition: Transition) -> Transition {
+    self.last_sequence += 1;
+    transition.sequence = self.last_sequence;
+    if let Some(current) = self.current {
+      self.totals.add(current.to, transition.at_instant.duration_since(current.at_instant));
+    }
+    // A test event would otherwise carry over a restart as if it were real
+    if transition.origin != Origin::Test {
+      self.totals.last_status = Some(transition.to);
+    }
+    self.current = Some(transition);
+    self.transitions.push_back(transition);
+    if self.transitions.len() > self.max_transitions {
+      self.transitions.pop_front();
+    }
+
+    // Subscribers that went away are dropped along the way
+    self.subscribers.retain(|subscriber| subscriber.send(transition).is_ok());
+    transition
+  }
+
+  // The totals including the time in the current status so far
+  pub fn get_totals(&self, now: Instant) -> StatusTotals {
+    let mut totals = self.totals.clone();
+    if let Some(current) = self.current {
+      totals.add(current.to, now.duration_since(current.at_instant));
+    }
+    totals
+  }
+
+  // One line per recent transition, oldest first, as dumped on request
+  pub fn get_history_description(&self) -> String {
+    let lines: Vec<String> = self.transitions.iter().map(|transition| format!(
+      "{}s ago: {} -> {:?} (beep {}ms, inter beep {}ms, confidence {:.2}, {})",
+      transition.at_instant.elapsed().as_secs(),
+      transition.from.map(|from| format!("{:?}", from)).unwrap_or_else(|| "none".to_string()),
+      transition.to,
+      transition.beep_duration.as_millis(),
+      transition.inter_beep_duration.as_millis(),
+      transition.confidence,
+      transition.origin.name(),
+    )).collect();
+    lines.join("\n")
+  }
+
+  // The recent transitions as the intervals between them, oldest first, the current status lasting until now,
+  // only as far back as the oldest transition still kept
+  #[cfg(feature = "http")]
+  pub fn get_timeline(&self, now: SystemTime) -> Vec<StatusInterval> {
+    let ends = self.transitions.iter().skip(1).map(|transition| transition.at).chain([now]);
+    self.transitions.iter().zip(ends)
+      .map(|(transition, end)| StatusInterval { status: transition.to, start: transition.at, end: end.max(transition.at) })
+      .collect()
+  }
+
+  #[cfg(all(test, feature = "http"))]
+  pub fn subscribers_count(&self) -> usize {
+    self.subscribers.len()
+  }
+
+  // Every transition recorded from now on gets sent to the returned receiver
+  pub fn subscribe(&mut self) -> Receiver<Transition> {
+    let (sender, receiver) = channel();
+    self.subscribers.push(sender);
+    receiver
+  }
+
+  // Measurements aren't kept, they only go to whoever is subscribed at the time
+  pub fn publish_measurement(&mut self, beep: Duration, inter_beep: Duration) {
+    self.measurement_subscribers.retain(|subscriber| subscriber.send((beep, inter_beep)).is_ok());
+  }
+
+  #[cfg(test)]
+  pub fn measurement_subscribers_count(&self) -> usize {
+    self.measurement_subscribers.len()
+  }
+
+  // Every beep and inter beep pair published from now on gets sent to the returned receiver
+  pub fn subscribe_to_measurements(&mut self) -> Receiver<(Duration, Duration)> {
+    let (sender, receiver) = channel();
+    self.measurement_subscribers.push(sender);
+    receiver
+  }
+
+  // Every receiver sees its channel disconnected once it has taken what was sent before, which is how the ones writing it all out know to finish
+  pub fn close_subscriptions(&mut self) {
+    self.subscribers.clear();
+    self.measurement_subscribers.clear();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::guidance::GuidanceTable;
+
+  fn get_transition(to: Status) -> Transition {
+    Transition {
+      from: None,
+      to,
+      guidance: GuidanceTable::new(vec![]).get(to),
+      cleared: false,
+      confidence: 1.0,
+      beep_duration: Duration::from_millis(250),
+      inter_beep_duration: Duration::from_secs(60),
+      origin: Origin::Observed,
+      at: SystemTime::now(),
+      at_instant: Instant::now(),
+      sequence: 0,
+      in_maintenance_window: false,
+    }
+  }
+
+  #[test]
+  fn keeps_only_recent_transitions() {
+    let mut state = StatusState::new(5);
+    for _ in 0..5 {
+      state.record(get_transition(Status::OnBattery));
+    }
+    state.record(get_transition(Status::OnMains));
+    assert_eq!(state.transitions.len(), 5);
+    assert_eq!(state.transitions.back().unwrap().to, Status::OnMains);
+    assert_eq!(state.current.unwrap().to, Status::OnMains);
+  }
+
+  #[test]
+  fn numbers_transitions_in_sequence() {
+    let mut state = StatusState::new(2);
+    let subscription = state.subscribe();
+    assert_eq!(state.record(get_transition(Status::OnBattery)).sequence, 1);
+    state.record(get_transition(Status::OnMains));
+    state.record(get_transition(Status::OnBattery));
+    // Still counting from the first one once it's no longer kept
+    assert_eq!(state.transitions.iter().map(|transition| transition.sequence).collect::<Vec<_>>(), vec![2, 3]);
+    assert_eq!(subscription.try_iter().map(|transition| transition.sequence).collect::<Vec<_>>(), vec![1, 2, 3]);
+  }
+
+  #[test]
+  fn totals_time_in_every_status() {
+    let mut state = StatusState::default();
+    let start = Instant::now();
+    state.record(Transition { at_instant: start, ..get_transition(Status::OnMains) });
+    state.record(Transition { at_instant: start + Duration::from_secs(60), ..get_transition(Status::OnBattery) });
+    state.record(Transition { at_instant: start + Duration::from_secs(80), ..get_transition(Status::OnMains) });
+
+    let totals = state.get_totals(start + Duration::from_secs(100));
+    assert_eq!(totals.get(Status::OnMains), Duration::from_secs(80));
+    assert_eq!(totals.get(Status::OnBattery), Duration::from_secs(20));
+    assert_eq!(state.totals.get(Status::OnMains), Duration::from_secs(60));
+  }
+
+  #[test]
+  fn describes_history_oldest_first() {
+    let mut state = StatusState::default();
+    state.record(get_transition(Status::OnBattery));
+    state.record(Transition { from: Some(Status::OnBattery), ..get_transition(Status::OnMains) });
+    assert_eq!(
+      state.get_history_description(),
+      "0s ago: none -> OnBattery (beep 250ms, inter beep 60000ms, confidence 1.00, observed)\n0s ago: OnBattery -> OnMains (beep 250ms, inter beep 60000ms, confidence 1.00, observed)",
+    );
+  }
+
+  #[cfg(feature = "http")]
+  #[test]
+  fn turns_transitions_into_intervals() {
+    let mut state = StatusState::new(2);
+    let start = SystemTime::now();
+    assert_eq!(state.get_timeline(start), vec![]);
+
+    state.record(Transition { at: start, ..get_transition(Status::LowOnBattery) });
+    state.record(Transition { at: start + Duration::from_secs(60), ..get_transition(Status::OnBattery) });
+    state.record(Transition { at: start + Duration::from_secs(80), ..get_transition(Status::OnMains) });
+    assert_eq!(state.get_timeline(start + Duration::from_secs(100)), vec![
+      StatusInterval { status: Status::OnBattery, start: start + Duration::from_secs(60), end: start + Duration::from_secs(80) },
+      StatusInterval { status: Status::OnMains, start: start + Duration::from_secs(80), end: start + Duration::from_secs(100) },
+    ]);
+    // A wall clock stepping back never makes an interval end before it starts
+    assert_eq!(state.get_timeline(start).last().unwrap().end, start + Duration::from_secs(80));
+  }
+
+  #[test]
+  fn sends_transitions_to_subscribers_until_they_go_away() {
+    let mut state = StatusState::default();
+    let receiver = state.subscribe();
+    let dropped_receiver = state.subscribe();
+    drop(dropped_receiver);
+
+    state.record(get_transition(Status::OnBattery));
+    assert_eq!(receiver.try_recv().unwrap().to, Status::OnBattery);
+    assert_eq!(state.subscribers.len(), 1);
+  }
+
+  #[test]
+  fn closed_subscriptions_still_deliver_what_was_sent() {
+    let mut state = StatusState::default();
+    let receiver = state.subscribe();
+    let measurements = state.subscribe_to_measurements();
+    state.record(get_transition(Status::OnBattery));
+    state.close_subscriptions();
+
+    assert_eq!(receiver.recv().unwrap().to, Status::OnBattery);
+    assert!(receiver.recv().is_err());
+    assert!(measurements.recv().is_err());
+  }
+}
@@ -0,0 +1,225 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use crate::status::{Status, get_status_from_name};
+
+// How far into the tolerance the matches of a status landed, 0 at the target and 1 at the edge of the tolerance,
+// a mean creeping up towards 1 means the tolerance is too tight for that UPS and misses are close
+#[derive(PartialEq, Clone, Copy, Default, Debug)]
+pub struct MatchDistances {
+  pub count: u64,
+  sum: f64,
+  sum_of_squares: f64,
+}
+
+impl MatchDistances {
+  fn add(&mut self, distance: f64) {
+    self.count += 1;
+    self.sum += distance;
+    self.sum_of_squares += distance * distance;
+  }
+
+  pub fn get_mean(&self) -> f64 {
+    if self.count == 0 { 0.0 } else { self.sum / self.count as f64 }
+  }
+
+  // Of the whole population of matches rather than a sample of them, clamped as rounding can take the variance just below zero
+  pub fn get_standard_deviation(&self) -> f64 {
+    if self.count == 0 {
+      return 0.0;
+    }
+    let mean = self.get_mean();
+    (self.sum_of_squares / self.count as f64 - mean * mean).max(0.0).sqrt()
+  }
+}
+
+// Cumulative time spent in every status, since the start or across restarts when persisted,
+// along with how well the patterns of every status matched
+#[derive(PartialEq, Clone, Default, Debug)]
+pub struct StatusTotals {
+  totals: Vec<(Status, Duration)>,
+  match_distances: Vec<(Status, MatchDistances)>,
+  // The status reported last, for carrying it over a restart
+  pub last_status: Option<Status>,
+}
+
+impl StatusTotals {
+  pub fn add(&mut self, status: Status, duration: Duration) {
+    match self.totals.iter_mut().find(|status_total| status_total.0 == status) {
+      Some(status_total) => status_total.1 += duration,
+      None => self.totals.push((status, duration)),
+    }
+  }
+
+  pub fn get(&self, status: Status) -> Duration {
+    self.totals.iter()
+      .find(|status_total| status_total.0 == status)
+      .map(|status_total| status_total.1)
+      .unwrap_or_default()
+  }
+
+  pub fn add_match_distance(&mut self, status: Status, distance: f64) {
+    match self.match_distances.iter_mut().find(|status_match_distances| status_match_distances.0 == status) {
+      Some(status_match_distances) => status_match_distances.1.add(distance),
+      None => {
+        let mut match_distances = MatchDistances::default();
+        match_distances.add(distance);
+        self.match_distances.push((status, match_distances));
+      },
+    }
+  }
+
+  #[cfg(feature = "http")]
+  pub fn iter(&self) -> impl Iterator<Item = &(Status, Duration)> {
+    self.totals.iter()
+  }
+
+  // Share of the time on mains without any issue, time in Unknown says nothing either way and is left out,
+  // None until there is any other time to compare against
+  pub fn get_availability(&self) -> Option<f64> {
+    let known_duration: Duration = self.totals.iter()
+      .filter(|status_total| status_total.0 != Status::Unknown)
+      .map(|status_total| status_total.1)
+      .sum();
+    (!known_duration.is_zero()).then(|| self.get(Status::OnMains).as_secs_f64() / known_duration.as_secs_f64())
+  }
+
+  pub fn get_description(&self) -> String {
+    let mut lines: Vec<String> = self.totals.iter()
+      .map(|status_total| format!("{:?}: {}s", status_total.0, status_total.1.as_secs()))
+      .collect();
+    lines.push(match self.get_availability() {
+      Some(availability) => format!("Availability: {:.3}%", availability * 100.0),
+      None => "Availability: unknown".to_string(),
+    });
+    if !self.match_distances.is_empty() {
+      lines.push("Match distance per status, 0 at the target and 1 at the edge of the tolerance:".to_string());
+      lines.extend(self.match_distances.iter().map(|status_match_distances| format!(
+        "{:?}: {} matches, mean {:.3}, stddev {:.3}",
+        status_match_distances.0,
+        status_match_distances.1.count,
+        status_match_distances.1.get_mean(),
+        status_match_distances.1.get_standard_deviation(),
+      )));
+    }
+    lines.join("\n")
+  }
+
+  // A file that doesn't exist yet is just no time tracked so far
+  pub fn load(path: &str) -> Result<StatusTotals, String> {
+    match fs::read_to_string(path) {
+      Ok(contents) => parse_totals(&contents).map_err(|error| format!("{} in {}", error, path)),
+      Err(error) if error.kind() == ErrorKind::NotFound => Ok(StatusTotals::default()),
+      Err(error) => Err(format!("could not read {}: {}", path, error)),
+    }
+  }
+
+  // Written to a temporary file first so a crash halfway through never leaves a truncated file behind
+  pub fn save(&self, path: &str) -> Result<(), String> {
+    let temporary_path = format!("{}.tmp", path);
+    fs::write(&temporary_path, get_totals_contents(self))
+      .and_then(|_| fs::rename(&temporary_path, path))
+      .map_err(|error| format!("could not write {}: {}", path, error))
+  }
+}
+
+// One "<status> <milliseconds>" line per status, then one "match <status> <count> <sum> <sum of squares>" line per matched status,
+// then a "last <status>" line once there is a status
+fn get_totals_contents(totals: &StatusTotals) -> String {
+  let time_lines = totals.totals.iter()
+    .map(|status_total| format!("{:?} {}\n", status_total.0, status_total.1.as_millis()));
+  let match_lines = totals.match_distances.iter()
+    .map(|(status, match_distances)| format!("match {:?} {} {} {}\n", status, match_distances.count, match_distances.sum, match_distances.sum_of_squares));
+  let last_status_line = totals.last_status.map(|last_status| format!("last {:?}\n", last_status));
+  time_lines.chain(match_lines).chain(last_status_line).collect()
+}
+
+fn parse_totals(contents: &str) -> Result<StatusTotals, String> {
+  let mut totals = StatusTotals::default();
+  for (index, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+    let invalid_line = || format!("invalid line {}", index + 1);
+    if let Some(status_name) = line.trim().strip_prefix("last ") {
+      totals.last_status = Some(get_status_from_name(status_name).ok_or_else(invalid_line)?);
+      continue;
+    }
+    if let Some(match_line) = line.trim().strip_prefix("match ") {
+      let fields: Vec<&str> = match_line.split(' ').collect();
+      let [status_name, count, sum, sum_of_squares] = fields[..] else {
+        return Err(invalid_line());
+      };
+      let status = get_status_from_name(status_name).ok_or_else(invalid_line)?;
+      let match_distances = MatchDistances {
+        count: count.parse().map_err(|_| invalid_line())?,
+        sum: sum.parse().map_err(|_| invalid_line())?,
+        sum_of_squares: sum_of_squares.parse().map_err(|_| invalid_line())?,
+      };
+      totals.match_distances.push((status, match_distances));
+      continue;
+    }
+    let (status_name, millis) = line.trim().split_once(' ').ok_or_else(invalid_line)?;
+    let status = get_status_from_name(status_name).ok_or_else(invalid_line)?;
+    let millis = millis.parse().map_err(|_| invalid_line())?;
+    totals.add(status, Duration::from_millis(millis));
+  }
+  Ok(totals)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::process;
+
+  #[test]
+  fn accumulates_per_status() {
+    let mut totals = StatusTotals::default();
+    assert_eq!(totals.get_availability(), None);
+
+    totals.add(Status::OnMains, Duration::from_secs(90));
+    totals.add(Status::OnBattery, Duration::from_secs(5));
+    totals.add(Status::LowOnBattery, Duration::from_secs(5));
+    totals.add(Status::Unknown, Duration::from_secs(100));
+    totals.add(Status::OnMains, Duration::from_secs(0));
+    assert_eq!(totals.get(Status::OnMains), Duration::from_secs(90));
+    assert_eq!(totals.get_availability(), Some(0.9));
+    assert!(totals.get_description().ends_with("Availability: 90.000%"));
+  }
+
+  #[test]
+  fn round_trips_through_file() {
+    let path = env::temp_dir().join(format!("ups-power-status-stats-{}", process::id()));
+    let path = path.to_str().unwrap();
+    assert_eq!(StatusTotals::load(path).unwrap(), StatusTotals::default());
+
+    let mut totals = StatusTotals::default();
+    totals.add(Status::OnMains, Duration::from_millis(1500));
+    totals.add(Status::PowerOff, Duration::from_secs(60));
+    totals.add_match_distance(Status::LowOnBattery, 0.25);
+    totals.add_match_distance(Status::LowOnBattery, 0.1);
+    totals.last_status = Some(Status::OnBattery);
+    totals.save(path).unwrap();
+    assert_eq!(StatusTotals::load(path).unwrap(), totals);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn sums_up_match_distances_per_status() {
+    let mut totals = StatusTotals::default();
+    for distance in [0.1, 0.3, 0.2, 0.4] {
+      totals.add_match_distance(Status::LowOnBattery, distance);
+    }
+    totals.add_match_distance(Status::OnBattery, 0.9);
+    assert!(totals.get_description().ends_with("\
+      Match distance per status, 0 at the target and 1 at the edge of the tolerance:\n\
+      LowOnBattery: 4 matches, mean 0.250, stddev 0.112\n\
+      OnBattery: 1 matches, mean 0.900, stddev 0.000"));
+  }
+
+  #[test]
+  fn rejects_invalid_files() {
+    assert_eq!(parse_totals("OnMains 10\nOnBattery ten\n").unwrap_err(), "invalid line 2");
+    assert_eq!(parse_totals("OnMains 10\nmatch OnMains 2 0.5\n").unwrap_err(), "invalid line 2");
+    assert!(parse_totals("Mains 10").is_err());
+  }
+}
@@ -0,0 +1,754 @@
+use std::time::Duration;
+
+use crate::guidance::GuidanceTable;
+
+// A duration matches a target when it is within the larger of the two, the share of the target alone would ask short beeps
+// for a precision the line rarely has, 5% of 250ms being only 12.5ms, zero targets are left exact as only synthetic pairs have them
+pub const ERROR_MARGIN: f64 = 0.05;
+pub const MIN_ERROR_DURATION: Duration = Duration::from_millis(30);
+
+pub const TIMEOUT_DURATION: Duration = Duration::from_secs(3);
+pub const ZERO_DURATION: Duration = Duration::from_millis(0);
+// How long a beep has to go on for to be a continuous one, on its own rather than the poll timeout so that
+// waking up more or less often to check for it never changes what it matches
+pub const CONTINUOUS_BEEP_DURATION: Duration = Duration::from_secs(3);
+
+const TARGET_NORMAL_BEEP_DURATION: Duration = Duration::from_millis(250);
+const TARGET_LONG_BEEP_DURATION: Duration = Duration::from_secs(2);
+// Halfway between the normal and long beeps, in log terms and otherwise
+pub const DEFAULT_LONG_BEEP_THRESHOLD_DURATION: Duration = Duration::from_secs(1);
+
+#[derive(Hash, Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Status {
+  OnMains,
+  OnBattery,
+  LowOnBattery,
+  NoLoadOnBattery,
+  OverloadOrShortCircuitOnBattery,
+  OverloadOrShortCircuitOnMains,
+  AdvanceLowRuntimeOnMains,
+  OverTemperatureOnMains,
+  // Only matched from a lone chirp followed by silence, see --avr-chirp
+  VoltageRegulating,
+  OverTemperatureOnBatteryOrInternalError,
+  ReplaceBattery,
+  PowerOff,
+  // Only matched from a beep group, never from the table, see --beep-group
+  TestInProgress,
+  // Never matched from beeps, only reported by a rule combining the beeps with the other inputs
+  SensorConflict,
+  Unknown,
+}
+
+impl Status {
+  pub fn is_on_battery(self) -> bool {
+    matches!(self, Status::OnBattery | Status::LowOnBattery | Status::NoLoadOnBattery | Status::OverloadOrShortCircuitOnBattery)
+  }
+
+  pub fn is_on_mains(self) -> bool {
+    matches!(self, Status::OnMains | Status::OverloadOrShortCircuitOnMains | Status::AdvanceLowRuntimeOnMains | Status::OverTemperatureOnMains | Status::VoltageRegulating)
+  }
+}
+
+const STATUS_DESCRIPTIONS: [(Status, &str); 15] = [
+  (Status::OnBattery, "On battery power, no issues detected"),
+  (Status::LowOnBattery, "Low battery, power backup will shut down in 1 minute"),
+  (Status::NoLoadOnBattery, "Battery saver mode is enabled and power load is below 30W, power backup will shut down in 2 minutes"),
+  (Status::OverloadOrShortCircuitOnBattery, "Overload or short circuit has occured on battery power, power backup will shut down in 5 minutes"),
+  (Status::OverloadOrShortCircuitOnMains, "Overload or short circuit has occured on mains power"),
+  (Status::AdvanceLowRuntimeOnMains, "Battery is on mains power and will have low runtime if it has to shift to battery power"),
+  (Status::OverTemperatureOnMains, "Battery is over temperature on mains power"),
+  (Status::OnMains, "On mains power, no issues detected"),
+  (Status::VoltageRegulating, "On mains power, the power backup is correcting the mains voltage without switching to battery power"),
+  (Status::OverTemperatureOnBatteryOrInternalError, "Battery is either over temperature on battery power or an internal error has occured"),
+  (Status::ReplaceBattery, "Battery needs replacement"),
+  (Status::PowerOff, "Power backup has shut down after running on battery power, the connected devices have lost power"),
+  (Status::TestInProgress, "A self test of the power backup has been started, the beeps are not from a power event"),
+  (Status::SensorConflict, "The beeps and the other inputs disagree about the power state, check how they are wired"),
+  (Status::Unknown, "Appropriate state could not be detected"),
+];
+
+// Exit codes of the one-shot mode, these are stable so scripts can rely on them, 1 and 2 stay reserved for errors and bad usage,
+// battery statuses are in the 10s, faults and regulation on mains in the 20s, other faults in the 30s, PowerOff and TestInProgress are in the 40s, and Unknown is on its own
+const STATUS_EXIT_CODES: [(Status, i32); 15] = [
+  (Status::OnMains, 0),
+  (Status::OnBattery, 10),
+  (Status::LowOnBattery, 11),
+  (Status::NoLoadOnBattery, 12),
+  (Status::OverloadOrShortCircuitOnBattery, 13),
+  (Status::OverloadOrShortCircuitOnMains, 20),
+  (Status::AdvanceLowRuntimeOnMains, 21),
+  (Status::OverTemperatureOnMains, 22),
+  (Status::VoltageRegulating, 23),
+  (Status::OverTemperatureOnBatteryOrInternalError, 30),
+  (Status::ReplaceBattery, 31),
+  (Status::SensorConflict, 32),
+  (Status::PowerOff, 40),
+  (Status::TestInProgress, 41),
+  (Status::Unknown, NO_STATUS_EXIT_CODE),
+];
+
+// Shared by Unknown and by the one-shot mode giving up without having detected anything
+pub const NO_STATUS_EXIT_CODE: i32 = 50;
+
+// OverTemperatureOnBatteryOrInternalError has no real beep pattern, it is only ever matched by the synthetic durations the main loop reports
+// when a poll times out in the middle of a beep, a zero duration target allows no error margin at all so real, measured durations can never match it,
+// OnMains has no entry at all as it is the silence itself rather than any pair, see DEFAULT_SILENCE_STATUS
+pub const STATUS_BEEP_DURATIONS: [(Status, [Duration; 2]); 9] = [
+  (Status::OnBattery, [TARGET_NORMAL_BEEP_DURATION, Duration::from_secs(60)]),
+  (Status::LowOnBattery, [TARGET_NORMAL_BEEP_DURATION, Duration::from_secs(1)]),
+  (Status::NoLoadOnBattery, [TARGET_NORMAL_BEEP_DURATION, Duration::from_secs(10)]),
+  (Status::OverloadOrShortCircuitOnBattery, [TARGET_NORMAL_BEEP_DURATION, Duration::from_secs(2)]),
+  (Status::OverloadOrShortCircuitOnMains, [TARGET_LONG_BEEP_DURATION, Duration::from_secs(2)]),
+  (Status::AdvanceLowRuntimeOnMains, [TARGET_LONG_BEEP_DURATION, Duration::from_secs(13)]),
+  (Status::OverTemperatureOnMains, [TARGET_NORMAL_BEEP_DURATION, Duration::from_secs(4)]),
+  (Status::OverTemperatureOnBatteryOrInternalError, [CONTINUOUS_BEEP_DURATION, ZERO_DURATION]),
+  (Status::ReplaceBattery, [TARGET_LONG_BEEP_DURATION, Duration::from_secs(40)]),
+];
+
+// What sustained silence after a beep pattern means, inferred by the detector once the silence outlasts its grace period
+// instead of being matched from any beep and inter beep durations
+pub const DEFAULT_SILENCE_STATUS: Status = Status::OnMains;
+
+// How long a status plausibly lasts, one gone before its minimum was most likely a misread pattern and one lasting past its maximum is worth a look,
+// statuses without bounds can last any time at all
+#[derive(Clone, Copy, Default, Debug)]
+pub struct DwellBounds {
+  pub min: Option<Duration>,
+  pub max: Option<Duration>,
+}
+
+const STATUS_DWELL_BOUNDS: [(Status, DwellBounds); 2] = [
+  // Two of its 4s gaps, a single match is about as likely to be a stray pair
+  (Status::OverTemperatureOnMains, DwellBounds { min: Some(Duration::from_secs(8)), max: None }),
+  // The backup gives out within a minute or so of getting low
+  (Status::LowOnBattery, DwellBounds { min: None, max: Some(Duration::from_secs(10 * 60)) }),
+];
+
+// Every status, in the order of their descriptions
+pub fn get_all_statuses() -> impl Iterator<Item = Status> {
+  STATUS_DESCRIPTIONS.iter().map(|status_description| status_description.0)
+}
+
+// Statuses are named after their variants, as in "LowOnBattery"
+pub fn get_status_from_name(name: &str) -> Option<Status> {
+  STATUS_DESCRIPTIONS.iter()
+    .map(|status_description| status_description.0)
+    .find(|status| format!("{:?}", status) == name)
+}
+
+pub fn get_status_description(status: Status) -> &'static str {
+  STATUS_DESCRIPTIONS.iter()
+    .find(|status_description| status_description.0 == status)
+    .map(|status_description| status_description.1)
+    .unwrap()
+}
+
+pub fn get_status_exit_code(status: Status) -> i32 {
+  STATUS_EXIT_CODES.iter()
+    .find(|status_exit_code| status_exit_code.0 == status)
+    .map(|status_exit_code| status_exit_code.1)
+    .unwrap()
+}
+
+pub fn get_status_dwell_bounds(status: Status) -> DwellBounds {
+  STATUS_DWELL_BOUNDS.iter()
+    .find(|status_dwell_bounds| status_dwell_bounds.0 == status)
+    .map(|status_dwell_bounds| status_dwell_bounds.1)
+    .unwrap_or_default()
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Classification {
+  pub status: Status,
+  // How centered the beep and inter beep durations were within the tolerance of the matched status,
+  // 1 right at the targets down to 0 at the edge of the tolerance, always 0 for Unknown
+  pub confidence: f64,
+  // The durations the status was classified from
+  pub beep_duration: Duration,
+  pub inter_beep_duration: Duration,
+}
+
+// What to match when the durations are within the tolerance of more than one status
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum AmbiguityPolicy {
+  // The status listed first in the table
+  First,
+  Unknown,
+  // The status closest by the match metric, the one listed first on a tie
+  Closest,
+  // The status with the highest severity, the closest one on a tie
+  HighestSeverity,
+}
+
+const AMBIGUITY_POLICY_NAMES: [(AmbiguityPolicy, &str); 4] = [
+  (AmbiguityPolicy::First, "first"),
+  (AmbiguityPolicy::Unknown, "unknown"),
+  (AmbiguityPolicy::Closest, "closest"),
+  (AmbiguityPolicy::HighestSeverity, "highest-severity"),
+];
+
+impl AmbiguityPolicy {
+  pub fn from_name(name: &str) -> Option<AmbiguityPolicy> {
+    AMBIGUITY_POLICY_NAMES.iter()
+      .find(|ambiguity_policy_name| ambiguity_policy_name.1 == name)
+      .map(|ambiguity_policy_name| ambiguity_policy_name.0)
+  }
+}
+
+// How the distance to the targets is measured when ranking statuses that are all within the tolerance,
+// the confidence reported stays the axis-wise one whatever the metric
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum MatchMetric {
+  // Whichever of the beep and inter beep is further into its tolerance, the same as the confidence
+  Axiswise,
+  // Straight line distance over the beep and inter beep errors in time, so misses on the much longer gaps weigh the most
+  Euclidean,
+  // Straight line distance with every error scaled by its tolerance, so misses of short beeps and long gaps weigh the same
+  Normalized,
+}
+
+const MATCH_METRIC_NAMES: [(MatchMetric, &str); 3] = [
+  (MatchMetric::Axiswise, "axiswise"),
+  (MatchMetric::Euclidean, "euclidean"),
+  (MatchMetric::Normalized, "normalized"),
+];
+
+impl MatchMetric {
+  pub fn from_name(name: &str) -> Option<MatchMetric> {
+    MATCH_METRIC_NAMES.iter()
+      .find(|match_metric_name| match_metric_name.1 == name)
+      .map(|match_metric_name| match_metric_name.0)
+  }
+}
+
+// How far a duration can be from its target and still match, the larger of the share of the target and the floor,
+// the same two settings as the whole table has but for one status alone
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Tolerance {
+  pub error_margin: f64,
+  pub min_error_duration: Duration,
+}
+
+#[derive(Clone)]
+pub struct MatchConfig {
+  // Target beep and inter beep durations of every status, the built-in table unless a profile says otherwise
+  pub beep_durations: Vec<(Status, [Duration; 2])>,
+  // Status of sustained silence, None for models whose silence means nothing and is reported as Unknown
+  pub silence_status: Option<Status>,
+  pub error_margin: f64,
+  pub min_error_duration: Duration,
+  // Beep and inter beep tolerances of the statuses that have their own, the others use the margin and floor above
+  pub status_tolerances: Vec<(Status, [Tolerance; 2])>,
+  pub on_ambiguous: AmbiguityPolicy,
+  pub metric: MatchMetric,
+  // When set, beeps at least this long are long ones and are only matched against patterns of long beeps, shorter ones only against patterns of short beeps
+  pub long_beep_threshold: Option<Duration>,
+  // Only consulted for ranking statuses by severity
+  pub guidance: GuidanceTable,
+}
+
+impl Default for MatchConfig {
+  fn default() -> MatchConfig {
+    MatchConfig {
+      beep_durations: STATUS_BEEP_DURATIONS.to_vec(),
+      silence_status: Some(DEFAULT_SILENCE_STATUS),
+      error_margin: ERROR_MARGIN,
+      min_error_duration: MIN_ERROR_DURATION,
+      status_tolerances: vec![],
+      on_ambiguous: AmbiguityPolicy::Closest,
+      metric: MatchMetric::Axiswise,
+      long_beep_threshold: None,
+      guidance: GuidanceTable::default(),
+    }
+  }
+}
+
+impl MatchConfig {
+  pub fn get_tolerances(&self, status: Status) -> [Tolerance; 2] {
+    self.status_tolerances.iter()
+      .find(|status_tolerance| status_tolerance.0 == status)
+      .map(|status_tolerance| status_tolerance.1)
+      .unwrap_or([Tolerance { error_margin: self.error_margin, min_error_duration: self.min_error_duration }; 2])
+  }
+}
+
+pub fn get_status_from_beep_durations(beep: Duration, inter_beep: Duration, config: &MatchConfig) -> Classification {
+  let mut matches = config.beep_durations.iter().filter_map(|status_beep_duration| {
+    if let Some(long_beep_threshold) = config.long_beep_threshold
+      && (beep >= long_beep_threshold) != (status_beep_duration.1[0] >= long_beep_threshold) {
+      return None;
+    }
+    let [beep_tolerance, inter_beep_tolerance] = config.get_tolerances(status_beep_duration.0);
+    let beep_error = get_error(beep, status_beep_duration.1[0], beep_tolerance.error_margin, beep_tolerance.min_error_duration)?;
+    let inter_beep_error = get_error(inter_beep, status_beep_duration.1[1], inter_beep_tolerance.error_margin, inter_beep_tolerance.min_error_duration)?;
+    let confidence = get_closeness_from_error(beep_error).min(get_closeness_from_error(inter_beep_error));
+    let distance = match config.metric {
+      MatchMetric::Axiswise => 1.0 - confidence,
+      MatchMetric::Euclidean => beep_error.0.hypot(inter_beep_error.0),
+      MatchMetric::Normalized => get_normalized_error(beep_error).hypot(get_normalized_error(inter_beep_error)),
+    };
+    Some((Classification {
+      status: status_beep_duration.0,
+      confidence,
+      beep_duration: beep,
+      inter_beep_duration: inter_beep,
+    }, distance))
+  });
+
+  let classification = match config.on_ambiguous {
+    AmbiguityPolicy::First => matches.next(),
+    AmbiguityPolicy::Unknown => {
+      let first_match = matches.next();
+      if matches.next().is_some() { None } else { first_match }
+    },
+    AmbiguityPolicy::Closest => matches.reduce(|closest, candidate| {
+      if candidate.1 < closest.1 { candidate } else { closest }
+    }),
+    AmbiguityPolicy::HighestSeverity => matches.reduce(|highest, candidate| {
+      let (highest_severity, candidate_severity) = (config.guidance.get(highest.0.status).severity, config.guidance.get(candidate.0.status).severity);
+      if candidate_severity > highest_severity || (candidate_severity == highest_severity && candidate.1 < highest.1) { candidate } else { highest }
+    }),
+  }.map(|classification_distance| classification_distance.0);
+
+  classification.unwrap_or(Classification {
+    status: Status::Unknown,
+    confidence: 0.0,
+    beep_duration: beep,
+    inter_beep_duration: inter_beep,
+  })
+}
+
+// Silence is no measurement and has nothing to be more or less close to, its status is certain whenever there is one
+pub fn get_status_from_silence(silence: Duration, config: &MatchConfig) -> Classification {
+  Classification {
+    status: config.silence_status.unwrap_or(Status::Unknown),
+    confidence: if config.silence_status.is_some() { 1.0 } else { 0.0 },
+    beep_duration: ZERO_DURATION,
+    inter_beep_duration: silence,
+  }
+}
+
+// The longest silence any pattern of the table still waits through for its next beep, its tolerance included,
+// None with nothing in the table
+pub fn get_longest_gap(config: &MatchConfig) -> Option<Duration> {
+  config.beep_durations.iter()
+    .map(|status_beep_duration| {
+      let [_, inter_beep_tolerance] = config.get_tolerances(status_beep_duration.0);
+      let inter_beep_duration = status_beep_duration.1[1];
+      inter_beep_duration + Duration::from_nanos(get_error_range(inter_beep_duration, inter_beep_tolerance.error_margin, inter_beep_tolerance.min_error_duration) as u64)
+    })
+    .max()
+}
+
+// How far from the target, in nanoseconds, a duration can be and still match
+pub fn get_error_range(target: Duration, error_margin: f64, min_error_duration: Duration) -> f64 {
+  let error_range = target.as_nanos() as f64 * error_margin;
+  if target.is_zero() { error_range } else { error_range.max(min_error_duration.as_nanos() as f64) }
+}
+
+// Shortest and longest matching durations around a target in milliseconds, both included
+type ToleranceWindow = (f64, f64);
+
+fn get_tolerance_window(target: Duration, error_margin: f64, min_error_duration: Duration) -> ToleranceWindow {
+  let error_range = get_error_range(target, error_margin, min_error_duration);
+  let target = target.as_nanos() as f64;
+  ((target - error_range).max(0.0) / 1e6, (target + error_range) / 1e6)
+}
+
+fn do_windows_overlap(window: ToleranceWindow, other_window: ToleranceWindow) -> bool {
+  window.0 <= other_window.1 && other_window.0 <= window.1
+}
+
+// For every table entry given the exact beep and inter beep durations of another status, the status it loses to every time,
+// the two always match together so the ambiguity policy alone picks one, the earlier entry as closest ties go to it,
+// under unknown neither is ever reported
+pub fn get_unreachable_entries(config: &MatchConfig) -> Vec<(usize, Status)> {
+  config.beep_durations.iter().enumerate().filter_map(|(index, status_beep_duration)| {
+    config.beep_durations.iter().enumerate()
+      .filter(|(_, other)| other.0 != status_beep_duration.0 && other.1 == status_beep_duration.1)
+      .find(|(other_index, other)| match config.on_ambiguous {
+        AmbiguityPolicy::First | AmbiguityPolicy::Closest => *other_index < index,
+        AmbiguityPolicy::Unknown => true,
+        AmbiguityPolicy::HighestSeverity => {
+          let (severity, other_severity) = (config.guidance.get(status_beep_duration.0).severity, config.guidance.get(other.0).severity);
+          other_severity > severity || (other_severity == severity && *other_index < index)
+        },
+      })
+      .map(|(_, other)| (index, other.0))
+  }).collect()
+}
+
+// One line per status with the beep and inter beep durations that match it, along with any other status whose durations match just as well,
+// the status of silence comes last as there are no durations to it
+pub fn get_windows_description(config: &MatchConfig) -> String {
+  let windows: Vec<(Status, ToleranceWindow, ToleranceWindow)> = config.beep_durations.iter()
+    .map(|status_beep_duration| {
+      let [beep_tolerance, inter_beep_tolerance] = config.get_tolerances(status_beep_duration.0);
+      (
+        status_beep_duration.0,
+        get_tolerance_window(status_beep_duration.1[0], beep_tolerance.error_margin, beep_tolerance.min_error_duration),
+        get_tolerance_window(status_beep_duration.1[1], inter_beep_tolerance.error_margin, inter_beep_tolerance.min_error_duration),
+      )
+    })
+    .collect();
+
+  let unreachable_entries = get_unreachable_entries(config);
+  let mut lines: Vec<String> = windows.iter().enumerate().map(|(index, window)| {
+    let mut line = format!("{:?}: beep {:.1}-{:.1}ms, inter beep {:.1}-{:.1}ms", window.0, window.1.0, window.1.1, window.2.0, window.2.1);
+    if let Some((_, winning_status)) = unreachable_entries.iter().find(|unreachable_entry| unreachable_entry.0 == index) {
+      line.push_str(&format!(" (unreachable, identical to {:?})", winning_status));
+      return line;
+    }
+    let overlapping_statuses: Vec<String> = windows.iter()
+      .filter(|other_window| other_window.0 != window.0 && do_windows_overlap(window.1, other_window.1) && do_windows_overlap(window.2, other_window.2))
+      .map(|other_window| format!("{:?}", other_window.0))
+      .collect();
+    if !overlapping_statuses.is_empty() {
+      line.push_str(&format!(" (overlaps {})", overlapping_statuses.join(", ")));
+    }
+    line
+  }).collect();
+  if let Some(silence_status) = config.silence_status {
+    lines.push(format!("{:?}: sustained silence", silence_status));
+  }
+  lines.join("\n")
+}
+
+#[cfg(test)]
+fn close_enough(duration: Duration, target: Duration, error_margin: f64, min_error_duration: Duration) -> bool {
+  get_closeness(duration, target, error_margin, min_error_duration).is_some()
+}
+
+// Nothing longer than a day can be the beep or gap of any pattern, rejecting such durations up front also keeps every nanosecond count
+// below exactly representable in a f64, so a stuck line reporting an enormous duration can't round its way into a match
+const MAX_COMPARABLE_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+// None when the duration is outside the tolerance around the target, otherwise how far it is from the target along with how far it could have been,
+// both in nanoseconds, the comparison is inclusive so that a zero duration target still matches an exactly zero duration
+fn get_error(duration: Duration, target: Duration, error_margin: f64, min_error_duration: Duration) -> Option<(f64, f64)> {
+  if duration > MAX_COMPARABLE_DURATION || target > MAX_COMPARABLE_DURATION || !(error_margin >= 0.0 && error_margin.is_finite()) {
+    return None;
+  }
+
+  let error_range = get_error_range(target, error_margin, min_error_duration);
+  let error = duration.abs_diff(target).as_nanos() as f64;
+  (error <= error_range).then_some((error, error_range))
+}
+
+// The share of the error range left over, from 0 at the edge of the margin to 1 at the target
+fn get_closeness_from_error((error, error_range): (f64, f64)) -> f64 {
+  if error_range == 0.0 { 1.0 } else { 1.0 - error / error_range }
+}
+
+fn get_normalized_error((error, error_range): (f64, f64)) -> f64 {
+  if error_range == 0.0 { 0.0 } else { error / error_range }
+}
+
+// Also what the beeps of a group are matched with, as those are outside the table
+pub fn get_closeness(duration: Duration, target: Duration, error_margin: f64, min_error_duration: Duration) -> Option<f64> {
+  get_error(duration, target, error_margin, min_error_duration).map(get_closeness_from_error)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  use crate::guidance::parse_guidance_override;
+
+  #[test]
+  fn silence_is_on_mains() {
+    let classification = get_status_from_silence(TIMEOUT_DURATION, &MatchConfig::default());
+    assert_eq!(classification.status, Status::OnMains);
+    assert_eq!(classification.confidence, 1.0);
+    assert_eq!(classification.inter_beep_duration, TIMEOUT_DURATION);
+
+    let meaningless_silence = get_status_from_silence(TIMEOUT_DURATION, &MatchConfig { silence_status: None, ..MatchConfig::default() });
+    assert_eq!(meaningless_silence.status, Status::Unknown);
+    assert_eq!(meaningless_silence.confidence, 0.0);
+  }
+
+  #[test]
+  fn longest_gap_is_the_one_of_on_battery() {
+    assert_eq!(get_longest_gap(&MatchConfig::default()), Some(Duration::from_secs(63)));
+    let status_tolerances = vec![(Status::OnBattery, [Tolerance { error_margin: 0.0, min_error_duration: ZERO_DURATION }; 2])];
+    assert_eq!(get_longest_gap(&MatchConfig { status_tolerances, ..MatchConfig::default() }), Some(Duration::from_secs(60)));
+    assert_eq!(get_longest_gap(&MatchConfig { beep_durations: vec![], ..MatchConfig::default() }), None);
+  }
+
+  #[test]
+  fn timeout_during_beep_is_over_temperature_or_internal_error() {
+    assert_eq!(get_status_from_beep_durations(CONTINUOUS_BEEP_DURATION, ZERO_DURATION, &MatchConfig::default()).status, Status::OverTemperatureOnBatteryOrInternalError);
+  }
+
+  // Not even the pair the silence used to be matched from, OnMains only ever comes from the silence itself
+  #[test]
+  fn durations_never_match_on_mains() {
+    assert_eq!(get_status_from_beep_durations(ZERO_DURATION, TIMEOUT_DURATION, &MatchConfig::default()).status, Status::Unknown);
+    assert_eq!(get_status_from_beep_durations(Duration::from_millis(1), TIMEOUT_DURATION, &MatchConfig::default()).status, Status::Unknown);
+    assert!(STATUS_BEEP_DURATIONS.iter().all(|status_beep_duration| status_beep_duration.0 != Status::OnMains));
+  }
+
+  #[test]
+  fn measured_durations_never_match_over_temperature_or_internal_error() {
+    assert_eq!(get_status_from_beep_durations(CONTINUOUS_BEEP_DURATION, Duration::from_millis(300), &MatchConfig::default()).status, Status::Unknown);
+    assert_eq!(get_status_from_beep_durations(CONTINUOUS_BEEP_DURATION, Duration::from_millis(1), &MatchConfig::default()).status, Status::Unknown);
+  }
+
+  #[test]
+  fn confidence_is_full_at_target() {
+    let classification = get_status_from_beep_durations(Duration::from_millis(250), Duration::from_secs(1), &MatchConfig::default());
+    assert_eq!(classification.status, Status::LowOnBattery);
+    assert_eq!(classification.confidence, 1.0);
+  }
+
+  #[test]
+  fn confidence_drops_towards_edge_of_tolerance() {
+    // Half of the 30ms beep tolerance off target, right at the target gap
+    let half_way = get_status_from_beep_durations(Duration::from_millis(265), Duration::from_secs(1), &MatchConfig::default());
+    assert_eq!(half_way.status, Status::LowOnBattery);
+    assert!((half_way.confidence - 0.5).abs() < 1e-9);
+
+    // The least centered of the two durations decides
+    let at_edge = get_status_from_beep_durations(Duration::from_millis(250), Duration::from_millis(1050), &MatchConfig::default());
+    assert_eq!(at_edge.status, Status::LowOnBattery);
+    assert!(at_edge.confidence.abs() < 1e-9);
+  }
+
+  #[test]
+  fn every_pattern_matches_its_status_at_the_target() {
+    for (status, [beep_duration, inter_beep_duration]) in STATUS_BEEP_DURATIONS {
+      let classification = get_status_from_beep_durations(beep_duration, inter_beep_duration, &MatchConfig::default());
+      assert_eq!((classification.status, classification.confidence), (status, 1.0));
+    }
+  }
+
+  #[test]
+  fn every_pattern_is_unknown_just_past_the_edge_of_its_tolerance() {
+    let get_edge = |target: Duration| Duration::from_nanos(get_error_range(target, ERROR_MARGIN, MIN_ERROR_DURATION) as u64);
+    let get_status = |beep: Duration, inter_beep: Duration| get_status_from_beep_durations(beep, inter_beep, &MatchConfig::default()).status;
+    for (status, [beep_duration, inter_beep_duration]) in STATUS_BEEP_DURATIONS {
+      let (beep_edge, inter_beep_edge) = (get_edge(beep_duration), get_edge(inter_beep_duration));
+      assert_eq!(get_status(beep_duration + beep_edge, inter_beep_duration), status);
+      assert_eq!(get_status(beep_duration - beep_edge, inter_beep_duration + inter_beep_edge), status);
+      assert_eq!(get_status(beep_duration + beep_edge + Duration::from_micros(1), inter_beep_duration), Status::Unknown);
+      assert_eq!(get_status(beep_duration, inter_beep_duration + inter_beep_edge + Duration::from_micros(1)), Status::Unknown);
+    }
+  }
+
+  #[test]
+  fn unknown_has_no_confidence() {
+    assert_eq!(get_status_from_beep_durations(Duration::from_millis(700), Duration::from_millis(700), &MatchConfig::default()).confidence, 0.0);
+  }
+
+  #[test]
+  fn synthetic_timeout_pair_has_full_confidence() {
+    assert_eq!(get_status_from_beep_durations(CONTINUOUS_BEEP_DURATION, ZERO_DURATION, &MatchConfig::default()).confidence, 1.0);
+  }
+
+  #[test]
+  fn close_enough_is_inclusive() {
+    assert!(close_enough(Duration::from_millis(105), Duration::from_millis(100), 0.05, ZERO_DURATION));
+    assert!(!close_enough(Duration::from_micros(105_001), Duration::from_millis(100), 0.05, ZERO_DURATION));
+    assert!(close_enough(ZERO_DURATION, ZERO_DURATION, 0.05, ZERO_DURATION));
+  }
+
+  #[test]
+  fn tolerance_is_the_larger_of_margin_and_floor() {
+    // The floor wins for short beeps, the margin for long gaps
+    assert!(close_enough(Duration::from_millis(280), Duration::from_millis(250), ERROR_MARGIN, MIN_ERROR_DURATION));
+    assert!(!close_enough(Duration::from_micros(280_001), Duration::from_millis(250), ERROR_MARGIN, MIN_ERROR_DURATION));
+    assert!(close_enough(Duration::from_secs(63), Duration::from_secs(60), ERROR_MARGIN, MIN_ERROR_DURATION));
+    assert!(!close_enough(Duration::from_secs(63), Duration::from_secs(60), ERROR_MARGIN / 2.0, MIN_ERROR_DURATION));
+    // Zero targets stay exact
+    assert!(!close_enough(Duration::from_millis(1), ZERO_DURATION, ERROR_MARGIN, MIN_ERROR_DURATION));
+    assert_eq!(get_status_from_beep_durations(Duration::from_millis(275), Duration::from_secs(1), &MatchConfig::default()).status, Status::LowOnBattery);
+  }
+
+  #[test]
+  fn finds_status_from_name() {
+    assert_eq!(get_status_from_name("LowOnBattery"), Some(Status::LowOnBattery));
+    assert_eq!(get_status_from_name("lowonbattery"), None);
+  }
+
+  #[test]
+  fn tiny_durations_only_match_zero_exactly() {
+    assert!(close_enough(ZERO_DURATION, ZERO_DURATION, ERROR_MARGIN, MIN_ERROR_DURATION));
+    assert!(!close_enough(Duration::from_nanos(1), ZERO_DURATION, ERROR_MARGIN, MIN_ERROR_DURATION));
+    assert!(!close_enough(Duration::from_nanos(1), Duration::from_millis(250), ERROR_MARGIN, MIN_ERROR_DURATION));
+    assert_eq!(get_status_from_beep_durations(Duration::from_nanos(1), TIMEOUT_DURATION, &MatchConfig::default()).status, Status::Unknown);
+  }
+
+  #[test]
+  fn enormous_durations_never_match() {
+    assert!(!close_enough(Duration::MAX, Duration::from_secs(60), ERROR_MARGIN, MIN_ERROR_DURATION));
+    assert!(!close_enough(Duration::MAX, Duration::MAX, ERROR_MARGIN, MIN_ERROR_DURATION));
+    assert!(!close_enough(Duration::from_secs(u64::MAX / 2), Duration::from_secs(60), f64::MAX, MIN_ERROR_DURATION));
+    assert_eq!(get_status_from_beep_durations(Duration::MAX, Duration::MAX, &MatchConfig::default()).status, Status::Unknown);
+    assert_eq!(get_status_from_beep_durations(Duration::from_millis(250), Duration::MAX, &MatchConfig::default()).status, Status::Unknown);
+  }
+
+  #[test]
+  fn invalid_error_margins_never_match() {
+    assert!(!close_enough(Duration::from_millis(250), Duration::from_millis(250), f64::NAN, MIN_ERROR_DURATION));
+    assert!(!close_enough(Duration::from_millis(250), Duration::from_millis(250), -0.05, MIN_ERROR_DURATION));
+    assert!(!close_enough(Duration::from_millis(250), Duration::from_millis(250), f64::INFINITY, MIN_ERROR_DURATION));
+  }
+
+  // With a 50% margin a 250ms beep after a 1.4s gap is within the tolerance of LowOnBattery (1s gap) and OverloadOrShortCircuitOnBattery (2s gap),
+  // it is 400ms into the 500ms tolerance of the former but only 600ms into the 1s tolerance of the latter
+  fn get_ambiguous_status(on_ambiguous: AmbiguityPolicy, guidance: GuidanceTable) -> Status {
+    let config = MatchConfig { error_margin: 0.5, on_ambiguous, guidance, ..MatchConfig::default() };
+    get_status_from_beep_durations(Duration::from_millis(250), Duration::from_millis(1400), &config).status
+  }
+
+  #[test]
+  fn ambiguous_match_follows_policy() {
+    assert_eq!(get_ambiguous_status(AmbiguityPolicy::First, GuidanceTable::default()), Status::LowOnBattery);
+    assert_eq!(get_ambiguous_status(AmbiguityPolicy::Unknown, GuidanceTable::default()), Status::Unknown);
+    assert_eq!(get_ambiguous_status(AmbiguityPolicy::Closest, GuidanceTable::default()), Status::OverloadOrShortCircuitOnBattery);
+  }
+
+  #[test]
+  fn closest_match_follows_metric() {
+    let get_status = |metric: MatchMetric| {
+      let config = MatchConfig { error_margin: 0.5, metric, ..MatchConfig::default() };
+      get_status_from_beep_durations(Duration::from_millis(250), Duration::from_millis(1400), &config)
+    };
+    // 400ms into a 500ms tolerance is further in than 600ms into a 1s one, but 400ms is still the shorter miss
+    assert_eq!(get_status(MatchMetric::Axiswise).status, Status::OverloadOrShortCircuitOnBattery);
+    assert_eq!(get_status(MatchMetric::Normalized).status, Status::OverloadOrShortCircuitOnBattery);
+    let euclidean = get_status(MatchMetric::Euclidean);
+    assert_eq!(euclidean.status, Status::LowOnBattery);
+    assert!((euclidean.confidence - 0.2).abs() < 1e-9);
+    assert_eq!(MatchMetric::from_name("normalized"), Some(MatchMetric::Normalized));
+  }
+
+  #[test]
+  fn highest_severity_honors_guidance_overrides_and_falls_back_to_closest() {
+    // Both are critical by default
+    assert_eq!(get_ambiguous_status(AmbiguityPolicy::HighestSeverity, GuidanceTable::default()), Status::OverloadOrShortCircuitOnBattery);
+
+    let lowered = GuidanceTable::new(vec![parse_guidance_override("OverloadOrShortCircuitOnBattery=warning:monitor").unwrap()]);
+    assert_eq!(get_ambiguous_status(AmbiguityPolicy::HighestSeverity, lowered), Status::LowOnBattery);
+  }
+
+  // With an 80% margin a 2s beep target takes anything from 400ms to 3.6s, the threshold keeps beeps below it away from that target
+  #[test]
+  fn long_beep_threshold_narrows_the_patterns() {
+    let get_status = |beep: Duration, long_beep_threshold: Option<Duration>| {
+      let config = MatchConfig { error_margin: 0.8, long_beep_threshold, ..MatchConfig::default() };
+      get_status_from_beep_durations(beep, Duration::from_secs(2), &config).status
+    };
+    let threshold = Some(DEFAULT_LONG_BEEP_THRESHOLD_DURATION);
+    assert_eq!(get_status(Duration::from_millis(900), None), Status::OverloadOrShortCircuitOnMains);
+    assert_eq!(get_status(Duration::from_millis(999), threshold), Status::Unknown);
+    assert_eq!(get_status(Duration::from_millis(1000), threshold), Status::OverloadOrShortCircuitOnMains);
+    assert_eq!(get_status(Duration::from_millis(250), threshold), Status::OverloadOrShortCircuitOnBattery);
+    assert_eq!(get_status(Duration::from_millis(400), Some(Duration::from_millis(300))), Status::OverloadOrShortCircuitOnMains);
+  }
+
+  #[test]
+  fn windows_are_where_matching_stops() {
+    let description = get_windows_description(&MatchConfig::default());
+    assert!(description.contains("LowOnBattery: beep 220.0-280.0ms, inter beep 950.0-1050.0ms\n"));
+    assert!(description.ends_with("\nOnMains: sustained silence"));
+    assert!(!description.contains("overlaps"));
+
+    for target in [Duration::from_millis(250), Duration::from_secs(1)] {
+      let (min, max) = get_tolerance_window(target, ERROR_MARGIN, MIN_ERROR_DURATION);
+      assert!(close_enough(Duration::from_secs_f64(min / 1e3), target, ERROR_MARGIN, MIN_ERROR_DURATION));
+      assert!(close_enough(Duration::from_secs_f64(max / 1e3), target, ERROR_MARGIN, MIN_ERROR_DURATION));
+      assert!(!close_enough(Duration::from_secs_f64(max / 1e3) + Duration::from_nanos(1), target, ERROR_MARGIN, MIN_ERROR_DURATION));
+    }
+  }
+
+  #[test]
+  fn overlapping_windows_are_pointed_out() {
+    let description = get_windows_description(&MatchConfig { error_margin: 0.5, ..MatchConfig::default() });
+    assert!(description.contains("LowOnBattery: beep 125.0-375.0ms, inter beep 500.0-1500.0ms (overlaps OverloadOrShortCircuitOnBattery)"));
+  }
+
+  #[test]
+  fn identical_patterns_are_unreachable() {
+    let mut beep_durations = STATUS_BEEP_DURATIONS.to_vec();
+    beep_durations.push((Status::ReplaceBattery, [Duration::from_millis(250), Duration::from_secs(1)]));
+    let config = MatchConfig { beep_durations, ..MatchConfig::default() };
+    let unreachable_index = config.beep_durations.len() - 1;
+    assert_eq!(get_unreachable_entries(&config), vec![(unreachable_index, Status::LowOnBattery)]);
+    assert!(get_windows_description(&config).contains("ReplaceBattery: beep 220.0-280.0ms, inter beep 950.0-1050.0ms (unreachable, identical to LowOnBattery)\n"));
+
+    // Severity goes before the order, the critical LowOnBattery wins even when listed after
+    let mut beep_durations = config.beep_durations.clone();
+    beep_durations.rotate_right(1);
+    let severity_config = MatchConfig { beep_durations, on_ambiguous: AmbiguityPolicy::HighestSeverity, ..config.clone() };
+    assert_eq!(get_unreachable_entries(&severity_config), vec![(0, Status::LowOnBattery)]);
+    let config = MatchConfig { on_ambiguous: AmbiguityPolicy::Unknown, ..config };
+    assert_eq!(get_unreachable_entries(&config).len(), 2);
+    // The same status listed twice is still reachable
+    assert!(get_unreachable_entries(&MatchConfig { beep_durations: vec![STATUS_BEEP_DURATIONS[0], STATUS_BEEP_DURATIONS[0]], ..MatchConfig::default() }).is_empty());
+  }
+
+  #[test]
+  fn unambiguous_match_is_the_same_under_every_policy() {
+    for ambiguity_policy_name in AMBIGUITY_POLICY_NAMES {
+      let config = MatchConfig { on_ambiguous: ambiguity_policy_name.0, ..MatchConfig::default() };
+      assert_eq!(get_status_from_beep_durations(Duration::from_millis(250), Duration::from_secs(1), &config).status, Status::LowOnBattery);
+      assert_eq!(AmbiguityPolicy::from_name(ambiguity_policy_name.1), Some(ambiguity_policy_name.0));
+    }
+  }
+
+  #[test]
+  fn every_status_has_a_distinct_exit_code() {
+    let mut exit_codes: Vec<i32> = STATUS_DESCRIPTIONS.iter().map(|status_description| get_status_exit_code(status_description.0)).collect();
+    assert_eq!(get_status_exit_code(Status::OnMains), 0);
+    assert!(!exit_codes.contains(&1) && !exit_codes.contains(&2));
+    exit_codes.sort();
+    exit_codes.dedup();
+    assert_eq!(exit_codes.len(), STATUS_DESCRIPTIONS.len());
+  }
+
+  #[test]
+  fn every_status_has_a_description() {
+    for status_beep_duration in STATUS_BEEP_DURATIONS {
+      assert!(!get_status_description(status_beep_duration.0).is_empty());
+    }
+    assert!(!get_status_description(Status::PowerOff).is_empty());
+    assert!(!get_status_description(Status::Unknown).is_empty());
+  }
+
+  fn any_duration() -> impl Strategy<Value = Duration> {
+    prop_oneof![
+      (0u64..120_000_000).prop_map(Duration::from_micros),
+      any::<u64>().prop_map(Duration::from_nanos),
+      (any::<u64>(), 0u32..1_000_000_000).prop_map(|(secs, nanos)| Duration::new(secs, nanos)),
+    ]
+  }
+
+  proptest! {
+    #[test]
+    fn matches_satisfy_their_status_pattern(beep in any_duration(), inter_beep in any_duration()) {
+      let classification = get_status_from_beep_durations(beep, inter_beep, &MatchConfig::default());
+      prop_assert!((0.0..=1.0).contains(&classification.confidence));
+
+      if classification.status == Status::Unknown {
+        prop_assert_eq!(classification.confidence, 0.0);
+      } else {
+        let status_beep_duration = STATUS_BEEP_DURATIONS.iter()
+          .find(|status_beep_duration| status_beep_duration.0 == classification.status)
+          .unwrap();
+        prop_assert!(close_enough(beep, status_beep_duration.1[0], ERROR_MARGIN, MIN_ERROR_DURATION));
+        prop_assert!(close_enough(inter_beep, status_beep_duration.1[1], ERROR_MARGIN, MIN_ERROR_DURATION));
+      }
+    }
+
+    #[test]
+    fn durations_around_targets_match(index in 0..STATUS_BEEP_DURATIONS.len(), beep_offset in -0.049f64..0.049, inter_beep_offset in -0.049f64..0.049) {
+      let status_beep_duration = STATUS_BEEP_DURATIONS[index];
+      let beep = status_beep_duration.1[0].mul_f64(1.0 + beep_offset);
+      let inter_beep = status_beep_duration.1[1].mul_f64(1.0 + inter_beep_offset);
+      prop_assert_eq!(get_status_from_beep_durations(beep, inter_beep, &MatchConfig::default()).status, status_beep_duration.0);
+    }
+  }
+}
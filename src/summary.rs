@@ -0,0 +1,212 @@
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::guidance::Severity;
+use crate::json::{get_pretty_json, get_summary_json};
+use crate::output::Output;
+use crate::report::Format;
+use crate::state::{SharedState, Transition};
+use crate::status::Status;
+
+const SUMMARY_PERIOD_NAMES: [(&str, Duration); 2] = [
+  ("daily", Duration::from_secs(24 * 60 * 60)),
+  ("weekly", Duration::from_secs(7 * 24 * 60 * 60)),
+];
+
+// Either one of the names above or a number of seconds
+pub fn parse_summary_period(value: &str) -> Result<Duration, String> {
+  if let Some(summary_period_name) = SUMMARY_PERIOD_NAMES.iter().find(|summary_period_name| summary_period_name.0 == value) {
+    return Ok(summary_period_name.1);
+  }
+  match value.parse() {
+    Ok(0) | Err(_) => Err(format!("invalid summary period {}, expected daily, weekly or a number of seconds", value)),
+    Ok(secs) => Ok(Duration::from_secs(secs)),
+  }
+}
+
+pub struct Summary {
+  pub period: Duration,
+  // Every time a battery status was entered from any other
+  pub outages: u32,
+  pub on_battery_duration: Duration,
+  // The whole of the longest outage, including the part of it before the period when it started earlier
+  pub longest_on_battery_duration: Duration,
+  pub replace_battery_warnings: u32,
+}
+
+impl Summary {
+  pub fn get_description(&self) -> String {
+    format!(
+      "Summary of the last {}s: {} outages, {}s on battery, longest {}s, {} ReplaceBattery warnings",
+      self.period.as_secs(),
+      self.outages,
+      self.on_battery_duration.as_secs(),
+      self.longest_on_battery_duration.as_secs(),
+      self.replace_battery_warnings,
+    )
+  }
+}
+
+// Gathers the transitions of one period, an outage still going on when the period ends carries on into the next one
+#[derive(Default)]
+struct SummaryAccumulator {
+  outages: u32,
+  on_battery_duration: Duration,
+  longest_on_battery_duration: Duration,
+  replace_battery_warnings: u32,
+  // When the current outage started, and when the time in it was last added up to
+  outage_started_at: Option<Instant>,
+  on_battery_counted_at: Option<Instant>,
+}
+
+impl SummaryAccumulator {
+  fn record(&mut self, transition: &Transition) {
+    if transition.to == Status::ReplaceBattery {
+      self.replace_battery_warnings += 1;
+    }
+
+    match (self.outage_started_at, transition.to.is_on_battery()) {
+      (None, true) => {
+        self.outages += 1;
+        self.outage_started_at = Some(transition.at_instant);
+        self.on_battery_counted_at = Some(transition.at_instant);
+      },
+      (Some(_), false) => {
+        self.add_on_battery_duration(transition.at_instant);
+        self.outage_started_at = None;
+        self.on_battery_counted_at = None;
+      },
+      _ => {},
+    }
+  }
+
+  fn add_on_battery_duration(&mut self, now: Instant) {
+    if let (Some(outage_started_at), Some(on_battery_counted_at)) = (self.outage_started_at, self.on_battery_counted_at) {
+      self.on_battery_duration += now.saturating_duration_since(on_battery_counted_at);
+      self.longest_on_battery_duration = self.longest_on_battery_duration.max(now.saturating_duration_since(outage_started_at));
+      self.on_battery_counted_at = Some(now);
+    }
+  }
+
+  // Ends the period at now and starts the next one
+  fn take_summary(&mut self, period: Duration, now: Instant) -> Summary {
+    self.add_on_battery_duration(now);
+    let summary = Summary {
+      period,
+      outages: self.outages,
+      on_battery_duration: self.on_battery_duration,
+      longest_on_battery_duration: self.longest_on_battery_duration,
+      replace_battery_warnings: self.replace_battery_warnings,
+    };
+    *self = SummaryAccumulator {
+      outage_started_at: self.outage_started_at,
+      on_battery_counted_at: self.on_battery_counted_at,
+      ..SummaryAccumulator::default()
+    };
+    summary
+  }
+}
+
+// Writes a summary of the transitions to every sink once per period, on its own thread so it comes on time even while the detection loop waits on edges,
+// the glyph of char sinks has nothing to show a summary with so they are skipped
+pub fn start_summaries(state: SharedState, period: Duration, mut sinks: Vec<(Output, Format)>, pretty_json: bool) {
+  let transitions = state.lock().unwrap().subscribe();
+
+  thread::spawn(move || {
+    let mut accumulator = SummaryAccumulator::default();
+    let mut deadline = Instant::now() + period;
+    loop {
+      match transitions.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(transition) => accumulator.record(&transition),
+        Err(RecvTimeoutError::Timeout) => {
+          let summary = accumulator.take_summary(period, deadline);
+          let summary_json = get_summary_json(&summary);
+          for (output, format) in &mut sinks {
+            match format {
+              Format::Text => output.write_line(&summary.get_description(), Severity::Info),
+              Format::Json => output.write_line(&if pretty_json { get_pretty_json(&summary_json) } else { summary_json.clone() }, Severity::Info),
+              Format::Char => {},
+            }
+          }
+          deadline += period;
+        },
+        Err(RecvTimeoutError::Disconnected) => return,
+      }
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::SystemTime;
+
+  use crate::guidance::GuidanceTable;
+  use crate::report::Origin;
+
+  const PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+  fn get_transition(to: Status, at_instant: Instant) -> Transition {
+    Transition {
+      from: None,
+      to,
+      guidance: GuidanceTable::new(vec![]).get(to),
+      cleared: false,
+      confidence: 1.0,
+      beep_duration: Duration::from_millis(250),
+      inter_beep_duration: Duration::from_secs(60),
+      origin: Origin::Observed,
+      at: SystemTime::now(),
+      at_instant,
+      sequence: 0,
+      in_maintenance_window: false,
+    }
+  }
+
+  #[test]
+  fn parses_summary_periods() {
+    assert_eq!(parse_summary_period("daily"), Ok(PERIOD));
+    assert_eq!(parse_summary_period("weekly"), Ok(PERIOD * 7));
+    assert_eq!(parse_summary_period("3600"), Ok(Duration::from_secs(3600)));
+    assert!(parse_summary_period("0").unwrap_err().starts_with("invalid summary period 0"));
+    assert!(parse_summary_period("monthly").unwrap_err().starts_with("invalid summary period monthly"));
+  }
+
+  #[test]
+  fn sums_up_outages_and_warnings() {
+    let start = Instant::now();
+    let mut accumulator = SummaryAccumulator::default();
+    accumulator.record(&get_transition(Status::OnBattery, start));
+    // Getting low is the same outage going on
+    accumulator.record(&get_transition(Status::LowOnBattery, start + Duration::from_secs(60)));
+    accumulator.record(&get_transition(Status::OnMains, start + Duration::from_secs(100)));
+    accumulator.record(&get_transition(Status::ReplaceBattery, start + Duration::from_secs(200)));
+    accumulator.record(&get_transition(Status::OnBattery, start + Duration::from_secs(300)));
+    accumulator.record(&get_transition(Status::OnMains, start + Duration::from_secs(330)));
+
+    let summary = accumulator.take_summary(PERIOD, start + PERIOD);
+    assert_eq!(summary.outages, 2);
+    assert_eq!(summary.on_battery_duration, Duration::from_secs(130));
+    assert_eq!(summary.longest_on_battery_duration, Duration::from_secs(100));
+    assert_eq!(summary.replace_battery_warnings, 1);
+    assert_eq!(summary.get_description(), "Summary of the last 86400s: 2 outages, 130s on battery, longest 100s, 1 ReplaceBattery warnings");
+  }
+
+  #[test]
+  fn ongoing_outage_carries_over_and_the_rest_resets() {
+    let start = Instant::now();
+    let mut accumulator = SummaryAccumulator::default();
+    accumulator.record(&get_transition(Status::ReplaceBattery, start));
+    accumulator.record(&get_transition(Status::OnBattery, start + PERIOD - Duration::from_secs(10)));
+    let summary = accumulator.take_summary(PERIOD, start + PERIOD);
+    assert_eq!((summary.outages, summary.on_battery_duration), (1, Duration::from_secs(10)));
+
+    accumulator.record(&get_transition(Status::OnMains, start + PERIOD + Duration::from_secs(20)));
+    let summary = accumulator.take_summary(PERIOD, start + PERIOD * 2);
+    assert_eq!(summary.outages, 0);
+    assert_eq!(summary.on_battery_duration, Duration::from_secs(20));
+    assert_eq!(summary.longest_on_battery_duration, Duration::from_secs(30));
+    assert_eq!(summary.replace_battery_warnings, 0);
+  }
+}
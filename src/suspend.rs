@@ -0,0 +1,62 @@
+use std::mem;
+use std::time::Duration;
+
+// Drift between the two clocks while running is far below this, anything past it is the host having been asleep
+const MIN_SUSPEND_DURATION: Duration = Duration::from_secs(1);
+
+// Notices the host suspending and resuming, which the detection loop never could on its own as the monotonic clock it measures with
+// stands still during a suspend, the boot clock keeps counting through it so the two drift apart by exactly as long as the host slept
+pub struct SuspendWatcher {
+  suspended_total: Duration,
+}
+
+impl SuspendWatcher {
+  pub fn new() -> SuspendWatcher {
+    SuspendWatcher { suspended_total: get_suspended_total() }
+  }
+
+  // How long the host was suspended since the last check, if it was at all
+  pub fn check(&mut self) -> Option<Duration> {
+    self.check_total(get_suspended_total())
+  }
+
+  fn check_total(&mut self, suspended_total: Duration) -> Option<Duration> {
+    let suspended_duration = suspended_total.saturating_sub(mem::replace(&mut self.suspended_total, suspended_total));
+    (suspended_duration >= MIN_SUSPEND_DURATION).then_some(suspended_duration)
+  }
+}
+
+pub fn get_suspend_description(suspended_duration: Duration) -> String {
+  format!("Host was suspended for {}s, the line went unobserved meanwhile so the timing in progress was dropped", suspended_duration.as_secs())
+}
+
+fn get_clock_duration(clock: libc::clockid_t) -> Duration {
+  let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+  // Both clocks exist on every kernel this runs on, a failure leaves the time at zero which only ever reads as no suspend
+  unsafe { libc::clock_gettime(clock, &mut time) };
+  Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+fn get_suspended_total() -> Duration {
+  get_clock_duration(libc::CLOCK_BOOTTIME).saturating_sub(get_clock_duration(libc::CLOCK_MONOTONIC))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reports_each_suspend_once() {
+    let mut watcher = SuspendWatcher { suspended_total: Duration::from_secs(10) };
+    assert_eq!(watcher.check_total(Duration::from_millis(10_500)), None);
+    assert_eq!(watcher.check_total(Duration::from_secs(70)), Some(Duration::from_millis(59_500)));
+    assert_eq!(watcher.check_total(Duration::from_secs(70)), None);
+    // Small drifts are never added up into a suspend
+    assert_eq!(watcher.check_total(Duration::from_millis(70_900)), None);
+  }
+
+  #[test]
+  fn running_is_no_suspend() {
+    assert_eq!(SuspendWatcher::new().check(), None);
+  }
+}
@@ -0,0 +1,281 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::time::Duration;
+
+use crate::classifier::{BuiltinClassifier, Classifier};
+use crate::detector::{Detector, DetectorConfig, Edge};
+use crate::replay::{ReplaySource, parse_events};
+use crate::source::{EdgeSource, SourceEvent};
+use crate::status::{MatchConfig, Status, TIMEOUT_DURATION, get_error_range, get_status_from_name};
+
+// The margins and floors tried, every margin with every floor
+const SWEPT_ERROR_MARGINS: [f64; 30] = [
+  0.01, 0.02, 0.03, 0.04, 0.05, 0.06, 0.07, 0.08, 0.09, 0.10,
+  0.11, 0.12, 0.13, 0.14, 0.15, 0.16, 0.17, 0.18, 0.19, 0.20,
+  0.21, 0.22, 0.23, 0.24, 0.25, 0.26, 0.27, 0.28, 0.29, 0.30,
+];
+const SWEPT_MIN_ERROR_MILLIS: [u64; 11] = [0, 10, 20, 30, 40, 50, 75, 100, 125, 150, 200];
+// Share of the pairs of a status its suggested window has to take in, the rest being left to the odd pair a noisy line splits or merges
+const WINDOW_COVERAGE: f64 = 0.9;
+
+// A replay file whose header says what the UPS was actually doing along the way, one "# label <milliseconds since start> <status>"
+// line for every time it changed, the edges replay just the same with --replay as the labels are comments
+#[derive(Debug)]
+pub struct LabeledCapture {
+  events: Vec<(Duration, Edge)>,
+  // Oldest first, each holding until the next one
+  labels: Vec<(Duration, Status)>,
+}
+
+pub fn parse_labeled_capture(contents: &str) -> Result<LabeledCapture, String> {
+  let mut labels: Vec<(Duration, Status)> = vec![];
+  for (index, line) in contents.lines().enumerate() {
+    let Some(label) = line.trim().strip_prefix('#').and_then(|comment| comment.trim().strip_prefix("label ")) else {
+      continue;
+    };
+    let invalid_label = || format!("invalid label line {}: {}", index + 1, line.trim());
+    let (offset, status_name) = label.trim().split_once(' ').ok_or_else(invalid_label)?;
+    let offset = Duration::from_millis(offset.parse().map_err(|_| invalid_label())?);
+    let status = get_status_from_name(status_name.trim()).ok_or_else(|| format!("unknown status {} in label line {}", status_name.trim(), index + 1))?;
+    if labels.last().is_some_and(|last_label| last_label.0 > offset) {
+      return Err(format!("label line {} goes back in time", index + 1));
+    }
+    labels.push((offset, status));
+  }
+  if labels.is_empty() {
+    return Err("capture has no # label lines saying what the UPS was doing".to_string());
+  }
+  Ok(LabeledCapture { events: parse_events(contents)?, labels })
+}
+
+pub fn load_labeled_capture(path: &str) -> Result<LabeledCapture, String> {
+  let contents = fs::read_to_string(path).map_err(|error| format!("could not read {}: {}", path, error))?;
+  parse_labeled_capture(&contents).map_err(|error| format!("{} in {}", error, path))
+}
+
+impl LabeledCapture {
+  fn get_label(&self, offset: Duration) -> Option<Status> {
+    self.labels.iter().rev().find(|label| label.0 <= offset).map(|label| label.1)
+  }
+
+  // Every pair measured from edges along with the status the UPS was in when it ended, pairs ahead of the first label are left out,
+  // as with validating only edges count as the silence in between says nothing about the tolerances
+  fn get_labeled_pairs(&self, detector_config: DetectorConfig, inverted: bool) -> Vec<(Status, Duration, Duration)> {
+    // Only the measured durations are kept, the table is tried on them afterwards
+    let mut detector = Detector::with_classifier(detector_config, Box::new(BuiltinClassifier::default()));
+    let mut source = ReplaySource::new(self.events.clone(), 0.0);
+    let mut labeled_pairs = vec![];
+    while let Some(event) = source.next_event(TIMEOUT_DURATION) {
+      match event {
+        SourceEvent::Edge(edge, at) => {
+          if let Some(classification) = detector.on_edge(if inverted { edge.inverted() } else { edge }, at)
+            && let Some(label) = self.get_label(source.get_offset(at)) {
+            labeled_pairs.push((label, classification.beep_duration, classification.inter_beep_duration));
+          }
+        },
+        SourceEvent::Timeout(at) => {
+          detector.on_timeout(at);
+        },
+      }
+    }
+    labeled_pairs
+  }
+}
+
+// How one margin and floor did over the labeled pairs, a wrong status counting against it more than Unknown does
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct SweepScore {
+  pub error_margin: f64,
+  pub min_error_duration: Duration,
+  pub correct_count: usize,
+  pub unknown_count: usize,
+  pub misclassified_count: usize,
+}
+
+impl SweepScore {
+  pub fn get_description(&self) -> String {
+    let pair_count = self.correct_count + self.unknown_count + self.misclassified_count;
+    format!(
+      "--error-margin {:.2} --error-floor-ms {}: {} of {} pairs correct ({:.1}%), {} Unknown, {} misclassified",
+      self.error_margin,
+      self.min_error_duration.as_millis(),
+      self.correct_count,
+      pair_count,
+      self.correct_count as f64 / pair_count as f64 * 100.0,
+      self.unknown_count,
+      self.misclassified_count,
+    )
+  }
+}
+
+fn get_score(labeled_pairs: &[(Status, Duration, Duration)], match_config: &MatchConfig) -> SweepScore {
+  let mut classifier = BuiltinClassifier::new(match_config.clone());
+  let mut score = SweepScore {
+    error_margin: match_config.error_margin,
+    min_error_duration: match_config.min_error_duration,
+    correct_count: 0,
+    unknown_count: 0,
+    misclassified_count: 0,
+  };
+  for (label, beep, inter_beep) in labeled_pairs {
+    match classifier.classify(*beep, *inter_beep).status {
+      status if status == *label => score.correct_count += 1,
+      Status::Unknown => score.unknown_count += 1,
+      _ => score.misclassified_count += 1,
+    }
+  }
+  score
+}
+
+// The outcome of a sweep, the margin and floor to use along with how the ones in use did, and a window for every labeled status of the table
+pub struct Sweep {
+  pub current: SweepScore,
+  pub best: SweepScore,
+  // Pattern overrides as --pattern takes them, each wide enough for WINDOW_COVERAGE of the pairs labeled with its status
+  pub windows: Vec<String>,
+}
+
+impl Sweep {
+  pub fn get_description(&self) -> String {
+    let mut lines = vec![
+      format!("Current: {}", self.current.get_description()),
+      format!("Best: {}", self.best.get_description()),
+    ];
+    lines.extend(self.windows.iter().map(|window| format!("Window: --pattern {}", window)));
+    lines.join("\n")
+  }
+}
+
+// Classifies the pairs of the capture with every margin and floor, measuring them takes no tolerance so that is only done once,
+// the best is the one getting the most pairs right, then the fewest wrong, then the tightest as it leaves the least room for noise,
+// tightness being how wide the windows of the whole table add up to as a floor can be tighter than a margin for the long gaps,
+// tolerances the statuses have of their own from --pattern are kept as they are
+pub fn sweep_capture(capture: &LabeledCapture, detector_config: DetectorConfig, inverted: bool, match_config: &MatchConfig) -> Result<Sweep, String> {
+  let labeled_pairs = capture.get_labeled_pairs(detector_config, inverted);
+  if labeled_pairs.is_empty() {
+    return Err("capture has no pairs measured after its first label".to_string());
+  }
+
+  let current = get_score(&labeled_pairs, match_config);
+  let best = SWEPT_MIN_ERROR_MILLIS.iter()
+    .flat_map(|min_error_millis| SWEPT_ERROR_MARGINS.iter().map(move |error_margin| (*error_margin, Duration::from_millis(*min_error_millis))))
+    .map(|(error_margin, min_error_duration)| {
+      let candidate_config = MatchConfig { error_margin, min_error_duration, ..match_config.clone() };
+      (get_score(&labeled_pairs, &candidate_config), get_window_width(&candidate_config))
+    })
+    .min_by(|(score, width), (other_score, other_width)| {
+      (Reverse(score.correct_count), score.misclassified_count).cmp(&(Reverse(other_score.correct_count), other_score.misclassified_count))
+        .then_with(|| width.total_cmp(other_width))
+    })
+    .map(|(score, _)| score)
+    .unwrap();
+  // Only the first table entry of a status is the one --pattern overrides
+  let windows = match_config.beep_durations.iter().enumerate()
+    .filter(|(index, status_beep_duration)| !match_config.beep_durations[..*index].iter().any(|earlier| earlier.0 == status_beep_duration.0))
+    .filter_map(|(_, (status, targets))| get_window(&labeled_pairs, *status, *targets))
+    .collect();
+  Ok(Sweep { current, best, windows })
+}
+
+// Summed over both durations of every table entry, in nanoseconds
+fn get_window_width(match_config: &MatchConfig) -> f64 {
+  match_config.beep_durations.iter()
+    .flat_map(|status_beep_duration| status_beep_duration.1)
+    .map(|target| get_error_range(target, match_config.error_margin, match_config.min_error_duration))
+    .sum()
+}
+
+// None for a status no pair is labeled with
+fn get_window(labeled_pairs: &[(Status, Duration, Duration)], status: Status, [beep_target, inter_beep_target]: [Duration; 2]) -> Option<String> {
+  let pairs: Vec<(Duration, Duration)> = labeled_pairs.iter()
+    .filter(|labeled_pair| labeled_pair.0 == status)
+    .map(|labeled_pair| (labeled_pair.1, labeled_pair.2))
+    .collect();
+  if pairs.is_empty() {
+    return None;
+  }
+  let beep_margin = get_covering_margin(pairs.iter().map(|pair| pair.0), beep_target);
+  let inter_beep_margin = get_covering_margin(pairs.iter().map(|pair| pair.1), inter_beep_target);
+  Some(format!(
+    "{:?}:beep={}ms±{}%,gap={}ms±{}%",
+    status,
+    beep_target.as_millis(),
+    beep_margin,
+    inter_beep_target.as_millis(),
+    inter_beep_margin,
+  ))
+}
+
+// The smallest whole percentage of the target that takes in WINDOW_COVERAGE of the durations, at least 1
+fn get_covering_margin(durations: impl Iterator<Item = Duration>, target: Duration) -> u64 {
+  let mut deviations: Vec<f64> = durations.map(|duration| (duration.as_secs_f64() - target.as_secs_f64()).abs() / target.as_secs_f64()).collect();
+  deviations.sort_by(f64::total_cmp);
+  let covered_index = ((deviations.len() as f64 * WINDOW_COVERAGE).ceil() as usize).clamp(1, deviations.len()) - 1;
+  ((deviations[covered_index] * 100.0 - 1e-9).ceil() as u64).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A 250ms beep at the start and another after each of the gaps, labeled as the given status throughout
+  fn get_capture(gaps_ms: &[u64], beep_ms: u64, labels: &[(u64, &str)]) -> String {
+    let mut lines: Vec<String> = labels.iter().map(|(offset, status)| format!("# label {} {}", offset, status)).collect();
+    let mut offset = 0;
+    lines.push(format!("{} 1", offset));
+    lines.push(format!("{} 0", offset + beep_ms));
+    for gap_ms in gaps_ms {
+      offset += beep_ms + gap_ms;
+      lines.push(format!("{} 1", offset));
+      lines.push(format!("{} 0", offset + beep_ms));
+    }
+    lines.join("\n")
+  }
+
+  fn sweep(contents: &str) -> Sweep {
+    sweep_capture(&parse_labeled_capture(contents).unwrap(), DetectorConfig::default(), false, &MatchConfig::default()).unwrap()
+  }
+
+  #[test]
+  fn parses_labels() {
+    let capture = parse_labeled_capture("# label 0 OnBattery\n0 1\n250 0\n# label 60000 LowOnBattery\n").unwrap();
+    assert_eq!(capture.labels, vec![(Duration::ZERO, Status::OnBattery), (Duration::from_secs(60), Status::LowOnBattery)]);
+    assert_eq!(capture.get_label(Duration::from_secs(59)), Some(Status::OnBattery));
+    assert_eq!(capture.get_label(Duration::from_secs(60)), Some(Status::LowOnBattery));
+    assert_eq!(capture.events.len(), 2);
+
+    assert!(parse_labeled_capture("0 1\n250 0").unwrap_err().starts_with("capture has no # label lines"));
+    assert_eq!(parse_labeled_capture("# label 0 Battery\n").unwrap_err(), "unknown status Battery in label line 1");
+    assert_eq!(parse_labeled_capture("# label soon OnBattery\n").unwrap_err(), "invalid label line 1: # label soon OnBattery");
+    assert_eq!(parse_labeled_capture("# label 100 OnBattery\n# label 50 OnMains\n").unwrap_err(), "label line 2 goes back in time");
+  }
+
+  #[test]
+  fn finds_the_tightest_tolerance_getting_the_drift_right() {
+    // LowOnBattery 100ms slow, the built-in 5% only matches the first of them, a floor gets there with less room around the long gaps than a margin
+    let sweep = sweep(&get_capture(&[1000, 1100, 1100, 1100, 1100], 250, &[(0, "LowOnBattery")]));
+    assert_eq!((sweep.current.correct_count, sweep.current.unknown_count), (1, 4));
+    assert_eq!((sweep.best.correct_count, sweep.best.misclassified_count), (5, 0));
+    assert_eq!(sweep.best.get_description(), "--error-margin 0.01 --error-floor-ms 100: 5 of 5 pairs correct (100.0%), 0 Unknown, 0 misclassified");
+    assert_eq!(sweep.windows, vec!["LowOnBattery:beep=250ms±1%,gap=1000ms±10%"]);
+  }
+
+  #[test]
+  fn prefers_unknown_over_another_status() {
+    // 1500ms gaps are as far from LowOnBattery as they are from the 2000ms of OverloadOrShortCircuitOnBattery, no tolerance gets them right
+    let sweep = sweep(&get_capture(&[1000, 1000, 1500], 250, &[(0, "LowOnBattery")]));
+    assert_eq!(sweep.best.correct_count, 2);
+    assert_eq!(sweep.best.misclassified_count, 0);
+    assert!(sweep.get_description().starts_with("Current: --error-margin 0.05 --error-floor-ms 30: 2 of 3 pairs correct (66.7%), 1 Unknown, 0 misclassified\nBest: "));
+  }
+
+  #[test]
+  fn pairs_ahead_of_the_first_label_are_left_out() {
+    let contents = get_capture(&[1000, 1000, 1000], 250, &[(3000, "LowOnBattery")]);
+    let sweep = sweep(&contents);
+    assert_eq!(sweep.current.correct_count, 1);
+    let contents = get_capture(&[1000], 250, &[(5000, "LowOnBattery")]);
+    let error = sweep_capture(&parse_labeled_capture(&contents).unwrap(), DetectorConfig::default(), false, &MatchConfig::default()).err();
+    assert_eq!(error.as_deref(), Some("capture has no pairs measured after its first label"));
+  }
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use crate::classifier::Classifier;
+use crate::status::{CONTINUOUS_BEEP_DURATION, Classification, ZERO_DURATION};
+
+// Upper bounds of the named gap bands, roughly halfway between the gaps the status patterns use
+const GAP_BANDS: [(Duration, &str); 4] = [
+  (Duration::from_millis(1500), "short"),
+  (Duration::from_secs(7), "medium"),
+  (Duration::from_secs(25), "long"),
+  (Duration::from_secs(50), "very-long"),
+];
+const LONGEST_GAP_BAND_NAME: &str = "longest";
+
+// Beeps at least as long as the threshold are long ones
+pub fn get_beep_symbol(beep: Duration, long_beep_threshold: Duration) -> &'static str {
+  if beep >= long_beep_threshold { "long" } else { "short" }
+}
+
+pub fn get_gap_symbol(inter_beep: Duration) -> &'static str {
+  GAP_BANDS.iter()
+    .find(|gap_band| inter_beep < gap_band.0)
+    .map(|gap_band| gap_band.1)
+    .unwrap_or(LONGEST_GAP_BAND_NAME)
+}
+
+// The symbol of a pair as printed, the synthetic pair of a timeout during a beep gets a symbol of its own
+pub fn get_symbol_description(beep: Duration, inter_beep: Duration, long_beep_threshold: Duration) -> String {
+  match (beep, inter_beep) {
+    (CONTINUOUS_BEEP_DURATION, ZERO_DURATION) => "continuous beep".to_string(),
+    _ => format!(
+      "{} beep after {} gap ({}ms after {}ms)",
+      get_beep_symbol(beep, long_beep_threshold),
+      get_gap_symbol(inter_beep),
+      beep.as_millis(),
+      inter_beep.as_millis(),
+    ),
+  }
+}
+
+// Prints the symbol of every pair before handing it on, so symbols can be correlated with the known state of the UPS
+pub struct SymbolPrinter {
+  classifier: Box<dyn Classifier>,
+  long_beep_threshold: Duration,
+}
+
+impl SymbolPrinter {
+  pub fn new(classifier: Box<dyn Classifier>, long_beep_threshold: Duration) -> SymbolPrinter {
+    SymbolPrinter { classifier, long_beep_threshold }
+  }
+}
+
+impl Classifier for SymbolPrinter {
+  fn classify(&mut self, beep: Duration, inter_beep: Duration) -> Classification {
+    println!("Symbol: {}", get_symbol_description(beep, inter_beep, self.long_beep_threshold));
+    self.classifier.classify(beep, inter_beep)
+  }
+
+  fn classify_silence(&mut self, silence: Duration) -> Classification {
+    println!("Symbol: silence");
+    self.classifier.classify_silence(silence)
+  }
+
+  fn get_longest_gap(&self) -> Option<Duration> {
+    self.classifier.get_longest_gap()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::status::DEFAULT_LONG_BEEP_THRESHOLD_DURATION;
+
+  #[test]
+  fn names_beeps_and_gaps() {
+    assert_eq!(get_beep_symbol(Duration::from_millis(250), DEFAULT_LONG_BEEP_THRESHOLD_DURATION), "short");
+    assert_eq!(get_beep_symbol(Duration::from_secs(2), DEFAULT_LONG_BEEP_THRESHOLD_DURATION), "long");
+    assert_eq!(get_beep_symbol(Duration::from_millis(250), Duration::from_millis(200)), "long");
+    assert_eq!(get_gap_symbol(Duration::from_secs(1)), "short");
+    assert_eq!(get_gap_symbol(Duration::from_secs(4)), "medium");
+    assert_eq!(get_gap_symbol(Duration::from_secs(13)), "long");
+    assert_eq!(get_gap_symbol(Duration::from_secs(40)), "very-long");
+    assert_eq!(get_gap_symbol(Duration::from_secs(60)), "longest");
+  }
+
+  #[test]
+  fn describes_pairs() {
+    assert_eq!(get_symbol_description(Duration::from_millis(250), Duration::from_secs(2), DEFAULT_LONG_BEEP_THRESHOLD_DURATION), "short beep after medium gap (250ms after 2000ms)");
+    assert_eq!(get_symbol_description(CONTINUOUS_BEEP_DURATION, ZERO_DURATION, DEFAULT_LONG_BEEP_THRESHOLD_DURATION), "continuous beep");
+  }
+}
@@ -0,0 +1,323 @@
+use std::fs;
+use std::time::Duration;
+
+use crate::config::ConfigError;
+use crate::output::OutputTarget;
+use crate::profile::check_distinct_patterns;
+use crate::report::{Format, get_sink_name, parse_sink};
+use crate::status::{Status, Tolerance, get_status_from_name};
+
+// The table of a --config file, one [[pattern]] table per entry of it, in the order they are matched in,
+// along with what the top level of the command line says about it when given ahead of them:
+//   pin = 17
+//   active_low = true
+//   error_margin = 0.1
+//   error_floor_ms = 50
+//   sinks = ["stdout:text", "/var/log/ups.jsonl:json"]
+//
+//   [[pattern]]
+//   status = "OnBattery"
+//   beep_ms = 250
+//   inter_beep_ms = 60000
+//   beep_error_margin = 0.2
+//   beep_error_floor_ms = 100
+//   inter_beep_error_margin = 0.1
+//   inter_beep_error_floor_ms = 1000
+// only as much TOML as that takes is understood, comments and blank lines included, the timeout during a beep
+// being the entry with the continuous beep duration and an inter beep duration of 0
+pub fn load_table_file(path: &str) -> Result<Table, ConfigError> {
+  let contents = fs::read_to_string(path).map_err(|error| ConfigError::MissingFile { source: format!("config {}", path), error })?;
+  parse_table_file(path, &contents)
+}
+
+// Each of the top level is kept from the defaults, profile or model when it isn't given, and given along with the flag of it
+// the flag wins, the polarity only ever being turned to active low by --active-low
+#[derive(PartialEq, Debug, Default)]
+pub struct Table {
+  pub pin: Option<u8>,
+  // Whether the line is low while beeping
+  pub active_low: Option<bool>,
+  pub error_margin: Option<f64>,
+  pub min_error_duration: Option<Duration>,
+  pub sinks: Option<Vec<(OutputTarget, Format)>>,
+  pub beep_durations: Vec<(Status, [Duration; 2])>,
+  // Of the statuses whose entries have tolerances of their own, the first entry's when a status has several
+  pub status_tolerances: Vec<(Status, [Tolerance; 2])>,
+}
+
+// An entry as far as it has been read, along with the line its table starts on
+#[derive(Default)]
+struct PartialEntry {
+  line: usize,
+  status: Option<Status>,
+  beep_duration: Option<Duration>,
+  inter_beep_duration: Option<Duration>,
+  // Of the beep and the gap after it
+  error_margins: [Option<f64>; 2],
+  min_error_durations: [Option<Duration>; 2],
+}
+
+pub fn parse_table_file(path: &str, contents: &str) -> Result<Table, ConfigError> {
+  let source = format!("config {}", path);
+  let mut table = Table::default();
+  let mut entries: Vec<PartialEntry> = vec![];
+
+  for (index, line) in contents.lines().enumerate() {
+    let text = line.trim();
+    if text.is_empty() || text.starts_with('#') {
+      continue;
+    }
+
+    let invalid_line = || ConfigError::Parse { source: source.clone(), line: index + 1, text: text.to_string() };
+    if strip_comment(text) == "[[pattern]]" {
+      entries.push(PartialEntry { line: index + 1, ..PartialEntry::default() });
+      continue;
+    }
+    let (key, value) = text.split_once('=').ok_or_else(invalid_line)?;
+    // Keys ahead of the first table are the top level's
+    if entries.is_empty() {
+      match key.trim() {
+        "pin" if table.pin.is_none() => table.pin = Some(strip_comment(value).parse().ok().ok_or_else(invalid_line)?),
+        "active_low" if table.active_low.is_none() => table.active_low = Some(parse_bool(value).ok_or_else(invalid_line)?),
+        "error_margin" if table.error_margin.is_none() => table.error_margin = Some(parse_margin(value).ok_or_else(invalid_line)?),
+        "error_floor_ms" if table.min_error_duration.is_none() => table.min_error_duration = Some(parse_millis(value).ok_or_else(invalid_line)?),
+        "sinks" if table.sinks.is_none() => table.sinks = Some(parse_sinks(value).ok_or_else(invalid_line)?),
+        _ => return Err(invalid_line()),
+      }
+      continue;
+    }
+    let entry = entries.last_mut().ok_or_else(invalid_line)?;
+    match key.trim() {
+      "status" if entry.status.is_none() => entry.status = Some(parse_string(value).and_then(get_status_from_name).ok_or_else(invalid_line)?),
+      "beep_ms" if entry.beep_duration.is_none() => entry.beep_duration = Some(parse_millis(value).ok_or_else(invalid_line)?),
+      "inter_beep_ms" if entry.inter_beep_duration.is_none() => entry.inter_beep_duration = Some(parse_millis(value).ok_or_else(invalid_line)?),
+      "beep_error_margin" if entry.error_margins[0].is_none() => entry.error_margins[0] = Some(parse_margin(value).ok_or_else(invalid_line)?),
+      "beep_error_floor_ms" if entry.min_error_durations[0].is_none() => entry.min_error_durations[0] = Some(parse_millis(value).ok_or_else(invalid_line)?),
+      "inter_beep_error_margin" if entry.error_margins[1].is_none() => entry.error_margins[1] = Some(parse_margin(value).ok_or_else(invalid_line)?),
+      "inter_beep_error_floor_ms" if entry.min_error_durations[1].is_none() => entry.min_error_durations[1] = Some(parse_millis(value).ok_or_else(invalid_line)?),
+      _ => return Err(invalid_line()),
+    }
+  }
+
+  if entries.is_empty() {
+    return Err(ConfigError::Invalid { source, reason: "has no [[pattern]] entries".to_string() });
+  }
+  for entry in &entries {
+    let (Some(status), Some(beep_duration), Some(inter_beep_duration)) = (entry.status, entry.beep_duration, entry.inter_beep_duration) else {
+      return Err(ConfigError::Invalid { source: source.clone(), reason: format!("entry at line {} needs a status, beep_ms and inter_beep_ms", entry.line) });
+    };
+    table.beep_durations.push((status, [beep_duration, inter_beep_duration]));
+    // Half of them would leave the rest to the table wide tolerances, which the flags could still change after the file is read
+    match (entry.error_margins, entry.min_error_durations) {
+      ([None, None], [None, None]) => {},
+      ([Some(beep_margin), Some(inter_beep_margin)], [Some(beep_floor), Some(inter_beep_floor)]) => {
+        if !table.status_tolerances.iter().any(|status_tolerance| status_tolerance.0 == status) {
+          table.status_tolerances.push((status, [
+            Tolerance { error_margin: beep_margin, min_error_duration: beep_floor },
+            Tolerance { error_margin: inter_beep_margin, min_error_duration: inter_beep_floor },
+          ]));
+        }
+      },
+      _ => return Err(ConfigError::Invalid {
+        source: source.clone(),
+        reason: format!("entry at line {} needs all of beep_error_margin, beep_error_floor_ms, inter_beep_error_margin and inter_beep_error_floor_ms or none of them", entry.line),
+      }),
+    }
+  }
+  check_distinct_patterns(&source, &table.beep_durations)?;
+  Ok(table)
+}
+
+// The lines of a config file parse_table_file reads back as the same table, durations are whole milliseconds in a config file
+// so anything finer is cut off, and as strings have no escapes a sink whose path has a " in it doesn't read back at all
+pub fn get_table_file(table: &Table) -> String {
+  let mut lines = vec![];
+  if let Some(pin) = table.pin {
+    lines.push(format!("pin = {}", pin));
+  }
+  if let Some(active_low) = table.active_low {
+    lines.push(format!("active_low = {}", active_low));
+  }
+  if let Some(error_margin) = table.error_margin {
+    lines.push(format!("error_margin = {}", error_margin));
+  }
+  if let Some(min_error_duration) = table.min_error_duration {
+    lines.push(format!("error_floor_ms = {}", min_error_duration.as_millis()));
+  }
+  if let Some(sinks) = &table.sinks {
+    let sink_names: Vec<String> = sinks.iter().map(|sink| format!("\"{}\"", get_sink_name(sink))).collect();
+    lines.push(format!("sinks = [{}]", sink_names.join(", ")));
+  }
+  for (status, [beep_duration, inter_beep_duration]) in &table.beep_durations {
+    lines.push(String::new());
+    lines.push("[[pattern]]".to_string());
+    lines.push(format!("status = \"{:?}\"", status));
+    lines.push(format!("beep_ms = {}", beep_duration.as_millis()));
+    lines.push(format!("inter_beep_ms = {}", inter_beep_duration.as_millis()));
+    if let Some((_, [beep_tolerance, inter_beep_tolerance])) = table.status_tolerances.iter().find(|status_tolerance| status_tolerance.0 == *status) {
+      lines.push(format!("beep_error_margin = {}", beep_tolerance.error_margin));
+      lines.push(format!("beep_error_floor_ms = {}", beep_tolerance.min_error_duration.as_millis()));
+      lines.push(format!("inter_beep_error_margin = {}", inter_beep_tolerance.error_margin));
+      lines.push(format!("inter_beep_error_floor_ms = {}", inter_beep_tolerance.min_error_duration.as_millis()));
+    }
+  }
+  lines.push(String::new());
+  lines.join("\n")
+}
+
+// What is left of a value after a trailing comment, a # within a string never comes through here
+fn strip_comment(value: &str) -> &str {
+  value.split_once('#').map_or(value, |(value, _)| value).trim()
+}
+
+// A basic string without escapes, the only kind a status name needs
+fn parse_string(value: &str) -> Option<&str> {
+  let (string, rest) = value.trim().strip_prefix('"')?.split_once('"')?;
+  strip_comment(rest).is_empty().then_some(string)
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+  match strip_comment(value) {
+    "true" => Some(true),
+    "false" => Some(false),
+    _ => None,
+  }
+}
+
+// An integer, with the underscores TOML allows between its digits
+fn parse_millis(value: &str) -> Option<Duration> {
+  let digits = strip_comment(value);
+  if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+    return None;
+  }
+  digits.replace('_', "").parse().ok().map(Duration::from_millis)
+}
+
+// A share of the target duration, as --error-margin takes it
+fn parse_margin(value: &str) -> Option<f64> {
+  strip_comment(value).parse().ok().filter(|margin: &f64| *margin >= 0.0 && margin.is_finite())
+}
+
+// An array of basic strings on the one line, each a sink as --sink takes it, strings being read ahead of any comment
+// as a file path can have a # of its own
+fn parse_sinks(value: &str) -> Option<Vec<(OutputTarget, Format)>> {
+  let mut rest = value.trim().strip_prefix('[')?;
+  let mut sinks = vec![];
+  loop {
+    rest = rest.trim_start();
+    if let Some(after) = rest.strip_prefix(']') {
+      return (strip_comment(after).is_empty() && !sinks.is_empty()).then_some(sinks);
+    }
+    let (name, after) = rest.strip_prefix('"')?.split_once('"')?;
+    sinks.push(parse_sink(name).ok()?);
+    rest = after.trim_start();
+    if !rest.starts_with(']') {
+      rest = rest.strip_prefix(',')?;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::output::SyslogFormat;
+  use crate::status::{CONTINUOUS_BEEP_DURATION, STATUS_BEEP_DURATIONS, ZERO_DURATION};
+
+  #[test]
+  fn parses_table_files() {
+    let contents = "# Acme 1500\n\n[[pattern]]\nstatus = \"OnBattery\"\nbeep_ms = 500\ninter_beep_ms = 30_000 # every half minute\n\n[[pattern]] # beeping on\ninter_beep_ms = 0\nstatus = \"ReplaceBattery\"\nbeep_ms = 3000\n";
+    assert_eq!(parse_table_file("acme.toml", contents).unwrap().beep_durations, vec![
+      (Status::OnBattery, [Duration::from_millis(500), Duration::from_secs(30)]),
+      (Status::ReplaceBattery, [CONTINUOUS_BEEP_DURATION, ZERO_DURATION]),
+    ]);
+  }
+
+  #[test]
+  fn reads_back_the_built_in_table() {
+    let contents: String = STATUS_BEEP_DURATIONS.iter()
+      .map(|(status, [beep_duration, inter_beep_duration])| format!("[[pattern]]\nstatus = \"{:?}\"\nbeep_ms = {}\ninter_beep_ms = {}\n", status, beep_duration.as_millis(), inter_beep_duration.as_millis()))
+      .collect();
+    assert_eq!(parse_table_file("standard.toml", &contents).unwrap().beep_durations, STATUS_BEEP_DURATIONS.to_vec());
+  }
+
+  #[test]
+  fn parses_the_polarity_ahead_of_the_patterns() {
+    let pattern = "[[pattern]]\nstatus = \"OnBattery\"\nbeep_ms = 250\ninter_beep_ms = 60000\n";
+    assert_eq!(parse_table_file("acme.toml", pattern).unwrap().active_low, None);
+    assert_eq!(parse_table_file("acme.toml", &format!("active_low = true # open collector\n{}", pattern)).unwrap().active_low, Some(true));
+    assert_eq!(parse_table_file("acme.toml", &format!("active_low = false\n{}", pattern)).unwrap().active_low, Some(false));
+    assert_eq!(parse_table_file("acme.toml", &format!("active_low = yes\n{}", pattern)).unwrap_err().to_string(), "invalid line 1 in config acme.toml: active_low = yes");
+    // Within a table it would be a key of the pattern
+    assert_eq!(parse_table_file("acme.toml", &format!("{}active_low = true\n", pattern)).unwrap_err().to_string(), "invalid line 5 in config acme.toml: active_low = true");
+  }
+
+  #[test]
+  fn parses_the_top_level_and_tolerances() {
+    let contents = "pin = 22\nerror_margin = 0.15\nerror_floor_ms = 1_000\nsinks = [\"stdout:text\", \"/var/log/ups#1.jsonl:json\",] # both\n\n[[pattern]]\nstatus = \"OnBattery\"\nbeep_ms = 250\ninter_beep_ms = 60000\ninter_beep_error_floor_ms = 5000\nbeep_error_margin = 0.5\nbeep_error_floor_ms = 0\ninter_beep_error_margin = 0\n";
+    let table = parse_table_file("acme.toml", contents).unwrap();
+    assert_eq!(table.pin, Some(22));
+    assert_eq!(table.error_margin, Some(0.15));
+    assert_eq!(table.min_error_duration, Some(Duration::from_secs(1)));
+    assert_eq!(table.sinks, Some(vec![(OutputTarget::Stdout, Format::Text), (OutputTarget::File("/var/log/ups#1.jsonl".to_string()), Format::Json)]));
+    assert_eq!(table.status_tolerances, vec![(Status::OnBattery, [
+      Tolerance { error_margin: 0.5, min_error_duration: Duration::ZERO },
+      Tolerance { error_margin: 0.0, min_error_duration: Duration::from_secs(5) },
+    ])]);
+
+    let get_error = |contents: &str| parse_table_file("acme.toml", contents).unwrap_err().to_string();
+    let pattern = "[[pattern]]\nstatus = \"OnBattery\"\nbeep_ms = 250\ninter_beep_ms = 60000\n";
+    assert_eq!(get_error(&format!("pin = 256\n{}", pattern)), "invalid line 1 in config acme.toml: pin = 256");
+    assert_eq!(get_error(&format!("error_margin = -0.1\n{}", pattern)), "invalid line 1 in config acme.toml: error_margin = -0.1");
+    assert_eq!(get_error(&format!("sinks = []\n{}", pattern)), "invalid line 1 in config acme.toml: sinks = []");
+    assert_eq!(get_error(&format!("sinks = [\"stdout:text\" \"stderr:json\"]\n{}", pattern)), "invalid line 1 in config acme.toml: sinks = [\"stdout:text\" \"stderr:json\"]");
+    assert_eq!(get_error(&format!("sinks = [\"stdout:xml\"]\n{}", pattern)), "invalid line 1 in config acme.toml: sinks = [\"stdout:xml\"]");
+    assert_eq!(
+      get_error(&format!("{}beep_error_margin = 0.5\n", pattern)),
+      "config acme.toml entry at line 1 needs all of beep_error_margin, beep_error_floor_ms, inter_beep_error_margin and inter_beep_error_floor_ms or none of them",
+    );
+  }
+
+  #[test]
+  fn reads_back_what_it_writes() {
+    let table = Table {
+      pin: Some(17),
+      active_low: Some(true),
+      error_margin: Some(0.1),
+      min_error_duration: Some(Duration::from_millis(50)),
+      sinks: Some(vec![(OutputTarget::Stdout, Format::Text), (OutputTarget::Syslog(SyslogFormat::Rfc5424), Format::Json), (OutputTarget::File("/var/log/ups:main.jsonl".to_string()), Format::Char)]),
+      beep_durations: STATUS_BEEP_DURATIONS.to_vec(),
+      status_tolerances: vec![(Status::OnBattery, [
+        Tolerance { error_margin: 0.25, min_error_duration: Duration::from_millis(100) },
+        Tolerance { error_margin: 0.05, min_error_duration: Duration::from_secs(2) },
+      ])],
+    };
+    let contents = get_table_file(&table);
+    assert_eq!(parse_table_file("dumped.toml", &contents).unwrap(), table);
+    // Nothing more than the patterns is needed for a config
+    let patterns_only = Table { beep_durations: table.beep_durations.clone(), ..Table::default() };
+    assert_eq!(parse_table_file("dumped.toml", &get_table_file(&patterns_only)).unwrap(), patterns_only);
+  }
+
+  #[test]
+  fn rejects_unknown_statuses_with_their_line() {
+    let contents = "[[pattern]]\nstatus = \"OnBatery\"\nbeep_ms = 250\ninter_beep_ms = 60000\n";
+    assert_eq!(parse_table_file("acme.toml", contents).unwrap_err().to_string(), "invalid line 2 in config acme.toml: status = \"OnBatery\"");
+  }
+
+  #[test]
+  fn rejects_invalid_table_files() {
+    let get_error = |contents: &str| parse_table_file("acme.toml", contents).unwrap_err().to_string();
+    assert_eq!(get_error("status = \"OnBattery\"\n"), "invalid line 1 in config acme.toml: status = \"OnBattery\"");
+    assert_eq!(get_error("[[pattern]]\nstatus = OnBattery\n"), "invalid line 2 in config acme.toml: status = OnBattery");
+    assert_eq!(get_error("[[pattern]]\nbeep_ms = 2.5\n"), "invalid line 2 in config acme.toml: beep_ms = 2.5");
+    assert_eq!(get_error("[[pattern]]\nbeep_ms = 1__000\n"), "invalid line 2 in config acme.toml: beep_ms = 1__000");
+    assert_eq!(get_error("[[pattern]]\nbeep_ms = 250\nbeep_ms = 500\n"), "invalid line 3 in config acme.toml: beep_ms = 500");
+    assert_eq!(get_error("[[pattern]]\ngap_ms = 250\n"), "invalid line 2 in config acme.toml: gap_ms = 250");
+    assert_eq!(get_error("[patterns]\n"), "invalid line 1 in config acme.toml: [patterns]");
+    assert_eq!(get_error("# nothing yet\n"), "config acme.toml has no [[pattern]] entries");
+    assert_eq!(get_error("[[pattern]]\nstatus = \"OnBattery\"\nbeep_ms = 250\n"), "config acme.toml entry at line 1 needs a status, beep_ms and inter_beep_ms");
+    let same_patterns = "[[pattern]]\nstatus = \"OnBattery\"\nbeep_ms = 250\ninter_beep_ms = 1000\n[[pattern]]\nstatus = \"LowOnBattery\"\nbeep_ms = 250\ninter_beep_ms = 1000\n";
+    assert_eq!(get_error(same_patterns), "config acme.toml gives LowOnBattery the same pattern as OnBattery, the two could never be told apart");
+  }
+}
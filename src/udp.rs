@@ -0,0 +1,63 @@
+use std::net::UdpSocket;
+use std::thread;
+
+use crate::logging::warn;
+use crate::state::SharedState;
+
+// Sends every measured pair to the address as its own "<beep_ms> <inter_beep_ms>" datagram, for plotting live while calibrating,
+// nothing is sent back so packets to an address nobody listens on are just lost, failing to set up only warns like the HTTP server
+pub fn start_udp_raw(address: &str, state: SharedState) {
+  let socket = match get_connected_socket(address) {
+    Ok(socket) => socket,
+    Err(error) => {
+      warn!("Could not send measurements to {}: {}", address, error);
+      return;
+    }
+  };
+  let measurements = state.lock().unwrap().subscribe_to_measurements();
+
+  thread::spawn(move || {
+    for (beep, inter_beep) in measurements {
+      let _ = socket.send(format!("{} {}\n", beep.as_millis(), inter_beep.as_millis()).as_bytes());
+    }
+  });
+}
+
+// Bound to any port of the same address family as the destination
+fn get_connected_socket(address: &str) -> std::io::Result<UdpSocket> {
+  let socket = UdpSocket::bind(if address.starts_with('[') { "[::]:0" } else { "0.0.0.0:0" })?;
+  socket.connect(address)?;
+  Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::{Arc, Mutex};
+  use std::time::Duration;
+
+  use crate::state::StatusState;
+
+  #[test]
+  fn sends_every_measurement_as_a_datagram() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let state = Arc::new(Mutex::new(StatusState::new(10)));
+    start_udp_raw(&receiver.local_addr().unwrap().to_string(), state.clone());
+
+    state.lock().unwrap().publish_measurement(Duration::from_millis(250), Duration::from_millis(1000));
+    state.lock().unwrap().publish_measurement(Duration::from_millis(2000), Duration::from_secs(40));
+    let mut buffer = [0; 64];
+    let length = receiver.recv(&mut buffer).unwrap();
+    assert_eq!(&buffer[..length], b"250 1000\n");
+    let length = receiver.recv(&mut buffer).unwrap();
+    assert_eq!(&buffer[..length], b"2000 40000\n");
+  }
+
+  #[test]
+  fn invalid_addresses_only_warn() {
+    let state = Arc::new(Mutex::new(StatusState::new(10)));
+    start_udp_raw("not an address", state.clone());
+    assert_eq!(state.lock().unwrap().measurement_subscribers_count(), 0);
+  }
+}
@@ -0,0 +1,124 @@
+use crate::detector::Decoder;
+use crate::source::{EdgeSource, SourceEvent};
+use crate::status::{Classification, Status, TIMEOUT_DURATION};
+
+// Share of the measured pairs that has to match for a capture to pass, leaving room for the odd pair a noisy line splits or merges
+const PASS_FRACTION: f64 = 0.9;
+
+// How a capture taken while the UPS was known to be in a status classifies, only pairs measured from edges count,
+// the silence in between says nothing about the table
+pub struct Validation {
+  pub status: Status,
+  pub matched_count: usize,
+  // The pairs that classified as anything else, in the order they came
+  pub mismatches: Vec<Classification>,
+}
+
+impl Validation {
+  fn get_pair_count(&self) -> usize {
+    self.matched_count + self.mismatches.len()
+  }
+
+  // None when the capture has no measured pairs at all
+  pub fn get_matched_fraction(&self) -> Option<f64> {
+    let pair_count = self.get_pair_count();
+    (pair_count > 0).then(|| self.matched_count as f64 / pair_count as f64)
+  }
+
+  pub fn is_passing(&self) -> bool {
+    self.get_matched_fraction().is_some_and(|matched_fraction| matched_fraction >= PASS_FRACTION)
+  }
+
+  pub fn get_description(&self) -> String {
+    let mut lines = vec![match self.get_matched_fraction() {
+      Some(matched_fraction) => format!(
+        "{:?}: {} of {} pairs matched ({:.1}%), {}",
+        self.status,
+        self.matched_count,
+        self.get_pair_count(),
+        matched_fraction * 100.0,
+        if self.is_passing() { "pass" } else { "fail" },
+      ),
+      None => format!("{:?}: no pairs measured, fail", self.status),
+    }];
+    lines.extend(self.mismatches.iter().map(|mismatch| format!(
+      "Mismatch: {:?} for beep {}ms, inter beep {}ms",
+      mismatch.status,
+      mismatch.beep_duration.as_millis(),
+      mismatch.inter_beep_duration.as_millis(),
+    )));
+    lines.join("\n")
+  }
+}
+
+pub fn validate_capture(source: &mut dyn EdgeSource, decoder: &mut dyn Decoder, inverted: bool, status: Status) -> Validation {
+  let mut validation = Validation { status, matched_count: 0, mismatches: vec![] };
+  while let Some(event) = source.next_event(TIMEOUT_DURATION) {
+    let classification = match event {
+      SourceEvent::Edge(edge, at) => decoder.on_edge(if inverted { edge.inverted() } else { edge }, at),
+      // Still handed to the decoder so it keeps its timing state just as when detecting
+      SourceEvent::Timeout(at) => {
+        decoder.on_timeout(at);
+        None
+      },
+    };
+    match classification {
+      Some(classification) if classification.status == status => validation.matched_count += 1,
+      Some(classification) => validation.mismatches.push(classification),
+      None => {},
+    }
+  }
+  validation
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  use crate::detector::{Detector, DetectorConfig, Edge};
+  use crate::replay::ReplaySource;
+
+  // A 250ms beep at the start and another after each of the gaps
+  fn get_capture(gaps_ms: &[u64]) -> ReplaySource {
+    let mut events = vec![(Duration::ZERO, Edge::BeepStart), (Duration::from_millis(250), Edge::BeepEnd)];
+    let mut offset = Duration::from_millis(250);
+    for gap_ms in gaps_ms {
+      offset += Duration::from_millis(*gap_ms);
+      events.push((offset, Edge::BeepStart));
+      offset += Duration::from_millis(250);
+      events.push((offset, Edge::BeepEnd));
+    }
+    ReplaySource::new(events, 0.0)
+  }
+
+  fn validate(gaps_ms: &[u64], status: Status) -> Validation {
+    let mut detector = Detector::new(DetectorConfig::default());
+    validate_capture(&mut get_capture(gaps_ms), &mut detector, false, status)
+  }
+
+  #[test]
+  fn matching_capture_passes() {
+    let validation = validate(&[1000; 10], Status::LowOnBattery);
+    assert_eq!((validation.matched_count, validation.mismatches.len()), (10, 0));
+    assert!(validation.is_passing());
+    assert_eq!(validation.get_description(), "LowOnBattery: 10 of 10 pairs matched (100.0%), pass");
+  }
+
+  #[test]
+  fn mismatches_are_listed_and_fail_past_the_pass_fraction() {
+    let validation = validate(&[1000, 1000, 2000, 1000, 1500], Status::LowOnBattery);
+    assert!(!validation.is_passing());
+    assert_eq!(validation.get_description(), "LowOnBattery: 3 of 5 pairs matched (60.0%), fail\n\
+      Mismatch: OverloadOrShortCircuitOnBattery for beep 250ms, inter beep 2000ms\n\
+      Mismatch: Unknown for beep 250ms, inter beep 1500ms");
+  }
+
+  #[test]
+  fn capture_without_pairs_fails() {
+    let validation = validate(&[], Status::OnBattery);
+    assert_eq!(validation.get_matched_fraction(), None);
+    assert!(!validation.is_passing());
+    assert_eq!(validation.get_description(), "OnBattery: no pairs measured, fail");
+  }
+}
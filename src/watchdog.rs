@@ -0,0 +1,64 @@
+use std::time::{Duration, Instant};
+
+// Warns when the line has gone without a single edge for longer than any activity is expected to be apart,
+// only worth enabling for UPSes that chirp every so often even on mains, where total silence points at the wiring instead
+pub struct ActivityWatchdog {
+  interval: Duration,
+  // Silence before this doesn't count, the warmup gives the line time to settle just as it does the reports
+  armed_at: Instant,
+  last_edge_at: Option<Instant>,
+  // Whether the current silence has already been warned about, so it is warned about once until the next edge
+  is_warned: bool,
+}
+
+impl ActivityWatchdog {
+  pub fn new(interval: Duration, warmup_duration: Duration, now: Instant) -> ActivityWatchdog {
+    ActivityWatchdog { interval, armed_at: now + warmup_duration, last_edge_at: None, is_warned: false }
+  }
+
+  pub fn on_edge(&mut self, now: Instant) {
+    self.last_edge_at = Some(now);
+    self.is_warned = false;
+  }
+
+  pub fn check(&mut self, now: Instant) -> Option<String> {
+    let silent_since = self.last_edge_at.map_or(self.armed_at, |last_edge_at| last_edge_at.max(self.armed_at));
+    if self.is_warned || now.saturating_duration_since(silent_since) < self.interval {
+      return None;
+    }
+    self.is_warned = true;
+    Some(match self.last_edge_at {
+      Some(_) => format!("No beeps for {}s although activity is expected at least every {}s, check the wiring", now.saturating_duration_since(silent_since).as_secs(), self.interval.as_secs()),
+      None => format!("No beeps since starting although activity is expected at least every {}s, check the wiring", self.interval.as_secs()),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const INTERVAL: Duration = Duration::from_secs(600);
+  const WARMUP: Duration = Duration::from_secs(60);
+
+  #[test]
+  fn warns_once_per_silence_after_the_warmup() {
+    let start = Instant::now();
+    let mut watchdog = ActivityWatchdog::new(INTERVAL, WARMUP, start);
+    assert_eq!(watchdog.check(start + INTERVAL), None);
+    assert_eq!(watchdog.check(start + WARMUP + INTERVAL).as_deref(), Some("No beeps since starting although activity is expected at least every 600s, check the wiring"));
+    assert_eq!(watchdog.check(start + WARMUP + INTERVAL * 2), None);
+  }
+
+  #[test]
+  fn edges_rearm_it() {
+    let start = Instant::now();
+    let mut watchdog = ActivityWatchdog::new(INTERVAL, WARMUP, start);
+    watchdog.on_edge(start + WARMUP + Duration::from_secs(100));
+    assert_eq!(watchdog.check(start + WARMUP + INTERVAL), None);
+    assert_eq!(watchdog.check(start + WARMUP + INTERVAL + Duration::from_secs(100)).as_deref(), Some("No beeps for 600s although activity is expected at least every 600s, check the wiring"));
+    watchdog.on_edge(start + WARMUP + INTERVAL * 2);
+    assert_eq!(watchdog.check(start + WARMUP + INTERVAL * 2 + Duration::from_secs(1)), None);
+    assert!(watchdog.check(start + WARMUP + INTERVAL * 3).is_some());
+  }
+}
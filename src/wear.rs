@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant};
+
+// ReplaceBattery sightings lose half their weight per day, so only a battery that keeps asking to be replaced builds up a score
+const SIGHTING_HALF_LIFE: Duration = Duration::from_secs(24 * 60 * 60);
+// Roughly five hours worth of the long beep every 40s within a day
+pub const DEFAULT_ESCALATION_SCORE: f64 = 450.0;
+
+// Counts ReplaceBattery sightings with exponential decay to tell a transient chirp from a battery that is actually dying
+pub struct BatteryWearTracker {
+  escalation_score: f64,
+
+  score: f64,
+  last_sighting_time: Option<Instant>,
+  escalated: bool,
+}
+
+impl BatteryWearTracker {
+  pub fn new(escalation_score: f64) -> BatteryWearTracker {
+    BatteryWearTracker {
+      escalation_score,
+      score: 0.0,
+      last_sighting_time: None,
+      escalated: false,
+    }
+  }
+
+  // Returns true the first time the sightings add up to a persistent request, after which it only escalates again
+  // once the score has decayed below half the escalation score in between
+  pub fn record_sighting(&mut self, now: Instant) -> bool {
+    self.decay(now);
+    self.score += 1.0;
+
+    if self.escalated && self.score < self.escalation_score / 2.0 {
+      self.escalated = false;
+    }
+    if !self.escalated && self.score >= self.escalation_score {
+      self.escalated = true;
+      return true;
+    }
+    false
+  }
+
+  fn decay(&mut self, now: Instant) {
+    if let Some(last_sighting_time) = self.last_sighting_time {
+      let elapsed = now.saturating_duration_since(last_sighting_time);
+      self.score *= 0.5f64.powf(elapsed.as_secs_f64() / SIGHTING_HALF_LIFE.as_secs_f64());
+    }
+    self.last_sighting_time = Some(now);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SIGHTING_INTERVAL: Duration = Duration::from_secs(40);
+
+  #[test]
+  fn transient_sightings_do_not_escalate() {
+    let mut tracker = BatteryWearTracker::new(DEFAULT_ESCALATION_SCORE);
+    let start = Instant::now();
+    // An hour of beeping every day for a week
+    for day in 0..7 {
+      for sighting in 0..90 {
+        assert!(!tracker.record_sighting(start + SIGHTING_HALF_LIFE * day + SIGHTING_INTERVAL * sighting));
+      }
+    }
+  }
+
+  #[test]
+  fn persistent_sightings_escalate_once() {
+    let mut tracker = BatteryWearTracker::new(DEFAULT_ESCALATION_SCORE);
+    let start = Instant::now();
+    let escalations: Vec<u32> = (0..2000)
+      .filter(|sighting| tracker.record_sighting(start + SIGHTING_INTERVAL * *sighting))
+      .collect();
+    assert_eq!(escalations.len(), 1);
+    // The decay over the hours it takes makes it a few more than the escalation score itself
+    assert!(escalations[0] > 450 && escalations[0] < 500);
+  }
+
+  #[test]
+  fn escalates_again_after_decaying() {
+    let mut tracker = BatteryWearTracker::new(9.5);
+    let start = Instant::now();
+    assert!((0..10).any(|sighting| tracker.record_sighting(start + SIGHTING_INTERVAL * sighting)));
+
+    let later = start + SIGHTING_HALF_LIFE * 3;
+    assert!(!tracker.record_sighting(later));
+    assert!((1..10).any(|sighting| tracker.record_sighting(later + SIGHTING_INTERVAL * sighting)));
+  }
+}